@@ -7,7 +7,7 @@ extern crate test;
 use std::io;
 use std::io::Write;
 
-use deflate::{deflate_bytes_zlib, deflate_bytes_zlib_conf, CompressionOptions};
+use deflate::{try_deflate_bytes_zlib, try_deflate_bytes_zlib_conf, CompressionOptions};
 use flate2::write;
 use flate2::Compression;
 use test::Bencher;
@@ -32,28 +32,28 @@ fn get_test_data() -> Vec<u8> {
 fn test_file_zlib_def(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib(&test_data));
+    b.iter(|| try_deflate_bytes_zlib(&test_data).unwrap());
 }
 
 #[bench]
 fn test_file_zlib_best(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::high()));
+    b.iter(|| try_deflate_bytes_zlib_conf(&test_data, CompressionOptions::high()).unwrap());
 }
 
 #[bench]
 fn test_file_zlib_fast(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::fast()));
+    b.iter(|| try_deflate_bytes_zlib_conf(&test_data, CompressionOptions::fast()).unwrap());
 }
 
 #[bench]
 fn test_file_zlib_rle(b: &mut Bencher) {
     let test_data = get_test_data();
 
-    b.iter(|| deflate_bytes_zlib_conf(&test_data, CompressionOptions::rle()));
+    b.iter(|| try_deflate_bytes_zlib_conf(&test_data, CompressionOptions::rle()).unwrap());
 }
 
 fn deflate_bytes_flate2_zlib(level: Compression, input: &[u8]) -> Vec<u8> {
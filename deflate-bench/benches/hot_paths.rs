@@ -0,0 +1,64 @@
+//! Micro-benchmarks of the hot paths of the encoder, to evaluate changes to individual parts
+//! rather than only the end-to-end compression time measured in `bench.rs`.
+//!
+//! Run with `cargo bench` in the `deflate-bench` directory.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use deflate::bench_internals::{hash_insert, Block, Matcher};
+use deflate::HashFunction;
+
+fn get_test_data() -> Vec<u8> {
+    use std::env;
+    let path = env::var("TEST_FILE").unwrap_or_else(|_| "../tests/pg11.txt".to_string());
+    std::fs::read(path).unwrap()
+}
+
+fn longest_match(c: &mut Criterion) {
+    let data = get_test_data();
+    let matcher = Matcher::new(&data, 29000);
+    let mut group = c.benchmark_group("longest_match");
+    for &checks in &[8u16, 128, 4096] {
+        group.bench_function(format!("{}_checks", checks), |b| {
+            b.iter(|| matcher.longest_match(black_box(checks)))
+        });
+    }
+    group.finish();
+}
+
+fn hash_insertion(c: &mut Criterion) {
+    let data = get_test_data();
+    let mut group = c.benchmark_group("hash_insert");
+    for &(name, hash_function) in &[
+        ("shift_xor", HashFunction::ShiftXor),
+        ("multiplicative", HashFunction::Multiplicative),
+        ("crc", HashFunction::Crc),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| hash_insert(black_box(&data), hash_function))
+        });
+    }
+    group.finish();
+}
+
+fn bitstream(c: &mut Criterion) {
+    let mut block = Block::new(&get_test_data());
+    c.bench_function("flush_to_bitstream", |b| {
+        b.iter(|| block.flush_to_bitstream())
+    });
+}
+
+fn length_encoding(c: &mut Criterion) {
+    let block = Block::new(&get_test_data());
+    c.bench_function("encode_lengths", |b| {
+        b.iter(|| black_box(&block).encode_lengths())
+    });
+}
+
+criterion_group!(
+    benches,
+    longest_match,
+    hash_insertion,
+    bitstream,
+    length_encoding
+);
+criterion_main!(benches);
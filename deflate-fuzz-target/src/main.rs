@@ -7,7 +7,7 @@ fn roundtrip(data: &[u8]) {
 }
 
 fn roundtrip_conf(data: &[u8], level: CompressionOptions) {
-    let compressed = deflate::deflate_bytes_zlib_conf(data, level);
+    let compressed = deflate::try_deflate_bytes_zlib_conf(data, level).unwrap();
     let decompressed =
         miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).expect("Decompression failed!");
     assert!(decompressed.as_slice() == data);
@@ -0,0 +1,141 @@
+//! Entry points into the internals of the encoder for the micro-benchmarks in the
+//! `deflate-bench` crate.
+//!
+//! Only available with the `bench-internals` feature. This is not part of the public API and
+//! may change at any time.
+use crate::chained_hash_table::{ChainedHashTable, HashFunction, WINDOW_SIZE};
+use crate::compress::flush_to_bitstream;
+use crate::compression_options::CompressionOptions;
+use crate::encoder_state::EncoderState;
+use crate::huffman_table::{NUM_DISTANCE_CODES, NUM_LITERALS_AND_LENGTHS};
+use crate::input_buffer::InputBuffer;
+use crate::length_encode::{
+    encode_lengths_m, huffman_lengths_from_frequency_m, EncodedLength, LeafVec,
+};
+use crate::lz77::{lz77_compress_block_finish, LZ77State};
+use crate::lzvalue::LZValue;
+use crate::matching::longest_match;
+use crate::output_writer::DynamicWriter;
+
+/// Insert the bytes of `data` (at most two windows) into a new hash table, returning the final
+/// hash value.
+pub fn hash_insert(data: &[u8], hash_function: HashFunction) -> u16 {
+    let mut table = ChainedHashTable::with_hash_function(hash_function);
+    table.add_initial_hash_values(data[0], data[1]);
+    let data = &data[..data.len().min(WINDOW_SIZE * 2 + 2)];
+    for (n, &b) in data[2..].iter().enumerate() {
+        table.add_hash_value(n, b);
+    }
+    table.get_prev(data.len() - 3)
+}
+
+/// A hash table filled with the data before a position, to search for matches at it.
+pub struct Matcher {
+    data: Vec<u8>,
+    table: ChainedHashTable,
+    position: usize,
+}
+
+impl Matcher {
+    /// Fill the hash table with `data` up to `position`, which has to be less than two windows.
+    pub fn new(data: &[u8], position: usize) -> Matcher {
+        assert!(position < WINDOW_SIZE * 2 && position + 2 < data.len());
+        let mut table = ChainedHashTable::with_hash_function(HashFunction::Multiplicative);
+        table.add_initial_hash_values(data[0], data[1]);
+        for (n, &b) in data[2..position + 3].iter().enumerate() {
+            table.add_hash_value(n, b);
+        }
+        Matcher {
+            data: data.to_vec(),
+            table,
+            position,
+        }
+    }
+
+    /// Find the longest match at the position, returning the length and distance.
+    pub fn longest_match(&self, max_hash_checks: u16) -> (usize, usize) {
+        longest_match(
+            &self.data,
+            &self.table,
+            self.position,
+            0,
+            max_hash_checks,
+            WINDOW_SIZE,
+        )
+    }
+}
+
+/// The LZ77 output of a block, to be written to a bitstream.
+pub struct Block {
+    values: Vec<LZValue>,
+    literal_length_frequencies: Vec<u16>,
+    distance_frequencies: Vec<u16>,
+    state: EncoderState,
+}
+
+impl Block {
+    /// Compress the first block of `data` using the default options.
+    pub fn new(data: &[u8]) -> Block {
+        let options = CompressionOptions::default();
+        let mut lz77_state = LZ77State::new(
+            options.max_hash_checks,
+            options.lazy_if_less_than,
+            options.matching_type,
+            options.window_bits,
+            options.max_search_distance,
+            options.hash_function,
+        );
+        let mut writer = DynamicWriter::new();
+        lz77_compress_block_finish(
+            data,
+            &mut lz77_state,
+            &mut InputBuffer::empty(),
+            &mut writer,
+        );
+        let (l_freqs, d_freqs) = writer.get_frequencies();
+        let mut state = EncoderState::new(Vec::with_capacity(data.len()));
+        state.set_huffman_to_fixed();
+        Block {
+            values: writer.get_buffer().to_vec(),
+            literal_length_frequencies: l_freqs.to_vec(),
+            distance_frequencies: d_freqs.to_vec(),
+            state,
+        }
+    }
+
+    /// Write the block to the bitstream using the fixed Huffman codes, returning the number of
+    /// bytes output.
+    pub fn flush_to_bitstream(&mut self) -> usize {
+        self.state.inner_vec().clear();
+        flush_to_bitstream(&self.values, &mut self.state);
+        self.state.inner_vec().len()
+    }
+
+    /// Generate Huffman code lengths of at most 15 bits from the symbol frequencies of the
+    /// block, and encode them the way they are stored in a dynamic block header.
+    pub fn encode_lengths(&self) -> usize {
+        let mut leaf_buffer = LeafVec::with_capacity(NUM_LITERALS_AND_LENGTHS);
+        let mut l_lengths = [0; NUM_LITERALS_AND_LENGTHS];
+        let mut d_lengths = [0; NUM_DISTANCE_CODES];
+        huffman_lengths_from_frequency_m(
+            &self.literal_length_frequencies,
+            15,
+            &mut leaf_buffer,
+            &mut l_lengths,
+        );
+        huffman_lengths_from_frequency_m(
+            &self.distance_frequencies,
+            15,
+            &mut leaf_buffer,
+            &mut d_lengths,
+        );
+        let mut encoded: Vec<EncodedLength> = Vec::with_capacity(NUM_LITERALS_AND_LENGTHS);
+        let mut frequencies = [0; 19];
+        encode_lengths_m(
+            l_lengths.iter().chain(d_lengths.iter()),
+            &mut encoded,
+            &mut frequencies,
+        );
+        encoded.len()
+    }
+}
@@ -0,0 +1,129 @@
+//! A small command line tool for compressing and decompressing files.
+//!
+//! Only built with the `cli` feature:
+//!
+//! ```text
+//! cargo run --features cli --bin deflate-cli -- [-d] [-f raw|zlib|gzip] [-l fast|default|best|0-9] INPUT OUTPUT
+//! ```
+//!
+//! `-` can be used for the input and output to read from stdin and write to stdout. The data is
+//! compressed while it is read, using the encoders from `deflate::write`, and decompressed using
+//! `deflate::inflate`.
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
+
+use deflate::inflate;
+use deflate::write::AnyEncoder;
+use deflate::{Compression, CompressionOptions, Format};
+
+const USAGE: &str =
+    "usage: deflate-cli [-d] [-f raw|zlib|gzip] [-l fast|default|best|0-9] INPUT OUTPUT";
+
+struct Args {
+    decompress: bool,
+    format: Format,
+    level: CompressionOptions,
+    input: String,
+    output: String,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut decompress = false;
+    let mut format = Format::Gzip;
+    let mut level = CompressionOptions::default();
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-d" => decompress = true,
+            "-f" => {
+                format = match args.next().as_ref().map(String::as_str) {
+                    Some("raw") => Format::Raw,
+                    Some("zlib") => Format::Zlib,
+                    Some("gzip") => Format::Gzip,
+                    other => return Err(format!("invalid format: {:?}", other)),
+                }
+            }
+            "-l" => {
+                level = match args.next().as_ref().map(String::as_str) {
+                    Some("fast") => Compression::Fast.into(),
+                    Some("default") => Compression::Default.into(),
+                    Some("best") => Compression::Best.into(),
+                    Some(n) if n.len() == 1 && n.as_bytes()[0].is_ascii_digit() => {
+                        CompressionOptions::from_level(n.as_bytes()[0] - b'0')
+                    }
+                    other => return Err(format!("invalid level: {:?}", other)),
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err("expected an input and an output path".to_string());
+    }
+    let output = paths.pop().unwrap();
+    let input = paths.pop().unwrap();
+    Ok(Args {
+        decompress,
+        format,
+        level,
+        input,
+        output,
+    })
+}
+
+fn open_input(path: &str) -> io::Result<Box<dyn Read>> {
+    Ok(if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    })
+}
+
+fn open_output(path: &str) -> io::Result<Box<dyn Write>> {
+    Ok(if path == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    })
+}
+
+fn compress(args: &Args) -> io::Result<()> {
+    let mut input = open_input(&args.input)?;
+    let mut encoder = AnyEncoder::new(open_output(&args.output)?, args.format, args.level);
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()
+}
+
+fn decompress(args: &Args) -> io::Result<()> {
+    let mut data = Vec::new();
+    open_input(&args.input)?.read_to_end(&mut data)?;
+    let decompressed = match args.format {
+        Format::Raw => inflate::inflate_bytes(&data)?,
+        Format::Zlib => inflate::inflate_bytes_zlib(&data)?,
+        Format::Gzip => inflate::inflate_bytes_gzip(&data)?,
+    };
+    let mut output = open_output(&args.output)?;
+    output.write_all(&decompressed)?;
+    output.flush()
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    let result = if args.decompress {
+        decompress(&args)
+    } else {
+        compress(&args)
+    };
+    if let Err(e) = result {
+        eprintln!("deflate-cli: {}", e);
+        process::exit(1);
+    }
+}
@@ -3,6 +3,7 @@
 // which is under both Apache 2.0 and MIT
 
 //! This module provides a bit writer
+use std::cmp;
 use std::io::{self, Write};
 
 #[cfg(target_pointer_width = "64")]
@@ -72,9 +73,24 @@ impl LsbWriter {
         self.bits
     }
 
+    /// The pending bits, after whole bytes have been flushed so there are less than 8 of them.
+    pub fn pending_byte(&self) -> u8 {
+        debug_assert!(self.bits < 8);
+        self.acc as u8
+    }
+
     /// Buffer n number of bits, and write them to the vec if there are enough pending bits.
+    ///
+    /// `n` has to be at most 16, and `v` can't have any bits set above the lowest `n` ones,
+    /// otherwise garbage is output. This is checked when debug assertions are enabled.
     pub fn write_bits(&mut self, v: u16, n: u8) {
-        // NOTE: This outputs garbage data if n is 0, but v is not 0
+        debug_assert!(n <= 16, "Tried to write {} bits at once!", n);
+        debug_assert!(
+            u32::from(v) >> n == 0,
+            "Value {:#x} doesn't fit in {} bits!",
+            v,
+            n
+        );
         self.acc |= (AccType::from(v)) << self.bits;
         self.bits += n;
         // Waiting until we have FLUSH_AT bits and pushing them all in one batch.
@@ -86,7 +102,7 @@ impl LsbWriter {
     }
 
     fn write_bits_finish(&mut self, v: u16, n: u8) {
-        // NOTE: This outputs garbage data if n is 0, but v is not 0
+        debug_assert!(n >= 16 || u32::from(v) >> n == 0);
         self.acc |= (AccType::from(v)) << self.bits;
         self.bits += n % 8;
         while self.bits >= 8 {
@@ -96,6 +112,29 @@ impl LsbWriter {
         }
     }
 
+    /// Write the bits `start..end` of `data`, where the bits of each byte are numbered starting
+    /// from the least significant one, like in the output.
+    pub fn write_bit_range(&mut self, data: &[u8], start: u64, end: u64) {
+        let mut pos = start;
+        while pos < end {
+            let shift = (pos % 8) as u8;
+            let n = cmp::min(u64::from(8 - shift), end - pos) as u8;
+            let bits = u16::from(data[(pos / 8) as usize] >> shift) & ((1 << n) - 1);
+            self.write_bits(bits, n);
+            pos += u64::from(n);
+        }
+    }
+
+    /// Push all the complete bytes that are pending to the vec, leaving at most 7 bits in the
+    /// accumulator.
+    pub fn flush_whole_bytes(&mut self) {
+        while self.bits >= 8 {
+            self.w.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
     pub fn flush_raw(&mut self) {
         let missing = FLUSH_AT - self.bits;
         // Have to test for self.bits > 0 here,
@@ -176,6 +215,22 @@ mod test {
         writer.flush_raw();
         assert_eq!(writer.w, expected);
     }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn write_bits_too_wide() {
+        let mut writer = LsbWriter::new(Vec::new());
+        writer.write_bits(0, 17);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn write_bits_value_too_large() {
+        let mut writer = LsbWriter::new(Vec::new());
+        writer.write_bits(5, 0);
+    }
 }
 
 #[cfg(all(test, feature = "benchmarks"))]
@@ -0,0 +1,259 @@
+//! A low-level encoder where the caller decides where each block starts and ends.
+use std::mem;
+
+use crate::compress::{write_huffman_block, write_stored_block, BlockKind, Flush};
+use crate::compression_options::CompressionOptions;
+use crate::deflate_state::DeflateState;
+use crate::error::Error;
+use crate::huffman_lengths::gen_huffman_lengths;
+use crate::lz77::{lz77_compress_block, LZ77Status};
+use crate::lzvalue::LZValue;
+use crate::two_pass::Frequencies;
+use crate::writer::finished_error;
+
+/// An encoder that outputs each piece of data given to it as a DEFLATE block of the requested
+/// type, for applications that need to control the block boundaries themselves, such as PNG
+/// encoders or custom container formats.
+///
+/// Matches may refer back to the data of earlier blocks, so the blocks only make sense as part of
+/// the same stream. The output is a raw DEFLATE stream, which is complete once a block has been
+/// written with `final_block` set.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{BlockEncoder, BlockKind, Compression};
+///
+/// let mut encoder = BlockEncoder::new(Compression::Default);
+/// encoder.write_block(b"A header that is output as is", BlockKind::Stored, false).unwrap();
+/// encoder.write_block(b"Some data, some more data", BlockKind::Dynamic, true).unwrap();
+/// let compressed = encoder.finish();
+/// # let _ = compressed;
+/// ```
+pub struct BlockEncoder {
+    // Only the LZ77 and Huffman parts of the state are used, the output is kept in the encoder
+    // state's buffer.
+    deflate_state: Box<DeflateState<Vec<u8>>>,
+    values: Vec<LZValue>,
+    /// Number of bytes of output taken with `take_output`.
+    bytes_taken: u64,
+}
+
+impl BlockEncoder {
+    /// Create a new block encoder using the provided compression options.
+    ///
+    /// Only the options used for finding matches and the code length limit have any effect, as
+    /// the block boundaries and types are chosen by the caller.
+    pub fn new<O: Into<CompressionOptions>>(options: O) -> BlockEncoder {
+        BlockEncoder::with_state(DeflateState::new(options.into(), Vec::new()))
+    }
+
+    /// Create a new block encoder using the provided compression options, or return an error if
+    /// they are invalid.
+    pub fn try_new<O: Into<CompressionOptions>>(options: O) -> Result<BlockEncoder, Error> {
+        Ok(BlockEncoder::with_state(DeflateState::try_new(
+            options.into(),
+            Vec::new(),
+        )?))
+    }
+
+    fn with_state(deflate_state: DeflateState<Vec<u8>>) -> BlockEncoder {
+        BlockEncoder {
+            deflate_state: Box::new(deflate_state),
+            values: Vec::new(),
+            bytes_taken: 0,
+        }
+    }
+
+    /// Output `data` as a block of type `kind`, setting the final block flag if `final_block` is
+    /// set.
+    ///
+    /// Empty data gives an empty block. Blocks of 4 bytes or less are always output as fixed
+    /// blocks, as they are too short for dynamic codes to pay off. A stored block can hold at most
+    /// 32 KiB, so longer data is output as several consecutive stored blocks.
+    ///
+    /// Returns an error if the final block has already been written.
+    pub fn write_block(
+        &mut self,
+        data: &[u8],
+        kind: BlockKind,
+        final_block: bool,
+    ) -> Result<(), Error> {
+        let ds = &mut *self.deflate_state;
+        if ds.finished {
+            return Err(finished_error().into());
+        }
+
+        // Find the matches in all of the data, even for stored blocks, so later blocks can refer
+        // back to it. The LZ77 stage ends a block whenever its buffer is full, so collect the
+        // output until all of the data has been processed.
+        self.values.clear();
+        let mut slice = data;
+        loop {
+            let (consumed, status, _) = lz77_compress_block(
+                slice,
+                &mut ds.lz77_state,
+                &mut ds.input_buffer,
+                &mut ds.lz77_writer,
+                Flush::Block,
+            );
+            slice = &slice[consumed..];
+            self.values.extend_from_slice(ds.lz77_writer.get_buffer());
+            ds.lz77_writer.clear();
+            ds.lz77_state.reset_input_bytes();
+            if status == LZ77Status::Finished {
+                break;
+            }
+        }
+        debug_assert!(slice.is_empty());
+
+        let es = &mut ds.encoder_state;
+        let kind = if data.len() <= 4 && kind == BlockKind::Dynamic {
+            BlockKind::Fixed
+        } else {
+            kind
+        };
+        match kind {
+            BlockKind::Stored => write_stored_block(data, &mut es.writer, final_block),
+            BlockKind::Fixed => {
+                write_huffman_block(None, &self.values, es, &ds.length_buffers, final_block)?;
+            }
+            BlockKind::Dynamic => {
+                let (l_freqs, d_freqs) = Frequencies::from_values(&self.values).scaled();
+                let pending_bits = es.writer.pending_bits();
+                let (l_lengths, d_lengths) = es.huffman_table.get_lengths_mut();
+                let lengths = gen_huffman_lengths(
+                    &l_freqs,
+                    &d_freqs,
+                    data.len() as u64,
+                    pending_bits,
+                    usize::from(ds.compression_options.max_code_length),
+                    l_lengths,
+                    d_lengths,
+                    &mut ds.length_buffers,
+                )
+                .expect("Block long enough for dynamic codes");
+                write_huffman_block(
+                    Some(&lengths.header),
+                    &self.values,
+                    es,
+                    &ds.length_buffers,
+                    final_block,
+                )?;
+            }
+        }
+        ds.bytes_written += data.len() as u64;
+        ds.finished = final_block;
+        Ok(())
+    }
+
+    /// The number of bits output so far.
+    pub fn bit_position(&self) -> u64 {
+        let writer = &self.deflate_state.encoder_state.writer;
+        (self.bytes_taken + writer.w.len() as u64) * 8 + u64::from(writer.pending_bits())
+    }
+
+    /// The total number of bytes of input written to the encoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.deflate_state.bytes_written
+    }
+
+    /// Whether a block with the final block flag set has been written.
+    pub fn is_finished(&self) -> bool {
+        self.deflate_state.finished
+    }
+
+    /// Take the whole bytes output so far, leaving the last few bits of an unfinished byte in the
+    /// encoder.
+    ///
+    /// This allows passing the output on as it is produced, rather than keeping all of it until
+    /// the encoder is finished.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let writer = &mut self.deflate_state.encoder_state.writer;
+        writer.flush_whole_bytes();
+        self.bytes_taken += writer.w.len() as u64;
+        mem::replace(&mut writer.w, Vec::new())
+    }
+
+    /// Return the output that hasn't been taken with [`take_output`](#method.take_output),
+    /// padding the last byte with zeroes.
+    ///
+    /// The output is only a complete DEFLATE stream if the final block has been written.
+    pub fn finish(mut self) -> Vec<u8> {
+        let es = &mut self.deflate_state.encoder_state;
+        es.flush();
+        mem::replace(es.inner_vec(), Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+
+    #[test]
+    fn block_encoder_roundtrip() {
+        let data = get_test_data();
+        let kinds = [BlockKind::Dynamic, BlockKind::Fixed, BlockKind::Stored];
+        for &options in &[
+            CompressionOptions::default(),
+            CompressionOptions::fast(),
+            CompressionOptions::rle(),
+        ] {
+            let mut encoder = BlockEncoder::new(options);
+            let mut output = Vec::new();
+            let mut start = 0;
+            // Blocks of varying length, including empty ones and ones longer than a stored
+            // block or the LZ77 buffer can hold.
+            for (n, &len) in [0, 1, 4, 5, 100, 40_000, 0, 70_000, 3000]
+                .iter()
+                .enumerate()
+            {
+                let end = (start + len).min(data.len());
+                encoder
+                    .write_block(&data[start..end], kinds[n % 3], false)
+                    .unwrap();
+                start = end;
+                output.extend(encoder.take_output());
+                assert!(encoder.bit_position() / 8 == output.len() as u64);
+            }
+            encoder
+                .write_block(&data[start..], BlockKind::Dynamic, true)
+                .unwrap();
+            assert!(encoder.is_finished());
+            assert_eq!(encoder.total_in(), data.len() as u64);
+            assert!(encoder
+                .write_block(b"more", BlockKind::Fixed, true)
+                .is_err());
+            output.extend(encoder.finish());
+            assert!(decompress_to_end(&output) == data);
+        }
+    }
+
+    #[test]
+    fn block_encoder_kinds() {
+        let data = &get_test_data()[..20_000];
+        let mut sizes = Vec::new();
+        for &kind in &[BlockKind::Stored, BlockKind::Fixed, BlockKind::Dynamic] {
+            let mut encoder = BlockEncoder::new(CompressionOptions::default());
+            encoder.write_block(data, kind, true).unwrap();
+            let bits = encoder.bit_position();
+            let compressed = encoder.finish();
+            assert!(decompress_to_end(&compressed) == data);
+            // The block type is in the second and third bits of the header.
+            assert_eq!((compressed[0] >> 1) & 0b11, kind as u8);
+            sizes.push(bits);
+        }
+        // Dynamic codes do best on text, and the stored block holds the data as is.
+        assert!(sizes[2] < sizes[1] && sizes[1] < sizes[0]);
+
+        // Matches can refer back to earlier blocks, so repeating the data is almost free.
+        let mut encoder = BlockEncoder::new(CompressionOptions::default());
+        encoder.write_block(data, BlockKind::Stored, false).unwrap();
+        let start = encoder.bit_position();
+        encoder.write_block(data, BlockKind::Dynamic, true).unwrap();
+        assert!(encoder.bit_position() - start < 2000);
+        let compressed = encoder.finish();
+        assert!(decompress_to_end(&compressed) == [data, data].concat());
+    }
+}
@@ -0,0 +1,65 @@
+//! The types of the large internal buffers, which are allocated using a custom allocator when
+//! the `allocator-api` feature is enabled.
+//!
+//! Without the feature, these are the plain standard library types, and the allocator handle is a
+//! unit struct, so none of this costs anything.
+
+#[cfg(feature = "allocator-api")]
+mod imp {
+    use std::alloc::{Allocator, Global};
+    use std::mem;
+    use std::sync::Arc;
+
+    /// Handle to the allocator the buffers are allocated with.
+    pub type BufferAllocator = Arc<dyn Allocator + Send + Sync>;
+    pub type BufVec<T> = Vec<T, BufferAllocator>;
+    pub type BufBox<T> = Box<T, BufferAllocator>;
+
+    pub fn global() -> BufferAllocator {
+        Arc::new(Global)
+    }
+
+    pub fn vec_with_capacity<T>(capacity: usize, alloc: &BufferAllocator) -> BufVec<T> {
+        Vec::with_capacity_in(capacity, alloc.clone())
+    }
+
+    pub fn boxed_default<T: Default>(alloc: &BufferAllocator) -> BufBox<T> {
+        Box::new_in(T::default(), alloc.clone())
+    }
+
+    /// Take the contents of `v`, leaving an empty vector using the same allocator.
+    pub fn take_vec<T>(v: &mut BufVec<T>) -> BufVec<T> {
+        let alloc = v.allocator().clone();
+        mem::replace(v, Vec::new_in(alloc))
+    }
+}
+
+#[cfg(not(feature = "allocator-api"))]
+mod imp {
+    use std::mem;
+
+    /// Stand-in for the allocator handle, the buffers always use the global allocator.
+    #[derive(Clone)]
+    pub struct BufferAllocator;
+    pub type BufVec<T> = Vec<T>;
+    pub type BufBox<T> = Box<T>;
+
+    pub fn global() -> BufferAllocator {
+        BufferAllocator
+    }
+
+    pub fn vec_with_capacity<T>(capacity: usize, _: &BufferAllocator) -> BufVec<T> {
+        Vec::with_capacity(capacity)
+    }
+
+    pub fn boxed_default<T: Default>(_: &BufferAllocator) -> BufBox<T> {
+        // Using default here avoids creating the value on the stack first.
+        Box::default()
+    }
+
+    pub fn take_vec<T>(v: &mut BufVec<T>) -> BufVec<T> {
+        mem::replace(v, Vec::new())
+    }
+}
+
+pub use self::imp::*;
@@ -0,0 +1,209 @@
+//! Sharing the large internal buffers between encoders.
+//!
+//! Each encoder allocates a few hundred kilobytes of buffers (the input window, the hash chains,
+//! the buffer of LZ77 values and the output buffer). When many short-lived encoders are created,
+//! e.g one per request in a server, these allocations can make up a large part of the time
+//! spent. A [`BufferPool`](struct.BufferPool.html) keeps the buffers of encoders that have been
+//! dropped, and hands them out to new encoders created from the same pool.
+#[cfg(feature = "allocator-api")]
+use std::alloc::Allocator;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::buffer_alloc::{global, vec_with_capacity, BufBox, BufVec, BufferAllocator};
+use crate::chained_hash_table::{create_tables, Tables};
+use crate::compress::LARGEST_OUTPUT_BUF_SIZE;
+use crate::input_buffer::BUFFER_SIZE;
+use crate::lzvalue::LZValue;
+use crate::output_writer::MAX_BUFFER_LENGTH;
+
+/// The buffers of one encoder.
+pub struct Buffers {
+    pub input: BufVec<u8>,
+    pub tables: BufBox<Tables>,
+    pub lz77: BufVec<LZValue>,
+    pub output: Vec<u8>,
+}
+
+impl Buffers {
+    fn new(alloc: &BufferAllocator) -> Buffers {
+        Buffers {
+            input: vec_with_capacity(BUFFER_SIZE, alloc),
+            tables: create_tables(alloc),
+            lz77: vec_with_capacity(MAX_BUFFER_LENGTH, alloc),
+            output: Vec::with_capacity(LARGEST_OUTPUT_BUF_SIZE),
+        }
+    }
+}
+
+/// A pool of encoder buffers that can be shared between encoders, and between threads.
+///
+/// Encoders created with one of the `with_pool` constructors take their buffers from the pool
+/// if there are any available, and allocate new ones otherwise. When the encoder is dropped
+/// (which includes calling `finish()`), the buffers are put back in the pool for the next
+/// encoder to use. The buffers are cleared before being reused, so no data is shared between
+/// encoders.
+///
+/// Cloning the pool gives another handle to the same pool.
+///
+/// With the `allocator-api` feature (which requires a nightly compiler), the pool can be
+/// created with a custom allocator using [`with_allocator`](#method.with_allocator).
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// use deflate::write::ZlibEncoder;
+/// use deflate::{BufferPool, Compression};
+///
+/// let pool = BufferPool::new();
+/// for data in &[&b"first stream"[..], &b"second stream"[..]] {
+///     let mut encoder = ZlibEncoder::with_pool(Vec::new(), Compression::Default, &pool);
+///     encoder.write_all(data).unwrap();
+///     let compressed = encoder.finish().unwrap();
+///     # let _ = compressed;
+/// }
+/// // The buffers of the first encoder were reused by the second one.
+/// assert_eq!(pool.idle(), 1);
+/// ```
+#[derive(Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Buffers>>>,
+    max_idle: usize,
+    allocator: BufferAllocator,
+}
+
+impl BufferPool {
+    /// Create an empty pool with no limit on the number of idle buffer sets it keeps.
+    pub fn new() -> BufferPool {
+        BufferPool::with_max_idle(usize::max_value())
+    }
+
+    /// Create an empty pool that keeps at most `max_idle` sets of buffers, freeing the buffers
+    /// of encoders dropped while it is full.
+    pub fn with_max_idle(max_idle: usize) -> BufferPool {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_idle,
+            allocator: global(),
+        }
+    }
+
+    /// Create an empty pool with no limit on the number of idle buffer sets it keeps, that
+    /// allocates new buffers using `allocator`.
+    ///
+    /// This covers the input window, the hash chains and the buffer of LZ77 values, which make
+    /// up most of the memory used by an encoder. The output buffer, which is passed on to the
+    /// wrapped writer, still uses the global allocator.
+    ///
+    /// Only available with the `allocator-api` feature.
+    #[cfg(feature = "allocator-api")]
+    pub fn with_allocator<A: Allocator + Send + Sync + 'static>(allocator: A) -> BufferPool {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_idle: usize::MAX,
+            allocator: Arc::new(allocator),
+        }
+    }
+
+    /// The number of sets of buffers currently kept in the pool, waiting to be used by a new
+    /// encoder.
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Free all the buffers currently kept in the pool.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Buffers>> {
+        // The pool can't be left in an invalid state by a panic while it's locked, so ignore
+        // poisoning.
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Take a set of buffers from the pool, allocating new ones if there are none.
+    pub(crate) fn take(&self) -> Buffers {
+        let idle = self.lock().pop();
+        idle.unwrap_or_else(|| Buffers::new(&self.allocator))
+    }
+
+    /// Put a set of buffers back in the pool, unless it is full.
+    pub(crate) fn put(&self, buffers: Buffers) {
+        let mut idle = self.lock();
+        if idle.len() < self.max_idle {
+            idle.push(buffers);
+        }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> BufferPool {
+        BufferPool::new()
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("idle", &self.idle())
+            .field("max_idle", &self.max_idle)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+    use crate::write::DeflateEncoder;
+    use crate::CompressionOptions;
+    use std::io::Write;
+    use std::thread;
+
+    #[test]
+    fn reuse_buffers() {
+        let data = get_test_data();
+        let expected = crate::try_deflate_bytes(&data).unwrap();
+        let pool = BufferPool::with_max_idle(2);
+        assert_eq!(pool.idle(), 0);
+
+        let encoders: Vec<_> = (0..3)
+            .map(|_| DeflateEncoder::with_pool(Vec::new(), CompressionOptions::default(), &pool))
+            .collect();
+        drop(encoders);
+        assert_eq!(pool.idle(), 2);
+
+        // Encoders using reused buffers, including ones left in the middle of a stream, should
+        // give the same output as new ones.
+        let mut encoder =
+            DeflateEncoder::with_pool(Vec::new(), CompressionOptions::default(), &pool);
+        encoder.write_all(&data[..data.len() / 2]).unwrap();
+        encoder.set_finish_on_drop(false);
+        drop(encoder);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                let data = data.clone();
+                thread::spawn(move || {
+                    let mut encoder =
+                        DeflateEncoder::with_pool(Vec::new(), CompressionOptions::default(), &pool);
+                    encoder.write_all(&data).unwrap();
+                    encoder.finish().unwrap()
+                })
+            })
+            .collect();
+        for handle in handles {
+            let compressed = handle.join().unwrap();
+            assert!(compressed == expected);
+            assert!(decompress_to_end(&compressed) == data);
+        }
+        assert_eq!(pool.idle(), 2);
+
+        pool.clear();
+        assert_eq!(pool.idle(), 0);
+    }
+}
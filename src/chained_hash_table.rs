@@ -1,12 +1,17 @@
+use std::mem;
+
+use crate::buffer_alloc::{boxed_default, global, BufBox, BufferAllocator};
+
 pub const WINDOW_SIZE: usize = 32768;
 pub const WINDOW_MASK: usize = WINDOW_SIZE - 1;
 #[cfg(test)]
 pub const HASH_BYTES: usize = 3;
 const HASH_SHIFT: u16 = 5;
+const HASH_BITS: u32 = 15;
 const HASH_MASK: u16 = WINDOW_MASK as u16;
 
 /// Helper struct to let us allocate both head and prev in the same block.
-struct Tables {
+pub struct Tables {
     /// Starts of hash chains (in prev)
     pub head: [u16; WINDOW_SIZE],
     /// Link to previous occurence of this hash value
@@ -31,7 +36,7 @@ impl Tables {
 }
 
 /// Create and box the hash chains.
-fn create_tables() -> Box<Tables> {
+pub fn create_tables(alloc: &BufferAllocator) -> BufBox<Tables> {
     // Using default here is a trick to get around the lack of box syntax on stable Rust.
     //
     // Box::new([0u16,n]) ends up creating an temporary array on the stack which is not optimised
@@ -39,26 +44,100 @@ fn create_tables() -> Box<Tables> {
     //
     // We could use vec instead, but using a boxed array helps the compiler optimise
     // away bounds checks as `n & WINDOW_MASK < WINDOW_SIZE` will always be true.
-    let mut t: Box<Tables> = Box::default();
-
-    for (n, b) in t.head.iter_mut().enumerate() {
-        *b = n as u16;
-    }
+    let mut t = boxed_default::<Tables>(alloc);
+    init_tables(&mut t);
+    t
+}
 
+/// Set the hash chains to their initial state.
+fn init_tables(t: &mut Tables) {
+    reset_array(&mut t.head);
     t.fill_prev();
-
-    t
 }
 
-/// Returns a new hash value based on the previous value and the next byte
-#[inline]
-pub fn update_hash(current_hash: u16, to_insert: u8) -> u16 {
-    update_hash_conf(current_hash, to_insert, HASH_SHIFT, HASH_MASK)
+/// The function used to hash the next three bytes of input when looking for matches.
+///
+/// All the functions give the same output for input without hash collisions, but differ in
+/// speed and in how well they spread out different byte sequences, which affects how many
+/// candidate positions that don't actually match the hash chains contain.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum HashFunction {
+    /// Shift each byte 5 bits and xor them together, like zlib.
+    ///
+    /// This is cheap, but only the lower 5 bits of the first of the three bytes contribute to
+    /// the hash, which leads to many collisions on some binary data. This was used in earlier
+    /// versions of this crate.
+    ShiftXor,
+    /// Multiply the three bytes by a large odd constant and keep the top bits (Knuth's
+    /// multiplicative hashing), which mixes in all the bits of each byte.
+    ///
+    /// This is the default, as it compressed as well or better than the other functions in our
+    /// tests, and was slightly faster than `ShiftXor` at the default settings.
+    Multiplicative,
+    /// Use the lower bits of the CRC-32 of the three bytes.
+    Crc,
 }
 
-#[inline]
-fn update_hash_conf(current_hash: u16, to_insert: u8, shift: u16, mask: u16) -> u16 {
-    ((current_hash << shift) ^ (u16::from(to_insert))) & mask
+/// Table for computing CRC-32 one byte at a time, using the polynomial 0xEDB8_8320.
+const CRC_TABLE: [u32; 256] = [
+    0x00000000, 0x77073096, 0xEE0E612C, 0x990951BA, 0x076DC419, 0x706AF48F, 0xE963A535, 0x9E6495A3,
+    0x0EDB8832, 0x79DCB8A4, 0xE0D5E91E, 0x97D2D988, 0x09B64C2B, 0x7EB17CBD, 0xE7B82D07, 0x90BF1D91,
+    0x1DB71064, 0x6AB020F2, 0xF3B97148, 0x84BE41DE, 0x1ADAD47D, 0x6DDDE4EB, 0xF4D4B551, 0x83D385C7,
+    0x136C9856, 0x646BA8C0, 0xFD62F97A, 0x8A65C9EC, 0x14015C4F, 0x63066CD9, 0xFA0F3D63, 0x8D080DF5,
+    0x3B6E20C8, 0x4C69105E, 0xD56041E4, 0xA2677172, 0x3C03E4D1, 0x4B04D447, 0xD20D85FD, 0xA50AB56B,
+    0x35B5A8FA, 0x42B2986C, 0xDBBBC9D6, 0xACBCF940, 0x32D86CE3, 0x45DF5C75, 0xDCD60DCF, 0xABD13D59,
+    0x26D930AC, 0x51DE003A, 0xC8D75180, 0xBFD06116, 0x21B4F4B5, 0x56B3C423, 0xCFBA9599, 0xB8BDA50F,
+    0x2802B89E, 0x5F058808, 0xC60CD9B2, 0xB10BE924, 0x2F6F7C87, 0x58684C11, 0xC1611DAB, 0xB6662D3D,
+    0x76DC4190, 0x01DB7106, 0x98D220BC, 0xEFD5102A, 0x71B18589, 0x06B6B51F, 0x9FBFE4A5, 0xE8B8D433,
+    0x7807C9A2, 0x0F00F934, 0x9609A88E, 0xE10E9818, 0x7F6A0DBB, 0x086D3D2D, 0x91646C97, 0xE6635C01,
+    0x6B6B51F4, 0x1C6C6162, 0x856530D8, 0xF262004E, 0x6C0695ED, 0x1B01A57B, 0x8208F4C1, 0xF50FC457,
+    0x65B0D9C6, 0x12B7E950, 0x8BBEB8EA, 0xFCB9887C, 0x62DD1DDF, 0x15DA2D49, 0x8CD37CF3, 0xFBD44C65,
+    0x4DB26158, 0x3AB551CE, 0xA3BC0074, 0xD4BB30E2, 0x4ADFA541, 0x3DD895D7, 0xA4D1C46D, 0xD3D6F4FB,
+    0x4369E96A, 0x346ED9FC, 0xAD678846, 0xDA60B8D0, 0x44042D73, 0x33031DE5, 0xAA0A4C5F, 0xDD0D7CC9,
+    0x5005713C, 0x270241AA, 0xBE0B1010, 0xC90C2086, 0x5768B525, 0x206F85B3, 0xB966D409, 0xCE61E49F,
+    0x5EDEF90E, 0x29D9C998, 0xB0D09822, 0xC7D7A8B4, 0x59B33D17, 0x2EB40D81, 0xB7BD5C3B, 0xC0BA6CAD,
+    0xEDB88320, 0x9ABFB3B6, 0x03B6E20C, 0x74B1D29A, 0xEAD54739, 0x9DD277AF, 0x04DB2615, 0x73DC1683,
+    0xE3630B12, 0x94643B84, 0x0D6D6A3E, 0x7A6A5AA8, 0xE40ECF0B, 0x9309FF9D, 0x0A00AE27, 0x7D079EB1,
+    0xF00F9344, 0x8708A3D2, 0x1E01F268, 0x6906C2FE, 0xF762575D, 0x806567CB, 0x196C3671, 0x6E6B06E7,
+    0xFED41B76, 0x89D32BE0, 0x10DA7A5A, 0x67DD4ACC, 0xF9B9DF6F, 0x8EBEEFF9, 0x17B7BE43, 0x60B08ED5,
+    0xD6D6A3E8, 0xA1D1937E, 0x38D8C2C4, 0x4FDFF252, 0xD1BB67F1, 0xA6BC5767, 0x3FB506DD, 0x48B2364B,
+    0xD80D2BDA, 0xAF0A1B4C, 0x36034AF6, 0x41047A60, 0xDF60EFC3, 0xA867DF55, 0x316E8EEF, 0x4669BE79,
+    0xCB61B38C, 0xBC66831A, 0x256FD2A0, 0x5268E236, 0xCC0C7795, 0xBB0B4703, 0x220216B9, 0x5505262F,
+    0xC5BA3BBE, 0xB2BD0B28, 0x2BB45A92, 0x5CB36A04, 0xC2D7FFA7, 0xB5D0CF31, 0x2CD99E8B, 0x5BDEAE1D,
+    0x9B64C2B0, 0xEC63F226, 0x756AA39C, 0x026D930A, 0x9C0906A9, 0xEB0E363F, 0x72076785, 0x05005713,
+    0x95BF4A82, 0xE2B87A14, 0x7BB12BAE, 0x0CB61B38, 0x92D28E9B, 0xE5D5BE0D, 0x7CDCEFB7, 0x0BDBDF21,
+    0x86D3D2D4, 0xF1D4E242, 0x68DDB3F8, 0x1FDA836E, 0x81BE16CD, 0xF6B9265B, 0x6FB077E1, 0x18B74777,
+    0x88085AE6, 0xFF0F6A70, 0x66063BCA, 0x11010B5C, 0x8F659EFF, 0xF862AE69, 0x616BFFD3, 0x166CCF45,
+    0xA00AE278, 0xD70DD2EE, 0x4E048354, 0x3903B3C2, 0xA7672661, 0xD06016F7, 0x4969474D, 0x3E6E77DB,
+    0xAED16A4A, 0xD9D65ADC, 0x40DF0B66, 0x37D83BF0, 0xA9BCAE53, 0xDEBB9EC5, 0x47B2CF7F, 0x30B5FFE9,
+    0xBDBDF21C, 0xCABAC28A, 0x53B39330, 0x24B4A3A6, 0xBAD03605, 0xCDD70693, 0x54DE5729, 0x23D967BF,
+    0xB3667A2E, 0xC4614AB8, 0x5D681B02, 0x2A6F2B94, 0xB40BBE37, 0xC30C8EA1, 0x5A05DF1B, 0x2D02EF8D,
+];
+
+impl HashFunction {
+    /// Hash the last three bytes added, which are stored in the lower 24 bits of `bytes`, with
+    /// the oldest in the highest bits.
+    #[inline]
+    fn hash(self, bytes: u32) -> u16 {
+        match self {
+            HashFunction::ShiftXor => {
+                let [_, b0, b1, b2] = bytes.to_be_bytes();
+                ((u16::from(b0) << (2 * HASH_SHIFT))
+                    ^ (u16::from(b1) << HASH_SHIFT)
+                    ^ u16::from(b2))
+                    & HASH_MASK
+            }
+            HashFunction::Multiplicative => {
+                (bytes.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as u16 & HASH_MASK
+            }
+            HashFunction::Crc => {
+                let crc = bytes.to_be_bytes()[1..].iter().fold(!0u32, |crc, &b| {
+                    CRC_TABLE[((crc ^ u32::from(b)) & 0xFF) as usize] ^ (crc >> 8)
+                });
+                crc as u16 & HASH_MASK
+            }
+        }
+    }
 }
 
 #[inline]
@@ -71,46 +150,101 @@ fn reset_array(arr: &mut [u16; WINDOW_SIZE]) {
 pub struct ChainedHashTable {
     // Current running hash value of the last 3 bytes
     current_hash: u16,
+    // The last 3 bytes added, used to compute the hash.
+    last_bytes: u32,
+    hash_function: HashFunction,
+    // Mask for the position of each entry in `prev`, one less than the window size.
+    window_mask: usize,
     // Hash chains.
-    c: Box<Tables>,
+    c: BufBox<Tables>,
     // Used for testing
     // count: DebugCounter,
 }
 
 impl ChainedHashTable {
+    #[cfg(test)]
     pub fn new() -> ChainedHashTable {
+        ChainedHashTable::with_hash_function(HashFunction::ShiftXor)
+    }
+
+    pub fn with_hash_function(hash_function: HashFunction) -> ChainedHashTable {
+        ChainedHashTable::with_tables(hash_function, create_tables(&global()))
+    }
+
+    /// Create a hash table using previously allocated hash chains, which are reset first.
+    pub fn with_tables(
+        hash_function: HashFunction,
+        mut tables: BufBox<Tables>,
+    ) -> ChainedHashTable {
+        init_tables(&mut tables);
         ChainedHashTable {
             current_hash: 0,
-            c: create_tables(),
+            last_bytes: 0,
+            hash_function,
+            window_mask: WINDOW_MASK,
+            c: tables,
             //count: DebugCounter::default(),
         }
     }
 
+    /// Set the size of the window the positions added refer to, which must be a power of two no
+    /// larger than `WINDOW_SIZE`.
+    ///
+    /// Positions are expected to be below twice the window size, and the table to be slid by the
+    /// window size. Only the first `window_size` entries of `prev` are used.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        debug_assert!(window_size.is_power_of_two() && window_size <= WINDOW_SIZE);
+        self.window_mask = window_size - 1;
+    }
+
+    /// Take the hash chains out of the table so they can be reused, replacing them with zeroed
+    /// ones, so the table has to be reset before it is used again.
+    ///
+    /// The replacement is only zeroed rather than initialized, as it is meant to be dropped
+    /// along with the table right away.
+    pub fn take_tables(&mut self) -> BufBox<Tables> {
+        mem::replace(&mut self.c, boxed_default(&global()))
+    }
+
+    #[inline]
+    fn tables(&self) -> &Tables {
+        &self.c
+    }
+
+    #[inline]
+    fn tables_mut(&mut self) -> &mut Tables {
+        &mut self.c
+    }
+
     #[cfg(test)]
     pub fn from_starting_values(v1: u8, v2: u8) -> ChainedHashTable {
         let mut t = ChainedHashTable::new();
-        t.current_hash = update_hash(t.current_hash, v1);
-        t.current_hash = update_hash(t.current_hash, v2);
+        t.add_initial_hash_values(v1, v2);
         t
     }
 
     /// Resets the hash value and hash chains
     pub fn reset(&mut self) {
         self.current_hash = 0;
-        reset_array(&mut self.c.head);
-        {
-            let h = self.c.head;
-            let mut c = self.c.prev;
-            c[..].copy_from_slice(&h[..]);
-        }
+        self.last_bytes = 0;
+        init_tables(self.tables_mut());
         /*if cfg!(debug_assertions) {
             self.count.reset();
         }*/
     }
 
+    /// Returns the hash value of the last two bytes added followed by `to_insert`, and
+    /// stores it as the current hash.
+    #[inline]
+    fn update_hash(&mut self, to_insert: u8) -> u16 {
+        self.last_bytes = ((self.last_bytes << 8) | u32::from(to_insert)) & 0xFF_FFFF;
+        self.current_hash = self.hash_function.hash(self.last_bytes);
+        self.current_hash
+    }
+
     pub fn add_initial_hash_values(&mut self, v1: u8, v2: u8) {
-        self.current_hash = update_hash(self.current_hash, v1);
-        self.current_hash = update_hash(self.current_hash, v2);
+        self.update_hash(v1);
+        self.update_hash(v2);
     }
 
     /// Insert a byte into the hash table
@@ -123,55 +257,72 @@ impl ChainedHashTable {
             self.count.get() as usize & WINDOW_MASK
         );*/
         debug_assert!(
-            position < WINDOW_SIZE * 2,
+            position < (self.window_mask + 1) * 2,
             "Position is larger than 2 * window size! {}",
             position
         );
         // Storing the hash in a temporary variable here makes the compiler avoid the
         // bounds checks in this function.
-        let new_hash = update_hash(self.current_hash, value);
+        let new_hash = self.update_hash(value);
 
         self.add_with_hash(position, new_hash);
-
-        // Update the stored hash value with the new hash.
-        self.current_hash = new_hash;
     }
 
-    /// Directly set the current hash value
+    /// Insert `position` using the hash of the three bytes starting at it, which are stored in
+    /// the lower 24 bits of `bytes` with the first in the highest bits, and return the previous
+    /// head of its hash chain.
+    ///
+    /// Unlike `add_hash_value`, this doesn't rely on the positions before it having been added,
+    /// so positions can be skipped. The running hash is left as it is.
     #[inline]
-    pub fn set_hash(&mut self, hash: u16) {
-        self.current_hash = hash;
+    pub fn insert_bytes(&mut self, position: usize, bytes: u32) -> u16 {
+        debug_assert!(position < (self.window_mask + 1) * 2);
+        let hash = self.hash_function.hash(bytes);
+        let prev_head = self.tables().head[hash as usize];
+        self.add_with_hash(position, hash);
+        prev_head
     }
 
     /// Update the tables directly, providing the hash.
     #[inline]
-    pub fn add_with_hash(&mut self, position: usize, hash: u16) {
+    fn add_with_hash(&mut self, position: usize, hash: u16) {
         /*if cfg!(debug_assertions) {
             self.count.add(1);
         }*/
 
-        self.c.prev[position & WINDOW_MASK] = self.c.head[hash as usize];
+        // Masking with `WINDOW_MASK` as well lets the compiler remove the bounds check.
+        let index = position & self.window_mask & WINDOW_MASK;
+        let t = self.tables_mut();
+        t.prev[index] = t.head[hash as usize];
 
         // Ignoring any bits over 16 here is deliberate, as we only concern ourselves about
         // where in the buffer (which is 64k bytes) we are referring to.
-        self.c.head[hash as usize] = position as u16;
+        t.head[hash as usize] = position as u16;
     }
 
     // Get the head of the hash chain for the current hash value
     #[cfg(test)]
     #[inline]
-    pub const fn current_head(&self) -> u16 {
-        self.c.head[self.current_hash as usize]
+    pub fn current_head(&self) -> u16 {
+        self.tables().head[self.current_hash as usize]
     }
 
+    #[cfg(test)]
     #[inline]
     pub const fn current_hash(&self) -> u16 {
         self.current_hash
     }
 
     #[inline]
-    pub const fn get_prev(&self, bytes: usize) -> u16 {
-        self.c.prev[bytes & WINDOW_MASK]
+    pub fn get_prev(&self, bytes: usize) -> u16 {
+        self.tables().prev[bytes & self.window_mask & WINDOW_MASK]
+    }
+
+    /// Get a pointer to the link `get_prev` would read for `bytes`, for prefetching it.
+    #[cfg(feature = "unsafe-speed")]
+    #[inline]
+    pub fn prev_ptr(&self, bytes: usize) -> *const u16 {
+        &self.tables().prev[bytes & self.window_mask & WINDOW_MASK]
     }
 
     #[cfg(test)]
@@ -203,7 +354,7 @@ impl ChainedHashTable {
     }
 
     #[inline]
-    fn slide_table(table: &mut [u16; WINDOW_SIZE], bytes: u16) {
+    fn slide_table(table: &mut [u16], bytes: u16) {
         for (n, b) in table.iter_mut().enumerate() {
             *b = ChainedHashTable::slide_value(*b, n as u16, bytes);
         }
@@ -214,8 +365,10 @@ impl ChainedHashTable {
             // This should only happen in tests in this file.
             self.count.reset();
         }*/
-        ChainedHashTable::slide_table(&mut self.c.head, bytes as u16);
-        ChainedHashTable::slide_table(&mut self.c.prev, bytes as u16);
+        let prev_len = self.window_mask + 1;
+        let t = self.tables_mut();
+        ChainedHashTable::slide_table(&mut t.head, bytes as u16);
+        ChainedHashTable::slide_table(&mut t.prev[..prev_len], bytes as u16);
     }
 }
 
@@ -304,7 +457,7 @@ mod test {
         hash_table.slide(window_size);
 
         {
-            let max_head = hash_table.c.head.iter().max().unwrap();
+            let max_head = hash_table.tables().head.iter().max().unwrap();
             // After sliding there should be no hashes referring to values
             // higher than the window size
             assert!(*max_head < window_size16);
@@ -321,7 +474,7 @@ mod test {
 
         // There should hashes referring to values in the upper part of the input window
         // at this point
-        let max_prev = hash_table.c.prev.iter().max().unwrap();
+        let max_prev = hash_table.tables().prev.iter().max().unwrap();
         assert!(*max_prev > window_size16);
 
         let mut pos = hash_table.current_head();
@@ -341,11 +494,55 @@ mod test {
     /// Ensure that the initial hash values are correct.
     fn initial_chains() {
         let t = ChainedHashTable::new();
-        for (n, &b) in t.c.head.iter().enumerate() {
+        for (n, &b) in t.tables().head.iter().enumerate() {
             assert_eq!(n, b as usize);
         }
-        for (n, &b) in t.c.prev.iter().enumerate() {
+        for (n, &b) in t.tables().prev.iter().enumerate() {
             assert_eq!(n, b as usize);
         }
     }
+
+    #[test]
+    fn hash_functions() {
+        use super::HashFunction;
+        use crate::test_utils::{decompress_to_end, get_test_data};
+        use crate::CompressionOptions;
+
+        let data = get_test_data();
+        for &hash_function in &[
+            HashFunction::ShiftXor,
+            HashFunction::Multiplicative,
+            HashFunction::Crc,
+        ] {
+            // Identical byte sequences should always give the same hash.
+            let mut table = ChainedHashTable::with_hash_function(hash_function);
+            let mut hashes = Vec::new();
+            for (n, &b) in b"abcxabc".iter().enumerate() {
+                table.add_hash_value(n, b);
+                hashes.push(table.current_hash());
+            }
+            assert_eq!(hashes[2], hashes[6]);
+            assert!(hashes.iter().all(|&h| h <= super::HASH_MASK));
+
+            let mut options = CompressionOptions::default();
+            options.hash_function = hash_function;
+            let compressed = crate::try_deflate_bytes_conf(&data, options).unwrap();
+            assert!(decompress_to_end(&compressed) == data);
+            assert!(compressed.len() < data.len() / 2);
+        }
+    }
+
+    #[test]
+    fn crc_table() {
+        for (n, &entry) in super::CRC_TABLE.iter().enumerate() {
+            let crc = (0..8).fold(n as u32, |c, _| {
+                if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            });
+            assert_eq!(entry, crc);
+        }
+    }
 }
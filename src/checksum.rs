@@ -1,14 +1,48 @@
-use adler32::RollingAdler32;
+//! This module contains the checksums used in the zlib and gzip trailers.
 
+/// A checksum that is updated with the input data as it is consumed by an encoder.
+///
+/// This is implemented by the checksums used by the encoders in this crate, and can be
+/// implemented for other checksums to use them with
+/// [`ZlibEncoder::with_checksum`](../write/struct.ZlibEncoder.html#method.with_checksum) for
+/// custom containers.
 pub trait RollingChecksum {
-    fn update(&mut self, byte: u8);
+    /// Update the checksum with a single byte.
+    fn update(&mut self, byte: u8) {
+        self.update_from_slice(&[byte]);
+    }
+    /// Update the checksum with the bytes in `data`.
     fn update_from_slice(&mut self, data: &[u8]);
+    /// The checksum of the data seen so far.
     fn current_hash(&self) -> u32;
+    /// Reset the checksum to its initial state.
+    fn reset(&mut self);
 }
 
+impl<C: RollingChecksum + ?Sized> RollingChecksum for &mut C {
+    fn update(&mut self, byte: u8) {
+        (**self).update(byte);
+    }
+
+    fn update_from_slice(&mut self, data: &[u8]) {
+        (**self).update_from_slice(data);
+    }
+
+    fn current_hash(&self) -> u32 {
+        (**self).current_hash()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset();
+    }
+}
+
+/// A checksum that doesn't compute anything, always returning 1.
+#[derive(Copy, Clone, Debug, Default)]
 pub struct NoChecksum {}
 
 impl NoChecksum {
+    /// Create a new `NoChecksum`.
     pub const fn new() -> NoChecksum {
         NoChecksum {}
     }
@@ -20,52 +54,342 @@ impl RollingChecksum for NoChecksum {
     fn current_hash(&self) -> u32 {
         1
     }
+    fn reset(&mut self) {}
 }
 
-impl<'a> RollingChecksum for &'a mut NoChecksum {
-    fn update(&mut self, _: u8) {}
-    fn update_from_slice(&mut self, _: &[u8]) {}
-    fn current_hash(&self) -> u32 {
-        1
+/// The modulus used by Adler-32.
+const ADLER_MOD: u32 = 65521;
+/// Number of bytes summed in parallel when computing Adler-32.
+const ADLER_LANES: usize = 8;
+/// Number of bytes that can be summed in each lane before the sums have to be reduced modulo
+/// `ADLER_MOD` to avoid overflowing.
+const ADLER_NMAX: usize = 5552;
+
+/// Update the Adler-32 checksum `adler` with the bytes in `data`.
+///
+/// The bytes are summed in `ADLER_LANES` independent lanes which are combined at the end. This
+/// lets the compiler vectorize the inner loop, making it several times faster than the
+/// straightforward byte-by-byte implementation.
+pub fn update_adler32(adler: u32, data: &[u8]) -> u32 {
+    let mut a = adler & 0xFFFF;
+    let mut b = adler >> 16;
+
+    let (vectorized, rest) = data.split_at(data.len() - data.len() % ADLER_LANES);
+    let mut a_lanes = [0u32; ADLER_LANES];
+    let mut b_lanes = [0u32; ADLER_LANES];
+    for chunk in vectorized.chunks(ADLER_NMAX * ADLER_LANES) {
+        for bytes in chunk.chunks_exact(ADLER_LANES) {
+            for ((a_lane, b_lane), &byte) in a_lanes.iter_mut().zip(&mut b_lanes).zip(bytes) {
+                *a_lane += u32::from(byte);
+                *b_lane += *a_lane;
+            }
+        }
+        // Each byte in the chunk adds the initial value of `a` to `b` once.
+        b = (b + chunk.len() as u32 * a) % ADLER_MOD;
+        for (a_lane, b_lane) in a_lanes.iter_mut().zip(&mut b_lanes) {
+            *a_lane %= ADLER_MOD;
+            *b_lane %= ADLER_MOD;
+        }
+    }
+
+    // Byte `n` of a lane is at position `n * ADLER_LANES + lane` in the input, so when combining
+    // the lanes, the lane sums have to be scaled accordingly, and lane `i` has been added to
+    // `b` `i` times too often.
+    let mut b_sum = u64::from(b);
+    for (lane, (&a_lane, &b_lane)) in a_lanes.iter().zip(&b_lanes).enumerate() {
+        a += a_lane;
+        b_sum +=
+            ADLER_LANES as u64 * u64::from(b_lane) + lane as u64 * u64::from(ADLER_MOD - a_lane);
+    }
+    let mut a = a % ADLER_MOD;
+    let mut b = (b_sum % u64::from(ADLER_MOD)) as u32;
+
+    for &byte in rest {
+        a += u32::from(byte);
+        b += a;
     }
+    ((b % ADLER_MOD) << 16) | (a % ADLER_MOD)
 }
 
+/// The Adler-32 checksum used in the zlib trailer.
+#[derive(Copy, Clone, Debug)]
 pub struct Adler32Checksum {
-    adler32: RollingAdler32,
+    adler32: u32,
+}
+
+impl Default for Adler32Checksum {
+    fn default() -> Adler32Checksum {
+        Adler32Checksum::new()
+    }
 }
 
 impl Adler32Checksum {
-    pub fn new() -> Adler32Checksum {
-        Adler32Checksum {
-            adler32: RollingAdler32::new(),
-        }
+    /// Create a new Adler-32 checksum with the initial value of 1.
+    pub const fn new() -> Adler32Checksum {
+        Adler32Checksum { adler32: 1 }
+    }
+
+    /// Create an Adler-32 checksum continuing from `adler32`, the checksum of earlier data.
+    pub const fn with_initial(adler32: u32) -> Adler32Checksum {
+        Adler32Checksum { adler32 }
     }
 }
 
 impl RollingChecksum for Adler32Checksum {
     fn update(&mut self, byte: u8) {
-        self.adler32.update(byte);
+        self.update_from_slice(&[byte]);
     }
 
     fn update_from_slice(&mut self, data: &[u8]) {
-        self.adler32.update_buffer(data);
+        self.adler32 = update_adler32(self.adler32, data);
     }
 
     fn current_hash(&self) -> u32 {
-        self.adler32.hash()
+        self.adler32
+    }
+
+    fn reset(&mut self) {
+        self.adler32 = 1;
+    }
+}
+
+/// Combine the Adler-32 checksums `adler1` and `adler2` of two pieces of data into the checksum of
+/// the data concatenated, where `len2` is the length of the second piece.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::checksum::{adler32_combine, update_adler32};
+///
+/// let (first, second) = (b"Some data ", b"and some more data");
+/// let combined = adler32_combine(update_adler32(1, first), update_adler32(1, second), 18);
+/// assert_eq!(combined, update_adler32(1, b"Some data and some more data"));
+/// ```
+pub fn adler32_combine(adler1: u32, adler2: u32, len2: u64) -> u32 {
+    let rem = (len2 % u64::from(ADLER_MOD)) as u32;
+    let a1 = adler1 & 0xFFFF;
+    let b1 = adler1 >> 16;
+    // Each byte of the second piece adds `a1` to the `b` sum an extra time, and the `a` sum of
+    // the second piece starts from 1 rather than `a1`.
+    let a = (a1 + (adler2 & 0xFFFF) + ADLER_MOD - 1) % ADLER_MOD;
+    let b = (rem * a1 % ADLER_MOD + b1 + (adler2 >> 16) + ADLER_MOD - rem) % ADLER_MOD;
+    (b << 16) | a
+}
+
+/// The CRC-32 polynomial used by gzip, in reversed bit order.
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+/// Multiply `a` and `b` modulo the CRC-32 polynomial.
+fn crc32_mult_mod(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut m = 1 << 31;
+    while m != 0 {
+        if a & m != 0 {
+            product ^= b;
+        }
+        b = if b & 1 != 0 {
+            (b >> 1) ^ CRC32_POLY
+        } else {
+            b >> 1
+        };
+        m >>= 1;
     }
+    product
 }
 
-impl<'a> RollingChecksum for &'a mut Adler32Checksum {
+/// Combine the CRC-32 checksums `crc1` and `crc2` of two pieces of data into the checksum of the
+/// data concatenated, where `len2` is the length of the second piece.
+///
+/// This takes time proportional to the logarithm of `len2`.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    // Appending `len2` bytes multiplies the checksum of the first piece by x^(8 * len2).
+    // `power` starts at x^8 (x^0 being represented by the highest bit.)
+    let mut power = 1 << 23;
+    let mut factor = 1 << 31;
+    let mut n = len2;
+    while n != 0 {
+        if n & 1 != 0 {
+            factor = crc32_mult_mod(power, factor);
+        }
+        power = crc32_mult_mod(power, power);
+        n >>= 1;
+    }
+    crc32_mult_mod(factor, crc1) ^ crc2
+}
+
+/// A CRC-32 checksum, which also keeps track of the number of bytes checksummed as needed for the
+/// gzip trailer.
+///
+/// This is the checksum used by gzip and zip. Only available with the `crc32` feature, which is
+/// enabled by the `gzip` feature.
+///
+/// The checksum is computed using `crc32fast`, which detects support for the ARMv8 CRC
+/// instructions and carry-less multiplication (PCLMULQDQ) on x86 at runtime, and falls back to a
+/// table-based implementation otherwise. (The SSE4.2 `crc32` instruction computes CRC-32C, which
+/// uses a different polynomial than gzip, so it can't be used here.)
+#[cfg(feature = "crc32")]
+#[derive(Clone, Debug, Default)]
+pub struct Crc32Checksum {
+    hasher: crc32fast::Hasher,
+    amount: u32,
+}
+
+#[cfg(feature = "crc32")]
+impl Crc32Checksum {
+    /// Create a new CRC-32 checksum with the initial value of 0.
+    pub fn new() -> Crc32Checksum {
+        Crc32Checksum {
+            hasher: crc32fast::Hasher::new(),
+            amount: 0,
+        }
+    }
+
+    /// Create a CRC-32 checksum continuing from `crc`, the checksum of `amount` bytes of earlier
+    /// data.
+    pub fn with_initial(crc: u32, amount: u32) -> Crc32Checksum {
+        Crc32Checksum {
+            hasher: crc32fast::Hasher::new_with_initial(crc),
+            amount,
+        }
+    }
+
+    /// The number of bytes checksummed so far, modulo 2^32.
+    pub const fn amount(&self) -> u32 {
+        self.amount
+    }
+}
+
+#[cfg(feature = "crc32")]
+impl RollingChecksum for Crc32Checksum {
     fn update(&mut self, byte: u8) {
-        self.adler32.update(byte);
+        self.update_from_slice(&[byte]);
     }
 
     fn update_from_slice(&mut self, data: &[u8]) {
-        self.adler32.update_buffer(data);
+        self.hasher.update(data);
+        self.amount = self.amount.wrapping_add(data.len() as u32);
     }
 
     fn current_hash(&self) -> u32 {
-        self.adler32.hash()
+        self.hasher.clone().finalize()
+    }
+
+    fn reset(&mut self) {
+        self.hasher.reset();
+        self.amount = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Straightforward Adler-32 implementation to compare against.
+    fn adler32_simple(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + u32::from(byte)) % ADLER_MOD;
+            b = (b + a) % ADLER_MOD;
+        }
+        (b << 16) | a
+    }
+
+    /// Straightforward CRC-32 implementation to compare against.
+    fn crc32_simple(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    CRC32_POLY ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn combine() {
+        let data: Vec<u8> = (0..70_000u32).map(|n| (n * 7 + n / 251) as u8).collect();
+        for &split in &[0, 1, 9, 5552, 65521, 65530, data.len()] {
+            let (first, second) = data.split_at(split);
+            let len2 = second.len() as u64;
+            assert_eq!(
+                adler32_combine(update_adler32(1, first), update_adler32(1, second), len2),
+                update_adler32(1, &data)
+            );
+            assert_eq!(
+                crc32_combine(crc32_simple(first), crc32_simple(second), len2),
+                crc32_simple(&data)
+            );
+        }
+        assert_eq!(crc32_simple(b"123456789"), 0xCBF4_3926);
+        let max = vec![255u8; 200_000];
+        let (first, second) = max.split_at(100_003);
+        assert_eq!(
+            adler32_combine(
+                update_adler32(1, first),
+                update_adler32(1, second),
+                second.len() as u64
+            ),
+            update_adler32(1, &max)
+        );
+    }
+
+    #[test]
+    fn adler32() {
+        assert_eq!(update_adler32(1, b""), 1);
+        assert_eq!(update_adler32(1, b"Wikipedia"), 0x11E6_0398);
+
+        let data: Vec<u8> = (0..200_000u32).map(|n| (n * 7 + n / 251) as u8).collect();
+        for &len in &[1, 7, 8, 9, 100, 5552 * 8 - 1, 5552 * 8 + 3, data.len()] {
+            assert_eq!(
+                update_adler32(1, &data[..len]),
+                adler32_simple(&data[..len])
+            );
+        }
+        let max = vec![255u8; 100_000];
+        assert_eq!(update_adler32(1, &max), adler32_simple(&max));
+
+        let mut checksum = Adler32Checksum::new();
+        checksum.update_from_slice(&data[..1234]);
+        for &byte in &data[1234..1300] {
+            checksum.update(byte);
+        }
+        checksum.update_from_slice(&data[1300..]);
+        assert_eq!(checksum.current_hash(), adler32_simple(&data));
+    }
+
+    #[test]
+    #[cfg(feature = "crc32")]
+    fn crc32() {
+        let mut crc = Crc32Checksum::new();
+        assert_eq!(crc.current_hash(), 0);
+        crc.update_from_slice(b"123456789");
+        assert_eq!(crc.current_hash(), 0xCBF4_3926);
+        assert_eq!(crc.amount(), 9);
+
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let mut crc = Crc32Checksum::new();
+        crc.update_from_slice(&data[..10]);
+        for &byte in &data[10..] {
+            crc.update(byte);
+        }
+        assert_eq!(crc.current_hash(), 0x414F_A339);
+        assert_eq!(crc.amount(), data.len() as u32);
+    }
+
+    #[test]
+    #[cfg(feature = "crc32")]
+    fn crc32_long_input() {
+        // Long enough to use the accelerated code paths where available, compared against a
+        // straightforward bitwise implementation.
+        let data: Vec<u8> = (0..100_000u32).map(|n| (n * 7 + n / 251) as u8).collect();
+        let mut crc = Crc32Checksum::new();
+        crc.update_from_slice(&data[..3]);
+        crc.update_from_slice(&data[3..]);
+        assert_eq!(crc.current_hash(), crc32_simple(&data));
     }
 }
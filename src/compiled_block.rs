@@ -0,0 +1,179 @@
+//! Compressing a piece of data once, and reusing the compressed blocks in several streams.
+use std::mem;
+
+use crate::compress::{write_stored_block, BlockInfo, BlockKind, Flush};
+use crate::compression_options::CompressionOptions;
+use crate::deflate_state::DeflateState;
+use crate::error::Error;
+use crate::sink::Sink;
+use crate::writer::compress_until_done;
+
+/// A piece of data compressed once into DEFLATE blocks, which can be spliced into any number of
+/// streams without compressing it again.
+///
+/// This is useful when many streams share large identical sections, such as responses generated
+/// from the same template. The blocks don't refer to any data outside of the piece, so they can
+/// be placed anywhere in a stream using the `write_compiled_block` method of the encoders. When
+/// spliced in, the bits are shifted to line up with the end of the stream, and the final block
+/// flag is set on the last block if it ends the stream.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Write;
+///
+/// use deflate::write::ZlibEncoder;
+/// use deflate::{CompiledBlock, Compression};
+///
+/// let footer = b"<footer>Shared by every page</footer>";
+/// let footer = CompiledBlock::try_new(footer, Compression::Default).unwrap();
+/// for body in &[&b"<p>First page</p>"[..], &b"<p>Second page</p>"[..]] {
+///     let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+///     encoder.write_all(body).unwrap();
+///     encoder.write_compiled_block(&footer, true).unwrap();
+///     let compressed = encoder.finish().unwrap();
+///     # let _ = compressed;
+/// }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CompiledBlock {
+    input: Vec<u8>,
+    /// The compressed blocks, padded to a whole number of bytes.
+    data: Vec<u8>,
+    blocks: Vec<BlockInfo>,
+}
+
+impl CompiledBlock {
+    /// Compress `input` using the provided compression options, or return the error that
+    /// occured during compression.
+    pub fn try_new<O: Into<CompressionOptions>>(
+        input: &[u8],
+        options: O,
+    ) -> Result<CompiledBlock, Error> {
+        let mut deflate_state = Box::new(DeflateState::try_new(options.into(), Vec::new())?);
+        deflate_state.set_input_size_hint(input.len() as u64);
+        deflate_state.block_log = Some(Vec::new());
+        compress_until_done(input, &mut deflate_state, Flush::Block)?;
+        // Output the last partial byte, the padding bits are not part of any block.
+        deflate_state.encoder_state.flush();
+        deflate_state.write_pending_output()?;
+        let blocks = deflate_state.block_log.take().unwrap_or_default();
+        let data = mem::replace(
+            deflate_state.inner.as_mut().expect("Missing writer!"),
+            Vec::new(),
+        );
+        Ok(CompiledBlock {
+            input: input.to_vec(),
+            data,
+            blocks,
+        })
+    }
+
+    /// The uncompressed data.
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
+    /// Information about the compressed blocks, with positions relative to the start of the
+    /// first one.
+    pub fn blocks(&self) -> &[BlockInfo] {
+        &self.blocks
+    }
+
+    /// The length of the compressed blocks in bits.
+    ///
+    /// Stored blocks are padded to a byte boundary, so the length may change a little depending
+    /// on where in a stream the blocks are spliced in.
+    pub fn bit_len(&self) -> u64 {
+        self.blocks.last().map_or(0, |b| b.end_bit)
+    }
+
+    /// Append the blocks to the output of `deflate_state`, which has to be at a block boundary,
+    /// setting the final block flag on the last one if `final_block` is set.
+    pub(crate) fn splice<W: Sink>(&self, deflate_state: &mut DeflateState<W>, final_block: bool) {
+        for (n, block) in self.blocks.iter().enumerate() {
+            let is_final = final_block && n + 1 == self.blocks.len();
+            let start_bit = deflate_state.output_bit_position();
+            let writer = &mut deflate_state.encoder_state.writer;
+            if block.kind == BlockKind::Stored {
+                // Stored blocks are aligned to a byte boundary, which may be in a different place
+                // in the new stream, so they have to be written again.
+                let start = block.input_start as usize;
+                let end = start + block.input_len as usize;
+                write_stored_block(&self.input[start..end], writer, is_final);
+            } else if is_final {
+                writer.write_bits(1, 1);
+                writer.write_bit_range(&self.data, block.start_bit + 1, block.end_bit);
+            } else {
+                writer.write_bit_range(&self.data, block.start_bit, block.end_bit);
+            }
+            let header_bits = if block.kind == BlockKind::Stored {
+                deflate_state.output_bit_position() - start_bit - block.input_len * 8
+            } else {
+                block.header_bits
+            };
+            deflate_state.record_block(
+                block.kind,
+                block.input_len,
+                start_bit,
+                header_bits,
+                is_final,
+            );
+        }
+        deflate_state.count_input(&self.input);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, decompress_zlib, get_test_data, noise};
+    use crate::write::{DeflateEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    #[test]
+    fn splice_compiled_blocks() {
+        let data = get_test_data();
+        // Pseudo-random bytes, which end up in stored blocks.
+        let mut section = noise(100_000);
+        section.extend_from_slice(&data[..100_000]);
+        let compiled = CompiledBlock::try_new(&section, CompressionOptions::default()).unwrap();
+        assert!(compiled.blocks().len() > 1);
+        assert!(compiled
+            .blocks()
+            .iter()
+            .any(|b| b.kind == BlockKind::Stored));
+        assert!(compiled.blocks().iter().all(|b| !b.final_block));
+
+        // Splice the blocks in at different bit offsets.
+        for prefix_len in 0..12 {
+            let prefix = &data[data.len() - prefix_len..];
+            let mut encoder = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+            encoder.write_all(prefix).unwrap();
+            encoder.write_compiled_block(&compiled, false).unwrap();
+            encoder.write_all(&data[..100]).unwrap();
+            encoder.write_compiled_block(&compiled, true).unwrap();
+            assert!(encoder.is_finished());
+            assert!(encoder.write(b"more").is_err());
+            let compressed = encoder.finish().unwrap();
+
+            let mut expected = prefix.to_vec();
+            expected.extend_from_slice(&section);
+            expected.extend_from_slice(&data[..100]);
+            expected.extend_from_slice(&section);
+            assert!(decompress_zlib(&compressed) == expected);
+        }
+    }
+
+    #[test]
+    fn splice_empty_compiled_block() {
+        let compiled = CompiledBlock::try_new(&[], CompressionOptions::default()).unwrap();
+        assert_eq!(compiled.bit_len(), 0);
+        let mut encoder = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        encoder.write_all(b"data").unwrap();
+        encoder.write_compiled_block(&compiled, false).unwrap();
+        encoder.write_compiled_block(&compiled, true).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress_to_end(&compressed), b"data");
+    }
+}
@@ -1,19 +1,23 @@
 use std::io;
-use std::io::Write;
 
 use crate::bitstream::LsbWriter;
-use crate::deflate_state::DeflateState;
+use crate::checksum::RollingChecksum;
+use crate::compression_options::SpecialOptions;
+use crate::deflate_state::{DeflateState, LengthBuffers};
 use crate::encoder_state::EncoderState;
-use crate::huffman_lengths::{gen_huffman_lengths, write_huffman_lengths, BlockType};
+use crate::huffman_lengths::{
+    gen_huffman_lengths, write_huffman_lengths, BlockType, DynamicBlockHeader,
+};
 use crate::lz77::{lz77_compress_block, LZ77Status};
 use crate::lzvalue::LZValue;
+use crate::sink::{Sink, SinkExt};
+use crate::stats::{Stage, StageTimer};
 use crate::stored_block::{compress_block_stored, write_stored_header, MAX_STORED_BLOCK_LENGTH};
 
-const LARGEST_OUTPUT_BUF_SIZE: usize = 1024 * 32;
+pub const LARGEST_OUTPUT_BUF_SIZE: usize = 1024 * 32;
 
 /// Flush mode to use when compressing input received in multiple steps.
 ///
-/// (The more obscure ZLIB flush modes are not implemented.)
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub enum Flush {
     // Simply wait for more input when we are out of input data to process.
@@ -22,13 +26,121 @@ pub enum Flush {
     // outputting all pending data, and then outputs an empty stored block.
     // (That is, the block header indicating a stored block followed by `0000FFFF`).
     Sync,
-    _Partial,
-    _Block,
-    _Full,
+    // End the current block after outputting all pending data, followed by an empty fixed block,
+    // without padding the output to a byte boundary. The empty block makes sure all of the data
+    // before it can be decompressed from the whole bytes output. Corresponds to Z_PARTIAL_FLUSH
+    // in zlib.
+    Partial,
+    // End the current block after outputting all pending data, without padding the output to a
+    // byte boundary, so the next block starts at the next input byte. Corresponds to Z_BLOCK in
+    // zlib.
+    Block,
+    // Like `Sync`, but also forget the data compressed so far, so the output after the flush
+    // can be decompressed without the output before it. Corresponds to Z_FULL_FLUSH in zlib.
+    Full,
     // Finish compressing and output all remaining input.
     Finish,
 }
 
+/// The type of a DEFLATE block.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Hash)]
+pub enum BlockKind {
+    /// Uncompressed data.
+    Stored,
+    /// Data compressed using the fixed Huffman codes defined in the DEFLATE specification.
+    Fixed,
+    /// Data compressed using Huffman codes described in the block header.
+    Dynamic,
+}
+
+/// Information about a block output by the encoder.
+///
+/// Stored data longer than the maximum stored block length is split into several stored blocks in
+/// the bitstream, but is described by a single `BlockInfo`.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Hash)]
+pub struct BlockInfo {
+    /// The type of the block.
+    pub kind: BlockKind,
+    /// Offset of the first byte of input data contained in the block.
+    pub input_start: u64,
+    /// Number of bytes of input data contained in the block.
+    pub input_len: u64,
+    /// Position in the output of the first bit of the block, counted from the start of the
+    /// output stream (including any header written by the encoder).
+    pub start_bit: u64,
+    /// Position in the output just past the last bit of the block.
+    pub end_bit: u64,
+    /// Number of bits at the start of the block used by the block header.
+    ///
+    /// For dynamic blocks this includes the Huffman code lengths, and for stored blocks the
+    /// padding up to the next byte boundary and the length fields.
+    pub header_bits: u64,
+    /// Whether this block has the final block flag set.
+    pub final_block: bool,
+}
+
+/// The estimated size of a block when output using each of the block types.
+///
+/// The sizes are in bits and exclude the 3-bit block header common to all block types.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Hash)]
+pub struct BlockCosts {
+    /// Offset of the first byte of input data contained in the block.
+    pub input_start: u64,
+    /// Number of bytes of input data contained in the block.
+    pub input_len: u64,
+    /// Size of the block if output as one or more stored blocks.
+    pub stored_bits: u64,
+    /// Size of the block if output using the fixed Huffman codes.
+    pub fixed_bits: u64,
+    /// Size of the block if output using dynamic Huffman codes, including the code lengths.
+    pub dynamic_bits: u64,
+}
+
+/// A trait for overriding the type of block the encoder outputs.
+///
+/// The selector is called for each block with the estimated size of the block using each of the
+/// block types, and the type the encoder would have chosen on its own. Very short blocks are
+/// always output as fixed blocks without consulting the selector.
+///
+/// A block can only be output as a stored block if its input data is still present in the
+/// encoder's input buffer. If it is not, a request for a stored block is ignored and the shorter of
+/// the fixed and dynamic block types is used instead.
+///
+/// This is implemented for closures taking the same arguments.
+pub trait BlockTypeSelector {
+    /// Return the type of block that should be used to output the block described by `costs`.
+    fn select_block_type(&mut self, costs: &BlockCosts, chosen: BlockKind) -> BlockKind;
+}
+
+impl<F> BlockTypeSelector for F
+where
+    F: FnMut(&BlockCosts, BlockKind) -> BlockKind,
+{
+    fn select_block_type(&mut self, costs: &BlockCosts, chosen: BlockKind) -> BlockKind {
+        self(costs, chosen)
+    }
+}
+
+/// A trait for listening to the blocks output by the encoder.
+///
+/// The listener is called once for each block after it has been written to the encoder's output
+/// buffer, which may be before the data is passed on to the wrapped writer.
+///
+/// This is implemented for closures taking the same argument.
+pub trait BlockListener {
+    /// Called after the block described by `info` has been output.
+    fn on_block(&mut self, info: &BlockInfo);
+}
+
+impl<F> BlockListener for F
+where
+    F: FnMut(&BlockInfo),
+{
+    fn on_block(&mut self, info: &BlockInfo) {
+        self(info)
+    }
+}
+
 /// Write all the lz77 encoded data in the buffer using the specified `EncoderState`, and finish
 /// with the end of block code.
 pub fn flush_to_bitstream(buffer: &[LZValue], state: &mut EncoderState) {
@@ -56,7 +168,55 @@ pub fn compress_data_fixed(input: &[u8]) -> Vec<u8> {
     state.reset(Vec::new())
 }
 
-fn write_stored_block(input: &[u8], mut writer: &mut LsbWriter, final_block: bool) {
+/// Write a block containing `values` using the Huffman codes for a dynamic block described by
+/// `header` (with the code lengths already in the encoder's Huffman table), or fixed Huffman codes
+/// if `header` is `None`.
+///
+/// Returns the length of the block header in bits.
+pub fn write_huffman_block(
+    header: Option<&DynamicBlockHeader>,
+    values: &[LZValue],
+    encoder_state: &mut EncoderState,
+    length_buffers: &LengthBuffers,
+    last_block: bool,
+) -> io::Result<u64> {
+    let header_bits = match header {
+        Some(header) => {
+            let bit_position = |w: &LsbWriter| w.w.len() as u64 * 8 + u64::from(w.pending_bits());
+            let start_bit = bit_position(&encoder_state.writer);
+            // Write the block header.
+            encoder_state.write_start_of_block(false, last_block);
+
+            // Output the lengths of the huffman codes used in this block.
+            write_huffman_lengths(
+                header,
+                &encoder_state.huffman_table,
+                &length_buffers.length_buf,
+                &mut encoder_state.writer,
+            )?;
+            let header_bits = bit_position(&encoder_state.writer) - start_bit;
+
+            // Uupdate the huffman codes that will be used to encode the
+            // lz77-compressed data.
+            encoder_state.huffman_table.update_from_lengths()?;
+            header_bits
+        }
+        None => {
+            // Write the block header for fixed code blocks.
+            encoder_state.write_start_of_block(true, last_block);
+
+            // Use the pre-defined static huffman codes.
+            encoder_state.set_huffman_to_fixed();
+            3
+        }
+    };
+
+    // Write the compressed data and the end of block marker.
+    flush_to_bitstream(values, encoder_state);
+    Ok(header_bits)
+}
+
+pub fn write_stored_block(input: &[u8], mut writer: &mut LsbWriter, final_block: bool) {
     // If the input is not zero, we write stored blocks for the input data.
     if !input.is_empty() {
         let mut i = input.chunks(MAX_STORED_BLOCK_LENGTH).peekable();
@@ -76,12 +236,102 @@ fn write_stored_block(input: &[u8], mut writer: &mut LsbWriter, final_block: boo
     }
 }
 
+/// End the stream with empty blocks, so that it ends at a multiple of `alignment` bytes once
+/// `trailer_bytes` more bytes (e.g a zlib or gzip trailer) have been written after it.
+///
+/// The current block has to have been ended already.
+pub fn write_padded_end<W: Sink>(
+    deflate_state: &mut DeflateState<W>,
+    trailer_bytes: u64,
+    alignment: u64,
+) {
+    // Start from a byte boundary.
+    write_empty_stored_block(deflate_state, false);
+    let position = deflate_state.output_bit_position() / 8;
+
+    // The shortest end is an empty fixed block, which takes up 2 bytes.
+    let mut padding = 2 + (alignment - (position + 2 + trailer_bytes) % alignment) % alignment;
+    // Empty stored blocks take up 5 bytes each from a byte boundary.
+    while padding > 6 {
+        write_empty_stored_block(deflate_state, false);
+        padding -= 5;
+    }
+    // Empty fixed blocks take up 10 bits each, so the rest can be made up by ending with up to
+    // two of those followed by a final empty fixed or stored block.
+    let (fixed_blocks, stored_end) = match padding {
+        2 => (0, false),
+        3 => (1, false),
+        4 => (2, false),
+        5 => (0, true),
+        _ => (1, true),
+    };
+    deflate_state.encoder_state.set_huffman_to_fixed();
+    for _ in 0..fixed_blocks {
+        write_empty_fixed_block(deflate_state, false);
+    }
+    if stored_end {
+        write_empty_stored_block(deflate_state, true);
+    } else {
+        write_empty_fixed_block(deflate_state, true);
+        deflate_state.encoder_state.flush();
+    }
+    debug_assert_eq!(
+        (deflate_state.output_bit_position() / 8 + trailer_bytes) % alignment,
+        0
+    );
+}
+
+/// Output an empty stored block, recording it like any other block.
+fn write_empty_stored_block<W: Sink>(deflate_state: &mut DeflateState<W>, final_block: bool) {
+    let start_bit = deflate_state.output_bit_position();
+    write_stored_block(&[], &mut deflate_state.encoder_state.writer, final_block);
+    let header_bits = deflate_state.output_bit_position() - start_bit;
+    deflate_state.record_block(BlockKind::Stored, 0, start_bit, header_bits, final_block);
+}
+
+/// Output an empty block using the fixed Huffman codes, which have to be set already.
+fn write_empty_fixed_block<W: Sink>(deflate_state: &mut DeflateState<W>, final_block: bool) {
+    let start_bit = deflate_state.output_bit_position();
+    let es = &mut deflate_state.encoder_state;
+    es.write_start_of_block(true, final_block);
+    es.write_end_of_block();
+    deflate_state.record_block(BlockKind::Fixed, 0, start_bit, 3, final_block);
+}
+
+/// Whether an error from the underlying writer means writing can be tried again later.
+fn is_retryable(kind: io::ErrorKind) -> bool {
+    kind == io::ErrorKind::WouldBlock || kind == io::ErrorKind::Interrupted
+}
+
 /// Inner compression function used by both the writers and the simple compression functions.
-pub fn compress_data_dynamic_n<W: Write>(
+///
+/// If the underlying writer fails with `WouldBlock` or `Interrupted` after some of the input has
+/// been consumed, the number of bytes consumed is returned and the compressed data is kept in
+/// the output buffer until the next call. If no input was consumed, the error is returned, and the
+/// same call can be retried later.
+///
+/// `checksum` is updated with the input as it's consumed.
+pub fn compress_data_dynamic_n<W: Sink, C: RollingChecksum>(
     input: &[u8],
     deflate_state: &mut DeflateState<W>,
     flush: Flush,
+    checksum: &mut C,
 ) -> io::Result<usize> {
+    if deflate_state.is_poisoned() {
+        // Writing failed earlier, so we don't know what the wrapped writer has received, and
+        // can't continue the stream.
+        return Err(deflate_state.poisoned_error());
+    }
+    if flush == Flush::Finish && deflate_state.needs_flush {
+        // The output of an earlier flush couldn't all be written, so write the rest of it before
+        // compressing anything, and then go on to output the final block.
+        compress_data_dynamic_n(&[], deflate_state, Flush::None, checksum)?;
+        if deflate_state.needs_flush {
+            return Ok(0);
+        }
+    }
+    deflate_state.reserve_output_buf();
+
     let mut bytes_written = 0;
 
     let mut slice = input;
@@ -94,11 +344,24 @@ pub fn compress_data_dynamic_n<W: Write>(
         // If the output buffer has too much data in it already, flush it before doing anything
         // else.
         if output_buf_len > LARGEST_OUTPUT_BUF_SIZE {
-            let written = deflate_state
+            instrument!(output_buffer_full);
+            let written = match deflate_state
                 .inner
                 .as_mut()
                 .expect("Missing writer!")
-                .write(&deflate_state.encoder_state.inner_vec()[output_buf_pos..])?;
+                .write_some_output(&deflate_state.encoder_state.inner_vec()[output_buf_pos..])
+            {
+                Ok(written) => written,
+                // The input we have consumed so far is stored in the internal buffers, so report
+                // it as written, and try writing the output again on the next call.
+                Err(ref e) if bytes_written > 0 && is_retryable(e.kind()) => {
+                    return Ok(bytes_written)
+                }
+                Err(e) => {
+                    deflate_state.record_error(&e);
+                    return Err(e);
+                }
+            };
 
             if written < output_buf_len.checked_sub(output_buf_pos).unwrap() {
                 // Only some of the data was flushed, so keep track of where we were.
@@ -106,8 +369,7 @@ pub fn compress_data_dynamic_n<W: Write>(
             } else {
                 // If we flushed all of the output, reset the output buffer.
                 deflate_state.needs_flush = false;
-                deflate_state.output_buf_pos = 0;
-                deflate_state.output_buf().clear();
+                deflate_state.clear_output_buf()?;
             }
 
             if bytes_written == 0 {
@@ -128,6 +390,7 @@ pub fn compress_data_dynamic_n<W: Write>(
             break;
         }
 
+        let timer = StageTimer::start();
         let (written, status, position) = lz77_compress_block(
             slice,
             &mut deflate_state.lz77_state,
@@ -135,6 +398,16 @@ pub fn compress_data_dynamic_n<W: Write>(
             &mut deflate_state.lz77_writer,
             flush,
         );
+        timer.stop(&mut deflate_state.stats, Stage::Lz77);
+
+        // Update the checksum with the input that was just copied into the input buffer, while
+        // it's still in the cache, rather than reading all of it again separately.
+        let timer = StageTimer::start();
+        checksum.update_from_slice(&slice[..written]);
+        timer.stop(&mut deflate_state.stats, Stage::Checksum);
+
+        #[cfg(feature = "verify")]
+        deflate_state.verifier.add_input(&slice[..written]);
 
         // Bytes written in this call
         bytes_written += written;
@@ -165,8 +438,17 @@ pub fn compress_data_dynamic_n<W: Write>(
         }
 
         let partial_bits = deflate_state.encoder_state.writer.pending_bits();
-
-        let res = {
+        let start_bit = deflate_state.output_bit_position();
+
+        let timer = StageTimer::start();
+        let special = deflate_state.compression_options.special;
+        // Stored blocks can only be forced if all of the input data for the block is still in
+        // the input buffer.
+        let force_stored = special == SpecialOptions::ForceStored
+            && position >= current_block_input_bytes as usize;
+        let lengths = if special == SpecialOptions::ForceFixed || force_stored {
+            None
+        } else {
             let (l_freqs, d_freqs) = deflate_state.lz77_writer.get_frequencies();
             let (l_lengths, d_lengths) =
                 deflate_state.encoder_state.huffman_table.get_lengths_mut();
@@ -176,56 +458,64 @@ pub fn compress_data_dynamic_n<W: Write>(
                 d_freqs,
                 current_block_input_bytes,
                 partial_bits,
+                usize::from(deflate_state.compression_options.max_code_length),
                 l_lengths,
                 d_lengths,
                 &mut deflate_state.length_buffers,
             )
         };
 
+        let res = match lengths {
+            Some(lengths) => {
+                let mut kind = lengths.shortest();
+                if let Some(ref mut selector) = deflate_state.block_type_selector {
+                    let costs = BlockCosts {
+                        input_start: deflate_state.block_input_start,
+                        input_len: current_block_input_bytes,
+                        stored_bits: lengths.stored_length,
+                        fixed_bits: lengths.static_length,
+                        dynamic_bits: lengths.dynamic_length,
+                    };
+                    kind = selector.select_block_type(&costs, kind);
+                }
+                if kind == BlockKind::Stored && position < current_block_input_bytes as usize {
+                    // The start of the input data for this block is no longer in the input
+                    // buffer, so we can't output it as a stored block.
+                    kind = lengths.shortest_compressed();
+                }
+                lengths.into_block_type(kind)
+            }
+            // Blocks this short include the empty final block written when the stream is
+            // finished right after a flush, where an empty fixed block (10 bits) is a lot shorter
+            // than an empty stored block (at least 35 bits).
+            None if force_stored => BlockType::Stored,
+            None => BlockType::Fixed,
+        };
+        timer.stop(&mut deflate_state.stats, Stage::Huffman);
+
         // Check if we've actually managed to compress the input, and output stored blocks
         // if not.
-        match res {
+        let timer = StageTimer::start();
+        let (kind, header_bits) = match res {
             BlockType::Dynamic(header) => {
-                // Write the block header.
-                deflate_state
-                    .encoder_state
-                    .write_start_of_block(false, last_block);
-
-                // Output the lengths of the huffman codes used in this block.
-                write_huffman_lengths(
-                    &header,
-                    &deflate_state.encoder_state.huffman_table,
-                    &deflate_state.length_buffers.length_buf,
-                    &mut deflate_state.encoder_state.writer,
-                );
-
-                // Uupdate the huffman codes that will be used to encode the
-                // lz77-compressed data.
-                deflate_state
-                    .encoder_state
-                    .huffman_table
-                    .update_from_lengths();
-
-                // Write the huffman compressed data and the end of block marker.
-                flush_to_bitstream(
+                let header_bits = write_huffman_block(
+                    Some(&header),
                     deflate_state.lz77_writer.get_buffer(),
                     &mut deflate_state.encoder_state,
-                );
+                    &deflate_state.length_buffers,
+                    last_block,
+                )?;
+                (BlockKind::Dynamic, header_bits)
             }
             BlockType::Fixed => {
-                // Write the block header for fixed code blocks.
-                deflate_state
-                    .encoder_state
-                    .write_start_of_block(true, last_block);
-
-                // Use the pre-defined static huffman codes.
-                deflate_state.encoder_state.set_huffman_to_fixed();
-
-                // Write the compressed data and the end of block marker.
-                flush_to_bitstream(
+                let header_bits = write_huffman_block(
+                    None,
                     deflate_state.lz77_writer.get_buffer(),
                     &mut deflate_state.encoder_state,
-                );
+                    &deflate_state.length_buffers,
+                    last_block,
+                )?;
+                (BlockKind::Fixed, header_bits)
             }
             BlockType::Stored => {
                 // If compression fails, output a stored block instead.
@@ -243,10 +533,43 @@ pub fn compress_data_dynamic_n<W: Write>(
                     &mut deflate_state.encoder_state.writer,
                     flush == Flush::Finish && last_block,
                 );
+                let total_bits = deflate_state.output_bit_position() - start_bit;
+                (
+                    BlockKind::Stored,
+                    total_bits - current_block_input_bytes * 8,
+                )
             }
         };
+        timer.stop(&mut deflate_state.stats, Stage::Bitstream);
+
+        let final_block = match kind {
+            BlockKind::Stored => flush == Flush::Finish && last_block,
+            _ => last_block,
+        };
+        deflate_state.record_block(
+            kind,
+            current_block_input_bytes,
+            start_bit,
+            header_bits,
+            final_block,
+        );
 
         // Clear the current lz77 data in the writer for the next call.
+        #[cfg(feature = "symbol-stats")]
+        deflate_state
+            .stats
+            .symbols
+            .add_values(deflate_state.lz77_writer.get_buffer());
+        #[cfg(feature = "lz-trace")]
+        if let Some(ref mut trace) = deflate_state.lz_trace {
+            trace.extend(
+                deflate_state
+                    .lz77_writer
+                    .get_buffer()
+                    .iter()
+                    .map(crate::lz_trace::to_token),
+            );
+        }
         deflate_state.lz77_writer.clear();
         // We are done with the block, so we reset the number of bytes taken
         // for the next one.
@@ -256,33 +579,57 @@ pub fn compress_data_dynamic_n<W: Write>(
         if status == LZ77Status::Finished {
             // This flush mode means that there should be an empty stored block at the end.
             if flush == Flush::Sync {
-                write_stored_block(&[], &mut deflate_state.encoder_state.writer, false);
+                write_empty_stored_block(deflate_state, false);
                 // Indicate that we need to flush the buffers before doing anything else.
                 deflate_state.needs_flush = true;
+            } else if flush == Flush::Block {
+                // The block has already been ended, but as with a sync flush, we need to flush
+                // the buffers before continuing, so the next call doesn't output an empty block.
+                deflate_state.needs_flush = true;
+            } else if flush == Flush::Partial {
+                // The empty block pushes the end of block code of the previous block into whole
+                // bytes, where the decompressor can get at it. Like with a block flush, the bits
+                // after it are held back until the next block.
+                deflate_state.encoder_state.set_huffman_to_fixed();
+                write_empty_fixed_block(deflate_state, false);
+                deflate_state.needs_flush = true;
             } else if !deflate_state.lz77_state.is_last_block() {
                 // Make sure a block with the last block header has been output.
                 // Not sure this can actually happen, but we make sure to finish properly
                 // if it somehow does.
                 // An empty fixed block is the shortest.
-                let es = &mut deflate_state.encoder_state;
-                es.set_huffman_to_fixed();
-                es.write_start_of_block(true, true);
-                es.write_end_of_block();
+                deflate_state.encoder_state.set_huffman_to_fixed();
+                write_empty_fixed_block(deflate_state, true);
             }
             break;
         }
     }
 
     // If we reach this point, the remaining data in the buffers is to be flushed.
-    deflate_state.encoder_state.flush();
+    if flush == Flush::Block || flush == Flush::Partial || flush == Flush::None {
+        // The output may end in the middle of a byte after a block or partial flush, in which case the
+        // remaining bits have to wait for the next block rather than being padded.
+        deflate_state.encoder_state.writer.flush_whole_bytes();
+    } else {
+        deflate_state.encoder_state.flush();
+    }
     // Make sure we've output everything, and return the number of bytes written if everything
     // went well.
     let output_buf_pos = deflate_state.output_buf_pos;
-    let written_to_writer = deflate_state
+    let written_to_writer = match deflate_state
         .inner
         .as_mut()
         .expect("Missing writer!")
-        .write(&deflate_state.encoder_state.inner_vec()[output_buf_pos..])?;
+        .write_some_output(&deflate_state.encoder_state.inner_vec()[output_buf_pos..])
+    {
+        Ok(written) => written,
+        // As above, the output stays in the buffer until the next call.
+        Err(ref e) if bytes_written > 0 && is_retryable(e.kind()) => return Ok(bytes_written),
+        Err(e) => {
+            deflate_state.record_error(&e);
+            return Err(e);
+        }
+    };
     if written_to_writer
         < deflate_state
             .output_buf()
@@ -293,9 +640,15 @@ pub fn compress_data_dynamic_n<W: Write>(
         deflate_state.output_buf_pos += written_to_writer;
     } else {
         // If we sucessfully wrote all the data, we can clear the output buffer.
-        deflate_state.output_buf_pos = 0;
-        deflate_state.output_buf().clear();
+        deflate_state.clear_output_buf()?;
         deflate_state.needs_flush = false;
+        #[cfg(feature = "verify")]
+        {
+            if flush == Flush::Finish && deflate_state.lz77_state.is_last_block() {
+                let res = deflate_state.verifier.check_finished();
+                deflate_state.check_verified(res)?;
+            }
+        }
     }
 
     Ok(bytes_written)
@@ -8,7 +8,12 @@
 //! compressor, which uses a specialised (but slow) algorithm to figure out the maximum
 //! of compression for the provided data.
 //!
+use crate::chained_hash_table::HashFunction;
+use crate::error::Error;
+use crate::huffman_table::{MAX_CODE_LENGTH, MIN_CODE_LENGTH_LIMIT};
 use crate::lz77::MatchingType;
+use crate::output_writer::MAX_BUFFER_LENGTH;
+use std::cmp;
 use std::convert::From;
 
 pub const HIGH_MAX_HASH_CHECKS: u16 = 1768;
@@ -18,6 +23,14 @@ pub const HIGH_LAZY_IF_LESS_THAN: u16 = 128;
 pub const MAX_HASH_CHECKS: u16 = 32 * 1024;
 pub const DEFAULT_MAX_HASH_CHECKS: u16 = 128;
 pub const DEFAULT_LAZY_IF_LESS_THAN: u16 = 32;
+/// The base two logarithm of the largest window size allowed by the `DEFLATE` format.
+pub const MAX_WINDOW_BITS: u8 = 15;
+/// The base two logarithm of the smallest window size that can be described in a zlib header.
+pub const MIN_WINDOW_BITS: u8 = 8;
+/// The largest distance a match can refer back to.
+pub const MAX_DISTANCE: u16 = 1 << MAX_WINDOW_BITS;
+/// The largest number of literals and length/distance pairs the encoder puts in one block.
+pub const MAX_BLOCK_SYMBOLS: u16 = MAX_BUFFER_LENGTH as u16;
 
 /// An enum describing the level of compression to be used by the encoder
 ///
@@ -47,15 +60,26 @@ impl Default for Compression {
     }
 }
 
-/// Enum allowing some special options (not implemented yet)!
+/// Enum allowing some special options.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum SpecialOptions {
     /// Compress normally.
     Normal,
-    /// Force fixed Huffman tables. (Unimplemented!).
-    _ForceFixed,
-    /// Force stored (uncompressed) blocks only. (Unimplemented!).
-    _ForceStored,
+    /// Force fixed Huffman tables.
+    ///
+    /// This skips generating Huffman codes for each block, which saves some time and avoids the
+    /// overhead of the code tables for small blocks, but usually compresses worse. The block type
+    /// selector, if any, is not consulted.
+    ForceFixed,
+    /// Force stored (uncompressed) blocks.
+    ///
+    /// This is what compression level `0` uses, see
+    /// [`CompressionOptions::from_level`](struct.CompressionOptions.html#method.from_level). No
+    /// matches are looked for, whatever the other options are set to, and blocks are ended before
+    /// they grow larger than the window, so the encoder still holds the input of each block when
+    /// it's output. The block type selector, if any, is not consulted. Ignored when
+    /// `output_version` is `OutputVersion::V1`, and by the experimental two-pass functions.
+    ForceStored,
 }
 
 impl Default for SpecialOptions {
@@ -69,8 +93,37 @@ pub const DEFAULT_OPTIONS: CompressionOptions = CompressionOptions {
     lazy_if_less_than: DEFAULT_LAZY_IF_LESS_THAN,
     matching_type: MatchingType::Lazy,
     special: SpecialOptions::Normal,
+    window_bits: MAX_WINDOW_BITS,
+    max_search_distance: MAX_DISTANCE,
+    max_block_symbols: MAX_BLOCK_SYMBOLS,
+    max_code_length: MAX_CODE_LENGTH as u8,
+    auto_flush_after: 0,
+    hash_function: HashFunction::Multiplicative,
+    adaptive_hash_checks: true,
+    output_version: OutputVersion::Latest,
 };
 
+/// The version of the compression algorithm to use.
+///
+/// The output of the encoder for a given input and set of options may change between releases
+/// as the compression algorithm is improved. Selecting a fixed version guarantees that the output
+/// stays byte for byte the same in all later releases, which is useful for reproducible builds
+/// and content-addressed storage. Improvements that change the output are only applied to
+/// `Latest`, and will be available as a new fixed version.
+///
+/// The guarantee covers the compression functions and encoders using the same options and the
+/// same sequence of writes and flushes, but not the experimental functions in this crate, like
+/// [`try_deflate_bytes_two_pass`](fn.try_deflate_bytes_two_pass.html).
+///
+/// The `tune-text` and `tune-binary` features only change the output of `Latest`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum OutputVersion {
+    /// Use the latest version of the algorithm.
+    Latest,
+    /// Produce the same output as version 1 of the stable output, introduced in release 1.0.0.
+    V1,
+}
+
 /// A struct describing the options for a compressor or compression function.
 ///
 /// These values are not stable and still subject to change!
@@ -83,9 +136,12 @@ pub struct CompressionOptions {
     /// this won't make any difference, and will be truncated to 2^15 by the compression
     /// function/writer.
     ///
+    /// With greedy matching, a value of `1` uses a faster matching method like the fastest level
+    /// of zlib and miniz, unless `output_version` is `OutputVersion::V1`. See
+    /// [`fast`](#method.fast).
+    ///
     /// Default value: `128`
     pub max_hash_checks: u16,
-    // pub _window_size: u16,
     /// Only lazy match if we have a length less than this value.
     ///
     /// Higher values degrade compression slightly, but improve compression speed.
@@ -97,16 +153,21 @@ pub struct CompressionOptions {
     /// As the maximum length of a match is `258`, values higher than this will have
     /// no further effect.
     ///
+    /// With `OutputVersion::Latest`, the `tune-text` feature doubles the value used, and
+    /// `tune-binary` halves it.
+    ///
     /// * Default value: `32`
     pub lazy_if_less_than: u16,
 
     // pub _decent_match: u16,
-    /// Whether to use lazy or greedy matching.
+    /// Whether to use greedy or lazy matching, or optimal parsing.
     ///
     /// Lazy matching will provide better compression, at the expense of compression speed.
+    /// Optimal parsing compresses better still, but is a lot slower.
     ///
     /// As a special case, if max_hash_checks is set to 0, and matching_type is set to lazy,
-    /// compression using only run-length encoding (i.e maximum match distance of 1) is performed.
+    /// compression using only run-length encoding (i.e maximum match distance of 4, or 1 with
+    /// `OutputVersion::V1`) is performed.
     /// (This may be changed in the future but is defined like this at the moment to avoid API
     /// breakage.
     ///
@@ -114,9 +175,99 @@ pub struct CompressionOptions {
     ///
     /// * Default value: `MatchingType::Lazy`
     pub matching_type: MatchingType,
-    /// Force fixed/stored blocks (Not implemented yet).
+    /// Force the type of the blocks output.
+    ///
+    /// `SpecialOptions::ForceFixed` outputs every block with the fixed Huffman codes, and
+    /// `SpecialOptions::ForceStored` outputs the input uncompressed in stored blocks without
+    /// looking for matches. `from_level(0)` selects `ForceStored`.
+    ///
+    /// [See `SpecialOptions`](./enum.SpecialOptions.html)
+    ///
     /// * Default value: `SpecialOptions::Normal`
     pub special: SpecialOptions,
+    /// The base two logarithm of the window size, i.e how far back matches may refer to.
+    ///
+    /// Smaller windows limit the match distances so the output can be decompressed by
+    /// decoders that only keep a small window of previous output around, at the cost of
+    /// compression ratio. The value is also written to the CINFO field of the zlib header.
+    ///
+    /// The input is processed in windows of this size (but at least 512 bytes), so smaller
+    /// windows also shrink the buffer the encoder keeps the input in, which is about twice the
+    /// window size. The hash chains use the same amount of memory regardless. Blocks can only
+    /// be output as stored blocks while all of their input is in that buffer, so incompressible
+    /// data may expand a bit more with small windows. With `OutputVersion::V1`, the input is
+    /// always processed in 32 KiB windows.
+    ///
+    /// Valid values are `8...15`.
+    ///
+    /// * Default value: `15` (32 KiB window)
+    pub window_bits: u8,
+    /// The maximum distance back the encoder will search for matches.
+    ///
+    /// Unlike `window_bits`, this does not change the window size written to the zlib header,
+    /// so the output can be decompressed by any decoder. Lower values reduce the compression
+    /// ratio somewhat, but can speed up compression of large inputs since less of the window
+    /// has to be accessed. If this is larger than the window size, the window size is used
+    /// instead.
+    ///
+    /// Valid values are `1...32768`.
+    ///
+    /// * Default value: `32768`
+    pub max_search_distance: u16,
+    /// The maximum number of literals and length/distance pairs in each block.
+    ///
+    /// Smaller blocks mean less data is held back in the encoder before being output, at the
+    /// cost of more block header overhead.
+    ///
+    /// Valid values are `1...31744`.
+    ///
+    /// * Default value: `31744`
+    pub max_block_symbols: u16,
+    /// The maximum length in bits of the Huffman codes generated for dynamic blocks.
+    ///
+    /// Limiting the code length makes the output easier to decode with simple table-driven
+    /// decoders, like the ones used in hardware, as smaller lookup tables are needed. The output
+    /// is still valid DEFLATE that any decoder can read, but compresses slightly worse the lower
+    /// the limit is.
+    ///
+    /// Valid values are `9...15`.
+    ///
+    /// * Default value: `15` (the maximum allowed by the format)
+    pub max_code_length: u8,
+    /// Automatically sync flush the encoder once this many bytes of input have been written
+    /// since the last flush, or `0` to only flush when requested.
+    ///
+    /// This bounds how much of the data written to an encoder a decoder may have to wait for,
+    /// which is useful for streaming protocols. Only the encoders in the `write` module flush
+    /// automatically.
+    ///
+    /// * Default value: `0`
+    pub auto_flush_after: u32,
+    /// The hash function used to find candidate matches.
+    ///
+    /// [See `HashFunction`](./enum.HashFunction.html)
+    ///
+    /// Ignored when `output_version` is `OutputVersion::V1`, which always uses
+    /// `HashFunction::ShiftXor`.
+    ///
+    /// * Default value: `HashFunction::Multiplicative`
+    pub hash_function: HashFunction,
+    /// Lower the number of hash checks made while the data being compressed barely contains any
+    /// matches, such as already compressed or encrypted data, and raise it back up to
+    /// `max_hash_checks` once matches are found again.
+    ///
+    /// This avoids spending a lot of time looking for matches in incompressible parts of the
+    /// input. It can make the output slightly larger, so of the preset options only the default
+    /// ones use it. Ignored when `output_version` is `OutputVersion::V1`.
+    ///
+    /// * Default value: `true`
+    pub adaptive_hash_checks: bool,
+    /// Which version of the compression algorithm to use.
+    ///
+    /// [See `OutputVersion`](./enum.OutputVersion.html)
+    ///
+    /// * Default value: `OutputVersion::Latest`
+    pub output_version: OutputVersion,
 }
 
 // Some standard profiles for the compression options.
@@ -129,21 +280,89 @@ impl CompressionOptions {
             lazy_if_less_than: HIGH_LAZY_IF_LESS_THAN,
             matching_type: MatchingType::Lazy,
             special: SpecialOptions::Normal,
+            window_bits: MAX_WINDOW_BITS,
+            max_search_distance: MAX_DISTANCE,
+            max_block_symbols: MAX_BLOCK_SYMBOLS,
+            max_code_length: MAX_CODE_LENGTH as u8,
+            auto_flush_after: 0,
+            hash_function: HashFunction::Multiplicative,
+            adaptive_hash_checks: false,
+            output_version: OutputVersion::Latest,
+        }
+    }
+
+    /// Returns compression settings corresponding to the zlib compression level `level`, from
+    /// `0` (no compression) to `9` (best compression), to make porting code using zlib or
+    /// `flate2` easier.
+    ///
+    /// Level `1` is the same as [`fast`](#method.fast), `6` is the same as the default options,
+    /// and `9` is the same as [`high`](#method.high). Level `0` outputs the input as stored
+    /// blocks without compressing it (see `SpecialOptions::ForceStored`). The other levels
+    /// use the same number of hash checks and lazy matching thresholds as zlib where this
+    /// library's matching works the same way. Levels above `9` are treated as `9`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use deflate::{try_deflate_bytes_conf, CompressionOptions};
+    ///
+    /// let data = b"This is some test data";
+    /// let compressed_data = try_deflate_bytes_conf(data, CompressionOptions::from_level(4)).unwrap();
+    /// # let _ = compressed_data;
+    /// ```
+    pub fn from_level(level: u8) -> CompressionOptions {
+        let (max_hash_checks, lazy_if_less_than, matching_type) = match level {
+            0 => {
+                return CompressionOptions {
+                    max_hash_checks: 0,
+                    lazy_if_less_than: 0,
+                    matching_type: MatchingType::Greedy,
+                    special: SpecialOptions::ForceStored,
+                    adaptive_hash_checks: false,
+                    ..DEFAULT_OPTIONS
+                }
+            }
+            1 => return CompressionOptions::fast(),
+            2 => (8, 0, MatchingType::Greedy),
+            3 => (32, 0, MatchingType::Greedy),
+            4 => (16, 4, MatchingType::Lazy),
+            5 => (32, 16, MatchingType::Lazy),
+            6 => return DEFAULT_OPTIONS,
+            7 => (512, 64, MatchingType::Lazy),
+            8 => (1024, HIGH_LAZY_IF_LESS_THAN, MatchingType::Lazy),
+            _ => return CompressionOptions::high(),
+        };
+        CompressionOptions {
+            max_hash_checks,
+            lazy_if_less_than,
+            matching_type,
+            adaptive_hash_checks: false,
+            ..DEFAULT_OPTIONS
         }
     }
 
     /// Returns  a fast set of compression settings
     ///
-    /// Ideally this should roughly correspond to the `FAST(1)` setting in miniz.
-    /// However, that setting makes miniz use a somewhat different algorithm,
-    /// so currently hte fast level in this library is slower and better compressing
-    /// than the corresponding level in miniz.
+    /// This roughly corresponds to the `FAST(1)` setting in miniz. Like miniz and zlib do at
+    /// their fastest level, only one earlier position is checked for a match at each byte, and the
+    /// bytes covered by a match are skipped without adding them to the hash table.
+    ///
+    /// With `OutputVersion::V1` the bytes covered by matches are added to the hash table like with
+    /// the other settings, which is slower and compresses slightly better.
     pub const fn fast() -> CompressionOptions {
         CompressionOptions {
             max_hash_checks: 1,
             lazy_if_less_than: 0,
             matching_type: MatchingType::Greedy,
             special: SpecialOptions::Normal,
+            window_bits: MAX_WINDOW_BITS,
+            max_search_distance: MAX_DISTANCE,
+            max_block_symbols: MAX_BLOCK_SYMBOLS,
+            max_code_length: MAX_CODE_LENGTH as u8,
+            auto_flush_after: 0,
+            hash_function: HashFunction::Multiplicative,
+            adaptive_hash_checks: false,
+            output_version: OutputVersion::Latest,
         }
     }
 
@@ -158,11 +377,20 @@ impl CompressionOptions {
             lazy_if_less_than: 0,
             matching_type: MatchingType::Greedy,
             special: SpecialOptions::Normal,
+            window_bits: MAX_WINDOW_BITS,
+            max_search_distance: MAX_DISTANCE,
+            max_block_symbols: MAX_BLOCK_SYMBOLS,
+            max_code_length: MAX_CODE_LENGTH as u8,
+            auto_flush_after: 0,
+            hash_function: HashFunction::Multiplicative,
+            adaptive_hash_checks: false,
+            output_version: OutputVersion::Latest,
         }
     }
 
     /// Returns a set of compression settings that makes the compressor compress only using
-    /// run-length encoding (i.e only looking for matches one byte back).
+    /// run-length encoding (i.e only looking for runs of the same byte, or of a repeating pattern
+    /// of up to four bytes unless `output_version` is `OutputVersion::V1`).
     ///
     /// This is very fast, but tends to compress worse than looking for more matches using hash
     /// chains that the slower settings do.
@@ -174,10 +402,121 @@ impl CompressionOptions {
             lazy_if_less_than: 0,
             matching_type: MatchingType::Lazy,
             special: SpecialOptions::Normal,
+            window_bits: MAX_WINDOW_BITS,
+            max_search_distance: MAX_DISTANCE,
+            max_block_symbols: MAX_BLOCK_SYMBOLS,
+            max_code_length: MAX_CODE_LENGTH as u8,
+            auto_flush_after: 0,
+            hash_function: HashFunction::Multiplicative,
+            adaptive_hash_checks: false,
+            output_version: OutputVersion::Latest,
+        }
+    }
+
+    /// Returns a set of compression settings aimed at keeping the latency low for interactive
+    /// streams such as network protocols, rather than at good compression.
+    ///
+    /// This uses greedy matching with few hash checks, fixed Huffman codes, small blocks, and
+    /// sync flushes automatically after `flush_after` bytes of input (see
+    /// [`auto_flush_after`](#structfield.auto_flush_after)).
+    pub const fn low_latency(flush_after: u32) -> CompressionOptions {
+        CompressionOptions {
+            max_hash_checks: 8,
+            lazy_if_less_than: 0,
+            matching_type: MatchingType::Greedy,
+            special: SpecialOptions::ForceFixed,
+            window_bits: MAX_WINDOW_BITS,
+            max_search_distance: MAX_DISTANCE,
+            max_block_symbols: 4096,
+            max_code_length: MAX_CODE_LENGTH as u8,
+            auto_flush_after: flush_after,
+            hash_function: HashFunction::Multiplicative,
+            adaptive_hash_checks: false,
+            output_version: OutputVersion::Latest,
+        }
+    }
+
+    /// Returns a copy of the options with any out-of-range values adjusted to the closest
+    /// supported ones, and special options not supported by the output version ignored.
+    pub(crate) fn clamped(self) -> CompressionOptions {
+        CompressionOptions {
+            max_hash_checks: cmp::min(self.max_hash_checks, MAX_HASH_CHECKS),
+            lazy_if_less_than: cmp::min(self.lazy_if_less_than, MAX_HASH_CHECKS),
+            matching_type: self.matching_type,
+            special: match (self.special, self.output_version) {
+                // Forcing stored blocks wasn't implemented in version 1.
+                (SpecialOptions::ForceStored, OutputVersion::V1) => SpecialOptions::Normal,
+                (special, _) => special,
+            },
+            window_bits: clamp(self.window_bits, MIN_WINDOW_BITS, MAX_WINDOW_BITS),
+            max_search_distance: clamp(self.max_search_distance, 1, MAX_DISTANCE),
+            max_block_symbols: clamp(self.max_block_symbols, 1, MAX_BLOCK_SYMBOLS),
+            max_code_length: clamp(
+                self.max_code_length,
+                MIN_CODE_LENGTH_LIMIT as u8,
+                MAX_CODE_LENGTH as u8,
+            ),
+            auto_flush_after: self.auto_flush_after,
+            hash_function: match self.output_version {
+                // Version 1 only had the shift/xor hash.
+                OutputVersion::V1 => HashFunction::ShiftXor,
+                OutputVersion::Latest => self.hash_function,
+            },
+            adaptive_hash_checks: self.adaptive_hash_checks,
+            output_version: self.output_version,
+        }
+    }
+
+    /// Check that the options are within the supported ranges.
+    ///
+    /// The encoders and compression functions that don't return a `Result` will instead silently
+    /// adjust out-of-range values.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOptions` describing the first problem found if any of the options
+    /// are out of range.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_hash_checks > MAX_HASH_CHECKS {
+            return Err(Error::InvalidOptions(
+                "max_hash_checks can not be larger than 32768",
+            ));
         }
+        if self.lazy_if_less_than > MAX_HASH_CHECKS {
+            return Err(Error::InvalidOptions(
+                "lazy_if_less_than can not be larger than 32768",
+            ));
+        }
+        if self.window_bits < MIN_WINDOW_BITS || self.window_bits > MAX_WINDOW_BITS {
+            return Err(Error::InvalidOptions(
+                "window_bits has to be between 8 and 15",
+            ));
+        }
+        if self.max_search_distance == 0 || self.max_search_distance > MAX_DISTANCE {
+            return Err(Error::InvalidOptions(
+                "max_search_distance has to be between 1 and 32768",
+            ));
+        }
+        if self.max_block_symbols == 0 || self.max_block_symbols > MAX_BLOCK_SYMBOLS {
+            return Err(Error::InvalidOptions(
+                "max_block_symbols has to be between 1 and 31744",
+            ));
+        }
+        let max_code_length = usize::from(self.max_code_length);
+        if max_code_length < MIN_CODE_LENGTH_LIMIT || max_code_length > MAX_CODE_LENGTH {
+            return Err(Error::InvalidOptions(
+                "max_code_length has to be between 9 and 15",
+            ));
+        }
+        Ok(())
     }
 }
 
+/// Restrict `value` to `min...max`.
+fn clamp<T: Ord>(value: T, min: T, max: T) -> T {
+    cmp::max(cmp::min(value, max), min)
+}
+
 impl Default for CompressionOptions {
     /// Returns the options describing the default compression level.
     fn default() -> CompressionOptions {
@@ -194,3 +533,77 @@ impl From<Compression> for CompressionOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_options() {
+        assert!(CompressionOptions::default().validate().is_ok());
+        assert!(CompressionOptions::high().validate().is_ok());
+        assert!(CompressionOptions::rle().validate().is_ok());
+        assert!(CompressionOptions::low_latency(1024).validate().is_ok());
+
+        let mut options = CompressionOptions::default();
+        options.lazy_if_less_than = MAX_HASH_CHECKS + 1;
+        match options.validate() {
+            Err(Error::InvalidOptions(_)) => (),
+            other => panic!("Expected invalid options error, got {:?}", other),
+        }
+
+        for level in 0..=10 {
+            assert!(CompressionOptions::from_level(level).validate().is_ok());
+        }
+        assert_eq!(
+            CompressionOptions::from_level(1),
+            CompressionOptions::fast()
+        );
+        assert_eq!(
+            CompressionOptions::from_level(6),
+            CompressionOptions::default()
+        );
+        assert_eq!(
+            CompressionOptions::from_level(9),
+            CompressionOptions::high()
+        );
+        assert_eq!(
+            CompressionOptions::from_level(10),
+            CompressionOptions::high()
+        );
+
+        let mut options = CompressionOptions::from_level(0);
+        assert_eq!(options.clamped().special, SpecialOptions::ForceStored);
+        options.output_version = OutputVersion::V1;
+        assert_eq!(options.clamped().special, SpecialOptions::Normal);
+
+        let mut options = CompressionOptions::default();
+        options.window_bits = 7;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().window_bits, MIN_WINDOW_BITS);
+        options.window_bits = 16;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().window_bits, MAX_WINDOW_BITS);
+
+        let mut options = CompressionOptions::default();
+        options.max_search_distance = 0;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().max_search_distance, 1);
+        options.max_search_distance = MAX_DISTANCE + 1;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().max_search_distance, MAX_DISTANCE);
+
+        let mut options = CompressionOptions::default();
+        options.max_block_symbols = 0;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().max_block_symbols, 1);
+
+        let mut options = CompressionOptions::default();
+        options.max_code_length = 8;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().max_code_length, 9);
+        options.max_code_length = 16;
+        assert!(options.validate().is_err());
+        assert_eq!(options.clamped().max_code_length, 15);
+    }
+}
@@ -0,0 +1,126 @@
+//! The state needed to continue a stream that was ended without a final block.
+
+/// Size of a `DeferredEnd` serialized with [`to_bytes`](struct.DeferredEnd.html#method.to_bytes).
+pub const DEFERRED_END_BYTES: usize = 22;
+
+/// The state left over when a stream is ended with `finish_deferred` instead of `finish`, which
+/// is used to resume the stream later, possibly in another process.
+///
+/// Ending a stream this way outputs all the data written so far, but not the final block, and
+/// leaves up to 7 bits of the last block that don't fill a whole byte. These bits are not output,
+/// but are kept here to be written first when the stream is resumed with `resume`, so the output
+/// of the resumed encoder has to be appended directly to the output from before.
+///
+/// The state can be stored with [`to_bytes`](#method.to_bytes) and read back with
+/// [`from_bytes`](#method.from_bytes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeferredEnd {
+    bits: u8,
+    bit_count: u8,
+    total_in: u64,
+    total_out: u64,
+    checksum: u32,
+}
+
+impl DeferredEnd {
+    pub(crate) fn new(
+        bits: u8,
+        bit_count: u8,
+        total_in: u64,
+        total_out: u64,
+        checksum: u32,
+    ) -> DeferredEnd {
+        debug_assert!(bit_count < 8 && u32::from(bits) >> bit_count == 0);
+        DeferredEnd {
+            bits,
+            bit_count,
+            total_in,
+            total_out,
+            checksum,
+        }
+    }
+
+    /// The bits of the last block that were not output because they don't fill a whole byte,
+    /// in the lowest [`pending_bit_count`](#method.pending_bit_count) bits.
+    pub const fn pending_bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// The number of bits that were not output, which is less than 8.
+    pub const fn pending_bit_count(&self) -> u8 {
+        self.bit_count
+    }
+
+    /// The number of bytes of input compressed so far.
+    pub const fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// The number of bytes output so far, including the zlib or gzip header.
+    pub const fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// The checksum of the input so far, which is the Adler-32 checksum for zlib streams, the
+    /// CRC-32 checksum for gzip streams and 1 for raw DEFLATE streams.
+    pub const fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    /// Serialize the state to a fixed-size array of bytes.
+    pub fn to_bytes(&self) -> [u8; DEFERRED_END_BYTES] {
+        let mut bytes = [0; DEFERRED_END_BYTES];
+        bytes[0] = self.bit_count;
+        bytes[1] = self.bits;
+        bytes[2..10].copy_from_slice(&self.total_in.to_le_bytes());
+        bytes[10..18].copy_from_slice(&self.total_out.to_le_bytes());
+        bytes[18..22].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Read back a state serialized with [`to_bytes`](#method.to_bytes), returning `None` if
+    /// `bytes` has the wrong length or doesn't hold a valid state.
+    pub fn from_bytes(bytes: &[u8]) -> Option<DeferredEnd> {
+        if bytes.len() != DEFERRED_END_BYTES {
+            return None;
+        }
+        let (bit_count, bits) = (bytes[0], bytes[1]);
+        if bit_count >= 8 || u32::from(bits) >> bit_count != 0 {
+            return None;
+        }
+        Some(DeferredEnd {
+            bits,
+            bit_count,
+            total_in: read_le(&bytes[2..10]),
+            total_out: read_le(&bytes[10..18]),
+            checksum: read_le(&bytes[18..22]) as u32,
+        })
+    }
+}
+
+/// Read a little-endian integer of up to 8 bytes.
+fn read_le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deferred_end_bytes() {
+        let end = DeferredEnd::new(0b101, 3, 123_456, 7890, 0xDEAD_BEEF);
+        let bytes = end.to_bytes();
+        assert_eq!(DeferredEnd::from_bytes(&bytes), Some(end));
+
+        assert_eq!(DeferredEnd::from_bytes(&bytes[..21]), None);
+        let mut invalid = bytes;
+        invalid[0] = 8;
+        assert_eq!(DeferredEnd::from_bytes(&invalid), None);
+        invalid[0] = 2;
+        assert_eq!(DeferredEnd::from_bytes(&invalid), None);
+    }
+}
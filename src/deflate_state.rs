@@ -1,15 +1,30 @@
-use std::io::Write;
 use std::{cmp, io, mem};
 
-use crate::compress::Flush;
-use crate::compression_options::{CompressionOptions, MAX_HASH_CHECKS};
+use crate::buffer_pool::{BufferPool, Buffers};
+use crate::chained_hash_table::ChainedHashTable;
+use crate::compress::{
+    BlockInfo, BlockKind, BlockListener, BlockTypeSelector, Flush, LARGEST_OUTPUT_BUF_SIZE,
+};
+use crate::compression_options::{CompressionOptions, OutputVersion, SpecialOptions};
+use crate::deferred::DeferredEnd;
 use crate::encoder_state::EncoderState;
+use crate::error::Error;
 pub use crate::huffman_table::MAX_MATCH;
 use crate::huffman_table::NUM_LITERALS_AND_LENGTHS;
 use crate::input_buffer::InputBuffer;
 use crate::length_encode::{EncodedLength, LeafVec};
-use crate::lz77::LZ77State;
+use crate::lz77::{self, LZ77State};
 use crate::output_writer::DynamicWriter;
+use crate::sink::{Sink, SinkExt};
+use crate::stats::{CompressionReport, CompressionStats};
+#[cfg(feature = "lz-trace")]
+use crate::tokens::Token;
+#[cfg(feature = "verify")]
+use crate::verify::Verifier;
+
+/// The default number of consecutive times writing is retried after the wrapped writer returns
+/// `Interrupted` without making any progress.
+pub const DEFAULT_MAX_INTERRUPTED_RETRIES: u32 = 1000;
 
 /// A counter used for checking values in debug mode.
 /// Does nothing when debug assertions are disabled.
@@ -54,7 +69,7 @@ pub struct LengthBuffers {
 
 impl LengthBuffers {
     #[inline]
-    fn new() -> LengthBuffers {
+    pub fn new() -> LengthBuffers {
         LengthBuffers {
             leaf_buf: Vec::with_capacity(NUM_LITERALS_AND_LENGTHS),
             length_buf: Vec::with_capacity(19),
@@ -63,7 +78,7 @@ impl LengthBuffers {
 }
 
 /// A struct containing all the stored state used for the encoder.
-pub struct DeflateState<W: Write> {
+pub struct DeflateState<W: Sink> {
     /// State of lz77 compression.
     pub lz77_state: LZ77State,
     pub input_buffer: InputBuffer,
@@ -76,6 +91,13 @@ pub struct DeflateState<W: Write> {
     pub length_buffers: LengthBuffers,
     /// Total number of bytes consumed/written to the input buffer.
     pub bytes_written: u64,
+    /// The value of `bytes_written` after the last sync flush.
+    pub bytes_written_at_flush: u64,
+    /// Whether a sync flush has been completed, meaning the output ends with a sync marker if no
+    /// data has been written since.
+    pub synced: bool,
+    /// Whether the final block has been output, after which no more data can be written.
+    pub finished: bool,
     /// Wrapped writer.
     /// Option is used to allow us to implement `Drop` and `finish()` at the same time for the
     /// writer structs.
@@ -84,6 +106,9 @@ pub struct DeflateState<W: Write> {
     /// what data has been output in case not all data is output when writing to the wrapped
     /// writer.
     pub output_buf_pos: usize,
+    /// Whether the output buffer has been sized, either from the input size hint or to the
+    /// default size when compression started.
+    pub output_buf_reserved: bool,
     pub flush_mode: Flush,
     /// Whether we need to flush everything before continuing.
     /// Currently only used after having output a sync flush.
@@ -94,27 +119,263 @@ pub struct DeflateState<W: Write> {
     /// Number of bytes written as calculated by sum of block input lengths.
     /// Used to check that they are correct when `debug_assertions` are enabled.
     pub bytes_written_control: DebugCounter,
+    /// Position in the output stream of the first byte in the output buffer.
+    pub output_buf_start: u64,
+    /// Total number of input bytes covered by the blocks that have been output so far.
+    pub block_input_start: u64,
+    /// Information about each block output so far, if recording is enabled.
+    pub block_log: Option<Vec<BlockInfo>>,
+    /// The position in the output of the first bit of each block output so far.
+    #[cfg(feature = "block-offsets")]
+    pub block_offsets: Vec<u64>,
+    /// User-provided function overriding the choice of block type, if any.
+    pub block_type_selector: Option<Box<dyn BlockTypeSelector + Send>>,
+    /// User-provided function called for each block output, if any.
+    pub block_listener: Option<Box<dyn BlockListener + Send>>,
+    /// How many consecutive times to retry after being interrupted without any progress when
+    /// flushing, or `None` to retry indefinitely.
+    pub max_interrupted_retries: Option<u32>,
+    /// Whether to output the rest of the data when the encoder is dropped.
+    pub finish_on_drop: bool,
+    /// Pad the finished stream to a multiple of this many bytes, if larger than 1.
+    pub pad_to: u64,
+    /// A copy of the last error returned when writing to the wrapped writer, if any.
+    pub last_error: Option<io::Error>,
+    /// Statistics about the blocks output so far.
+    pub stats: CompressionStats,
+    /// The LZ77 tokens output so far, if they are being recorded for a trace.
+    #[cfg(feature = "lz-trace")]
+    pub lz_trace: Option<Vec<Token>>,
+    /// Decompresses the output to check that it matches the input.
+    #[cfg(feature = "verify")]
+    pub verifier: Verifier,
+    /// The pool to return the buffers to when dropped, if they were taken from one.
+    pub pool: Option<BufferPool>,
 }
 
-impl<W: Write> DeflateState<W> {
+impl<W: Sink> DeflateState<W> {
     pub fn new(compression_options: CompressionOptions, writer: W) -> DeflateState<W> {
+        let compression_options = compression_options.clamped();
+        let lz77_state = LZ77State::new(
+            compression_options.max_hash_checks,
+            compression_options.lazy_if_less_than,
+            compression_options.matching_type,
+            compression_options.window_bits,
+            compression_options.max_search_distance,
+            compression_options.hash_function,
+        );
+        let window_size = lz77::window_size(
+            compression_options.window_bits,
+            compression_options.output_version,
+        );
+        DeflateState::from_parts(
+            compression_options,
+            writer,
+            InputBuffer::with_window_size(window_size),
+            lz77_state,
+            DynamicWriter::new(),
+            // Allocated once compression starts, so it can be sized by the input size hint.
+            Vec::new(),
+        )
+    }
+
+    /// Create a new state using buffers from `pool` if there are any available, returning them
+    /// to the pool when dropped.
+    pub fn with_pool(
+        compression_options: CompressionOptions,
+        writer: W,
+        pool: &BufferPool,
+    ) -> DeflateState<W> {
+        let compression_options = compression_options.clamped();
+        let buffers = pool.take();
+        let lz77_state = LZ77State::with_hash_table(
+            ChainedHashTable::with_tables(compression_options.hash_function, buffers.tables),
+            compression_options.max_hash_checks,
+            compression_options.lazy_if_less_than,
+            compression_options.matching_type,
+            compression_options.window_bits,
+            compression_options.max_search_distance,
+        );
+        let mut output = buffers.output;
+        output.clear();
+        let mut state = DeflateState::from_parts(
+            compression_options,
+            writer,
+            InputBuffer::with_buffer(buffers.input),
+            lz77_state,
+            DynamicWriter::with_buffer(buffers.lz77),
+            output,
+        );
+        state.pool = Some(pool.clone());
+        state
+    }
+
+    fn from_parts(
+        compression_options: CompressionOptions,
+        writer: W,
+        mut input_buffer: InputBuffer,
+        mut lz77_state: LZ77State,
+        mut lz77_writer: DynamicWriter,
+        output_buf: Vec<u8>,
+    ) -> DeflateState<W> {
+        let window_size = lz77::window_size(
+            compression_options.window_bits,
+            compression_options.output_version,
+        );
+        input_buffer.set_window_size(window_size);
+        lz77_state.set_window_size(window_size);
+        lz77_writer.set_max_length(usize::from(compression_options.max_block_symbols));
+        lz77_state.set_adaptive_search(
+            compression_options.adaptive_hash_checks
+                && compression_options.output_version != OutputVersion::V1,
+        );
+        lz77_state.set_fast_runs(compression_options.output_version != OutputVersion::V1);
+        lz77_state.set_fast_path(compression_options.output_version != OutputVersion::V1);
+        lz77_state.set_rle_patterns(compression_options.output_version != OutputVersion::V1);
+        lz77_state.set_tuned(compression_options.output_version != OutputVersion::V1);
+        if compression_options.special == SpecialOptions::ForceStored {
+            // Each block has to fit in the window for its input to still be in the input buffer
+            // when it's output.
+            lz77_state.set_literals_only();
+            lz77_writer.set_max_length(cmp::min(
+                usize::from(compression_options.max_block_symbols),
+                window_size,
+            ));
+        }
         DeflateState {
-            input_buffer: InputBuffer::empty(),
-            lz77_state: LZ77State::new(
-                compression_options.max_hash_checks,
-                cmp::min(compression_options.lazy_if_less_than, MAX_HASH_CHECKS),
-                compression_options.matching_type,
-            ),
-            encoder_state: EncoderState::new(Vec::with_capacity(1024 * 32)),
-            lz77_writer: DynamicWriter::new(),
+            input_buffer,
+            lz77_state,
+            encoder_state: EncoderState::new(output_buf),
+            lz77_writer,
             length_buffers: LengthBuffers::new(),
             compression_options,
             bytes_written: 0,
+            bytes_written_at_flush: 0,
+            synced: false,
+            finished: false,
             inner: Some(writer),
             output_buf_pos: 0,
+            output_buf_reserved: false,
             flush_mode: Flush::None,
             needs_flush: false,
             bytes_written_control: DebugCounter::default(),
+            output_buf_start: 0,
+            block_input_start: 0,
+            block_log: None,
+            #[cfg(feature = "block-offsets")]
+            block_offsets: Vec::new(),
+            block_type_selector: None,
+            block_listener: None,
+            max_interrupted_retries: Some(DEFAULT_MAX_INTERRUPTED_RETRIES),
+            finish_on_drop: true,
+            pad_to: 0,
+            last_error: None,
+            stats: CompressionStats::default(),
+            #[cfg(feature = "lz-trace")]
+            lz_trace: None,
+            #[cfg(feature = "verify")]
+            verifier: Verifier::new(),
+            pool: None,
+        }
+    }
+
+    /// Create a new state, returning an error instead of adjusting the options if they are
+    /// invalid.
+    pub fn try_new(
+        compression_options: CompressionOptions,
+        writer: W,
+    ) -> Result<DeflateState<W>, Error> {
+        compression_options.validate()?;
+        Ok(DeflateState::new(compression_options, writer))
+    }
+
+    /// Whether the output already ends with a sync marker, with no input written since, making
+    /// another sync flush redundant.
+    pub fn is_synced(&self) -> bool {
+        self.synced && self.bytes_written == self.bytes_written_at_flush
+    }
+
+    /// Forget the input compressed so far, so the data compressed after this doesn't refer back
+    /// to it.
+    ///
+    /// This should only be called at a block boundary, after all the input has been compressed.
+    pub fn reset_window(&mut self) {
+        self.input_buffer.clear();
+        self.lz77_state.reset();
+    }
+
+    /// Add `data`, which has been output without going through the compressor, to the window so
+    /// the data compressed afterwards can refer back to it.
+    ///
+    /// The current block has to be ended first.
+    pub fn add_to_window(&mut self, data: &[u8]) {
+        lz77::lz77_add_without_matching(
+            data,
+            &mut self.lz77_state,
+            &mut self.input_buffer,
+            &mut self.lz77_writer,
+        );
+    }
+
+    /// Use `dictionary` as a preset dictionary, which the data compressed afterwards can refer
+    /// back to. Only the last 32 KiB of the dictionary can be referred back to.
+    ///
+    /// The encoder has to be new or just reset.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        debug_assert!(self.bytes_written == 0);
+        self.lz77_state
+            .set_dictionary(dictionary, &mut self.input_buffer);
+        // The verifier doesn't know about the dictionary, so it can't decompress the output.
+        #[cfg(feature = "verify")]
+        self.verifier.disable();
+    }
+
+    /// Continue a stream ended by `finish_deferred`, so the output follows on from the output
+    /// before it.
+    ///
+    /// The encoder has to be new or just reset. The window starts out empty, so data written
+    /// after resuming can't refer back to data from before.
+    pub fn resume(&mut self, end: &DeferredEnd) {
+        debug_assert!(self.bytes_written == 0 && self.output_bit_position() == 0);
+        self.encoder_state
+            .writer
+            .write_bits(u16::from(end.pending_bits()), end.pending_bit_count());
+        self.bytes_written = end.total_in();
+        self.bytes_written_at_flush = end.total_in();
+        self.block_input_start = end.total_in();
+        self.bytes_written_control.add(end.total_in());
+        self.output_buf_start = end.total_out();
+        #[cfg(feature = "verify")]
+        self.verifier.disable();
+    }
+
+    /// Size the output buffer to fit the output for an input of `size` bytes in total.
+    pub fn set_input_size_hint(&mut self, size: u64) {
+        // The output buffer is passed on to the wrapped writer once it grows larger than
+        // `LARGEST_OUTPUT_BUF_SIZE`, so there is no point in making it larger than that. For
+        // smaller input, leave space for incompressible data, which is output as stored blocks
+        // with 5 bytes of overhead each.
+        let remaining = size.saturating_sub(self.bytes_written);
+        let wanted = remaining
+            .saturating_add(remaining / 1024)
+            .saturating_add(64)
+            .min(LARGEST_OUTPUT_BUF_SIZE as u64) as usize;
+        self.output_buf_reserved = true;
+        let buf = self.output_buf();
+        if buf.is_empty() {
+            *buf = Vec::with_capacity(wanted);
+        } else {
+            buf.reserve(wanted.saturating_sub(buf.len()));
+        }
+    }
+
+    /// Make room for a full output buffer, unless the buffer has been sized already.
+    pub fn reserve_output_buf(&mut self) {
+        if !self.output_buf_reserved {
+            self.output_buf_reserved = true;
+            let buf = self.output_buf();
+            let additional = LARGEST_OUTPUT_BUF_SIZE.saturating_sub(buf.len());
+            buf.reserve(additional);
         }
     }
 
@@ -123,31 +384,262 @@ impl<W: Write> DeflateState<W> {
         self.encoder_state.inner_vec()
     }
 
+    /// Clear the output buffer after its contents have been passed on to the wrapped writer.
+    ///
+    /// With the `verify` feature, the contents are checked against the input first, and an error
+    /// is returned if they don't match.
+    pub fn clear_output_buf(&mut self) -> io::Result<()> {
+        #[cfg(feature = "verify")]
+        {
+            let res = self.verifier.add_output(&self.encoder_state.writer.w);
+            self.check_verified(res)?;
+        }
+        self.output_buf_start += self.encoder_state.writer.w.len() as u64;
+        self.output_buf_pos = 0;
+        self.output_buf().clear();
+        Ok(())
+    }
+
+    /// Turn a verification failure into an error, poisoning the stream.
+    #[cfg(feature = "verify")]
+    pub fn check_verified(&mut self, res: Result<(), &'static str>) -> io::Result<()> {
+        res.map_err(|msg| {
+            let err = io::Error::new(
+                io::ErrorKind::Other,
+                format!("Internal error, output verification failed: {}", msg),
+            );
+            self.record_error(&err);
+            err
+        })
+    }
+
+    /// Keep a copy of `err` as the last error that occured, unless it only signals that the
+    /// operation should be tried again.
+    pub fn record_error(&mut self, err: &io::Error) {
+        match err.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => (),
+            kind => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %err, "write error, encoder poisoned");
+                self.last_error = Some(io::Error::new(kind, err.to_string()))
+            }
+        }
+    }
+
+    /// Whether an earlier error when writing to the wrapped writer has left the output stream in
+    /// an unknown state, meaning no further data can be output.
+    pub fn is_poisoned(&self) -> bool {
+        self.last_error.is_some()
+    }
+
+    /// Create the error returned for operations attempted after the stream has been poisoned.
+    ///
+    /// This uses the same kind as the error that caused it.
+    pub fn poisoned_error(&self) -> io::Error {
+        let kind = self
+            .last_error
+            .as_ref()
+            .map_or(io::ErrorKind::Other, io::Error::kind);
+        io::Error::new(kind, "Stream poisoned by an earlier write error.")
+    }
+
+    /// The number of bytes that have been passed on to the wrapped writer so far.
+    pub fn bytes_flushed(&self) -> u64 {
+        self.output_buf_start + self.output_buf_pos as u64
+    }
+
+    /// Pass the output that is ready on to the wrapped writer, then replace it with `writer`,
+    /// returning the old one, while keeping the rest of the compression state.
+    ///
+    /// If writing the output fails with a temporary error, the output written so far is kept
+    /// track of, so the call can be repeated.
+    pub fn replace_writer(&mut self, writer: W) -> io::Result<W> {
+        self.write_pending_output()?;
+        self.inner
+            .replace(writer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Missing writer"))
+    }
+
+    /// Count `input`, which has been output without going through the compressor, as written.
+    pub fn count_input(&mut self, input: &[u8]) {
+        #[cfg(feature = "verify")]
+        self.verifier.add_input(input);
+        self.bytes_written += input.len() as u64;
+        self.bytes_written_control.add(input.len() as u64);
+    }
+
+    /// Pass all the output that is ready on to the wrapped writer, retrying if it's interrupted.
+    ///
+    /// Any other error poisons the stream, as it's not known how much of the output the writer
+    /// received.
+    pub fn write_pending_output(&mut self) -> io::Result<()> {
+        if self.is_poisoned() {
+            return Err(self.poisoned_error());
+        }
+        while self.output_buf_pos < self.encoder_state.writer.w.len() {
+            let pending = &self.encoder_state.writer.w[self.output_buf_pos..];
+            match self
+                .inner
+                .as_mut()
+                .expect("Missing writer!")
+                .write_some_output(pending)
+            {
+                Ok(0) => {
+                    let err = io::Error::new(io::ErrorKind::WriteZero, "failed to write output");
+                    self.record_error(&err);
+                    return Err(err);
+                }
+                Ok(n) => self.output_buf_pos += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.record_error(&e);
+                    return Err(e);
+                }
+            }
+        }
+        self.clear_output_buf()?;
+        self.needs_flush = false;
+        Ok(())
+    }
+
+    /// Create a report for the finished stream, given the sizes of the header written to the
+    /// output buffer and the trailer written directly to the wrapped writer.
+    pub fn report(&self, header_bytes: u64, trailer_bytes: u64) -> CompressionReport {
+        CompressionReport {
+            stats: self.stats,
+            header_bytes,
+            trailer_bytes,
+            input_bytes: self.stats.total_input_bytes(),
+            output_bytes: self.bytes_flushed() + trailer_bytes,
+        }
+    }
+
+    /// The current position in the output stream in bits, including the bits that are still
+    /// pending in the bit writer.
+    pub fn output_bit_position(&self) -> u64 {
+        let writer = &self.encoder_state.writer;
+        (self.output_buf_start + writer.w.len() as u64) * 8 + u64::from(writer.pending_bits())
+    }
+
+    /// Note that a block covering `input_bytes` bytes of input was output starting at
+    /// `start_bit`, with a header `header_bits` long, and record it if block recording is enabled.
+    pub fn record_block(
+        &mut self,
+        kind: BlockKind,
+        input_bytes: u64,
+        start_bit: u64,
+        header_bits: u64,
+        final_block: bool,
+    ) {
+        let input_start = self.block_input_start;
+        self.block_input_start += input_bytes;
+        let info = BlockInfo {
+            kind,
+            input_start,
+            input_len: input_bytes,
+            start_bit,
+            end_bit: self.output_bit_position(),
+            header_bits,
+            final_block,
+        };
+        self.stats.add_block(&info);
+        #[cfg(feature = "verify")]
+        self.verifier.block_start(start_bit);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            kind = ?info.kind,
+            input_start = info.input_start,
+            input_len = info.input_len,
+            start_bit = info.start_bit,
+            end_bit = info.end_bit,
+            header_bits = info.header_bits,
+            final_block = info.final_block,
+            "output block"
+        );
+        if let Some(ref mut listener) = self.block_listener {
+            listener.on_block(&info);
+        }
+        #[cfg(feature = "block-offsets")]
+        self.block_offsets.push(start_bit);
+        if let Some(ref mut log) = self.block_log {
+            log.push(info);
+        }
+    }
+
     /// Resets the status of the decoder, leaving the compression options intact
     ///
     /// If flushing the current writer succeeds, it is replaced with the provided one,
     /// buffers and status (except compression options) is reset and the old writer
-    /// is returned.
+    /// is returned. The buffers are reused, so this doesn't allocate.
     ///
     /// If flushing fails, the rest of the writer is not cleared.
+    ///
+    /// If the stream is poisoned, any remaining output is discarded rather than flushed.
     pub fn reset(&mut self, writer: W) -> io::Result<W> {
+        self.reset_keep_writer()?;
+        mem::replace(&mut self.inner, Some(writer))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Missing writer"))
+    }
+
+    /// Resets the status of the encoder like `reset`, but keeps writing to the current writer,
+    /// so a new stream can be output directly after the previous one.
+    pub fn reset_keep_writer(&mut self) -> io::Result<()> {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            bytes_in = self.bytes_written,
+            poisoned = self.is_poisoned(),
+            "resetting encoder"
+        );
         self.encoder_state.flush();
-        self.inner
-            .as_mut()
-            .expect("Missing writer!")
-            .write_all(self.encoder_state.inner_vec())?;
+        if !self.is_poisoned() {
+            self.inner
+                .as_mut()
+                .expect("Missing writer!")
+                .write_all_output(self.encoder_state.inner_vec())?;
+        }
+        // The buffers are cleared rather than replaced, so resetting doesn't allocate.
         self.encoder_state.inner_vec().clear();
-        self.input_buffer = InputBuffer::empty();
+        self.input_buffer.clear();
         self.lz77_writer.clear();
         self.lz77_state.reset();
         self.bytes_written = 0;
+        self.bytes_written_at_flush = 0;
+        self.synced = false;
+        self.finished = false;
         self.output_buf_pos = 0;
+        self.output_buf_start = 0;
+        self.block_input_start = 0;
+        if let Some(ref mut log) = self.block_log {
+            log.clear();
+        }
+        #[cfg(feature = "block-offsets")]
+        self.block_offsets.clear();
         self.flush_mode = Flush::None;
         self.needs_flush = false;
+        self.last_error = None;
+        self.stats = CompressionStats::default();
+        #[cfg(feature = "lz-trace")]
+        if let Some(ref mut trace) = self.lz_trace {
+            trace.clear();
+        }
+        #[cfg(feature = "verify")]
+        self.verifier.reset();
         if cfg!(debug_assertions) {
             self.bytes_written_control.reset();
         }
-        mem::replace(&mut self.inner, Some(writer))
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Missing writer"))
+        Ok(())
+    }
+}
+
+impl<W: Sink> Drop for DeflateState<W> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(Buffers {
+                input: self.input_buffer.take_buffer(),
+                tables: self.lz77_state.take_tables(),
+                lz77: self.lz77_writer.take_buffer(),
+                output: mem::replace(self.output_buf(), Vec::new()),
+            });
+        }
     }
 }
@@ -0,0 +1,198 @@
+//! Preset dictionaries for common kinds of short messages.
+//!
+//! DEFLATE compresses data by referring back to earlier data, so messages of a few hundred bytes,
+//! like HTTP headers or small JSON documents, compress poorly on their own: there is nothing to
+//! refer back to yet. A preset dictionary is data that both the compressor and the decompressor
+//! treat as if it came just before the message, which gives the compressor something to refer
+//! back to from the first byte. This is what SPDY did for HTTP headers.
+//!
+//! The dictionaries in this module are tuned for protocols in general rather than a particular
+//! one, so a dictionary built from real traffic will usually do better. Both sides have to use
+//! exactly the same dictionary, so the contents of these are never changed. Improved versions
+//! will be added under new names instead.
+//!
+//! With zlib streams, the decompressor finds out which dictionary is needed from the dictionary
+//! id in the header, which is the Adler-32 checksum of the dictionary, see
+//! [`dictionary_id`](fn.dictionary_id.html). Compress using a dictionary with
+//! [`try_deflate_bytes_zlib_dict`](../fn.try_deflate_bytes_zlib_dict.html) or
+//! [`ZlibEncoder::new_with_dict`](../write/struct.ZlibEncoder.html#method.new_with_dict).
+//!
+//! ```rust
+//! use deflate::dictionaries::HTTP_HEADERS;
+//! use deflate::inflate::inflate_bytes_zlib_dict;
+//! use deflate::{try_deflate_bytes_zlib_dict, Compression};
+//!
+//! let headers = b"content-type: application/json\r\ncache-control: no-cache\r\n";
+//! let compressed = try_deflate_bytes_zlib_dict(headers, HTTP_HEADERS, Compression::Default).unwrap();
+//! assert_eq!(inflate_bytes_zlib_dict(&compressed, HTTP_HEADERS).unwrap(), &headers[..]);
+//! ```
+//!
+//! Only available with the `dictionaries` feature.
+
+/// Common HTTP/1.1 request and response header lines, names and values.
+///
+/// The header names are in both the usual capitalization and the lowercase used by HTTP/2.
+pub const HTTP_HEADERS: &[u8] = b"\
+OPTIONSTRACECONNECTPATCHDELETEPUTHEAD\
+100 Continue101 Switching Protocols\
+201 Created202 Accepted204 No Content206 Partial Content\
+301 Moved Permanently302 Found303 See Other307 Temporary Redirect308 Permanent Redirect\
+400 Bad Request401 Unauthorized403 Forbidden404 Not Found405 Method Not Allowed\
+409 Conflict410 Gone412 Precondition Failed413 Payload Too Large415 Unsupported Media Type\
+429 Too Many Requests500 Internal Server Error501 Not Implemented502 Bad Gateway\
+503 Service Unavailable504 Gateway Timeout\
+proxy-authorizationproxy-authenticatewww-authenticatecontent-security-policy\
+strict-transport-securitymax-age=31536000; includeSubDomains; preload\
+x-content-type-options: nosniffx-frame-options: SAMEORIGINx-xss-protection: 1; mode=block\
+access-control-allow-origin: *access-control-allow-methodsaccess-control-allow-headers\
+access-control-allow-credentialsaccess-control-max-ageaccess-control-expose-headers\
+originrefererreferrer-policy: strict-origin-when-cross-originupgrade-insecure-requests: 1\
+sec-fetch-dest: documentsec-fetch-mode: navigatesec-fetch-site: same-originsec-fetch-user: ?1\
+sec-ch-uasec-ch-ua-mobile: ?0sec-ch-ua-platform: \"Windows\"\"macOS\"\"Linux\"\
+dnt: 1te: trailerstransfer-encoding: chunkedtrailerupgrade: websocket\
+sec-websocket-keysec-websocket-version: 13sec-websocket-acceptsec-websocket-extensions\
+permessage-deflate; client_max_window_bits\
+viaforwardedx-forwarded-forx-forwarded-protox-forwarded-hostx-real-ipx-request-id\
+content-dispositionattachment; filename=\"inline\
+content-languagecontent-locationcontent-rangebytes=accept-ranges: bytes\
+etag: W/\"if-match: if-none-match: if-modified-since: if-unmodified-since: if-range: \
+last-modified: expires: age: 0pragma: no-cachevary: Accept-Encoding, Origin\
+cache-control: public, max-age=no-store, no-cache, must-revalidateprivate, immutable\
+set-cookie: ; Path=/; Domain=; Expires=; Max-Age=; HttpOnly; Secure; SameSite=Lax\
+SameSite=Strictcookie: location: https://www.server: nginxapacheretry-after: \
+user-agent: Mozilla/5.0 (Windows NT 10.0; Win64; x64) (Macintosh; Intel Mac OS X 10_15_7) \
+(X11; Linux x86_64) (iPhone; CPU iPhone OS like Mac OS X) \
+AppleWebKit/537.36 (KHTML, like Gecko) Chrome/Safari/537.36 Firefox/Edg/\
+curl/accept: text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,\
+image/apng,*/*;q=0.8accept-language: en-US,en;q=0.9accept-charset: utf-8\
+accept-encoding: gzip, deflate, br, zstd\
+content-encoding: gzipdeflatecontent-length: \
+content-type: application/octet-streamapplication/x-www-form-urlencoded\
+multipart/form-data; boundary=application/javascripttext/css\
+text/plain; charset=utf-8text/html; charset=UTF-8application/json; charset=utf-8\
+image/pngimage/jpegimage/gifimage/svg+xml\
+authorization: Bearer Basic host: connection: keep-aliveclose\
+date: Mon, Tue, Wed, Thu, Fri, Sat, Sun, Jan Feb Mar Apr May Jun Jul Aug Sep Oct Nov Dec  GMT\r\n\
+Proxy-AuthorizationProxy-AuthenticateWWW-AuthenticateContent-Security-Policy\
+Strict-Transport-SecurityX-Content-Type-OptionsX-Frame-OptionsX-XSS-Protection\
+Access-Control-Allow-OriginAccess-Control-Allow-MethodsAccess-Control-Allow-Headers\
+Access-Control-Allow-CredentialsAccess-Control-Max-AgeAccess-Control-Expose-Headers\
+OriginRefererReferrer-PolicyUpgrade-Insecure-RequestsDNTTETransfer-EncodingTrailerUpgrade\
+Sec-WebSocket-KeySec-WebSocket-VersionSec-WebSocket-AcceptSec-WebSocket-Extensions\
+ViaForwardedX-Forwarded-ForX-Forwarded-ProtoX-Forwarded-HostX-Real-IPX-Request-ID\
+Content-DispositionContent-LanguageContent-LocationContent-RangeAccept-Ranges: bytes\r\n\
+ETag: If-Match: If-None-Match: If-Modified-Since: If-Unmodified-Since: If-Range: \
+Last-Modified: Expires: Age: Pragma: Vary: Cache-Control: \
+Set-Cookie: Cookie: Location: Server: Retry-After: \
+User-Agent: Accept: */*\r\nAccept-Language: Accept-Charset: Accept-Encoding: \
+Content-Encoding: Content-Length: Content-Type: \
+Authorization: Host: Connection: keep-alive\r\nDate: \
+GET / HTTP/1.1\r\nPOST HTTP/1.0\r\nHTTP/1.1 200 OK\r\n";
+
+/// Common JSON keys and values, as used in web APIs, configuration files and structured logs.
+pub const JSON: &[u8] = b"\
+{\"$schema\":\"http://json-schema.org/draft-07/schema#\",\"definitions\":{\"properties\":\
+{\"required\":[\"additionalProperties\":false,\"items\":{\"enum\":[\"$ref\":\"#/definitions/\
+\"oneOf\":[\"anyOf\":[\"allOf\":[\"format\":\"date-time\",\"pattern\":\"^\
+\"dependencies\":{\"devDependencies\":{\"scripts\":{\"main\":\"index.js\",\"license\":\"MIT\",\
+\"repository\":{\"homepage\":\"https://\",\"keywords\":[\"author\":\"\
+\"jsonrpc\":\"2.0\",\"method\":\"params\":{\"result\":{\"error\":{\"code\":-32600,\
+\"level\":\"info\",\"warn\",\"debug\",\"trace\",\"msg\":\"message\":\"timestamp\":\"\
+\"@timestamp\":\"logger\":\"thread\":\"service\":\"host\":\"hostname\":\"pid\":\
+\"request_id\":\"requestId\":\"trace_id\":\"traceId\":\"span_id\":\"spanId\":\"\
+\"duration_ms\":\"latency\":\"status_code\":\"statusCode\":\"user_agent\":\"userAgent\":\"\
+\"ip\":\"127.0.0.1\",\"path\":\"/api/v1/\",\"query\":\"headers\":{\"body\":\
+\"Content-Type\":\"application/json\",\"Accept\":\"Authorization\":\"Bearer \
+\"access_token\":\"refresh_token\":\"token_type\":\"Bearer\",\"expires_in\":3600,\
+\"scope\":\"client_id\":\"grant_type\":\"authorization_code\",\"redirect_uri\":\"\
+\"errors\":[{\"code\":\"detail\":\"details\":\"reason\":\"status\":\"error\",\"success\",\
+\"ok\":true,\"success\":true,\"success\":false,\"valid\":\"enabled\":true,\"enabled\":false,\
+\"visible\":\"active\":true,\"deleted\":false,\"hidden\":\"default\":\"optional\":\
+\"page\":1,\"per_page\":\"pageSize\":\"total\":\"total_count\":\"totalCount\":\"count\":\
+\"limit\":\"offset\":\"next\":\"previous\":\"prev\":null,\"cursor\":\"has_more\":false,\
+\"links\":{\"self\":{\"href\":\"https://\",\"rel\":\"related\":\"meta\":{\"included\":[\
+\"attributes\":{\"relationships\":{\"data\":[{\"data\":{\"items\":[{\"results\":[{\
+\"created_at\":\"updated_at\":\"deleted_at\":null,\"createdAt\":\"updatedAt\":\"\
+\"first_name\":\"last_name\":\"firstName\":\"lastName\":\"full_name\":\"username\":\"\
+\"email\":\"email_verified\":\"phone\":\"address\":{\"street\":\"city\":\"state\":\"\
+\"country\":\"US\",\"postal_code\":\"zip\":\"latitude\":\"longitude\":\"lat\":\"lng\":\
+\"location\":{\"coordinates\":[\"geometry\":{\"Point\",\"Feature\",\"FeatureCollection\",\
+\"currency\":\"USD\",\"EUR\",\"amount\":\"price\":\"quantity\":\"total\":\"tax\":\"discount\":\
+\"language\":\"en\",\"locale\":\"en-US\",\"timezone\":\"UTC\",\"country_code\":\"\
+\"image\":\"image_url\":\"avatar_url\":\"thumbnail\":\"width\":\"height\":\"size\":\"\
+\"url\":\"https://www.\",\"html_url\":\"uri\":\"href\":\".com/\",\".org/\",\".png\",\".jpg\",\
+\"title\":\"description\":\"summary\":\"content\":\"text\":\"label\":\"category\":\"\
+\"tags\":[\"version\":\"1.0.0\",\"role\":\"admin\",\"user\",\"owner\":{\"roles\":[\"\
+\"user\":{\"user_id\":\"userId\":\"account_id\":\"accountId\":\"parent_id\":\"\
+\"order\":\"sort\":\"filter\":\"fields\":\"options\":{\"settings\":{\"config\":{\"\
+\"event\":\"events\":[{\"action\":\"source\":\"target\":\"key\":\"value\":\"values\":[\
+\"start\":\"end\":\"start_date\":\"end_date\":\"date\":\"time\":\"expires_at\":\"\
+\"value\":null,\"type\":\"string\",\"type\":\"object\",\"type\":\"array\",\
+\"type\":\"integer\",\"type\":\"number\",\"type\":\"boolean\",\"type\":\"null\",\
+\"id\":\"name\":\"id\":1,\"name\":\"type\":\"status\":\"active\",\"data\":\
+{\"id\":\"\":\"\",\"\":true,\"\":false,\"\":null,\"\":[],\"\":{},\"\":0,\"\":[{\"\":{\"";
+
+/// The id of `dictionary` in the header of zlib streams compressed using it, which is the
+/// Adler-32 checksum of the dictionary.
+pub fn dictionary_id(dictionary: &[u8]) -> u32 {
+    crate::zlib::dictionary_id(dictionary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dictionaries_fit_window() {
+        for dictionary in &[HTTP_HEADERS, JSON] {
+            assert!(dictionary.len() <= 32 * 1024);
+        }
+    }
+
+    #[test]
+    fn dictionaries_unchanged() {
+        // The dictionaries must never change, as data compressed with them could no longer be
+        // decompressed.
+        assert_eq!(dictionary_id(HTTP_HEADERS), 0x03F7_79E6);
+        assert_eq!(dictionary_id(JSON), 0x001B_9C6B);
+    }
+
+    #[test]
+    fn dictionaries_compress_better() {
+        use crate::inflate::inflate_bytes_zlib_dict;
+        use crate::{try_deflate_bytes_zlib, try_deflate_bytes_zlib_dict, Compression};
+
+        let request = &b"GET /api/v1/users?page=2 HTTP/1.1\r\nHost: example.com\r\n\
+            User-Agent: Mozilla/5.0\r\nAccept: application/json\r\n\
+            Accept-Encoding: gzip, deflate\r\nConnection: keep-alive\r\n\r\n"[..];
+        let response = &b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+            Content-Length: 1234\r\nCache-Control: no-cache\r\nVary: Accept-Encoding\r\n\
+            Date: Fri, 16 Oct 2026 12:00:00 GMT\r\nServer: nginx\r\n\r\n"[..];
+        let document = &br#"{"data":[{"id":"42","type":"user","attributes":{"username":"alice",
+            "email":"alice@example.com","created_at":"2026-01-01T00:00:00Z","active":true}}],
+            "links":{"self":{"href":"https://example.com/api/v1/users"}},"meta":{"total":1}}"#[..];
+        let log_line = &br#"{"timestamp":"2026-10-16T12:00:00Z","level":"info","message":"done",
+            "request_id":"abc123","status_code":200,"duration_ms":12}"#[..];
+
+        for &(dictionary, input) in &[
+            (HTTP_HEADERS, request),
+            (HTTP_HEADERS, response),
+            (JSON, document),
+            (JSON, log_line),
+        ] {
+            let plain = try_deflate_bytes_zlib(input).unwrap();
+            let compressed =
+                try_deflate_bytes_zlib_dict(input, dictionary, Compression::Default).unwrap();
+            assert!(
+                compressed.len() * 4 < plain.len() * 3,
+                "with dictionary: {}, without: {}",
+                compressed.len(),
+                plain.len()
+            );
+            assert_eq!(
+                inflate_bytes_zlib_dict(&compressed, dictionary).unwrap(),
+                input
+            );
+        }
+    }
+}
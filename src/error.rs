@@ -0,0 +1,101 @@
+//! This module contains the error type used by the non-panicking APIs in this crate.
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::huffman_table::HuffmanError;
+
+/// Errors that can occur during compression.
+#[derive(Debug)]
+pub enum Error {
+    /// An error occured when writing to the underlying writer.
+    Io(io::Error),
+    /// The compression options provided were invalid, with a description of the problem.
+    InvalidOptions(&'static str),
+    /// Creating Huffman codes for a block failed.
+    Huffman(HuffmanError),
+    /// The provided output buffer was not large enough to hold the compressed data.
+    OutputTooSmall,
+    /// The LZ77 token at the contained index was out of range or referred back to before the
+    /// start of the data.
+    InvalidToken(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::InvalidOptions(desc) => write!(f, "invalid compression options: {}", desc),
+            Error::Huffman(ref err) => write!(f, "Huffman code error: {}", err),
+            Error::OutputTooSmall => f.write_str("output buffer too small"),
+            Error::InvalidToken(n) => write!(f, "invalid token at index {}", n),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            Error::Huffman(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        // Internal errors are passed through the io-based compression functions wrapped in an
+        // `io::Error`, so unwrap them again here.
+        let huffman_error = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<HuffmanError>())
+            .cloned();
+        match huffman_error {
+            Some(huffman_error) => Error::Huffman(huffman_error),
+            None => Error::Io(err),
+        }
+    }
+}
+
+impl From<HuffmanError> for Error {
+    fn from(err: HuffmanError) -> Error {
+        Error::Huffman(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::Huffman(err) => return err.into(),
+            Error::InvalidOptions(_) => io::ErrorKind::InvalidInput,
+            Error::OutputTooSmall => io::ErrorKind::WriteZero,
+            Error::InvalidToken(_) => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn io_roundtrip() {
+        let err: Error = io::Error::from(HuffmanError::CodeTooLong).into();
+        match err {
+            Error::Huffman(HuffmanError::CodeTooLong) => (),
+            _ => panic!("Expected a Huffman error, got {:?}", err),
+        }
+
+        let err: Error = io::Error::new(io::ErrorKind::BrokenPipe, "pipe").into();
+        match err {
+            Error::Io(ref e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+            _ => panic!("Expected an io error, got {:?}", err),
+        }
+
+        let io_err: io::Error = Error::OutputTooSmall.into();
+        assert_eq!(io_err.kind(), io::ErrorKind::WriteZero);
+    }
+}
@@ -0,0 +1,354 @@
+//! This module contains a fast estimator for the compressed size of data.
+//!
+//! Rather than doing full compression, the estimator finds matches using a simple hash table
+//! with a single entry per hash value, and computes the entropy of the resulting literals,
+//! lengths and distances. This is much cheaper than compressing the data, and useful for
+//! comparing how well different candidate inputs will compress.
+//!
+//! It also contains `max_compressed_len`, which gives an upper bound rather than an estimate.
+use std::cmp;
+
+use crate::compression_options::{CompressionOptions, SpecialOptions};
+use crate::huffman_table::{
+    get_distance_code, get_length_code, num_extra_bits_for_distance_code,
+    num_extra_bits_for_length_code, LENGTH_BITS_START, MAX_DISTANCE, MAX_MATCH, NUM_DISTANCE_CODES,
+    NUM_LITERALS_AND_LENGTHS,
+};
+use crate::matching::get_match_length;
+use crate::stored_block::MAX_STORED_BLOCK_LENGTH;
+use crate::writer::Format;
+
+const HASH_BITS: u32 = 15;
+const WINDOW_MASK: usize = MAX_DISTANCE as usize - 1;
+/// Maximum number of earlier positions probed for each match.
+const MAX_PROBES: usize = 8;
+/// Minimum length of matches found by the estimator.
+///
+/// Matches of length 3 rarely save much, so they are ignored to keep the probing cheap.
+const MIN_ESTIMATE_MATCH: usize = 4;
+/// Number of input bytes covered by a dynamic block on average, used to estimate the cost of
+/// the Huffman tables in the block headers.
+const BLOCK_INPUT_SIZE: usize = 64 * 1024;
+/// Rough size of the header of a dynamic block in bits.
+const DYNAMIC_HEADER_BITS: f64 = 600.0;
+
+/// Size of the header and length fields of a stored block in bytes, including the padding to a
+/// byte boundary.
+const STORED_HEADER_BYTES: usize = 5;
+/// Size of the header and end of block code of a block using the fixed Huffman codes in bits.
+const FIXED_OVERHEAD_BITS: usize = 3 + 7;
+/// Size of the zlib header and trailer, without a preset dictionary.
+const ZLIB_WRAPPER_BYTES: usize = 2 + 4;
+/// Size of a gzip header with no optional fields and the gzip trailer.
+#[cfg(feature = "gzip")]
+const GZIP_WRAPPER_BYTES: usize = 10 + 8;
+
+/// Size of each of the samples used by `estimate_compressibility`.
+const SAMPLE_SIZE: usize = 16 * 1024;
+/// Number of samples used by `estimate_compressibility` for large inputs.
+const NUM_SAMPLES: usize = 4;
+
+#[inline]
+fn hash4(data: &[u8], pos: usize) -> usize {
+    let v = u32::from(data[pos])
+        | u32::from(data[pos + 1]) << 8
+        | u32::from(data[pos + 2]) << 16
+        | u32::from(data[pos + 3]) << 24;
+    (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Sum of `-log2(p)` over all the symbols counted in `freqs`.
+fn entropy_bits(freqs: &[u32]) -> f64 {
+    let total: u64 = freqs.iter().map(|&f| u64::from(f)).sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let total = total as f64;
+    freqs
+        .iter()
+        .filter(|&&f| f > 0)
+        .map(|&f| {
+            let f = f64::from(f);
+            f * (total / f).log2()
+        })
+        .sum()
+}
+
+/// Estimate the size in bytes of the raw DEFLATE stream that compressing `data` would produce.
+///
+/// The estimate is usually within a few percent of the output of the default compression level,
+/// but may be further off for very short or unusual inputs.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::estimate_compressed_size;
+///
+/// let data = b"This is some test data, some test data, some test data.".repeat(20);
+/// assert!(estimate_compressed_size(&data) < data.len());
+/// ```
+pub fn estimate_compressed_size(data: &[u8]) -> usize {
+    if data.is_empty() {
+        // An empty fixed block.
+        return 2;
+    }
+
+    let mut ll_freqs = [0u32; NUM_LITERALS_AND_LENGTHS];
+    let mut d_freqs = [0u32; NUM_DISTANCE_CODES];
+    let mut extra_bits = 0u64;
+    // Heads of the hash chains, and links to the previous position with the same hash.
+    let mut head = vec![usize::max_value(); 1 << HASH_BITS];
+    let mut prev = vec![usize::max_value(); MAX_DISTANCE as usize];
+    let last_hashed = data.len().saturating_sub(MIN_ESTIMATE_MATCH - 1);
+    let insert = |pos: usize, head: &mut [usize], prev: &mut [usize]| {
+        let hash = hash4(data, pos);
+        prev[pos & WINDOW_MASK] = head[hash];
+        head[hash] = pos;
+    };
+
+    let mut pos = 0;
+    while pos < last_hashed {
+        let mut candidate = head[hash4(data, pos)];
+        insert(pos, &mut head, &mut prev);
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        for _ in 0..MAX_PROBES {
+            if candidate >= pos || pos - candidate > usize::from(MAX_DISTANCE) {
+                break;
+            }
+            let length = get_match_length(data, pos, candidate);
+            if length > best_length {
+                best_length = length;
+                best_distance = pos - candidate;
+            }
+            candidate = prev[candidate & WINDOW_MASK];
+        }
+
+        if best_length >= MIN_ESTIMATE_MATCH {
+            let length = best_length.min(usize::from(MAX_MATCH));
+            let distance = best_distance as u16;
+            let l_code = get_length_code(length as u16);
+            ll_freqs[l_code] += 1;
+            extra_bits += u64::from(num_extra_bits_for_length_code(
+                (l_code - usize::from(LENGTH_BITS_START)) as u8,
+            ));
+            let d_code = get_distance_code(distance);
+            d_freqs[usize::from(d_code)] += 1;
+            extra_bits += u64::from(num_extra_bits_for_distance_code(d_code));
+            for p in pos + 1..(pos + length).min(last_hashed) {
+                insert(p, &mut head, &mut prev);
+            }
+            pos += length;
+            continue;
+        }
+        ll_freqs[usize::from(data[pos])] += 1;
+        pos += 1;
+    }
+    for &b in &data[pos..] {
+        ll_freqs[usize::from(b)] += 1;
+    }
+    // One end of block marker per block.
+    let num_blocks = (data.len() + BLOCK_INPUT_SIZE - 1) / BLOCK_INPUT_SIZE;
+    ll_freqs[256] += num_blocks as u32;
+
+    let compressed_bits = entropy_bits(&ll_freqs)
+        + entropy_bits(&d_freqs)
+        + extra_bits as f64
+        + num_blocks as f64 * DYNAMIC_HEADER_BITS;
+    let compressed = (compressed_bits / 8.0).ceil() as usize;
+
+    // Data that doesn't compress will be output as stored blocks instead.
+    let stored =
+        data.len() + (data.len() + MAX_STORED_BLOCK_LENGTH - 1) / MAX_STORED_BLOCK_LENGTH * 5;
+    compressed.min(stored)
+}
+
+/// Estimate how well `data` will compress, as the ratio of the compressed size to the input size.
+///
+/// Larger inputs are sampled at a few evenly spaced places rather than scanned in full, so this
+/// is cheap enough to call on every chunk before deciding whether compressing it is worthwhile.
+/// A value close to or above `1.0` indicates that the data is unlikely to compress.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::estimate_compressibility;
+///
+/// let data = vec![0u8; 1_000_000];
+/// assert!(estimate_compressibility(&data) < 0.1);
+/// ```
+pub fn estimate_compressibility(data: &[u8]) -> f32 {
+    if data.is_empty() {
+        return 1.0;
+    }
+    if data.len() <= SAMPLE_SIZE * NUM_SAMPLES {
+        return estimate_compressed_size(data) as f32 / data.len() as f32;
+    }
+
+    let step = (data.len() - SAMPLE_SIZE) / (NUM_SAMPLES - 1);
+    let compressed: usize = (0..NUM_SAMPLES)
+        .map(|n| estimate_compressed_size(&data[n * step..n * step + SAMPLE_SIZE]))
+        .sum();
+    compressed as f32 / (SAMPLE_SIZE * NUM_SAMPLES) as f32
+}
+
+/// The largest size in bytes the output of compressing `input_len` bytes with `options` in
+/// `format` can have, analogous to `deflateBound` in zlib.
+///
+/// The encoder never outputs a block that is larger than the same data would be using the fixed
+/// Huffman codes, which take at most 9 bits per input byte, so the bound is a little over 9/8 of
+/// the input size. If the options force stored blocks, the bound is instead the input size plus
+/// the stored block headers, which add up to more with a smaller window. Blocks end after at most `max_block_symbols` symbols, so smaller
+/// values give a larger bound.
+///
+/// This holds for the one-shot functions like
+/// [`deflate_bytes_into`](fn.deflate_bytes_into.html), and for the encoders if they are not
+/// flushed before they are finished and have no block type selector. A zlib preset dictionary
+/// adds 4 bytes and the optional gzip header fields add their length to the wrapper.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{deflate_bytes_into, max_compressed_len, Compression, Format};
+///
+/// let data = b"This is some test data";
+/// let mut output = vec![0; max_compressed_len(data.len(), Compression::Default, Format::Raw)];
+/// let len = deflate_bytes_into(data, &mut output, Compression::Default).unwrap();
+/// # let _ = len;
+/// ```
+pub fn max_compressed_len<O: Into<CompressionOptions>>(
+    input_len: usize,
+    options: O,
+    format: Format,
+) -> usize {
+    let options = options.into().clamped();
+    // Every symbol covers at least one byte of input, and the stream may end with an extra,
+    // empty, block.
+    let block_symbols = usize::from(options.max_block_symbols);
+    let blocks = input_len.saturating_add(block_symbols - 1) / block_symbols + 1;
+    let deflate_len = if options.special == SpecialOptions::ForceStored {
+        // Blocks longer than a stored block can hold are split into several, and the blocks
+        // also end when the input fills the window, which may be smaller.
+        let window_size = 1 << options.window_bits;
+        let stored_blocks = blocks + input_len / cmp::min(MAX_STORED_BLOCK_LENGTH, window_size);
+        input_len.saturating_add(stored_blocks * STORED_HEADER_BYTES)
+    } else {
+        // One more bit per input byte than storing it, plus the block headers, rounded up to
+        // whole bytes.
+        let extra_bits = input_len.saturating_add(blocks * FIXED_OVERHEAD_BITS);
+        input_len.saturating_add(extra_bits.saturating_add(7) / 8)
+    };
+    let wrapper_len = match format {
+        Format::Raw => 0,
+        Format::Zlib => ZLIB_WRAPPER_BYTES,
+        #[cfg(feature = "gzip")]
+        Format::Gzip => GZIP_WRAPPER_BYTES,
+    };
+    deflate_len.saturating_add(wrapper_len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{get_test_data, noise};
+    use crate::try_deflate_bytes;
+
+    fn assert_close(data: &[u8], tolerance: f64) {
+        let estimate = estimate_compressed_size(data) as f64;
+        let actual = try_deflate_bytes(data).unwrap().len() as f64;
+        let error = (estimate - actual).abs() / actual;
+        assert!(
+            error <= tolerance,
+            "Estimate {} too far from actual size {}",
+            estimate,
+            actual
+        );
+    }
+
+    #[test]
+    fn estimate_text() {
+        assert_close(&get_test_data(), 0.08);
+    }
+
+    #[test]
+    fn estimate_random() {
+        let data = noise(100_000);
+        assert_close(&data, 0.05);
+    }
+
+    #[test]
+    fn estimate_repetitive() {
+        let data: Vec<u8> = (0..200_000u32).map(|n| (n % 251) as u8).collect();
+        assert!(estimate_compressed_size(&data) < 2000);
+        assert!(estimate_compressed_size(&[]) > 0);
+    }
+
+    #[test]
+    fn compressibility() {
+        let random = noise(500_000);
+        assert!(estimate_compressibility(&random) > 0.95);
+
+        let text = get_test_data();
+        let ratio = estimate_compressibility(&text);
+        assert!(ratio > 0.2 && ratio < 0.6, "Ratio: {}", ratio);
+
+        assert!(estimate_compressibility(&vec![7u8; 300_000]) < 0.05);
+        assert_eq!(estimate_compressibility(&[]), 1.0);
+    }
+
+    #[test]
+    fn compressed_len_bound() {
+        use crate::{try_deflate_bytes_conf, try_deflate_bytes_zlib_conf, OutputVersion};
+
+        // Bytes of 144 and up take 9 bits each as fixed literals.
+        let high: Vec<u8> = noise(20_000).iter().map(|&b| b | 0x80).collect();
+        let mut inputs = vec![
+            noise(100_000),
+            high,
+            get_test_data()[..40_000].to_vec(),
+            vec![0xff; 70_000],
+        ];
+        inputs.extend((0..10).map(noise));
+
+        let mut stored = CompressionOptions::from_level(0);
+        stored.output_version = OutputVersion::Latest;
+        let mut fixed = CompressionOptions::default();
+        fixed.special = SpecialOptions::ForceFixed;
+        let mut options = vec![
+            CompressionOptions::default(),
+            CompressionOptions::fast(),
+            CompressionOptions::rle(),
+            fixed,
+        ];
+        for &max_block_symbols in &[1, 7, 1000] {
+            options.extend([stored, fixed].iter().map(|&o| CompressionOptions {
+                max_block_symbols,
+                ..o
+            }));
+        }
+        // Stored blocks also end when the input fills the window.
+        options.extend((8..16).map(|window_bits| CompressionOptions {
+            window_bits,
+            ..stored
+        }));
+
+        for data in &inputs {
+            for &options in &options {
+                let bound = max_compressed_len(data.len(), options, Format::Raw);
+                let len = try_deflate_bytes_conf(&data[..], options).unwrap().len();
+                assert!(len <= bound, "{} > {} for {:?}", len, bound, options);
+                let bound = max_compressed_len(data.len(), options, Format::Zlib);
+                assert!(
+                    try_deflate_bytes_zlib_conf(&data[..], options)
+                        .unwrap()
+                        .len()
+                        <= bound
+                );
+            }
+        }
+        assert_eq!(
+            max_compressed_len(0, CompressionOptions::default(), Format::Raw),
+            try_deflate_bytes(&[]).unwrap().len()
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use crate::bitstream::LsbWriter;
+use crate::compress::BlockKind;
 use crate::deflate_state::LengthBuffers;
 use crate::huffman_table::{
     create_codes_in_place, num_extra_bits_for_distance_code, num_extra_bits_for_length_code,
-    HuffmanTable, FIXED_CODE_LENGTHS, LENGTH_BITS_START, MAX_CODE_LENGTH, NUM_DISTANCE_CODES,
-    NUM_LITERALS_AND_LENGTHS,
+    HuffmanError, HuffmanTable, FIXED_CODE_LENGTHS, LENGTH_BITS_START, MAX_CODE_LENGTH,
+    MIN_CODE_LENGTH_LIMIT, NUM_DISTANCE_CODES, NUM_LITERALS_AND_LENGTHS,
 };
 use crate::length_encode::{
     encode_lengths_m, huffman_lengths_from_frequency_m, EncodedLength, COPY_PREVIOUS,
@@ -148,6 +149,56 @@ pub enum BlockType {
     Dynamic(DynamicBlockHeader),
 }
 
+/// The generated dynamic block header along with the number of bits the block would take up using
+/// each of the block types.
+pub struct BlockLengths {
+    pub header: DynamicBlockHeader,
+    pub stored_length: u64,
+    pub static_length: u64,
+    pub dynamic_length: u64,
+}
+
+impl BlockLengths {
+    /// Get the block type that gives the shortest representation of the block.
+    pub fn shortest(&self) -> BlockKind {
+        let used_length = cmp::min(
+            cmp::min(self.dynamic_length, self.static_length),
+            self.stored_length,
+        );
+
+        // Check if the block is actually compressed. If using a dynamic block
+        // increases the length of the block (for instance if the input data is mostly random or
+        // already compressed), we want to output a stored(uncompressed) block instead to avoid
+        // wasting space.
+        if used_length == self.static_length {
+            BlockKind::Fixed
+        } else if used_length == self.stored_length {
+            BlockKind::Stored
+        } else {
+            BlockKind::Dynamic
+        }
+    }
+
+    /// Get the block type that gives the shortest representation of the block, excluding stored
+    /// blocks.
+    pub fn shortest_compressed(&self) -> BlockKind {
+        if self.static_length <= self.dynamic_length {
+            BlockKind::Fixed
+        } else {
+            BlockKind::Dynamic
+        }
+    }
+
+    /// Convert into the `BlockType` describing how to output a block of the type `kind`.
+    pub fn into_block_type(self, kind: BlockKind) -> BlockType {
+        match kind {
+            BlockKind::Stored => BlockType::Stored,
+            BlockKind::Fixed => BlockType::Fixed,
+            BlockKind::Dynamic => BlockType::Dynamic(self.header),
+        }
+    }
+}
+
 /// A struct containing the different data needed to write the header for a dynamic block.
 ///
 /// The code lengths are stored directly in the `HuffmanTable` struct.
@@ -161,23 +212,31 @@ pub struct DynamicBlockHeader {
 }
 
 /// Generate the lengths of the Huffman codes we will be using, using the
-/// frequency of the different symbols/lengths/distances, and determine what block type will give
-/// the shortest representation.
+/// frequency of the different symbols/lengths/distances, and calculate how long the block would be
+/// using the different block types.
+///
+/// The literal/length and distance codes are limited to `max_code_length` bits, which has to be
+/// between `MIN_CODE_LENGTH_LIMIT` and `MAX_CODE_LENGTH`.
+///
+/// Returns `None` if the block is so short that there is no point in doing the calculations, in
+/// which case a fixed block should be used.
 /// TODO: This needs a test
+#[allow(clippy::too_many_arguments)]
 pub fn gen_huffman_lengths(
     l_freqs: &[FrequencyType],
     d_freqs: &[FrequencyType],
     num_input_bytes: u64,
     pending_bits: u8,
+    max_code_length: usize,
     l_lengths: &mut [u8; 288],
     d_lengths: &mut [u8; 32],
     length_buffers: &mut LengthBuffers,
-) -> BlockType {
+) -> Option<BlockLengths> {
     // Avoid corner cases and issues if this is called for an empty block.
     // For blocks this short, a fixed block will be the shortest.
     // TODO: Find the minimum value it's worth doing calculations for.
     if num_input_bytes <= 4 {
-        return BlockType::Fixed;
+        return None;
     };
 
     let l_freqs = remove_trailing_zeroes(l_freqs, MIN_NUM_LITERALS_AND_LENGTHS);
@@ -191,15 +250,16 @@ pub fn gen_huffman_lengths(
     // There is however a minimum number of values we have to keep
     // according to the deflate spec.
     // TODO: We could probably compute some of this in parallel.
+    debug_assert!(max_code_length >= MIN_CODE_LENGTH_LIMIT && max_code_length <= MAX_CODE_LENGTH);
     huffman_lengths_from_frequency_m(
         l_freqs,
-        MAX_CODE_LENGTH,
+        max_code_length,
         &mut length_buffers.leaf_buf,
         l_lengths,
     );
     huffman_lengths_from_frequency_m(
         d_freqs,
-        MAX_CODE_LENGTH,
+        max_code_length,
         &mut length_buffers.leaf_buf,
         d_lengths,
     );
@@ -268,22 +328,15 @@ pub fn gen_huffman_lengths(
     // Calculate how many bits it will take to store the data in uncompressed (stored) block(s).
     let stored_length = stored_length(num_input_bytes) + stored_padding(pending_bits % 8);
 
-    let used_length = cmp::min(cmp::min(dynamic_length, static_length), stored_length);
-
-    // Check if the block is actually compressed. If using a dynamic block
-    // increases the length of the block (for instance if the input data is mostly random or
-    // already compressed), we want to output a stored(uncompressed) block instead to avoid wasting
-    // space.
-    if used_length == static_length {
-        BlockType::Fixed
-    } else if used_length == stored_length {
-        BlockType::Stored
-    } else {
-        BlockType::Dynamic(DynamicBlockHeader {
+    Some(BlockLengths {
+        header: DynamicBlockHeader {
             huffman_table_lengths,
             used_hclens,
-        })
-    }
+        },
+        stored_length,
+        static_length,
+        dynamic_length,
+    })
 }
 
 /// Write the specified Huffman lengths to the bit writer
@@ -292,7 +345,7 @@ pub fn write_huffman_lengths(
     huffman_table: &HuffmanTable,
     encoded_lengths: &[EncodedLength],
     writer: &mut LsbWriter,
-) {
+) -> Result<(), HuffmanError> {
     // Ignore trailing zero lengths as allowed by the deflate spec.
     let (literal_len_lengths, distance_lengths) = huffman_table.get_lengths();
     let literal_len_lengths =
@@ -329,7 +382,7 @@ pub fn write_huffman_lengths(
 
     // Generate codes for the main huffman table using the lengths we just wrote
     let mut codes = [0u16; NUM_HUFFMAN_LENGTHS];
-    create_codes_in_place(&mut codes[..], huffman_table_lengths);
+    create_codes_in_place(&mut codes[..], huffman_table_lengths)?;
 
     // Write the actual huffman lengths
     for v in encoded_lengths {
@@ -366,11 +419,12 @@ pub fn write_huffman_lengths(
             }
         }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use super::stored_padding;
+    use super::*;
     #[test]
     fn padding() {
         assert_eq!(stored_padding(0), 5);
@@ -382,4 +436,40 @@ mod test {
         assert_eq!(stored_padding(6), 7);
         assert_eq!(stored_padding(7), 6);
     }
+
+    #[test]
+    fn limited_code_lengths() {
+        // Frequencies growing like the Fibonacci sequence give the longest possible codes.
+        let mut l_freqs = [0u16; 286];
+        let (mut a, mut b) = (1u16, 1u16);
+        for f in l_freqs.iter_mut().take(24) {
+            *f = a;
+            let next = a.saturating_add(b);
+            a = b;
+            b = next;
+        }
+        l_freqs[256] = 1;
+        let mut d_freqs = [0u16; 30];
+        d_freqs[..20].copy_from_slice(&l_freqs[..20]);
+
+        for max_code_length in MIN_CODE_LENGTH_LIMIT..=MAX_CODE_LENGTH {
+            let mut l_lengths = [0; 288];
+            let mut d_lengths = [0; 32];
+            gen_huffman_lengths(
+                &l_freqs,
+                &d_freqs,
+                1000,
+                0,
+                max_code_length,
+                &mut l_lengths,
+                &mut d_lengths,
+                &mut LengthBuffers::new(),
+            )
+            .unwrap();
+            let longest = l_lengths.iter().chain(&d_lengths).max().copied();
+            assert_eq!(longest, Some(max_code_length as u8));
+            // Panics if the lengths don't form valid codes.
+            HuffmanTable::from_length_tables(&l_lengths, &d_lengths);
+        }
+    }
 }
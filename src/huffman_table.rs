@@ -1,6 +1,7 @@
 use crate::bit_reverse::reverse_bits;
 use crate::lzvalue::StoredLength;
-use std::fmt;
+use std::error::Error;
+use std::{fmt, io};
 
 /// The number of length codes in the Huffman table
 pub const NUM_LENGTH_CODES: usize = 29;
@@ -15,6 +16,9 @@ pub const NUM_LITERALS_AND_LENGTHS: usize = 286;
 
 /// The maximum length of a Huffman code
 pub const MAX_CODE_LENGTH: usize = 15;
+/// The lowest the length of the literal/length and distance codes can be limited to, as 9 bits
+/// is needed to give each of the 286 literal/length symbols a code.
+pub const MIN_CODE_LENGTH_LIMIT: usize = 9;
 
 /// The minimum and maximum lengths for a match according to the DEFLATE specification
 pub const MIN_MATCH: u16 = 3;
@@ -62,7 +66,7 @@ const LENGTH_CODE: [u8; 256] = [
 ];
 
 /// Base values to calculate the value of the bits in length codes
-const BASE_LENGTH: [u8; NUM_LENGTH_CODES] = [
+pub const BASE_LENGTH: [u8; NUM_LENGTH_CODES] = [
     0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 20, 24, 28, 32, 40, 48, 56, 64, 80, 96, 112, 128,
     160, 192, 224, 255,
 ]; // 258 - MIN_MATCh
@@ -105,7 +109,7 @@ const DISTANCE_EXTRA_BITS: [u8; NUM_DISTANCE_CODES] = [
     13,
 ];
 
-const DISTANCE_BASE: [u16; NUM_DISTANCE_CODES] = [
+pub const DISTANCE_BASE: [u16; NUM_DISTANCE_CODES] = [
     0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536,
     2048, 3072, 4096, 6144, 8192, 12288, 16384, 24576,
 ];
@@ -193,6 +197,36 @@ fn get_distance_code_and_extra_bits(distance: u16) -> ExtraBits {
     }
 }
 
+/// Errors that can occur when creating Huffman codes from a table of code lengths.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HuffmanError {
+    /// The table of code lengths was empty.
+    EmptyLengthTable,
+    /// One of the code lengths was longer than the maximum of 15 bits.
+    CodeTooLong,
+    /// There are more codes of some lengths than there are bit patterns available to represent
+    /// them.
+    Oversubscribed,
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            HuffmanError::EmptyLengthTable => "empty Huffman code length table",
+            HuffmanError::CodeTooLong => "Huffman code length exceeds 15 bits",
+            HuffmanError::Oversubscribed => "oversubscribed Huffman code lengths",
+        })
+    }
+}
+
+impl Error for HuffmanError {}
+
+impl From<HuffmanError> for io::Error {
+    fn from(err: HuffmanError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct HuffmanCode {
     pub code: u16,
@@ -229,30 +263,46 @@ pub struct LengthAndDistanceBits {
 /// Returns a tuple containing the longest length value in the table, it's position,
 /// and fills in lengths in the `len_counts` slice.
 /// Returns an error if `table` is empty, or if any of the lengths exceed 15.
-fn build_length_count_table(table: &[u8], len_counts: &mut [u16; 16]) -> (usize, usize) {
-    // TODO: Validate the length table properly in debug mode.
-    let max_length = (*table.iter().max().expect("BUG! Empty lengths!")).into();
-
-    assert!(max_length <= MAX_CODE_LENGTH);
+/// Returns an error if `table` is empty, if any of the lengths exceed 15, or if there are more
+/// codes of some length than there are bit patterns available.
+fn build_length_count_table(
+    table: &[u8],
+    len_counts: &mut [u16; 16],
+) -> Result<(usize, usize), HuffmanError> {
+    let max_length = usize::from(*table.iter().max().ok_or(HuffmanError::EmptyLengthTable)?);
+
+    if max_length > MAX_CODE_LENGTH {
+        return Err(HuffmanError::CodeTooLong);
+    }
 
     let mut max_length_pos = 0;
 
     for (n, &length) in table.iter().enumerate() {
-        // TODO: Make sure we don't have more of one length than we can make
-        // codes for
         if length > 0 {
             len_counts[usize::from(length)] += 1;
             max_length_pos = n;
         }
     }
-    (max_length, max_length_pos)
+
+    // Each code of length n uses up 2^(15 - n) of the 2^15 available bit patterns.
+    let used: u32 = (1..=MAX_CODE_LENGTH)
+        .map(|n| u32::from(len_counts[n]) << (MAX_CODE_LENGTH - n))
+        .sum();
+    if used > 1 << MAX_CODE_LENGTH {
+        return Err(HuffmanError::Oversubscribed);
+    }
+
+    Ok((max_length, max_length_pos))
 }
 
 /// Generates a vector of Huffman codes given a table of bit lengths
-/// Returns an error if any of the lengths are > 15
-pub fn create_codes_in_place(code_table: &mut [u16], length_table: &[u8]) {
+/// Returns an error if the lengths don't describe a valid set of codes.
+pub fn create_codes_in_place(
+    code_table: &mut [u16],
+    length_table: &[u8],
+) -> Result<(), HuffmanError> {
     let mut len_counts = [0; 16];
-    let (max_length, max_length_pos) = build_length_count_table(length_table, &mut len_counts);
+    let (max_length, max_length_pos) = build_length_count_table(length_table, &mut len_counts)?;
     let lengths = len_counts;
 
     let mut code = 0u16;
@@ -275,6 +325,7 @@ pub fn create_codes_in_place(code_table: &mut [u16], length_table: &[u8]) {
             next_code[length] = next_code[length].wrapping_add(1);
         }
     }
+    Ok(())
 }
 
 /// A structure containing the tables of Huffman codes for lengths, literals and distances
@@ -309,7 +360,7 @@ impl HuffmanTable {
             distance_code_lengths: *distances,
         };
 
-        table.update_from_lengths();
+        table.update_from_lengths().expect("Invalid length tables!");
         table
     }
 
@@ -328,18 +379,20 @@ impl HuffmanTable {
     }
 
     /// Update the Huffman codes using the existing length values in the Huffman table.
-    pub fn update_from_lengths(&mut self) {
-        create_codes_in_place(self.codes.as_mut(), &self.code_lengths[..]);
+    pub fn update_from_lengths(&mut self) -> Result<(), HuffmanError> {
+        create_codes_in_place(self.codes.as_mut(), &self.code_lengths[..])?;
         create_codes_in_place(
             self.distance_codes.as_mut(),
             &self.distance_code_lengths[..],
-        );
+        )
     }
 
     pub fn set_to_fixed(&mut self) {
         self.code_lengths = FIXED_CODE_LENGTHS;
         self.distance_code_lengths = FIXED_CODE_LENGTHS_DISTANCE;
-        self.update_from_lengths();
+        // The fixed tables are always valid.
+        self.update_from_lengths()
+            .expect("BUG! Invalid fixed Huffman tables!");
     }
 
     /// Create a `HuffmanTable` using the fixed tables specified in the DEFLATE format specification.
@@ -428,6 +481,7 @@ mod test {
     use super::*;
     use super::{
         build_length_count_table, get_distance_code_and_extra_bits, get_length_code_and_extra_bits,
+        HuffmanError,
     };
 
     use crate::lzvalue::StoredLength;
@@ -486,21 +540,36 @@ mod test {
 
     #[test]
     fn test_length_table_fixed() {
-        let _ = build_length_count_table(&FIXED_CODE_LENGTHS, &mut [0; 16]);
+        assert!(build_length_count_table(&FIXED_CODE_LENGTHS, &mut [0; 16]).is_ok());
     }
 
     #[test]
-    #[should_panic]
     fn test_length_table_max_length() {
         let table = [16u8; 288];
-        build_length_count_table(&table, &mut [0; 16]);
+        assert_eq!(
+            build_length_count_table(&table, &mut [0; 16]),
+            Err(HuffmanError::CodeTooLong)
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_empty_table() {
         let table = [];
-        build_length_count_table(&table, &mut [0; 16]);
+        assert_eq!(
+            build_length_count_table(&table, &mut [0; 16]),
+            Err(HuffmanError::EmptyLengthTable)
+        );
+    }
+
+    #[test]
+    fn test_oversubscribed_table() {
+        let table = [1u8, 1, 1];
+        assert_eq!(
+            build_length_count_table(&table, &mut [0; 16]),
+            Err(HuffmanError::Oversubscribed)
+        );
+        // Incomplete codes are allowed.
+        assert!(build_length_count_table(&[1, 0, 2], &mut [0; 16]).is_ok());
     }
 
     #[test]
@@ -0,0 +1,121 @@
+//! This module contains a zlib compressor tailored for the IDAT chunks of PNG images.
+use std::io::{self, Write};
+use std::mem;
+
+use crate::compression_options::CompressionOptions;
+use crate::writer::ZlibEncoder;
+
+/// A zlib compressor producing output split into pieces suitable for PNG IDAT chunks.
+///
+/// Filtered scanlines can be added in batches of any size with
+/// [`write_scanlines`](#method.write_scanlines), and full chunks can be taken out with
+/// [`next_chunk`](#method.next_chunk) as soon as enough compressed data is available, so the
+/// whole compressed image never has to be kept in memory. The compressor state, including the
+/// window and hash tables, is kept across chunk boundaries, so the chunks form one continuous
+/// zlib stream.
+///
+/// After an image is done, [`finish_image`](#method.finish_image) returns the remaining chunks
+/// and resets the compressor so it can be reused for the next image without reallocating.
+///
+/// # Examples
+///
+/// ```rust
+/// use deflate::{Compression, IdatEncoder};
+///
+/// // Two rows of 3 RGB pixels, each prefixed by the filter type.
+/// let rows = [0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0];
+/// let mut encoder = IdatEncoder::new(Compression::Default, 8192);
+/// let mut chunks = Vec::new();
+/// for row in rows.chunks(10) {
+///     encoder.write_scanlines(row).unwrap();
+///     while let Some(chunk) = encoder.next_chunk() {
+///         chunks.push(chunk);
+///     }
+/// }
+/// chunks.extend(encoder.finish_image().unwrap());
+/// # assert_eq!(chunks.len(), 1);
+/// ```
+pub struct IdatEncoder {
+    encoder: ZlibEncoder<Vec<u8>>,
+    chunk_size: usize,
+}
+
+impl IdatEncoder {
+    /// Create a new compressor using the provided compression options, outputting chunks of
+    /// `chunk_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn new<O: Into<CompressionOptions>>(options: O, chunk_size: usize) -> IdatEncoder {
+        assert!(chunk_size > 0, "chunk_size has to be larger than 0");
+        IdatEncoder {
+            encoder: ZlibEncoder::new(Vec::with_capacity(chunk_size), options),
+            chunk_size,
+        }
+    }
+
+    /// Add a batch of filtered scanlines (each including the filter type byte) to the image
+    /// data.
+    ///
+    /// The batch does not need to end at a scanline boundary.
+    pub fn write_scanlines(&mut self, data: &[u8]) -> io::Result<()> {
+        self.encoder.write_all(data)
+    }
+
+    /// Take out the next chunk of `chunk_size` bytes of compressed data if enough is available.
+    pub fn next_chunk(&mut self) -> Option<Vec<u8>> {
+        let chunk_size = self.chunk_size;
+        let buf = self.encoder.writer_mut();
+        if buf.len() >= chunk_size {
+            let rest = buf.split_off(chunk_size);
+            Some(mem::replace(buf, rest))
+        } else {
+            None
+        }
+    }
+
+    /// Finish compressing the current image, returning all the remaining output as chunks of at
+    /// most `chunk_size` bytes, and reset the compressor so it can be used for a new image.
+    ///
+    /// The last chunk may be shorter than `chunk_size`.
+    pub fn finish_image(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let rest = self.encoder.reset(Vec::with_capacity(self.chunk_size))?;
+        Ok(rest.chunks(self.chunk_size).map(<[u8]>::to_vec).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_zlib, get_test_data};
+
+    #[test]
+    fn idat_chunks() {
+        // Use enough data that blocks are output before the end regardless of where exactly the
+        // encoder ends them.
+        let data = get_test_data().repeat(2);
+        let chunk_size = 1000;
+        let mut encoder = IdatEncoder::new(CompressionOptions::default(), chunk_size);
+
+        // Compress two images with the same encoder to check that it's reset properly.
+        for _ in 0..2 {
+            let mut chunks = Vec::new();
+            let mut chunks_while_writing = 0;
+            for rows in data.chunks(4097) {
+                encoder.write_scanlines(rows).unwrap();
+                while let Some(chunk) = encoder.next_chunk() {
+                    chunks.push(chunk);
+                    chunks_while_writing += 1;
+                }
+            }
+            assert!(chunks_while_writing > 0);
+            chunks.extend(encoder.finish_image().unwrap());
+
+            let (last, full) = chunks.split_last().unwrap();
+            assert!(full.iter().all(|c| c.len() == chunk_size));
+            assert!(!last.is_empty() && last.len() <= chunk_size);
+            assert!(decompress_zlib(&chunks.concat()) == data);
+        }
+    }
+}
@@ -0,0 +1,906 @@
+//! Decompression of DEFLATE, zlib and gzip streams.
+//!
+//! This is a straightforward decoder meant for round-tripping data compressed with this crate,
+//! e.g in tests, without depending on another crate. It handles any valid stream, but isn't as
+//! fast as the decoders in crates like `miniz_oxide`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use deflate::try_deflate_bytes_zlib;
+//! use deflate::inflate::inflate_bytes_zlib;
+//!
+//! let data = b"Some data to compress and decompress again";
+//! let compressed = try_deflate_bytes_zlib(data).unwrap();
+//! assert_eq!(inflate_bytes_zlib(&compressed).unwrap(), &data[..]);
+//! ```
+use std::error::Error;
+use std::{fmt, io};
+
+use crate::chained_hash_table::WINDOW_SIZE;
+#[cfg(feature = "gzip")]
+use crate::checksum::Crc32Checksum;
+use crate::checksum::{Adler32Checksum, RollingChecksum};
+use crate::huffman_table::{
+    create_codes_in_place, num_extra_bits_for_distance_code, num_extra_bits_for_length_code,
+    HuffmanError, BASE_LENGTH, DISTANCE_BASE, END_OF_BLOCK_POSITION, FIXED_CODE_LENGTHS,
+    FIXED_CODE_LENGTHS_DISTANCE, LENGTH_BITS_START, MAX_CODE_LENGTH, MAX_DISTANCE, MIN_MATCH,
+    NUM_DISTANCE_CODES, NUM_LENGTH_CODES,
+};
+use crate::zlib::dictionary_id;
+
+/// Number of bits looked up at once when decoding Huffman codes. Longer codes are decoded one
+/// bit at a time.
+const FAST_BITS: u8 = 10;
+/// Maximum number of code length codes in a dynamic block header.
+const NUM_CODE_LENGTH_CODES: usize = 19;
+/// The order the code lengths of the code length codes are stored in.
+const CODE_LENGTH_ORDER: [usize; NUM_CODE_LENGTH_CODES] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Errors that can occur when decompressing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum InflateError {
+    /// The zlib or gzip header is invalid, or uses a compression method other than DEFLATE.
+    InvalidHeader,
+    /// The zlib stream was compressed using a preset dictionary, but none was provided.
+    PresetDictionary,
+    /// The zlib stream was compressed using a different preset dictionary than the one provided.
+    WrongDictionary,
+    /// A block header has the reserved block type 3.
+    InvalidBlockType,
+    /// The length of a stored block doesn't match the one's complement stored after it.
+    InvalidStoredLength,
+    /// The code lengths in a dynamic block header don't describe valid Huffman codes.
+    InvalidCodeLengths(HuffmanError),
+    /// The code lengths in a dynamic block header leave some codes unused, which is only
+    /// allowed when there is a single code of length 1, or no codes at all for distances.
+    IncompleteCodeLengths,
+    /// The code lengths in a dynamic block header don't include the end of block symbol.
+    MissingEndOfBlock,
+    /// The data contains a Huffman code that isn't assigned to any symbol, or one of the unused
+    /// length or distance symbols.
+    InvalidSymbol,
+    /// A match refers back to before the start of the data.
+    InvalidDistance,
+    /// The checksum in the zlib or gzip trailer, or the size in the gzip trailer, doesn't match
+    /// the decompressed data.
+    ChecksumMismatch,
+    /// The input ended before the end of the stream.
+    UnexpectedEnd,
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InflateError::InvalidHeader => f.write_str("invalid header"),
+            InflateError::PresetDictionary => f.write_str("missing preset dictionary"),
+            InflateError::WrongDictionary => f.write_str("wrong preset dictionary"),
+            InflateError::InvalidBlockType => f.write_str("invalid block type"),
+            InflateError::InvalidStoredLength => f.write_str("invalid stored block length"),
+            InflateError::InvalidCodeLengths(err) => write!(f, "invalid code lengths: {}", err),
+            InflateError::IncompleteCodeLengths => f.write_str("incomplete code lengths"),
+            InflateError::MissingEndOfBlock => f.write_str("missing end of block code"),
+            InflateError::InvalidSymbol => f.write_str("invalid symbol"),
+            InflateError::InvalidDistance => f.write_str("distance too far back"),
+            InflateError::ChecksumMismatch => f.write_str("checksum mismatch"),
+            InflateError::UnexpectedEnd => f.write_str("unexpected end of input"),
+        }
+    }
+}
+
+impl Error for InflateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            InflateError::InvalidCodeLengths(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<InflateError> for io::Error {
+    fn from(err: InflateError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Whether decompression is done.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum InflateStatus {
+    /// The end of the stream hasn't been reached yet, so more input is needed.
+    NeedsInput,
+    /// The whole stream, including the zlib trailer if any, has been decompressed.
+    Done,
+}
+
+/// Reads bits from a slice, LSB first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn available(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    /// Get the next `n` bits without consuming them, padded with zeroes past the end of the
+    /// data. `n` can be at most 24.
+    fn peek(&self, n: u8) -> u32 {
+        let start = self.pos / 8;
+        let mut value = 0;
+        for (i, &byte) in self.data[start..].iter().take(4).enumerate() {
+            value |= u32::from(byte) << (i * 8);
+        }
+        (value >> (self.pos % 8)) & ((1 << n) - 1)
+    }
+
+    /// Take the next `n` bits, or return `None` if there aren't enough left.
+    fn take(&mut self, n: u8) -> Option<u32> {
+        if self.available() < usize::from(n) {
+            return None;
+        }
+        let value = self.peek(n);
+        self.pos += usize::from(n);
+        Some(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.pos = (self.pos + 7) & !7;
+    }
+}
+
+/// A table for decoding one Huffman code.
+struct Decoder {
+    /// The symbol and length of the codes up to `FAST_BITS` long, indexed by the next
+    /// `FAST_BITS` bits of input, stored as `length << 9 | symbol`. Entries for longer codes are
+    /// 0.
+    fast: Vec<u16>,
+    /// The number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// The symbols sorted by code.
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    /// Create a decoder for the codes with the given lengths, where `allow_incomplete` allows
+    /// a single code of length 1 or no codes, as needed for literal/length and distance codes.
+    fn new(lengths: &[u8], allow_incomplete: bool) -> Result<Decoder, InflateError> {
+        let mut codes = vec![0; lengths.len()];
+        create_codes_in_place(&mut codes, lengths).map_err(InflateError::InvalidCodeLengths)?;
+
+        let mut fast = vec![0; 1 << FAST_BITS];
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+        for (symbol, (&code, &length)) in codes.iter().zip(lengths).enumerate() {
+            counts[usize::from(length)] += 1;
+            if length > 0 && length <= FAST_BITS {
+                // The codes are already reversed to the order they appear in the input.
+                let entry = u16::from(length) << 9 | symbol as u16;
+                for i in (usize::from(code)..fast.len()).step_by(1 << length) {
+                    fast[i] = entry;
+                }
+            }
+        }
+        counts[0] = 0;
+
+        // Each code of length n uses up 2^(15 - n) of the 2^15 available bit patterns.
+        let used: u32 = (1..=MAX_CODE_LENGTH)
+            .map(|n| u32::from(counts[n]) << (MAX_CODE_LENGTH - n))
+            .sum();
+        let max_length = lengths.iter().cloned().max().unwrap_or(0);
+        if used != 1 << MAX_CODE_LENGTH && !(allow_incomplete && max_length <= 1) {
+            return Err(InflateError::IncompleteCodeLengths);
+        }
+
+        let mut offsets = [0; MAX_CODE_LENGTH + 2];
+        for length in 1..=MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_CODE_LENGTH + 1])];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                let offset = &mut offsets[usize::from(length)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+
+        Ok(Decoder {
+            fast,
+            counts,
+            symbols,
+        })
+    }
+
+    /// Decode the next symbol, or return `None` if the input ends before the end of the code.
+    fn decode(&self, reader: &mut BitReader) -> Result<Option<u16>, InflateError> {
+        let entry = self.fast[reader.peek(FAST_BITS) as usize];
+        if entry != 0 {
+            let length = (entry >> 9) as u8;
+            return Ok(reader.take(length).map(|_| entry & 0x1FF));
+        }
+
+        // Walk the canonical code one bit at a time, as in zlib's `puff`.
+        let bits = reader.peek(MAX_CODE_LENGTH as u8);
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_CODE_LENGTH {
+            if reader.available() < length {
+                return Ok(None);
+            }
+            code |= ((bits >> (length - 1)) & 1) as i32;
+            let count = i32::from(self.counts[length]);
+            if code - first < count {
+                reader.pos += length;
+                return Ok(Some(self.symbols[(index + code - first) as usize]));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::InvalidSymbol)
+    }
+}
+
+/// Return `None` from the surrounding function if there wasn't enough input.
+macro_rules! need {
+    ($e:expr) => {
+        match $e {
+            Some(value) => value,
+            None => return Ok(None),
+        }
+    };
+}
+
+/// Read the code lengths in the header of a dynamic block and create the decoders.
+fn read_dynamic_header(reader: &mut BitReader) -> Result<Option<(Decoder, Decoder)>, InflateError> {
+    let num_literals = need!(reader.take(5)) as usize + 257;
+    let num_distances = need!(reader.take(5)) as usize + 1;
+    let num_code_lengths = need!(reader.take(4)) as usize + 4;
+
+    let mut code_length_lengths = [0; NUM_CODE_LENGTH_CODES];
+    for &n in &CODE_LENGTH_ORDER[..num_code_lengths] {
+        code_length_lengths[n] = need!(reader.take(3)) as u8;
+    }
+    let code_lengths = Decoder::new(&code_length_lengths, false)?;
+
+    let mut lengths = vec![0u8; num_literals + num_distances];
+    let mut n = 0;
+    while n < lengths.len() {
+        let symbol = need!(code_lengths.decode(reader)?);
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..n].last().ok_or(InflateError::InvalidSymbol)?;
+                (previous, need!(reader.take(2)) as usize + 3)
+            }
+            17 => (0, need!(reader.take(3)) as usize + 3),
+            _ => (0, need!(reader.take(7)) as usize + 11),
+        };
+        if n + repeat > lengths.len() {
+            return Err(InflateError::InvalidSymbol);
+        }
+        lengths[n..n + repeat].iter_mut().for_each(|l| *l = value);
+        n += repeat;
+    }
+
+    let (literals, distances) = lengths.split_at(num_literals);
+    if literals[END_OF_BLOCK_POSITION] == 0 {
+        return Err(InflateError::MissingEndOfBlock);
+    }
+    Ok(Some((
+        Decoder::new(literals, true)?,
+        Decoder::new(distances, true)?,
+    )))
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Stage {
+    ZlibHeader,
+    BlockHeader,
+    Stored { remaining: u16 },
+    Huffman,
+    ZlibTrailer,
+    Done,
+}
+
+/// A streaming decoder for raw DEFLATE or zlib streams.
+///
+/// # Examples
+///
+/// ```rust
+/// use deflate::try_deflate_bytes;
+/// use deflate::inflate::{InflateState, InflateStatus};
+///
+/// let data = b"Some data to compress and decompress again";
+/// let compressed = try_deflate_bytes(data).unwrap();
+/// let mut state = InflateState::new();
+/// let mut output = Vec::new();
+/// for chunk in compressed.chunks(4) {
+///     state.update(chunk, &mut output).unwrap();
+/// }
+/// assert!(state.is_done());
+/// assert_eq!(output, &data[..]);
+/// ```
+pub struct InflateState {
+    stage: Stage,
+    zlib: bool,
+    /// Whether the current block is the last one.
+    final_block: bool,
+    decoders: Option<(Decoder, Decoder)>,
+    /// Input that couldn't be decoded yet, and the bit position in it to continue from.
+    pending: Vec<u8>,
+    pending_bit: usize,
+    /// The output, of which at least the last `MAX_DISTANCE` bytes are kept for matches to
+    /// refer back to.
+    window: Vec<u8>,
+    /// Number of bytes at the start of `window` that have been added to the checksum.
+    checksummed: usize,
+    checksum: Adler32Checksum,
+    /// The part of the preset dictionary that can be referred back to, and its id, if any.
+    dictionary: Option<(Vec<u8>, u32)>,
+    /// The longest match distance seen so far, for tests checking the window limits.
+    #[cfg(test)]
+    max_distance: usize,
+}
+
+impl Default for InflateState {
+    fn default() -> InflateState {
+        InflateState::new()
+    }
+}
+
+impl InflateState {
+    /// Create a decoder for raw DEFLATE data.
+    pub fn new() -> InflateState {
+        InflateState {
+            stage: Stage::BlockHeader,
+            zlib: false,
+            final_block: false,
+            decoders: None,
+            pending: Vec::new(),
+            pending_bit: 0,
+            window: Vec::new(),
+            checksummed: 0,
+            checksum: Adler32Checksum::new(),
+            dictionary: None,
+            #[cfg(test)]
+            max_distance: 0,
+        }
+    }
+
+    /// Create a decoder for zlib streams, which checks the header and the checksum in the
+    /// trailer.
+    pub fn new_zlib() -> InflateState {
+        InflateState {
+            stage: Stage::ZlibHeader,
+            zlib: true,
+            ..InflateState::new()
+        }
+    }
+
+    /// Create a decoder for zlib streams that can decompress streams compressed using the
+    /// preset dictionary `dictionary`.
+    ///
+    /// Streams compressed without a preset dictionary are decompressed as usual, while streams
+    /// compressed using another one fail with `InflateError::WrongDictionary`.
+    pub fn new_zlib_with_dict(dictionary: &[u8]) -> InflateState {
+        let start = dictionary.len().saturating_sub(WINDOW_SIZE);
+        let mut state = InflateState::new_zlib();
+        state.dictionary = Some((dictionary[start..].to_vec(), dictionary_id(dictionary)));
+        state.fill_dictionary();
+        state
+    }
+
+    /// Put the dictionary, if any, at the start of the window so matches can refer back to it.
+    ///
+    /// It's neither output nor included in the checksum.
+    fn fill_dictionary(&mut self) {
+        if let Some((ref dictionary, _)) = self.dictionary {
+            self.window.extend_from_slice(dictionary);
+            self.checksummed = self.window.len();
+        }
+    }
+
+    /// Reset the decoder to decompress a new stream of the same format, using the same preset
+    /// dictionary if any.
+    pub fn reset(&mut self) {
+        let dictionary = self.dictionary.take();
+        *self = if self.zlib {
+            InflateState::new_zlib()
+        } else {
+            InflateState::new()
+        };
+        self.dictionary = dictionary;
+        self.fill_dictionary();
+    }
+
+    /// Whether the end of the stream has been reached.
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Decompress `input`, appending the decompressed data to `output`.
+    ///
+    /// Returns the number of bytes of input that were part of the stream, which is all of it
+    /// unless the stream ended partway through, and whether the end of the stream has been
+    /// reached. Input that ends partway through a symbol is kept until more input is provided.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is not a valid stream. The decoder can't continue after
+    /// that until it is reset.
+    pub fn update(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(usize, InflateStatus), InflateError> {
+        if self.is_done() {
+            return Ok((0, InflateStatus::Done));
+        }
+        let previous = self.pending.len();
+        self.pending.extend_from_slice(input);
+        let start = self.window.len();
+
+        let mut data = std::mem::replace(&mut self.pending, Vec::new());
+        let mut reader = BitReader {
+            data: &data,
+            pos: self.pending_bit,
+        };
+        let res = self.run(&mut reader);
+        let pos = reader.pos;
+        output.extend_from_slice(&self.window[start..]);
+        res?;
+
+        let consumed = if self.is_done() {
+            ((pos + 7) / 8).saturating_sub(previous)
+        } else {
+            input.len()
+        };
+        data.drain(..pos / 8);
+        self.pending = data;
+        self.pending_bit = pos % 8;
+
+        // Keep the window from growing without bounds.
+        let max_distance = usize::from(MAX_DISTANCE);
+        if self.window.len() > max_distance * 4 {
+            self.update_checksum();
+            let excess = self.window.len() - max_distance;
+            self.window.drain(..excess);
+            self.checksummed -= excess;
+        }
+
+        let status = if self.is_done() {
+            InflateStatus::Done
+        } else {
+            InflateStatus::NeedsInput
+        };
+        Ok((consumed, status))
+    }
+
+    fn update_checksum(&mut self) {
+        if self.zlib {
+            self.checksum
+                .update_from_slice(&self.window[self.checksummed..]);
+        }
+        self.checksummed = self.window.len();
+    }
+
+    /// Decode as much of the input as possible, stopping at the end of the stream or when more
+    /// input is needed. The reader is left at the start of the first step that couldn't be
+    /// completed.
+    fn run(&mut self, reader: &mut BitReader) -> Result<(), InflateError> {
+        loop {
+            let start = reader.pos;
+            // Decoding the symbols of a block stops at the start of the incomplete symbol, the
+            // other steps have to be started over when more input is available.
+            let restart = self.stage != Stage::Huffman;
+            let progress = match self.stage {
+                Stage::Done => return Ok(()),
+                Stage::ZlibHeader => self.read_zlib_header(reader)?,
+                Stage::BlockHeader => self.read_block_header(reader)?,
+                Stage::Stored { remaining } => self.copy_stored(reader, remaining),
+                Stage::Huffman => self.decode_symbols(reader)?,
+                Stage::ZlibTrailer => self.read_zlib_trailer(reader)?,
+            };
+            if progress.is_none() {
+                if restart {
+                    reader.pos = start;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_zlib_header(&mut self, reader: &mut BitReader) -> Result<Option<()>, InflateError> {
+        let cmf = need!(reader.take(8));
+        let flg = need!(reader.take(8));
+        if cmf & 0x0F != 8 || cmf >> 4 > 7 || (cmf << 8 | flg) % 31 != 0 {
+            return Err(InflateError::InvalidHeader);
+        }
+        if flg & 0x20 != 0 {
+            let mut id = 0;
+            for _ in 0..4 {
+                id = id << 8 | need!(reader.take(8));
+            }
+            match self.dictionary {
+                None => return Err(InflateError::PresetDictionary),
+                Some((_, expected)) if expected != id => return Err(InflateError::WrongDictionary),
+                Some(_) => (),
+            }
+        }
+        self.stage = Stage::BlockHeader;
+        Ok(Some(()))
+    }
+
+    fn read_block_header(&mut self, reader: &mut BitReader) -> Result<Option<()>, InflateError> {
+        let header = need!(reader.take(3));
+        self.final_block = header & 1 != 0;
+        match header >> 1 {
+            0 => {
+                reader.align_to_byte();
+                let length = need!(reader.take(16)) as u16;
+                let complement = need!(reader.take(16)) as u16;
+                if length != !complement {
+                    return Err(InflateError::InvalidStoredLength);
+                }
+                self.stage = Stage::Stored { remaining: length };
+            }
+            1 => {
+                self.decoders = Some((
+                    Decoder::new(&FIXED_CODE_LENGTHS, false)?,
+                    Decoder::new(&FIXED_CODE_LENGTHS_DISTANCE, false)?,
+                ));
+                self.stage = Stage::Huffman;
+            }
+            2 => {
+                self.decoders = Some(need!(read_dynamic_header(reader)?));
+                self.stage = Stage::Huffman;
+            }
+            _ => return Err(InflateError::InvalidBlockType),
+        }
+        Ok(Some(()))
+    }
+
+    /// Copy the contents of a stored block, which can be done partially.
+    fn copy_stored(&mut self, reader: &mut BitReader, remaining: u16) -> Option<()> {
+        if remaining == 0 {
+            self.end_block();
+            return Some(());
+        }
+        let start = reader.pos / 8;
+        let n = (reader.data.len() - start).min(usize::from(remaining));
+        if n == 0 {
+            return None;
+        }
+        self.window
+            .extend_from_slice(&reader.data[start..start + n]);
+        reader.pos += n * 8;
+        self.stage = Stage::Stored {
+            remaining: remaining - n as u16,
+        };
+        Some(())
+    }
+
+    fn end_block(&mut self) {
+        self.stage = match (self.final_block, self.zlib) {
+            (false, _) => Stage::BlockHeader,
+            (true, true) => Stage::ZlibTrailer,
+            (true, false) => Stage::Done,
+        };
+    }
+
+    /// Decode the symbols of a Huffman block until the end of the block or the input, which
+    /// returns `None` with the reader left at the start of the incomplete symbol.
+    fn decode_symbols(&mut self, reader: &mut BitReader) -> Result<Option<()>, InflateError> {
+        let (literals, distances) = self.decoders.as_ref().expect("Missing Huffman decoders!");
+        let window = &mut self.window;
+        loop {
+            let start = reader.pos;
+            let symbol = match literals.decode(reader)? {
+                Some(symbol) => usize::from(symbol),
+                None => return Ok(None),
+            };
+            if symbol < END_OF_BLOCK_POSITION {
+                window.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK_POSITION {
+                break;
+            }
+
+            let code = symbol - usize::from(LENGTH_BITS_START);
+            if code >= NUM_LENGTH_CODES {
+                return Err(InflateError::InvalidSymbol);
+            }
+            let (length, distance) = match read_match(reader, distances, code)? {
+                Some(length_distance) => length_distance,
+                None => {
+                    reader.pos = start;
+                    return Ok(None);
+                }
+            };
+            if distance > window.len() {
+                return Err(InflateError::InvalidDistance);
+            }
+            #[cfg(test)]
+            {
+                if distance > self.max_distance {
+                    self.max_distance = distance;
+                }
+            }
+            let from = window.len() - distance;
+            // The match may overlap the data it produces, so it is copied bytewise.
+            for i in from..from + length {
+                let byte = window[i];
+                window.push(byte);
+            }
+        }
+        self.end_block();
+        Ok(Some(()))
+    }
+
+    fn read_zlib_trailer(&mut self, reader: &mut BitReader) -> Result<Option<()>, InflateError> {
+        reader.align_to_byte();
+        let mut expected = 0;
+        for _ in 0..4 {
+            expected = expected << 8 | need!(reader.take(8));
+        }
+        self.update_checksum();
+        if self.checksum.current_hash() != expected {
+            return Err(InflateError::ChecksumMismatch);
+        }
+        self.stage = Stage::Done;
+        Ok(Some(()))
+    }
+}
+
+/// Read the rest of a match after the length symbol `code` positions after the first one.
+fn read_match(
+    reader: &mut BitReader,
+    distances: &Decoder,
+    code: usize,
+) -> Result<Option<(usize, usize)>, InflateError> {
+    let extra = need!(reader.take(num_extra_bits_for_length_code(code as u8)));
+    let length = usize::from(BASE_LENGTH[code]) + usize::from(MIN_MATCH) + extra as usize;
+
+    let distance_code = need!(distances.decode(reader)?);
+    if usize::from(distance_code) >= NUM_DISTANCE_CODES {
+        return Err(InflateError::InvalidSymbol);
+    }
+    let extra_bits = num_extra_bits_for_distance_code(distance_code as u8);
+    let extra = need!(reader.take(extra_bits));
+    let distance = usize::from(DISTANCE_BASE[usize::from(distance_code)]) + 1 + extra as usize;
+    Ok(Some((length, distance)))
+}
+
+fn inflate(input: &[u8], mut state: InflateState) -> Result<Vec<u8>, InflateError> {
+    let mut reader = BitReader {
+        data: input,
+        pos: 0,
+    };
+    // Skip the preset dictionary, if any.
+    let start = state.window.len();
+    state.run(&mut reader)?;
+    if state.is_done() {
+        state.window.drain(..start);
+        Ok(state.window)
+    } else {
+        Err(InflateError::UnexpectedEnd)
+    }
+}
+
+/// Decompress a raw DEFLATE stream.
+///
+/// Any data after the end of the stream is ignored.
+pub fn inflate_bytes(input: &[u8]) -> Result<Vec<u8>, InflateError> {
+    inflate(input, InflateState::new())
+}
+
+/// Decompress a zlib stream, checking the checksum in the trailer.
+///
+/// Any data after the end of the stream is ignored.
+pub fn inflate_bytes_zlib(input: &[u8]) -> Result<Vec<u8>, InflateError> {
+    inflate(input, InflateState::new_zlib())
+}
+
+/// Decompress a zlib stream that may have been compressed using the preset dictionary
+/// `dictionary`, checking the checksum in the trailer.
+///
+/// Any data after the end of the stream is ignored.
+pub fn inflate_bytes_zlib_dict(input: &[u8], dictionary: &[u8]) -> Result<Vec<u8>, InflateError> {
+    inflate(input, InflateState::new_zlib_with_dict(dictionary))
+}
+
+/// Decompress a gzip stream, checking the CRC-32 checksum and the size in the trailer.
+///
+/// Only the first member of the stream is decompressed, any data after it is ignored. Only
+/// available with the `gzip` feature.
+#[cfg(feature = "gzip")]
+pub fn inflate_bytes_gzip(input: &[u8]) -> Result<Vec<u8>, InflateError> {
+    let mut data = input;
+    gzip_header::read_gz_header(&mut data).map_err(|_| InflateError::InvalidHeader)?;
+    let mut reader = BitReader { data, pos: 0 };
+    let mut state = InflateState::new();
+    state.run(&mut reader)?;
+    if !state.is_done() {
+        return Err(InflateError::UnexpectedEnd);
+    }
+    let start = (reader.pos + 7) / 8;
+    let trailer = data
+        .get(start..start + 8)
+        .ok_or(InflateError::UnexpectedEnd)?;
+    let mut checksum = Crc32Checksum::new();
+    checksum.update_from_slice(&state.window);
+    if trailer[..4] != checksum.current_hash().to_le_bytes()
+        || trailer[4..] != checksum.amount().to_le_bytes()
+    {
+        return Err(InflateError::ChecksumMismatch);
+    }
+    Ok(state.window)
+}
+
+/// Decompress a zlib stream and return the longest distance any of its matches refer back.
+#[cfg(test)]
+pub fn max_match_distance_zlib(input: &[u8]) -> usize {
+    let mut state = InflateState::new_zlib();
+    let mut output = Vec::new();
+    state
+        .update(input, &mut output)
+        .expect("Decompression failed!");
+    assert!(state.is_done());
+    state.max_distance
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::get_test_data;
+    use crate::write::DeflateEncoder;
+    use crate::{try_deflate_bytes, try_deflate_bytes_conf, try_deflate_bytes_zlib, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn inflate_roundtrip() {
+        let data = get_test_data();
+        for &level in &[Compression::Fast, Compression::Default, Compression::Best] {
+            let compressed = try_deflate_bytes_conf(&data, level).unwrap();
+            assert!(inflate_bytes(&compressed).unwrap() == data);
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(&data[..1000]).unwrap();
+        encoder.write_stored_chunk(&data[1000..]).unwrap();
+        assert!(inflate_bytes(&encoder.finish().unwrap()).unwrap() == data);
+        // Small inputs are compressed using fixed Huffman codes.
+        for input in &[
+            &b""[..],
+            b"a",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            b"abcabcd",
+        ] {
+            assert_eq!(
+                inflate_bytes(&try_deflate_bytes(input).unwrap()).unwrap(),
+                *input
+            );
+            assert_eq!(
+                inflate_bytes_zlib(&try_deflate_bytes_zlib(input).unwrap()).unwrap(),
+                *input
+            );
+        }
+
+        let compressed = try_deflate_bytes_zlib(&data).unwrap();
+        assert!(inflate_bytes_zlib(&compressed).unwrap() == data);
+        assert!(
+            inflate_bytes(&compressed[2..]).unwrap()
+                == miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap()
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn inflate_gzip() {
+        let data = get_test_data();
+        let mut compressed = crate::try_deflate_bytes_gzip(&data).unwrap();
+        assert!(inflate_bytes_gzip(&compressed).unwrap() == data);
+
+        let len = compressed.len();
+        assert_eq!(
+            inflate_bytes_gzip(&compressed[..len - 1]),
+            Err(InflateError::UnexpectedEnd)
+        );
+        compressed[len - 1] ^= 1;
+        assert_eq!(
+            inflate_bytes_gzip(&compressed),
+            Err(InflateError::ChecksumMismatch)
+        );
+        assert_eq!(
+            inflate_bytes_gzip(&compressed[10..]),
+            Err(InflateError::InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn inflate_streaming() {
+        let data = get_test_data();
+        let compressed = try_deflate_bytes_zlib(&data).unwrap();
+        for &chunk_size in &[1, 7, 1000, compressed.len()] {
+            let mut state = InflateState::new_zlib();
+            let mut output = Vec::new();
+            let mut consumed = 0;
+            for chunk in compressed.chunks(chunk_size) {
+                assert!(!state.is_done());
+                consumed += state.update(chunk, &mut output).unwrap().0;
+            }
+            assert!(state.is_done());
+            assert_eq!(consumed, compressed.len());
+            assert!(output == data);
+        }
+
+        // Data after the end of the stream is not consumed.
+        let mut state = InflateState::new();
+        let mut input = try_deflate_bytes(&data[..1000]).unwrap();
+        let len = input.len();
+        input.extend_from_slice(b"trailing");
+        let mut output = Vec::new();
+        let (consumed, status) = state.update(&input, &mut output).unwrap();
+        assert_eq!((consumed, status), (len, InflateStatus::Done));
+        assert!(output == &data[..1000]);
+    }
+
+    #[test]
+    fn inflate_errors() {
+        let data = get_test_data();
+        let compressed = try_deflate_bytes_zlib(&data[..5000]).unwrap();
+
+        let mut corrupted = compressed.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert_eq!(
+            inflate_bytes_zlib(&corrupted),
+            Err(InflateError::ChecksumMismatch)
+        );
+        assert_eq!(
+            inflate_bytes_zlib(&compressed[..compressed.len() - 1]),
+            Err(InflateError::UnexpectedEnd)
+        );
+        assert_eq!(
+            inflate_bytes_zlib(&[0x78, 0x9D]),
+            Err(InflateError::InvalidHeader)
+        );
+        assert_eq!(
+            inflate_bytes_zlib(&[0x78, 0xBB, 0, 0, 0, 1]),
+            Err(InflateError::PresetDictionary)
+        );
+        // A final block of the reserved type.
+        assert_eq!(inflate_bytes(&[0b111]), Err(InflateError::InvalidBlockType));
+        // A stored block with a wrong length complement.
+        assert_eq!(
+            inflate_bytes(&[1, 5, 0, 0, 0]),
+            Err(InflateError::InvalidStoredLength)
+        );
+        // A fixed block starting with a match.
+        let mut match_first = crate::bitstream::LsbWriter::new(Vec::new());
+        match_first.write_bits(0b011, 3);
+        // Length code 257 is 0000001, written in reverse, followed by distance code 0.
+        match_first.write_bits(0b1000000, 7);
+        match_first.write_bits(0, 5);
+        match_first.flush_raw();
+        assert_eq!(
+            inflate_bytes(&match_first.w),
+            Err(InflateError::InvalidDistance)
+        );
+        // A dynamic block where the code length code only has a single code.
+        let mut incomplete = crate::bitstream::LsbWriter::new(Vec::new());
+        incomplete.write_bits(0b101, 3);
+        incomplete.write_bits(0, 14);
+        for &length in &[0, 0, 0, 1] {
+            incomplete.write_bits(length, 3);
+        }
+        incomplete.flush_raw();
+        assert_eq!(
+            inflate_bytes(&incomplete.w),
+            Err(InflateError::IncompleteCodeLengths)
+        );
+    }
+}
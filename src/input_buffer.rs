@@ -1,5 +1,6 @@
 use std::cmp;
 
+use crate::buffer_alloc::{global, take_vec, vec_with_capacity, BufVec};
 use crate::chained_hash_table::WINDOW_SIZE;
 
 const MAX_MATCH: usize = crate::huffman_table::MAX_MATCH as usize;
@@ -7,8 +8,15 @@ const MAX_MATCH: usize = crate::huffman_table::MAX_MATCH as usize;
 /// The maximum size of the buffer.
 pub const BUFFER_SIZE: usize = (WINDOW_SIZE * 2) + MAX_MATCH;
 
+/// The size of the buffer needed for windows of `window_size` bytes.
+const fn buffer_size(window_size: usize) -> usize {
+    (window_size * 2) + MAX_MATCH
+}
+
 pub struct InputBuffer {
-    buffer: Vec<u8>,
+    buffer: BufVec<u8>,
+    /// How far the buffer is slid, the buffer holds two windows and the lookahead.
+    window_size: usize,
 }
 
 impl InputBuffer {
@@ -20,20 +28,51 @@ impl InputBuffer {
     }
 
     pub fn empty() -> InputBuffer {
+        InputBuffer::with_window_size(WINDOW_SIZE)
+    }
+
+    /// Create an empty buffer for windows of `window_size` bytes, which must be at most
+    /// `WINDOW_SIZE`.
+    pub fn with_window_size(window_size: usize) -> InputBuffer {
+        debug_assert!(window_size <= WINDOW_SIZE);
         InputBuffer {
-            buffer: Vec::with_capacity(BUFFER_SIZE),
+            buffer: vec_with_capacity(buffer_size(window_size), &global()),
+            window_size,
         }
     }
 
+    /// Create an empty buffer reusing the allocation of `buffer`.
+    pub fn with_buffer(mut buffer: BufVec<u8>) -> InputBuffer {
+        buffer.clear();
+        buffer.reserve(BUFFER_SIZE);
+        InputBuffer {
+            buffer,
+            window_size: WINDOW_SIZE,
+        }
+    }
+
+    /// Set the size of the windows the buffer slides by, which must be at most `WINDOW_SIZE`.
+    /// Must be called while the buffer is empty.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        debug_assert!(window_size <= WINDOW_SIZE && self.current_end() == 0);
+        self.window_size = window_size;
+    }
+
+    /// Take the allocation out of the buffer so it can be reused, leaving it empty.
+    pub fn take_buffer(&mut self) -> BufVec<u8> {
+        take_vec(&mut self.buffer)
+    }
+
     /// Add data to the buffer.
     ///
     /// Returns a slice of the data that was not added (including the lookahead if any).
     pub fn add_data<'a>(&mut self, data: &'a [u8]) -> Option<&'a [u8]> {
-        debug_assert!(self.current_end() <= BUFFER_SIZE);
-        if self.current_end() + data.len() > BUFFER_SIZE {
+        let buffer_size = buffer_size(self.window_size);
+        debug_assert!(self.current_end() <= buffer_size);
+        if self.current_end() + data.len() > buffer_size {
             // Add data and return how much was left.
             let consumed = {
-                let space_left = BUFFER_SIZE - self.buffer.len();
+                let space_left = buffer_size - self.buffer.len();
                 self.buffer.extend_from_slice(&data[..space_left]);
                 space_left
             };
@@ -45,6 +84,11 @@ impl InputBuffer {
         }
     }
 
+    /// Remove all the data from the buffer.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
     /// Get the current amount of data in the buffer.
     pub fn current_end(&self) -> usize {
         self.buffer.len()
@@ -55,17 +99,18 @@ impl InputBuffer {
     /// Returns a slice containing the data that did not fit, or `None` if all data was consumed.
     pub fn slide<'a>(&mut self, data: &'a [u8]) -> Option<&'a [u8]> {
         // This should only be used when the buffer is full
-        assert!(self.buffer.len() > WINDOW_SIZE * 2);
+        let window_size = self.window_size;
+        assert!(self.buffer.len() > window_size * 2);
 
         // Do this in a closure to to end the borrow of buffer.
         let (final_len, upper_len, end) = {
             // Split into lower window and upper window + lookahead
-            let (lower, upper) = self.buffer.split_at_mut(WINDOW_SIZE);
+            let (lower, upper) = self.buffer.split_at_mut(window_size);
             // Copy the upper window to the lower window
-            lower.copy_from_slice(&upper[..WINDOW_SIZE]);
+            lower.copy_from_slice(&upper[..window_size]);
             let lookahead_len = {
                 // Copy the lookahead to the start of the upper window
-                let (upper_2, lookahead) = upper.split_at_mut(WINDOW_SIZE);
+                let (upper_2, lookahead) = upper.split_at_mut(window_size);
                 let lookahead_len = lookahead.len();
                 debug_assert!(lookahead_len <= MAX_MATCH);
                 upper_2[..lookahead_len].copy_from_slice(lookahead);
@@ -144,4 +189,17 @@ mod test {
         }
         assert_eq!(buf.current_end(), WINDOW_SIZE + MAX_MATCH + to_add.len());
     }
+
+    #[test]
+    fn slide_small_window() {
+        let window_size = 512;
+        let data: Vec<u8> = (0..window_size * 4).map(|n| n as u8).collect();
+        let mut buf = InputBuffer::with_window_size(window_size);
+        let rem = buf.add_data(&data).unwrap();
+        assert_eq!(buf.current_end(), window_size * 2 + MAX_MATCH);
+        let rem = buf.slide(rem).unwrap();
+        assert_eq!(buf.current_end(), window_size * 2 + MAX_MATCH);
+        assert!(buf.get_buffer()[..] == data[window_size..window_size * 3 + MAX_MATCH]);
+        assert!(rem == &data[window_size * 3 + MAX_MATCH..]);
+    }
 }
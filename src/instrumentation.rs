@@ -0,0 +1,86 @@
+//! Counters for internal events in the encoder, used to guide performance work.
+//!
+//! The counters are only updated when the `instrumentation` feature is enabled. They are kept
+//! per thread, and count events from all the encoders used on the current thread.
+
+/// Add to one of the instrumentation counters.
+///
+/// This expands to nothing if the `instrumentation` feature is disabled.
+macro_rules! instrument {
+    ($counter:ident) => {
+        instrument!($counter, 1)
+    };
+    ($counter:ident, $n:expr) => {
+        #[cfg(feature = "instrumentation")]
+        $crate::instrumentation::add(|c| c.$counter += $n as u64);
+    };
+}
+
+#[cfg(feature = "instrumentation")]
+use std::cell::Cell;
+
+/// Values of the instrumentation counters.
+#[cfg(feature = "instrumentation")]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Counters {
+    /// Number of searches for a match in the hash chains.
+    pub match_searches: u64,
+    /// Number of hash chain entries looked at while searching for matches.
+    pub chain_links_walked: u64,
+    /// Number of searches made at the position after an already found match to look for a
+    /// better one.
+    pub lazy_searches: u64,
+    /// Number of times the buffer of lz77-encoded data filled up, ending the block.
+    pub lz77_buffer_full: u64,
+    /// Number of times the output buffer was too full to continue compressing before it was
+    /// passed on to the wrapped writer.
+    pub output_buffer_full: u64,
+}
+
+#[cfg(feature = "instrumentation")]
+thread_local! {
+    static COUNTERS: Cell<Counters> = Cell::new(Counters::default());
+}
+
+#[cfg(feature = "instrumentation")]
+pub(crate) fn add<F: FnOnce(&mut Counters)>(f: F) {
+    COUNTERS.with(|c| {
+        let mut counters = c.get();
+        f(&mut counters);
+        c.set(counters);
+    });
+}
+
+/// Get the current values of the instrumentation counters for this thread.
+#[cfg(feature = "instrumentation")]
+pub fn counters() -> Counters {
+    COUNTERS.with(Cell::get)
+}
+
+/// Reset the instrumentation counters for this thread to zero.
+#[cfg(feature = "instrumentation")]
+pub fn reset_counters() {
+    COUNTERS.with(|c| c.set(Counters::default()));
+}
+
+#[cfg(all(test, feature = "instrumentation"))]
+mod test {
+    use super::*;
+    use crate::test_utils::get_test_data;
+    use crate::{try_deflate_bytes_conf, CompressionOptions};
+
+    #[test]
+    fn counters_updated() {
+        reset_counters();
+        let data = get_test_data();
+        let _ = try_deflate_bytes_conf(&data, CompressionOptions::default()).unwrap();
+        let c = counters();
+        assert!(c.match_searches > 0);
+        assert!(c.chain_links_walked > 0);
+        assert!(c.lazy_searches > 0);
+        assert!(c.lz77_buffer_full > 0);
+
+        reset_counters();
+        assert_eq!(counters(), Counters::default());
+    }
+}
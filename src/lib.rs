@@ -16,8 +16,18 @@
 //! `.7z`, `.rar`, `.xz` and `.bz2`, and is thus not the ideal choice for applications where
 //! the `DEFLATE` format (with or without wrappers) is not required.
 //!
+//! A simple decoder for raw DEFLATE and zlib streams is included in the
+//! [`inflate`](inflate/index.html) module, for round-tripping without depending on another crate.
+//!
 //! Support for the gzip wrapper (the wrapper that is used in `.gz` files) is disabled by default
-//! but can be enabled with the `gzip` feature.
+//! but can be enabled with the `gzip` feature. The `crc32` feature adds the CRC-32 checksum and
+//! [`try_deflate_bytes_with_crc`](fn.try_deflate_bytes_with_crc.html) for writing zip archives and other
+//! containers without the gzip wrapper.
+//!
+//! The crate contains no unsafe code by default. The `unsafe-speed` feature enables a few
+//! unsafe hot paths in the match finder (unaligned loads, unchecked indexing and prefetching)
+//! for extra speed.
+//! The output is the same with and without the feature.
 //!
 //! As this library is still in development, the compression output may change slightly
 //! between versions.
@@ -26,10 +36,10 @@
 //! # Examples:
 //! ## Simple compression function:
 //! ``` rust
-//! use deflate::deflate_bytes;
+//! use deflate::try_deflate_bytes;
 //!
 //! let data = b"Some data";
-//! let compressed = deflate_bytes(data);
+//! let compressed = try_deflate_bytes(data).expect("Compression failed!");
 //! # let _ = compressed;
 //! ```
 //!
@@ -46,9 +56,29 @@
 //! let compressed_data = encoder.finish().expect("Failed to finish compression!");
 //! # let _ = compressed_data;
 //! ```
+//!
+//! ## Using a reader:
+//! ``` rust
+//! use std::io::Read;
+//!
+//! use deflate::Compression;
+//! use deflate::read::ZlibEncoder;
+//!
+//! let data = &b"This is some test data"[..];
+//! let mut encoder = ZlibEncoder::new(data, Compression::Default);
+//! let mut compressed_data = Vec::new();
+//! encoder.read_to_end(&mut compressed_data).expect("Read error!");
+//! ```
 
-#![forbid(unsafe_code)]
+// The only unsafe code is behind the `unsafe-speed` feature, every unsafe block has to explain
+// why it is sound.
+#![cfg_attr(not(feature = "unsafe-speed"), forbid(unsafe_code))]
+#![cfg_attr(
+    feature = "unsafe-speed",
+    deny(unsafe_op_in_unsafe_fn, clippy::undocumented_unsafe_blocks)
+)]
 #![cfg_attr(all(feature = "benchmarks", test), feature(test))]
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
 
 #[cfg(all(test, feature = "benchmarks"))]
 extern crate test as test_std;
@@ -56,38 +86,77 @@ extern crate test as test_std;
 #[cfg(test)]
 extern crate miniz_oxide;
 
-extern crate adler32;
+#[cfg(feature = "crc32")]
+extern crate crc32fast;
 #[cfg(feature = "gzip")]
 extern crate gzip_header;
 
+#[macro_use]
+mod instrumentation;
+
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+pub mod bench_internals;
 mod bit_reverse;
 mod bitstream;
+mod block_encoder;
+mod buffer_alloc;
+mod buffer_pool;
 mod chained_hash_table;
-mod checksum;
+pub mod checksum;
+mod compiled_block;
 mod compress;
 mod compression_options;
+mod deferred;
 mod deflate_state;
+#[cfg(feature = "dictionaries")]
+pub mod dictionaries;
 mod encoder_state;
+mod error;
+mod estimate;
 mod huffman_lengths;
 mod huffman_table;
+mod idat;
+pub mod inflate;
 mod input_buffer;
 mod length_encode;
 mod lz77;
+#[cfg(feature = "lz-trace")]
+pub mod lz_trace;
 mod lzvalue;
 mod matching;
+mod optimal;
 mod output_writer;
+mod parallel;
+pub mod prefilter;
+mod reader;
 mod rle;
+mod segmented;
+mod sink;
+mod stats;
 mod stored_block;
+#[cfg(feature = "tar")]
+pub mod tar;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 #[cfg(test)]
 mod test_utils;
+mod tokens;
+mod tuning;
+mod two_pass;
+#[cfg(feature = "verify")]
+mod verify;
+// The code generated by `wasm_bindgen` uses newer library functions, which is fine as
+// wasm-bindgen itself needs a much newer compiler than the rest of the crate.
+#[cfg(feature = "wasm")]
+#[allow(clippy::incompatible_msrv)]
+pub mod wasm;
 mod writer;
 mod zlib;
 
-use std::io;
-use std::io::Write;
+use std::cmp;
+use std::io::{self, Write};
 
-#[cfg(feature = "gzip")]
-use gzip_header::Crc;
 #[cfg(feature = "gzip")]
 use gzip_header::GzBuilder;
 
@@ -95,16 +164,60 @@ use crate::checksum::RollingChecksum;
 use crate::deflate_state::DeflateState;
 
 use crate::compress::Flush;
-pub use compression_options::{Compression, CompressionOptions, SpecialOptions};
+pub use block_encoder::BlockEncoder;
+pub use buffer_pool::BufferPool;
+pub use chained_hash_table::HashFunction;
+pub use compiled_block::CompiledBlock;
+pub use compress::{BlockCosts, BlockInfo, BlockKind, BlockListener, BlockTypeSelector};
+pub use compression_options::{Compression, CompressionOptions, OutputVersion, SpecialOptions};
+pub use deferred::DeferredEnd;
+pub use error::Error;
+pub use estimate::{estimate_compressed_size, estimate_compressibility, max_compressed_len};
+pub use huffman_table::HuffmanError;
+pub use idat::IdatEncoder;
 pub use lz77::MatchingType;
+pub use segmented::{BlockSegment, IndependentSegment};
+pub use sink::Sink;
+#[cfg(feature = "timing")]
+pub use stats::StageTimings;
+#[cfg(feature = "symbol-stats")]
+pub use stats::SymbolStats;
+pub use stats::{BlockStats, CompressionReport, CompressionStats};
+pub use tokens::{deflate_tokens, Token};
+pub use writer::Format;
+pub use zlib::CompressionLevel;
+
+use crate::writer::{compress_until_done, compress_until_done_with_checksum};
+
+/// Counters for internal events in the encoder.
+///
+/// Only available with the `instrumentation` feature.
+#[cfg(feature = "instrumentation")]
+pub mod instrument {
+    pub use crate::instrumentation::{counters, reset_counters, Counters};
+}
+
+/// Reading and writing gzip header metadata.
+///
+/// Only available with the `gzip` feature.
+#[cfg(feature = "gzip")]
+pub mod gzip {
+    pub use crate::writer::gzip::{builder_from_header, default_os};
+    pub use gzip_header::{read_gz_header, ExtraFlags, FileSystemType, GzBuilder, GzHeader};
+}
 
-use crate::writer::compress_until_done;
+/// Encoders implementing a `Read` interface, compressing the data read from a wrapped reader.
+pub mod read {
+    #[cfg(feature = "gzip")]
+    pub use crate::reader::GzEncoder;
+    pub use crate::reader::{DeflateEncoder, ZlibEncoder};
+}
 
 /// Encoders implementing a `Write` interface.
 pub mod write {
     #[cfg(feature = "gzip")]
     pub use crate::writer::gzip::GzEncoder;
-    pub use crate::writer::{DeflateEncoder, ZlibEncoder};
+    pub use crate::writer::{write_strided, AnyEncoder, DeflateEncoder, ZlibEncoder};
 }
 
 fn compress_data_dynamic<RC: RollingChecksum, W: Write>(
@@ -112,116 +225,536 @@ fn compress_data_dynamic<RC: RollingChecksum, W: Write>(
     writer: &mut W,
     mut checksum: RC,
     compression_options: CompressionOptions,
-) -> io::Result<()> {
-    checksum.update_from_slice(input);
+    dictionary: Option<&[u8]>,
+) -> Result<(), Error> {
     // We use a box here to avoid putting the buffers on the stack
     // It's done here rather than in the structs themselves for now to
     // keep the data close in memory.
-    let mut deflate_state = Box::new(DeflateState::new(compression_options, writer));
-    compress_until_done(input, &mut deflate_state, Flush::Finish)
+    let mut deflate_state = Box::new(DeflateState::try_new(compression_options, writer)?);
+    if let Some(dictionary) = dictionary {
+        deflate_state.set_dictionary(dictionary);
+    }
+    deflate_state.set_input_size_hint(input.len() as u64);
+    Ok(compress_until_done_with_checksum(
+        input,
+        &mut deflate_state,
+        Flush::Finish,
+        &mut checksum,
+    )?)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression.
+///
+/// Like [`try_deflate_bytes_conf`](fn.try_deflate_bytes_conf.html),
+/// but panics if compression fails.
+#[deprecated(
+    since = "2.0.0",
+    note = "use `try_deflate_bytes_conf`, which returns the error instead of panicking"
+)]
+pub fn deflate_bytes_conf<O: Into<CompressionOptions>>(input: &[u8], options: O) -> Vec<u8> {
+    try_deflate_bytes_conf(input, options.into().clamped()).expect("Write error!")
 }
 
 /// Compress the given slice of bytes with DEFLATE compression.
 ///
-/// Returns a `Vec<u8>` of the compressed data.
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
 ///
 /// # Examples
 ///
 /// ```
-/// use deflate::{deflate_bytes_conf, Compression};
+/// use deflate::{try_deflate_bytes_conf, Compression};
 ///
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_conf(data, Compression::Best);
+/// let compressed_data = try_deflate_bytes_conf(data, Compression::Best).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes_conf<O: Into<CompressionOptions>>(input: &[u8], options: O) -> Vec<u8> {
+pub fn try_deflate_bytes_conf<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+) -> Result<Vec<u8>, Error> {
     let mut writer = Vec::with_capacity(input.len() / 3);
     compress_data_dynamic(
         input,
         &mut writer,
         checksum::NoChecksum::new(),
         options.into(),
-    )
-    .expect("Write error!");
-    writer
+        None,
+    )?;
+    Ok(writer)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, updating `checksum` with the
+/// input.
+///
+/// This is meant for container formats other than zlib and gzip, such as zip, that store a
+/// checksum of the uncompressed data next to the raw DEFLATE stream, and saves hashing the input
+/// separately. Any [`RollingChecksum`](checksum/trait.RollingChecksum.html) can be used. The
+/// checksum is updated from its current state, so it can already hold the checksum of earlier
+/// data.
+///
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::checksum::{Adler32Checksum, RollingChecksum};
+/// use deflate::{try_deflate_bytes_with_checksum, Compression};
+///
+/// let data = b"This is some test data";
+/// let mut checksum = Adler32Checksum::new();
+/// let compressed_data =
+///     try_deflate_bytes_with_checksum(data, Compression::Default, &mut checksum).unwrap();
+/// let adler32 = checksum.current_hash();
+/// # let _ = (compressed_data, adler32);
+/// ```
+pub fn try_deflate_bytes_with_checksum<C: RollingChecksum, O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+    checksum: &mut C,
+) -> Result<Vec<u8>, Error> {
+    let mut writer = Vec::with_capacity(input.len() / 3);
+    compress_data_dynamic(input, &mut writer, checksum, options.into(), None)?;
+    Ok(writer)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, returning the compressed data
+/// along with the CRC-32 checksum and the length of the input, or the error that occured during
+/// compression.
+///
+/// These are the values a zip archive stores for each entry compressed with the DEFLATE method.
+/// Only available with the `crc32` feature.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_with_crc, Compression};
+///
+/// let data = b"This is some test data";
+/// let (compressed_data, crc32, uncompressed_size) =
+///     try_deflate_bytes_with_crc(data, Compression::Default).unwrap();
+/// assert_eq!(uncompressed_size, data.len() as u64);
+/// # let _ = (compressed_data, crc32);
+/// ```
+#[cfg(feature = "crc32")]
+pub fn try_deflate_bytes_with_crc<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+) -> Result<(Vec<u8>, u32, u64), Error> {
+    let mut checksum = checksum::Crc32Checksum::new();
+    let compressed = try_deflate_bytes_with_checksum(input, options, &mut checksum)?;
+    Ok((compressed, checksum.current_hash(), input.len() as u64))
+}
+
+/// Compress the given slice of bytes with DEFLATE compression using the default compression
+/// level.
+///
+/// Like [`try_deflate_bytes`](fn.try_deflate_bytes.html), but panics if compression fails.
+#[deprecated(
+    since = "2.0.0",
+    note = "use `try_deflate_bytes`, which returns the error instead of panicking"
+)]
+pub fn deflate_bytes(input: &[u8]) -> Vec<u8> {
+    try_deflate_bytes(input).expect("Write error!")
 }
 
 /// Compress the given slice of bytes with DEFLATE compression using the default compression
 /// level.
 ///
-/// Returns a `Vec<u8>` of the compressed data.
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
 ///
 /// # Examples
 ///
 /// ```
-/// use deflate::deflate_bytes;
+/// use deflate::try_deflate_bytes;
 ///
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes(data);
+/// let compressed_data = try_deflate_bytes(data).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes(input: &[u8]) -> Vec<u8> {
-    deflate_bytes_conf(input, Compression::Default)
+pub fn try_deflate_bytes(input: &[u8]) -> Result<Vec<u8>, Error> {
+    try_deflate_bytes_conf(input, Compression::Default)
 }
 
-/// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer.
+/// Compress the data in the given slices of bytes with DEFLATE compression, as if they were one
+/// contiguous slice.
+///
+/// This avoids having to join chunked input into a single buffer before compressing it.
 ///
-/// Returns a `Vec<u8>` of the compressed data.
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_iter, Compression};
+///
+/// let parts: [&[u8]; 3] = [b"This is ", b"some ", b"test data"];
+/// let compressed_data = try_deflate_bytes_iter(parts.iter().cloned(), Compression::Default);
+/// # let _ = compressed_data.unwrap();
+/// ```
+pub fn try_deflate_bytes_iter<'a, I, O>(input: I, options: O) -> Result<Vec<u8>, Error>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+    O: Into<CompressionOptions>,
+{
+    let mut encoder = write::DeflateEncoder::try_new(Vec::new(), options)?;
+    for chunk in input {
+        encoder.write_all(chunk)?;
+    }
+    Ok(encoder.finish()?)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, returning the compressed data
+/// split into one segment per DEFLATE block, or the error that occured during compression.
 ///
-/// Zlib dictionaries are not yet suppored.
+/// Joining the segments together bit by bit gives the same output as
+/// [`try_deflate_bytes_conf`](fn.try_deflate_bytes_conf.html).
 ///
 /// # Examples
 ///
 /// ```
-/// use deflate::{deflate_bytes_zlib_conf, Compression};
+/// use deflate::{try_deflate_bytes_segmented, Compression};
 ///
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_zlib_conf(data, Compression::Best);
+/// let segments = try_deflate_bytes_segmented(data, Compression::Default).unwrap();
+/// assert!(segments.last().unwrap().final_block);
+/// ```
+pub fn try_deflate_bytes_segmented<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+) -> Result<Vec<BlockSegment>, Error> {
+    let mut writer = Vec::with_capacity(input.len() / 3);
+    let mut deflate_state = Box::new(DeflateState::try_new(options.into(), &mut writer)?);
+    deflate_state.block_log = Some(Vec::new());
+    compress_until_done(input, &mut deflate_state, Flush::Finish)?;
+    let blocks = deflate_state.block_log.take().unwrap_or_default();
+    drop(deflate_state);
+    Ok(segmented::split_into_segments(&writer, &blocks))
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, ending a DEFLATE block at each of
+/// the given input offsets, and return the compressed data split into one segment per block, or
+/// the error that occured during compression.
+///
+/// This is useful to make blocks line up with logical records in the input, so the compressed
+/// blocks can be mapped back to them. The encoder may still end blocks at other places as well,
+/// but every offset in `boundaries` is the start of a block (or the end of the input). Unlike a
+/// sync flush, ending a block doesn't pad the output to a byte boundary or add any markers, so
+/// the cost in compression ratio is small.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_with_block_boundaries, Compression};
+///
+/// let data = b"first record\nsecond record\nthird record\n";
+/// let segments =
+///     try_deflate_bytes_with_block_boundaries(data, Compression::Default, &[13, 27]).unwrap();
+/// let lengths: Vec<_> = segments.iter().map(|s| s.input_len).collect();
+/// assert_eq!(lengths, [13, 14, 13]);
+/// ```
+///
+/// # Errors
+///
+/// Returns `Error::InvalidOptions` if the boundaries are not in increasing order, or are past the
+/// end of the input, otherwise the error that occured during compression, if any.
+pub fn try_deflate_bytes_with_block_boundaries<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+    boundaries: &[usize],
+) -> Result<Vec<BlockSegment>, Error> {
+    let in_order = boundaries.windows(2).all(|pair| pair[0] <= pair[1]);
+    if !in_order || boundaries.last().map_or(false, |&end| end > input.len()) {
+        return Err(Error::InvalidOptions(
+            "block boundaries have to be in increasing order and within the input",
+        ));
+    }
+    let mut writer = Vec::with_capacity(input.len() / 3);
+    let mut deflate_state = Box::new(DeflateState::try_new(options.into(), &mut writer)?);
+    deflate_state.block_log = Some(Vec::new());
+    let mut start = 0;
+    for &end in boundaries {
+        compress_until_done(&input[start..end], &mut deflate_state, Flush::Block)?;
+        start = end;
+    }
+    compress_until_done(&input[start..], &mut deflate_state, Flush::Finish)?;
+    let blocks = deflate_state.block_log.take().unwrap_or_default();
+    drop(deflate_state);
+    Ok(segmented::split_into_segments(&writer, &blocks))
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, returning the compressed data
+/// split into byte-aligned segments that can each be decompressed on their own, or the error
+/// that occured during compression.
+///
+/// The input is compressed in pieces of `segment_len` bytes, with a full flush after each piece,
+/// so the data in one segment never refers back to an earlier one. A decompressor can start at
+/// the start of any segment, which is useful for serving ranges of the compressed data or
+/// decompressing the segments in parallel. Joining the segments together gives one valid
+/// DEFLATE stream. The cost is some compression ratio, as matches can't span segments.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_independent_segments, Compression};
+///
+/// let data = vec![7; 100_000];
+/// let segments =
+///     try_deflate_bytes_independent_segments(&data, Compression::Default, 32768).unwrap();
+/// assert_eq!(segments.len(), 4);
+/// assert_eq!(segments[1].input_start, 32768);
+/// ```
+///
+/// # Errors
+///
+/// Returns `Error::InvalidOptions` if `segment_len` is 0, otherwise the error that occured during
+/// compression, if any.
+pub fn try_deflate_bytes_independent_segments<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+    segment_len: usize,
+) -> Result<Vec<IndependentSegment>, Error> {
+    segmented::compress_independent_segments(input, options.into(), segment_len)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression on `n_threads` threads, returning
+/// the error that occured during compression if any.
+///
+/// The input is split into chunks of 128 KiB, which are compressed independently on separate
+/// threads and joined back together with sync flushes in between, similar to `pigz`. Each chunk
+/// is compressed with the 32 KiB of input before it as a preset dictionary, so matches can still
+/// refer back across the chunk boundaries, and the output is usually only slightly larger than
+/// that of [`try_deflate_bytes_conf`](fn.try_deflate_bytes_conf.html).
+///
+/// If `n_threads` is `0`, 4 threads are used. No more threads than there are chunks are used,
+/// and inputs of a single chunk or less are compressed on the calling thread. The input is copied
+/// once to share it between the threads. The output doesn't depend on the number of threads.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_parallel, Compression};
+///
+/// let data = vec![7; 1_000_000];
+/// let compressed_data = try_deflate_bytes_parallel(&data, Compression::Default, 4).unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub fn try_deflate_bytes_parallel<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+    n_threads: usize,
+) -> Result<Vec<u8>, Error> {
+    parallel::compress_parallel(input, options.into(), n_threads)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, optimizing the block boundaries
+/// and Huffman codes for the input as a whole, or return the error that occured during
+/// compression.
+///
+/// The streaming compressor has to pick the Huffman codes for each block without knowing what
+/// comes after it. This function instead finds all the matches in the input first, and then
+/// merges neighbouring blocks that compress better with a shared set of codes. This gives output
+/// that is usually a bit smaller than
+/// [`try_deflate_bytes_conf`](fn.try_deflate_bytes_conf.html), at the cost of keeping the
+/// intermediate representation of the whole input in memory, roughly twice the size of the
+/// input.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_two_pass, Compression};
+///
+/// let data = b"This is some test data";
+/// let compressed_data = try_deflate_bytes_two_pass(data, Compression::Best).unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub fn try_deflate_bytes_two_pass<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+) -> Result<Vec<u8>, Error> {
+    two_pass::compress_two_pass(input, options.into(), false)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, outputting all of it as a single
+/// block.
+///
+/// The normal compression functions may end a block part way through the input, which for small
+/// inputs means paying for the block headers and Huffman tables more than once. This is intended
+/// for inputs of up to a few tens of kilobytes, e.g. network messages; larger inputs will usually
+/// compress better using [`try_deflate_bytes_two_pass`](fn.try_deflate_bytes_two_pass.html), which can
+/// change codes between blocks.
+///
+/// The block is a stored block if the input doesn't compress and fits in one, otherwise it uses
+/// fixed or dynamic Huffman codes, whichever is shorter.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_single_block, Compression};
+///
+/// let data = b"This is some test data";
+/// let compressed_data = try_deflate_bytes_single_block(data, Compression::Default).unwrap();
 /// # let _ = compressed_data;
 /// ```
+pub fn try_deflate_bytes_single_block<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+) -> Result<Vec<u8>, Error> {
+    two_pass::compress_two_pass(input, options.into(), true)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer.
+///
+/// Like [`try_deflate_bytes_zlib_conf`](fn.try_deflate_bytes_zlib_conf.html),
+/// but panics if compression fails.
+#[deprecated(
+    since = "2.0.0",
+    note = "use `try_deflate_bytes_zlib_conf`, which returns the error instead of panicking"
+)]
 pub fn deflate_bytes_zlib_conf<O: Into<CompressionOptions>>(input: &[u8], options: O) -> Vec<u8> {
+    try_deflate_bytes_zlib_conf(input, options.into().clamped()).expect("Write error!")
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer.
+///
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
+///
+/// To compress using a preset dictionary, use
+/// [`try_deflate_bytes_zlib_dict`](fn.try_deflate_bytes_zlib_dict.html).
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_zlib_conf, Compression};
+///
+/// let data = b"This is some test data";
+/// let compressed_data = try_deflate_bytes_zlib_conf(data, Compression::Best).unwrap();
+/// # let _ = compressed_data;
+/// ```
+pub fn try_deflate_bytes_zlib_conf<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+) -> Result<Vec<u8>, Error> {
     let mut writer = Vec::with_capacity(input.len() / 3);
+    compress_zlib(input, &mut writer, options.into(), None)?;
+    Ok(writer)
+}
+
+fn compress_zlib<W: Write>(
+    input: &[u8],
+    writer: &mut W,
+    options: CompressionOptions,
+    dictionary: Option<&[u8]>,
+) -> Result<(), Error> {
     // Write header
-    zlib::write_zlib_header(&mut writer, zlib::CompressionLevel::Default)
-        .expect("Write error when writing zlib header!");
+    let level = zlib::CompressionLevel::Default;
+    let window_bits = options.clamped().window_bits;
+    match dictionary {
+        Some(dictionary) => zlib::write_zlib_header_with_dict(
+            writer,
+            level,
+            window_bits,
+            zlib::dictionary_id(dictionary),
+        )?,
+        None => zlib::write_zlib_header(writer, level, window_bits)?,
+    }
 
     let mut checksum = checksum::Adler32Checksum::new();
-    compress_data_dynamic(input, &mut writer, &mut checksum, options.into())
-        .expect("Write error when writing compressed data!");
+    compress_data_dynamic(input, writer, &mut checksum, options, dictionary)?;
 
     let hash = checksum.current_hash();
 
-    writer
-        .write_all(&hash.to_be_bytes())
-        .expect("Write error when writing checksum!");
-    writer
+    writer.write_all(&hash.to_be_bytes())?;
+    Ok(())
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer,
+/// using `dictionary` as a preset dictionary.
+///
+/// The compressed data can refer back to the dictionary as if it came just before the input,
+/// which helps compressing short inputs that are similar to it. The header records the Adler-32
+/// checksum of the dictionary, and the same dictionary has to be given to the decompressor to
+/// decompress the data. Only the last 32 KiB of the dictionary are used.
+///
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{try_deflate_bytes_zlib_dict, Compression};
+/// use deflate::inflate::inflate_bytes_zlib_dict;
+///
+/// let dictionary = b"This is some test data";
+/// let data = b"This is some more test data";
+/// let compressed_data =
+///     try_deflate_bytes_zlib_dict(data, dictionary, Compression::Default).unwrap();
+/// assert_eq!(
+///     inflate_bytes_zlib_dict(&compressed_data, dictionary).unwrap(),
+///     &data[..]
+/// );
+/// ```
+pub fn try_deflate_bytes_zlib_dict<O: Into<CompressionOptions>>(
+    input: &[u8],
+    dictionary: &[u8],
+    options: O,
+) -> Result<Vec<u8>, Error> {
+    let mut writer = Vec::with_capacity(input.len() / 3);
+    compress_zlib(input, &mut writer, options.into(), Some(dictionary))?;
+    Ok(writer)
 }
 
 /// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer,
 /// using the default compression level.
 ///
-/// Returns a Vec<u8> of the compressed data.
+/// Like [`try_deflate_bytes_zlib`](fn.try_deflate_bytes_zlib.html),
+/// but panics if compression fails.
+#[deprecated(
+    since = "2.0.0",
+    note = "use `try_deflate_bytes_zlib`, which returns the error instead of panicking"
+)]
+pub fn deflate_bytes_zlib(input: &[u8]) -> Vec<u8> {
+    try_deflate_bytes_zlib(input).expect("Write error!")
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a zlib header and trailer,
+/// using the default compression level.
 ///
-/// Zlib dictionaries are not yet suppored.
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
 ///
 /// # Examples
 ///
 /// ```
-/// use deflate::deflate_bytes_zlib;
+/// use deflate::try_deflate_bytes_zlib;
 ///
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_zlib(data);
+/// let compressed_data = try_deflate_bytes_zlib(data).unwrap();
 /// # let _ = compressed_data;
 /// ```
-pub fn deflate_bytes_zlib(input: &[u8]) -> Vec<u8> {
-    deflate_bytes_zlib_conf(input, Compression::Default)
+pub fn try_deflate_bytes_zlib(input: &[u8]) -> Result<Vec<u8>, Error> {
+    try_deflate_bytes_zlib_conf(input, Compression::Default)
 }
 
 /// Compress the given slice of bytes with DEFLATE compression, including a gzip header and trailer
 /// using the given gzip header and compression options.
 ///
-/// Returns a `Vec<u8>` of the compressed data.
+/// Like [`try_deflate_bytes_gzip_conf`](fn.try_deflate_bytes_gzip_conf.html),
+/// but panics if compression fails.
+#[cfg(feature = "gzip")]
+#[deprecated(
+    since = "2.0.0",
+    note = "use `try_deflate_bytes_gzip_conf`, which returns the error instead of panicking"
+)]
+pub fn deflate_bytes_gzip_conf<O: Into<CompressionOptions>>(
+    input: &[u8],
+    options: O,
+    gzip_header: GzBuilder,
+) -> Vec<u8> {
+    try_deflate_bytes_gzip_conf(input, options.into().clamped(), gzip_header).expect("Write error!")
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a gzip header and trailer
+/// using the given gzip header and compression options.
 ///
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
 ///
 /// # Examples
 ///
@@ -230,59 +763,244 @@ pub fn deflate_bytes_zlib(input: &[u8]) -> Vec<u8> {
 /// extern crate deflate;
 ///
 /// # fn main() {
-/// use deflate::{deflate_bytes_gzip_conf, Compression};
+/// use deflate::{try_deflate_bytes_gzip_conf, Compression};
 /// use gzip_header::GzBuilder;
 ///
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_gzip_conf(data, Compression::Best, GzBuilder::new());
+/// let compressed_data =
+///     try_deflate_bytes_gzip_conf(data, Compression::Best, GzBuilder::new()).unwrap();
 /// # let _ = compressed_data;
 /// # }
 /// ```
 #[cfg(feature = "gzip")]
-pub fn deflate_bytes_gzip_conf<O: Into<CompressionOptions>>(
+pub fn try_deflate_bytes_gzip_conf<O: Into<CompressionOptions>>(
     input: &[u8],
     options: O,
     gzip_header: GzBuilder,
-) -> Vec<u8> {
+) -> Result<Vec<u8>, Error> {
     let mut writer = Vec::with_capacity(input.len() / 3);
+    compress_gzip(input, &mut writer, options.into(), gzip_header)?;
+    Ok(writer)
+}
 
+#[cfg(feature = "gzip")]
+fn compress_gzip<W: Write>(
+    input: &[u8],
+    writer: &mut W,
+    options: CompressionOptions,
+    gzip_header: GzBuilder,
+) -> Result<(), Error> {
     // Write header
-    writer
-        .write_all(&gzip_header.into_header())
-        .expect("Write error when writing header!");
-    let mut checksum = checksum::NoChecksum::new();
-    compress_data_dynamic(input, &mut writer, &mut checksum, options.into())
-        .expect("Write error when writing compressed data!");
-
-    let mut crc = Crc::new();
-    crc.update(input);
+    writer.write_all(&gzip_header.into_header())?;
+    let mut checksum = checksum::Crc32Checksum::new();
+    compress_data_dynamic(input, writer, &mut checksum, options, None)?;
 
-    writer
-        .write_all(&crc.sum().to_le_bytes())
-        .expect("Write error when writing checksum!");
-    writer
-        .write_all(&crc.amt_as_u32().to_le_bytes())
-        .expect("Write error when writing amt!");
-    writer
+    writer.write_all(&checksum.current_hash().to_le_bytes())?;
+    writer.write_all(&checksum.amount().to_le_bytes())?;
+    Ok(())
 }
 
 /// Compress the given slice of bytes with DEFLATE compression, including a gzip header and trailer,
 /// using the default compression level, and a gzip header with default values.
 ///
-/// Returns a `Vec<u8>` of the compressed data.
+/// Like [`try_deflate_bytes_gzip`](fn.try_deflate_bytes_gzip.html),
+/// but panics if compression fails.
+#[cfg(feature = "gzip")]
+#[deprecated(
+    since = "2.0.0",
+    note = "use `try_deflate_bytes_gzip`, which returns the error instead of panicking"
+)]
+pub fn deflate_bytes_gzip(input: &[u8]) -> Vec<u8> {
+    try_deflate_bytes_gzip(input).expect("Write error!")
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a gzip header and trailer,
+/// using the default compression level, and a gzip header with default values.
 ///
+/// Returns a `Vec<u8>` of the compressed data, or the error that occured during compression.
 ///
 /// # Examples
 ///
 /// ```
-/// use deflate::deflate_bytes_gzip;
+/// use deflate::try_deflate_bytes_gzip;
+///
 /// let data = b"This is some test data";
-/// let compressed_data = deflate_bytes_gzip(data);
+/// let compressed_data = try_deflate_bytes_gzip(data).unwrap();
 /// # let _ = compressed_data;
 /// ```
 #[cfg(feature = "gzip")]
-pub fn deflate_bytes_gzip(input: &[u8]) -> Vec<u8> {
-    deflate_bytes_gzip_conf(input, Compression::Default, GzBuilder::new())
+pub fn try_deflate_bytes_gzip(input: &[u8]) -> Result<Vec<u8>, Error> {
+    try_deflate_bytes_gzip_conf(
+        input,
+        Compression::Default,
+        GzBuilder::new().os(writer::gzip::default_os()),
+    )
+}
+
+/// Passes writes on to the wrapped writer, counting the number of bytes written.
+struct CountingWriter<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    count: u64,
+}
+
+impl<'a, W: Write + ?Sized> CountingWriter<'a, W> {
+    fn new(inner: &'a mut W) -> CountingWriter<'a, W> {
+        CountingWriter { inner, count: 0 }
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes into a fixed slice, failing with `OutputTooSmall` once it's full rather than accepting
+/// nothing, which the encoder would keep retrying.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    overflowed: bool,
+}
+
+impl<'a> Write for SliceWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let space = &mut self.buf[self.pos..];
+        if space.is_empty() {
+            self.overflowed = true;
+            return Err(Error::OutputTooSmall.into());
+        }
+        let len = cmp::min(space.len(), buf.len());
+        space[..len].copy_from_slice(&buf[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compress the given slice of bytes with DEFLATE compression directly into `writer`.
+///
+/// Returns the number of bytes written, or the error that occured while writing. Out-of-range
+/// options are adjusted to the closest supported values.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{deflate_to_writer, Compression};
+///
+/// let data = b"This is some test data";
+/// let mut output = Vec::new();
+/// let written = deflate_to_writer(data, Compression::Default, &mut output).unwrap();
+/// assert_eq!(written, output.len() as u64);
+/// ```
+pub fn deflate_to_writer<O: Into<CompressionOptions>, W: Write + ?Sized>(
+    input: &[u8],
+    options: O,
+    writer: &mut W,
+) -> io::Result<u64> {
+    let mut writer = CountingWriter::new(writer);
+    compress_data_dynamic(
+        input,
+        &mut writer,
+        checksum::NoChecksum::new(),
+        options.into().clamped(),
+        None,
+    )?;
+    Ok(writer.count)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a zlib header and
+/// trailer, directly into `writer`.
+///
+/// Returns the number of bytes written, or the error that occured while writing.
+pub fn deflate_zlib_to_writer<O: Into<CompressionOptions>, W: Write + ?Sized>(
+    input: &[u8],
+    options: O,
+    writer: &mut W,
+) -> io::Result<u64> {
+    let mut writer = CountingWriter::new(writer);
+    compress_zlib(input, &mut writer, options.into().clamped(), None)?;
+    Ok(writer.count)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression, including a gzip header with
+/// default values and a trailer, directly into `writer`.
+///
+/// Returns the number of bytes written, or the error that occured while writing.
+#[cfg(feature = "gzip")]
+pub fn deflate_gzip_to_writer<O: Into<CompressionOptions>, W: Write + ?Sized>(
+    input: &[u8],
+    options: O,
+    writer: &mut W,
+) -> io::Result<u64> {
+    let mut writer = CountingWriter::new(writer);
+    compress_gzip(
+        input,
+        &mut writer,
+        options.into().clamped(),
+        GzBuilder::new().os(crate::writer::gzip::default_os()),
+    )?;
+    Ok(writer.count)
+}
+
+/// Compress the given slice of bytes with DEFLATE compression into `output`, without allocating
+/// an output buffer.
+///
+/// Returns the length of the compressed data at the start of `output`, or
+/// [`Error::OutputTooSmall`](enum.Error.html#variant.OutputTooSmall) if it doesn't fit, in which
+/// case the contents of `output` are unspecified.
+///
+/// # Examples
+///
+/// ```
+/// use deflate::{deflate_bytes_into, Compression, Error};
+///
+/// let data = b"This is some test data";
+/// let mut output = [0; 64];
+/// let len = deflate_bytes_into(data, &mut output, Compression::Default).unwrap();
+/// let compressed_data = &output[..len];
+/// # let _ = compressed_data;
+///
+/// let mut too_small = [0; 4];
+/// match deflate_bytes_into(data, &mut too_small, Compression::Default) {
+///     Err(Error::OutputTooSmall) => (),
+///     _ => panic!("the output should not fit"),
+/// }
+/// ```
+pub fn deflate_bytes_into<O: Into<CompressionOptions>>(
+    input: &[u8],
+    output: &mut [u8],
+    options: O,
+) -> Result<usize, Error> {
+    let mut writer = SliceWriter {
+        buf: output,
+        pos: 0,
+        overflowed: false,
+    };
+    let res = compress_data_dynamic(
+        input,
+        &mut writer,
+        checksum::NoChecksum::new(),
+        options.into(),
+        None,
+    );
+    match res {
+        Err(_) if writer.overflowed => Err(Error::OutputTooSmall),
+        Err(e) => Err(e),
+        Ok(()) => Ok(writer.pos),
+    }
 }
 
 #[cfg(test)]
@@ -290,6 +1008,7 @@ mod test {
     use super::*;
     use std::io::Write;
 
+    use inflate::max_match_distance_zlib;
     #[cfg(feature = "gzip")]
     use test_utils::decompress_gzip;
     use test_utils::{decompress_to_end, decompress_zlib, get_test_data};
@@ -306,7 +1025,7 @@ mod test {
     #[test]
     fn dynamic_string_mem() {
         let test_data = String::from("                    GNU GENERAL PUBLIC LICENSE").into_bytes();
-        let compressed = deflate_bytes(&test_data);
+        let compressed = try_deflate_bytes(&test_data).unwrap();
 
         assert!(compressed.len() < test_data.len());
 
@@ -317,7 +1036,7 @@ mod test {
     #[test]
     fn dynamic_string_file() {
         let input = get_test_data();
-        let compressed = deflate_bytes(&input);
+        let compressed = try_deflate_bytes(&input).unwrap();
 
         let result = decompress_to_end(&compressed);
         for (n, (&a, &b)) in input.iter().zip(result.iter()).enumerate() {
@@ -340,7 +1059,7 @@ mod test {
     #[test]
     fn file_rle() {
         let input = get_test_data();
-        let compressed = deflate_bytes_conf(&input, CO::rle());
+        let compressed = try_deflate_bytes_conf(&input, CO::rle()).unwrap();
 
         let result = decompress_to_end(&compressed);
         assert!(input == result);
@@ -350,7 +1069,7 @@ mod test {
     fn file_zlib() {
         let test_data = get_test_data();
 
-        let compressed = deflate_bytes_zlib(&test_data);
+        let compressed = try_deflate_bytes_zlib(&test_data).unwrap();
         // {
         //     use std::fs::File;
         //     use std::io::Write;
@@ -366,6 +1085,31 @@ mod test {
         assert!(compressed.len() < test_data.len());
     }
 
+    #[test]
+    fn to_writer() {
+        let data = get_test_data();
+        let mut output = Vec::new();
+        let written = deflate_to_writer(&data, CO::fast(), &mut output).unwrap();
+        assert_eq!(written, output.len() as u64);
+        assert!(output == try_deflate_bytes_conf(&data, CO::fast()).unwrap());
+
+        // Append to existing data, through a trait object.
+        let mut output = vec![1, 2, 3];
+        let written =
+            deflate_zlib_to_writer(&data, CO::default(), &mut output as &mut dyn Write).unwrap();
+        assert_eq!(written + 3, output.len() as u64);
+        assert!(output[3..] == try_deflate_bytes_zlib(&data).unwrap()[..]);
+
+        #[cfg(feature = "gzip")]
+        {
+            let mut output = Vec::new();
+            let written = deflate_gzip_to_writer(&data, CO::default(), &mut output).unwrap();
+            assert_eq!(written, output.len() as u64);
+            assert!(output == try_deflate_bytes_gzip(&data).unwrap());
+            assert!(decompress_gzip(&output).1 == data);
+        }
+    }
+
     #[test]
     fn zlib_short() {
         let test_data = [10, 10, 10, 10, 10, 55];
@@ -379,10 +1123,82 @@ mod test {
         roundtrip_zlib(&test_data, CO::default());
     }
 
+    #[test]
+    fn zlib_small_window() {
+        let test_data = get_test_data();
+        let mut options = CO::default();
+        options.window_bits = 10;
+        let compressed = try_deflate_bytes_zlib_conf(&test_data, options).unwrap();
+        // CINFO is the window size as log2(size) - 8.
+        assert_eq!(compressed[0], 8 | (2 << 4));
+        assert!(decompress_zlib(&compressed) == test_data);
+        assert!(compressed.len() > try_deflate_bytes_zlib(&test_data).unwrap().len());
+
+        // The input is processed in smaller windows as well, except for version 1.
+        for window_bits in 8..=15 {
+            for &output_version in &[OutputVersion::Latest, OutputVersion::V1] {
+                for &level in &[0, 1, 6, 9] {
+                    let mut options = CO::from_level(level);
+                    options.window_bits = window_bits;
+                    options.output_version = output_version;
+                    let compressed = try_deflate_bytes_zlib_conf(&test_data, options).unwrap();
+                    assert_eq!(compressed[0], 8 | ((window_bits - 8) << 4));
+                    assert!(decompress_zlib(&compressed) == test_data);
+                    assert!(max_match_distance_zlib(&compressed) <= 1 << window_bits);
+
+                    let mut encoder = write::ZlibEncoder::new(Vec::new(), options);
+                    for chunk in test_data.chunks(1000) {
+                        encoder.write_all(chunk).unwrap();
+                        if chunk[0] == b'a' {
+                            encoder.flush().unwrap();
+                        }
+                    }
+                    let compressed = encoder.finish().unwrap();
+                    assert!(decompress_zlib(&compressed) == test_data);
+                    assert!(max_match_distance_zlib(&compressed) <= 1 << window_bits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zlib_max_search_distance() {
+        let test_data = get_test_data();
+        let mut options = CO::default();
+        options.max_search_distance = 1024;
+        let compressed = try_deflate_bytes_zlib_conf(&test_data, options).unwrap();
+        // The header should still specify a 32k window.
+        assert_eq!(compressed[0], 8 | (7 << 4));
+        assert!(decompress_zlib(&compressed) == test_data);
+        let max_distance = max_match_distance_zlib(&compressed);
+        assert!(max_distance <= 1024);
+        // The limit shouldn't keep matches from reaching all the way back to it.
+        assert!(max_distance > 1000);
+    }
+
+    #[test]
+    fn max_code_length() {
+        let test_data = get_test_data();
+        let default = try_deflate_bytes(&test_data).unwrap();
+        let mut shortest_codes = Vec::new();
+        for max_code_length in 9..=15 {
+            let mut options = CO::default();
+            options.max_code_length = max_code_length;
+            let compressed = try_deflate_bytes_conf(&test_data, options).unwrap();
+            assert!(decompress_to_end(&compressed) == test_data);
+            // A limit of 15 is what the format allows anyhow, so it shouldn't change anything.
+            assert_eq!(compressed == default, max_code_length == 15);
+            if max_code_length == 9 {
+                shortest_codes = compressed;
+            }
+        }
+        assert!(shortest_codes.len() > default.len());
+    }
+
     #[test]
     fn deflate_short() {
         let test_data = [10, 10, 10, 10, 10, 55];
-        let compressed = deflate_bytes(&test_data);
+        let compressed = try_deflate_bytes(&test_data).unwrap();
 
         let result = decompress_to_end(&compressed);
         assert_eq!(&test_data, result.as_slice());
@@ -395,16 +1211,104 @@ mod test {
     fn gzip() {
         let data = get_test_data();
         let comment = b"Test";
-        let compressed = deflate_bytes_gzip_conf(
+        let compressed = try_deflate_bytes_gzip_conf(
             &data,
             Compression::Default,
             GzBuilder::new().comment(&comment[..]),
-        );
+        )
+        .unwrap();
         let (dec, decompressed) = decompress_gzip(&compressed);
         assert_eq!(dec.comment().unwrap(), comment);
         assert!(data == decompressed);
     }
 
+    #[test]
+    fn try_variants() {
+        let data = get_test_data();
+        assert!(try_deflate_bytes(&data).unwrap() == try_deflate_bytes(&data).unwrap());
+        assert!(try_deflate_bytes_zlib(&data).unwrap() == try_deflate_bytes_zlib(&data).unwrap());
+        assert!(
+            try_deflate_bytes_iter(data.chunks(1000), CO::default()).unwrap()
+                == try_deflate_bytes(&data).unwrap()
+        );
+        #[cfg(feature = "gzip")]
+        assert!(try_deflate_bytes_gzip(&data).unwrap() == try_deflate_bytes_gzip(&data).unwrap());
+    }
+
+    #[test]
+    fn with_checksum() {
+        use checksum::{update_adler32, Adler32Checksum};
+        let data = get_test_data();
+        let mut checksum = Adler32Checksum::new();
+        let compressed =
+            try_deflate_bytes_with_checksum(&data, Compression::Default, &mut checksum).unwrap();
+        assert!(compressed == try_deflate_bytes_conf(&data, Compression::Default).unwrap());
+        assert_eq!(checksum.current_hash(), update_adler32(1, &data));
+
+        // The checksum continues from its current state.
+        let (first, second) = data.split_at(10_000);
+        let mut checksum = Adler32Checksum::new();
+        checksum.update_from_slice(first);
+        let _ = try_deflate_bytes_with_checksum(second, Compression::Fast, &mut checksum).unwrap();
+        assert_eq!(checksum.current_hash(), update_adler32(1, &data));
+    }
+
+    #[test]
+    #[cfg(feature = "crc32")]
+    fn with_crc() {
+        let data = get_test_data();
+        let (compressed, crc, len) =
+            try_deflate_bytes_with_crc(&data, Compression::Default).unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&data);
+        assert_eq!(crc, hasher.finalize());
+        assert_eq!(len, data.len() as u64);
+
+        assert_eq!(
+            try_deflate_bytes_with_crc(&[], Compression::Fast)
+                .unwrap()
+                .1,
+            0
+        );
+    }
+
+    #[test]
+    fn into_slice() {
+        let data = get_test_data();
+        let expected = try_deflate_bytes_conf(&data, Compression::Default).unwrap();
+        let mut output = vec![0; expected.len() + 10];
+        let len = deflate_bytes_into(&data, &mut output, Compression::Default).unwrap();
+        assert!(output[..len] == expected[..]);
+
+        // The output fits exactly.
+        let len = deflate_bytes_into(&data, &mut output[..len], Compression::Default).unwrap();
+        assert_eq!(len, expected.len());
+
+        for &size in &[0, 1, 100, expected.len() - 1] {
+            match deflate_bytes_into(&data, &mut output[..size], Compression::Default) {
+                Err(Error::OutputTooSmall) => (),
+                res => panic!("{} bytes: {:?}", size, res),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_options() {
+        let data = get_test_data();
+        let mut options = CO::default();
+        options.lazy_if_less_than = u16::max_value();
+        match try_deflate_bytes_conf(&data, options) {
+            Err(Error::InvalidOptions(_)) => (),
+            _ => panic!("Expected invalid options error!"),
+        }
+        assert!(write::ZlibEncoder::try_new(Vec::new(), options).is_err());
+        // The panicking variants adjust the options instead.
+        #[allow(deprecated)]
+        let compressed = deflate_bytes_conf(&data, options);
+        assert!(decompress_to_end(&compressed) == data);
+    }
+
     fn chunk_test(chunk_size: usize, level: CompressionOptions) {
         let mut compressed = Vec::with_capacity(32000);
         let data = get_test_data();
@@ -413,13 +1317,133 @@ mod test {
             chunked_write(&mut compressor, &data, chunk_size);
             compressor.finish().unwrap();
         }
-        let compressed2 = deflate_bytes_zlib_conf(&data, level);
+        let compressed2 = try_deflate_bytes_zlib_conf(&data, level).unwrap();
         let res = decompress_zlib(&compressed);
         assert!(res == data);
         assert_eq!(compressed.len(), compressed2.len());
         assert!(compressed == compressed2);
     }
 
+    #[test]
+    fn zlib_dictionary() {
+        use inflate::{inflate_bytes_zlib, inflate_bytes_zlib_dict, InflateError};
+
+        let data = get_test_data();
+        let (dictionary, input) = data.split_at(40_000);
+        let input = &input[..2000];
+        for &options in &[CO::default(), CO::fast(), CO::high(), CO::rle()] {
+            let compressed = try_deflate_bytes_zlib_dict(input, dictionary, options).unwrap();
+            assert!(inflate_bytes_zlib_dict(&compressed, dictionary).unwrap() == input);
+            let without = try_deflate_bytes_zlib_conf(input, options).unwrap();
+            if options != CO::rle() {
+                assert!(compressed.len() < without.len());
+            }
+        }
+
+        let compressed = try_deflate_bytes_zlib_dict(input, dictionary, CO::default()).unwrap();
+        assert_eq!(compressed[1] & 0x20, 0x20);
+        assert_eq!(
+            compressed[2..6],
+            zlib::dictionary_id(dictionary).to_be_bytes()
+        );
+        assert_eq!(
+            inflate_bytes_zlib(&compressed),
+            Err(InflateError::PresetDictionary)
+        );
+        assert_eq!(
+            inflate_bytes_zlib_dict(&compressed, &dictionary[1..]),
+            Err(InflateError::WrongDictionary)
+        );
+
+        // Dictionaries shorter than the minimum match length, and empty input.
+        for dictionary in &[&b""[..], b"a", b"ab", b"abc"] {
+            for input in &[&b""[..], b"abcabcabc", b"aaaaaaaa"] {
+                let compressed =
+                    try_deflate_bytes_zlib_dict(input, dictionary, CO::default()).unwrap();
+                assert_eq!(
+                    inflate_bytes_zlib_dict(&compressed, dictionary).unwrap(),
+                    *input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compression_levels() {
+        let data = get_test_data();
+        let mut previous_len = usize::max_value();
+        for level in 0..=9 {
+            let compressed = try_deflate_bytes_conf(&data, CO::from_level(level)).unwrap();
+            assert!(decompress_to_end(&compressed) == data);
+            if level == 0 {
+                // Only stored blocks, which start with two zero bits after the final block bit.
+                assert_eq!(compressed[0] & 0b110, 0);
+                assert!(compressed.len() > data.len());
+            } else if level != 4 {
+                // Level 4 switches from greedy to lazy matching with fewer hash checks, like in
+                // zlib, so it's not necessarily smaller than level 3.
+                assert!(compressed.len() <= previous_len, "level {}", level);
+            }
+            previous_len = compressed.len();
+        }
+
+        let compressed = try_deflate_bytes_zlib_conf(&data, CO::from_level(0)).unwrap();
+        assert!(decompress_zlib(&compressed) == data);
+
+        // Level 0 stores even data that compresses extremely well, whatever the other options
+        // are set to.
+        let zeros = vec![0; 4 * 1024 * 1024];
+        for &options in &[
+            CO::from_level(0),
+            CO {
+                special: SpecialOptions::ForceStored,
+                window_bits: 9,
+                ..CO::high()
+            },
+        ] {
+            let compressed = try_deflate_bytes_conf(&zeros, options).unwrap();
+            assert_eq!(compressed[0] & 0b110, 0);
+            assert!(compressed.len() > zeros.len());
+            assert!(decompress_to_end(&compressed) == zeros);
+        }
+    }
+
+    #[test]
+    fn chunk_permutations() {
+        use test_support::assert_roundtrip_chunked;
+
+        let data = get_test_data();
+        for seed in 0..6 {
+            assert_roundtrip_chunked(&data, CO::default(), seed);
+            assert_roundtrip_chunked(&data, CO::fast(), seed);
+            assert_roundtrip_chunked(&data, CO::high(), seed);
+            assert_roundtrip_chunked(&data, CO::rle(), seed);
+            assert_roundtrip_chunked(&data, CO::huffman_only(), seed);
+        }
+        // Long runs of the same byte, like the data from issue 26.
+        let mut runs = vec![0; 100_000];
+        runs.extend((0..50_000u32).map(|n| (n / 1000) as u8));
+        for seed in 0..6 {
+            assert_roundtrip_chunked(&runs, CO::default(), seed);
+            assert_roundtrip_chunked(&runs, CO::rle(), seed);
+        }
+    }
+
+    #[test]
+    fn deflate_iter() {
+        let data = get_test_data();
+        for &chunk_size in &[1, 400, 32768, 70000] {
+            let compressed =
+                try_deflate_bytes_iter(data.chunks(chunk_size), CO::default()).unwrap();
+            assert!(compressed == try_deflate_bytes(&data).unwrap());
+        }
+        let empty: [&[u8]; 2] = [&[], &[]];
+        assert!(decompress_to_end(
+            &try_deflate_bytes_iter(empty.iter().cloned(), CO::default()).unwrap()
+        )
+        .is_empty());
+    }
+
     fn writer_chunks_level(level: CompressionOptions) {
         use input_buffer::BUFFER_SIZE;
         let ct = |n| chunk_test(n, level);
@@ -444,14 +1468,15 @@ mod test {
     /// Check that the frequency values don't overflow.
     #[test]
     fn frequency_overflow() {
-        let _ = deflate_bytes_conf(
+        let _ = try_deflate_bytes_conf(
             &vec![5; 100000],
             compression_options::CompressionOptions::default(),
-        );
+        )
+        .unwrap();
     }
 
     fn roundtrip_zlib(data: &[u8], level: CompressionOptions) {
-        let compressed = deflate_bytes_zlib_conf(data, level);
+        let compressed = try_deflate_bytes_zlib_conf(data, level).unwrap();
         let res = decompress_zlib(&compressed);
         if data.len() <= 32 {
             assert_eq!(res, data, "Failed with level: {:?}", level);
@@ -6,22 +6,31 @@ use std::iter::{self, Iterator};
 use std::ops::{Range, RangeFrom};
 use std::slice::Iter;
 
-use crate::chained_hash_table::{update_hash, ChainedHashTable};
+use crate::buffer_alloc::BufBox;
+use crate::chained_hash_table::{ChainedHashTable, HashFunction, Tables};
 use crate::compress::Flush;
+use crate::compression_options::OutputVersion;
 #[cfg(test)]
-use crate::compression_options::{HIGH_LAZY_IF_LESS_THAN, HIGH_MAX_HASH_CHECKS};
+use crate::compression_options::{
+    HIGH_LAZY_IF_LESS_THAN, HIGH_MAX_HASH_CHECKS, MAX_DISTANCE, MAX_WINDOW_BITS,
+};
 use crate::input_buffer::InputBuffer;
 #[cfg(test)]
 use crate::lzvalue::{LZType, LZValue};
-use crate::matching::longest_match;
+use crate::matching::{get_match_length, longest_match};
+use crate::optimal::{process_chunk_optimal, OptimalState};
 use crate::output_writer::{BufferStatus, DynamicWriter};
-use crate::rle::process_chunk_greedy_rle;
+use crate::rle::{get_match_length_rle, process_chunk_greedy_rle};
+use crate::tuning::{self, DEFAULT_TOO_FAR};
 
 const MAX_MATCH: usize = crate::huffman_table::MAX_MATCH as usize;
 const MIN_MATCH: usize = crate::huffman_table::MIN_MATCH as usize;
 
 const NO_RLE: u16 = 43212;
 
+/// The lowest number of hash checks the adaptive search depth goes down to.
+const MIN_ADAPTIVE_HASH_CHECKS: u16 = 4;
+
 /// An enum describing whether we use lazy or greedy matching.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum MatchingType {
@@ -32,8 +41,17 @@ pub enum MatchingType {
     /// if there is a better match starting at that byte.
     ///
     /// As a special case, if max_hash_checks is set to 0, compression using only run-length
-    /// (i.e maximum match distance of 1) is performed instead.
+    /// (i.e maximum match distance of 4, or 1 with `OutputVersion::V1`) is performed instead.
     Lazy,
+    /// Use optimal parsing: all the matches in a piece of the input are found first, and the
+    /// combination of literals and matches that gives the smallest output is then chosen by
+    /// finding the cheapest path through them. The cost of each symbol is estimated from the
+    /// result of the previous attempt, and this is repeated a few times, similar to what zopfli
+    /// does.
+    ///
+    /// This gives the best compression, but is several times slower than lazy matching.
+    /// `lazy_if_less_than` is not used.
+    OptimalParse,
 }
 
 impl fmt::Display for MatchingType {
@@ -41,6 +59,7 @@ impl fmt::Display for MatchingType {
         match *self {
             MatchingType::Greedy => write!(f, "Greedy matching"),
             MatchingType::Lazy => write!(f, "Lazy matching"),
+            MatchingType::OptimalParse => write!(f, "Optimal parsing"),
         }
     }
 }
@@ -59,18 +78,41 @@ pub struct LZ77State {
     current_block_input_bytes: u64,
     /// The maximum number of hash entries to search.
     max_hash_checks: u16,
+    /// The number of hash entries currently searched, which is lowered from `max_hash_checks`
+    /// while the data doesn't compress well if `adaptive_search` is set.
+    search_depth: u16,
+    /// Whether to adjust the search depth depending on how well the recent data compressed.
+    adaptive_search: bool,
+    /// Whether to output runs of a single byte directly, without searching the hash chains.
+    fast_runs: bool,
+    /// Whether to use the single probe matching of `process_chunk_fast`.
+    fast_path: bool,
+    /// Whether RLE mode also looks for repeating patterns of more than one byte.
+    rle_patterns: bool,
+    /// Whether to use the values tuned by the `tune-text` and `tune-binary` features.
+    tuned: bool,
     /// Only lazy match if we have a match length less than this.
     lazy_if_less_than: u16,
     /// Whether to use greedy or lazy parsing
     matching_type: MatchingType,
+    /// The maximum distance back matches may refer to.
+    max_distance: usize,
+    /// The size of the windows the input is processed in, which is how far the input buffer and
+    /// hash chains are slid at a time.
+    window_size: usize,
     /// Keep track of the previous match and byte in case the buffer is full when lazy matching.
     match_state: ChunkState,
+    /// Buffers and symbol statistics used with optimal parsing.
+    optimal_state: OptimalState,
     /// Keep track of how many bytes in the lookahead that was part of a match, but has not been
     /// added to the hash chain yet.
     bytes_to_hash: usize,
     /// Keep track of if sync flush was used. If this is the case, the two first bytes needs to be
     /// hashed.
     was_synced: bool,
+    /// Set if the hash chains were filled from a preset dictionary, in which case they must not
+    /// be warmed up with the two first bytes of the buffer.
+    has_dictionary: bool,
 }
 
 impl LZ77State {
@@ -79,19 +121,52 @@ impl LZ77State {
         max_hash_checks: u16,
         lazy_if_less_than: u16,
         matching_type: MatchingType,
+        window_bits: u8,
+        max_search_distance: u16,
+        hash_function: HashFunction,
+    ) -> LZ77State {
+        LZ77State::with_hash_table(
+            ChainedHashTable::with_hash_function(hash_function),
+            max_hash_checks,
+            lazy_if_less_than,
+            matching_type,
+            window_bits,
+            max_search_distance,
+        )
+    }
+
+    /// Creates a new LZ77 state using the provided hash table.
+    pub fn with_hash_table(
+        hash_table: ChainedHashTable,
+        max_hash_checks: u16,
+        lazy_if_less_than: u16,
+        matching_type: MatchingType,
+        window_bits: u8,
+        max_search_distance: u16,
     ) -> LZ77State {
+        debug_assert!(1 << window_bits <= DEFAULT_WINDOW_SIZE);
         LZ77State {
-            hash_table: ChainedHashTable::new(),
+            hash_table,
             is_first_window: true,
             is_last_block: false,
             overlap: 0,
             current_block_input_bytes: 0,
             max_hash_checks,
+            search_depth: max_hash_checks,
+            adaptive_search: false,
+            fast_runs: false,
+            fast_path: false,
+            rle_patterns: false,
+            tuned: false,
             lazy_if_less_than,
             matching_type,
+            max_distance: cmp::min(1 << window_bits, usize::from(max_search_distance)),
+            window_size: DEFAULT_WINDOW_SIZE,
             match_state: ChunkState::new(),
+            optimal_state: OptimalState::new(),
             bytes_to_hash: 0,
             was_synced: false,
+            has_dictionary: false,
         }
     }
 
@@ -102,8 +177,117 @@ impl LZ77State {
         self.is_last_block = false;
         self.overlap = 0;
         self.current_block_input_bytes = 0;
+        self.search_depth = self.max_hash_checks;
         self.match_state = ChunkState::new();
-        self.bytes_to_hash = 0
+        self.optimal_state.reset();
+        self.bytes_to_hash = 0;
+        self.was_synced = false;
+        self.has_dictionary = false;
+    }
+
+    /// Set whether to lower the number of hash checks while the data doesn't compress well.
+    pub fn set_adaptive_search(&mut self, adaptive: bool) {
+        self.adaptive_search = adaptive;
+        self.search_depth = self.max_hash_checks;
+    }
+
+    /// Set whether to output runs of a single byte as matches of the maximum length straight away.
+    ///
+    /// These are the matches the hash chains would give anyhow, but finding them that way
+    /// means searching and adding hashes for every byte of the run, which makes compressing e.g
+    /// zero-filled data much slower than it needs to be.
+    pub fn set_fast_runs(&mut self, fast_runs: bool) {
+        self.fast_runs = fast_runs;
+    }
+
+    /// Set whether to use `process_chunk_fast` instead of greedy matching.
+    ///
+    /// This only has an effect when using greedy matching with a single hash check.
+    pub fn set_fast_path(&mut self, fast_path: bool) {
+        self.fast_path =
+            fast_path && self.matching_type == MatchingType::Greedy && self.max_hash_checks == 1;
+    }
+
+    /// Output every byte as a literal without looking for matches, for when the blocks are output
+    /// as stored blocks anyhow.
+    pub fn set_literals_only(&mut self) {
+        self.matching_type = MatchingType::Greedy;
+        self.max_hash_checks = 0;
+        self.search_depth = 0;
+        self.adaptive_search = false;
+        self.fast_runs = false;
+        self.fast_path = false;
+    }
+
+    /// Set whether RLE mode also looks for repeating patterns of up to four bytes, rather than
+    /// only runs of a single byte.
+    pub fn set_rle_patterns(&mut self, rle_patterns: bool) {
+        self.rle_patterns = rle_patterns;
+    }
+
+    /// Set whether to use the lazy matching threshold and `TOO_FAR` value tuned by the `tune-text`
+    /// and `tune-binary` features, see the `tuning` module.
+    pub fn set_tuned(&mut self, tuned: bool) {
+        self.tuned = tuned;
+    }
+
+    /// Set the size of the windows the input is processed in, see `window_size`.
+    ///
+    /// The input buffer used with the state must use the same window size.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        debug_assert!(window_size >= MIN_WINDOW_SIZE && window_size <= DEFAULT_WINDOW_SIZE);
+        debug_assert!(self.max_distance <= window_size);
+        self.window_size = window_size;
+        self.hash_table.set_window_size(window_size);
+    }
+
+    /// Adjust the search depth after `bytes` bytes of input were turned into `symbols` literals
+    /// and matches.
+    ///
+    /// When nearly every byte ends up as a literal, long searches through the hash chains are
+    /// mostly wasted, so the depth is halved. As soon as matches are found again, it's restored
+    /// to `max_hash_checks`.
+    fn adjust_search_depth(&mut self, bytes: usize, symbols: usize) {
+        if bytes < 1024 {
+            // Too little data to tell.
+            return;
+        }
+        if symbols * 8 > bytes * 7 {
+            let min = cmp::min(MIN_ADAPTIVE_HASH_CHECKS, self.max_hash_checks);
+            self.search_depth = cmp::max(self.search_depth / 2, min);
+        } else if symbols * 4 < bytes * 3 {
+            self.search_depth = self.max_hash_checks;
+        }
+    }
+
+    /// Fill the window with the last window size bytes of `dictionary`, so the data compressed
+    /// afterwards can refer back to it as if it had been compressed just before.
+    ///
+    /// This leaves the state as if the dictionary had been compressed and sync flushed, which
+    /// means the last two bytes are hashed once the data following them is known.
+    /// Must be called before any data is added to `buffer`.
+    pub fn set_dictionary(&mut self, dictionary: &[u8], buffer: &mut InputBuffer) {
+        debug_assert!(self.is_first_window && buffer.current_end() == 0);
+        let dictionary = &dictionary[dictionary.len().saturating_sub(self.window_size)..];
+        buffer.add_data(dictionary);
+        // Dictionaries of two bytes or less are too short to be hashed on their own, so only
+        // matches found without the hash chains can refer back to them.
+        if dictionary.len() > 2 {
+            self.hash_table
+                .add_initial_hash_values(dictionary[0], dictionary[1]);
+            for (n, &b) in dictionary[2..].iter().enumerate() {
+                self.hash_table.add_hash_value(n, b);
+            }
+            self.was_synced = true;
+            self.has_dictionary = true;
+        }
+        self.overlap = dictionary.len();
+    }
+
+    /// Take the hash chains out of the state so they can be reused, see
+    /// `ChainedHashTable::take_tables`.
+    pub fn take_tables(&mut self) -> BufBox<Tables> {
+        self.hash_table.take_tables()
     }
 
     pub fn set_last(&mut self) {
@@ -143,6 +327,22 @@ impl LZ77State {
 }
 
 const DEFAULT_WINDOW_SIZE: usize = 32768;
+/// The smallest window size the input can be processed in, as the last match in one window can
+/// extend up to `MAX_MATCH` bytes into the next.
+const MIN_WINDOW_SIZE: usize = 512;
+
+/// Returns the size of the windows to process the input in when compressing with a window of
+/// `1 << window_bits` bytes.
+///
+/// Smaller windows need a smaller input buffer, at the cost of sliding the buffer and the hash
+/// chains more often. Version 1 of the output always used 32 KiB windows, which can change
+/// which matches are found.
+pub fn window_size(window_bits: u8, output_version: OutputVersion) -> usize {
+    match output_version {
+        OutputVersion::V1 => DEFAULT_WINDOW_SIZE,
+        OutputVersion::Latest => cmp::max(1 << window_bits, MIN_WINDOW_SIZE),
+    }
+}
 
 #[derive(Debug)]
 /// Status after calling `process_chunk`.
@@ -193,11 +393,17 @@ fn process_chunk(
     data: &[u8],
     iterated_data: &Range<usize>,
     mut match_state: &mut ChunkState,
+    optimal_state: &mut OptimalState,
     hash_table: &mut ChainedHashTable,
     writer: &mut DynamicWriter,
     max_hash_checks: u16,
     lazy_if_less_than: usize,
     matching_type: MatchingType,
+    max_distance: usize,
+    too_far: usize,
+    fast_runs: bool,
+    fast_path: bool,
+    rle_patterns: bool,
 ) -> (usize, ProcessStatus) {
     let avoid_rle = if cfg!(test) {
         // Avoid RLE if lazy_if_less than is a specific value.
@@ -209,9 +415,24 @@ fn process_chunk(
         false
     };
     match matching_type {
-        MatchingType::Greedy => {
-            process_chunk_greedy(data, iterated_data, hash_table, writer, max_hash_checks)
-        }
+        MatchingType::Greedy if fast_path => process_chunk_fast(
+            data,
+            iterated_data,
+            hash_table,
+            writer,
+            max_distance,
+            too_far,
+        ),
+        MatchingType::Greedy => process_chunk_greedy(
+            data,
+            iterated_data,
+            hash_table,
+            writer,
+            max_hash_checks,
+            max_distance,
+            too_far,
+            fast_runs,
+        ),
         MatchingType::Lazy => {
             if max_hash_checks > 0 || avoid_rle {
                 process_chunk_lazy(
@@ -222,12 +443,26 @@ fn process_chunk(
                     writer,
                     max_hash_checks,
                     lazy_if_less_than,
+                    max_distance,
+                    too_far,
+                    fast_runs,
                 )
             } else {
-                // Use the RLE method if max_hash_checks is set to 0.
-                process_chunk_greedy_rle(data, iterated_data, writer)
+                // Use the RLE method if max_hash_checks is set to 0. Limiting the distance to 1
+                // only looks for runs of a single byte.
+                let rle_distance = if rle_patterns { max_distance } else { 1 };
+                process_chunk_greedy_rle(data, iterated_data, writer, rle_distance)
             }
         }
+        MatchingType::OptimalParse => process_chunk_optimal(
+            data,
+            iterated_data,
+            optimal_state,
+            hash_table,
+            writer,
+            max_hash_checks,
+            max_distance,
+        ),
     }
 }
 
@@ -241,18 +476,39 @@ fn add_to_hash_table(
 ) {
     let taker = insert_it.by_ref().take(bytes_to_add);
     let mut hash_taker = hash_it.by_ref().take(bytes_to_add);
-    // Update the hash manually here to keep it in a register.
-    let mut hash = hash_table.current_hash();
     // Advance the iterators and add the bytes we jump over to the hash table and
     // checksum
     for (ipos, _) in taker {
         if let Some(&i_hash_byte) = hash_taker.next() {
-            hash = update_hash(hash, i_hash_byte);
-            hash_table.add_with_hash(ipos, hash);
+            hash_table.add_hash_value(ipos, i_hash_byte);
         }
     }
-    // Write the hash back once we are done.
-    hash_table.set_hash(hash);
+}
+
+/// Returns true if the `MAX_MATCH` bytes starting at `position` are all the same as the byte
+/// before it, and end before `end`.
+#[inline]
+fn is_full_run(data: &[u8], position: usize, end: usize) -> bool {
+    position > 0
+        && position + MAX_MATCH <= end
+        && data[position - 1] == data[position]
+        && get_match_length_rle(&data[position..], data[position]) == MAX_MATCH
+}
+
+/// Advance the iterators past the bytes of a run found by `is_full_run` after the first one.
+///
+/// The running hash is the same for every position inside the run, so only the last two
+/// positions, which are hashed together with the bytes after the run, are added to the hash
+/// table. The rest would only make the hash chain of the run longer.
+fn skip_run(
+    insert_it: &mut iter::Zip<RangeFrom<usize>, Iter<u8>>,
+    hash_it: &mut Iter<u8>,
+    hash_table: &mut ChainedHashTable,
+) {
+    let skip = MAX_MATCH - 3;
+    insert_it.nth(skip - 1);
+    hash_it.nth(skip - 1);
+    add_to_hash_table(2, insert_it, hash_it, hash_table);
 }
 
 /// Write the specified literal `byte` to the writer `w`, and return
@@ -269,12 +525,11 @@ macro_rules! write_literal {
     };
 }
 
-/// If the match is only 3 bytes long and the distance is more than 8 * 1024, it's likely to take
-/// up more space than it would save.
+/// If the match is only 3 bytes long and the distance is more than `too_far` (8 * 1024 unless
+/// tuned for a particular kind of data), it's likely to take up more space than it would save.
 #[inline]
-fn match_too_far(match_len: usize, match_dist: usize) -> bool {
-    const TOO_FAR: usize = 8 * 1024;
-    match_len == MIN_MATCH && match_dist > TOO_FAR
+fn match_too_far(match_len: usize, match_dist: usize, too_far: usize) -> bool {
+    match_len == MIN_MATCH && match_dist > too_far
 }
 
 ///Create the iterators used when processing through a chunk of data.
@@ -302,6 +557,7 @@ fn create_iterators<'a>(
     (end, insert_it, hash_it)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_chunk_lazy(
     data: &[u8],
     iterated_data: &Range<usize>,
@@ -310,6 +566,9 @@ fn process_chunk_lazy(
     writer: &mut DynamicWriter,
     max_hash_checks: u16,
     lazy_if_less_than: usize,
+    max_distance: usize,
+    too_far: usize,
+    fast_runs: bool,
 ) -> (usize, ProcessStatus) {
     let (end, mut insert_it, mut hash_it) = create_iterators(data, iterated_data);
 
@@ -342,6 +601,28 @@ fn process_chunk_lazy(
         if let Some(&hash_byte) = hash_it.next() {
             hash_table.add_hash_value(position, hash_byte);
 
+            // There is no better match than a run of the maximum length, so output it right
+            // away unless there is a previous match to deal with.
+            if fast_runs && prev_length < MIN_MATCH as u16 && is_full_run(data, position, end) {
+                if state.add {
+                    write_literal!(writer, state.prev_byte, position + 1);
+                }
+                let b_status = writer.write_length_distance(MAX_MATCH as u16, 1);
+                skip_run(&mut insert_it, &mut hash_it, hash_table);
+
+                state.add = false;
+                state.current_length = NO_LENGTH;
+                state.current_distance = 0;
+                prev_length = NO_LENGTH;
+                prev_distance = 0;
+                ignore_next = false;
+
+                if let BufferStatus::Full = b_status {
+                    return (0, buffer_full(position + MAX_MATCH));
+                }
+                continue;
+            }
+
             // Only lazy match if we have a match shorter than a set value
             // TODO: This should be cleaned up a bit
             if !ignore_next {
@@ -354,6 +635,11 @@ fn process_chunk_lazy(
                         max_hash_checks
                     };
 
+                    instrument!(
+                        lazy_searches,
+                        u64::from(usize::from(prev_length) >= MIN_MATCH)
+                    );
+
                     // Check if we can find a better match here than the one we had at
                     // the previous byte.
                     longest_match(
@@ -362,12 +648,13 @@ fn process_chunk_lazy(
                         position,
                         prev_length as usize,
                         max_hash_checks,
+                        max_distance,
                     )
                 };
 
                 // If the match is only 3 bytes long and very far back, it's probably not worth
                 // outputting.
-                if match_too_far(match_len, match_dist) {
+                if match_too_far(match_len, match_dist, too_far) {
                     match_len = NO_LENGTH as usize;
                 };
 
@@ -485,12 +772,16 @@ fn process_chunk_lazy(
     (overlap, ProcessStatus::Ok)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_chunk_greedy(
     data: &[u8],
     iterated_data: &Range<usize>,
-    mut hash_table: &mut ChainedHashTable,
+    hash_table: &mut ChainedHashTable,
     writer: &mut DynamicWriter,
     max_hash_checks: u16,
+    max_distance: usize,
+    too_far: usize,
+    fast_runs: bool,
 ) -> (usize, ProcessStatus) {
     let (end, mut insert_it, mut hash_it) = create_iterators(data, iterated_data);
 
@@ -505,11 +796,22 @@ fn process_chunk_greedy(
         if let Some(&hash_byte) = hash_it.next() {
             hash_table.add_hash_value(position, hash_byte);
 
+            let run = fast_runs && is_full_run(data, position, end);
             // TODO: This should be cleaned up a bit.
-            let (match_len, match_dist) =
-                { longest_match(data, hash_table, position, NO_LENGTH, max_hash_checks) };
+            let (match_len, match_dist) = if run {
+                (MAX_MATCH, 1)
+            } else {
+                longest_match(
+                    data,
+                    hash_table,
+                    position,
+                    NO_LENGTH,
+                    max_hash_checks,
+                    max_distance,
+                )
+            };
 
-            if match_len >= MIN_MATCH as usize && !match_too_far(match_len, match_dist) {
+            if match_len >= MIN_MATCH as usize && !match_too_far(match_len, match_dist, too_far) {
                 // Casting note: length and distance is already bounded by the longest match
                 // function. Usize is just used for convenience.
                 let b_status = writer.write_length_distance(match_len as u16, match_dist as u16);
@@ -517,8 +819,12 @@ fn process_chunk_greedy(
                 // We add the bytes to the hash table and checksum.
                 // Since we've already added one of them, we need to add one less than
                 // the length.
-                let bytes_to_add = match_len - 1;
-                add_to_hash_table(bytes_to_add, &mut insert_it, &mut hash_it, &mut hash_table);
+                if run {
+                    skip_run(&mut insert_it, &mut hash_it, hash_table);
+                } else {
+                    let bytes_to_add = match_len - 1;
+                    add_to_hash_table(bytes_to_add, &mut insert_it, &mut hash_it, hash_table);
+                }
 
                 // If the match is longer than the current window, we have note how many
                 // bytes we overlap, since we don't need to do any matching on these bytes
@@ -546,6 +852,65 @@ fn process_chunk_greedy(
     (overlap, ProcessStatus::Ok)
 }
 
+/// LZ77-compress the data in `iterated_data` looking for a match at only one earlier position for
+/// each byte, like the fastest levels of zlib and miniz do.
+///
+/// Only the positions matches are looked for at are added to the hash table, so the bytes covered
+/// by a match are skipped entirely, which makes this a lot faster than greedy matching with one
+/// hash check on data with many matches. Matches don't extend past the end of the range, so there
+/// is never any overlap into the next window.
+fn process_chunk_fast(
+    data: &[u8],
+    iterated_data: &Range<usize>,
+    hash_table: &mut ChainedHashTable,
+    writer: &mut DynamicWriter,
+    max_distance: usize,
+    too_far: usize,
+) -> (usize, ProcessStatus) {
+    let end = cmp::min(data.len(), iterated_data.end);
+    let window = &data[..end];
+    let mut position = iterated_data.start;
+    while position < end {
+        if position + 2 >= data.len() {
+            // We are at the last two bytes, so there is nothing to hash.
+            write_literal!(writer, data[position], position + 1);
+            position += 1;
+            continue;
+        }
+        let bytes = u32::from_be_bytes([0, data[position], data[position + 1], data[position + 2]]);
+        // The head of the hash chain may be left over from older data or another hash value,
+        // so the match is checked before use.
+        let candidate = usize::from(hash_table.insert_bytes(position, bytes));
+        let match_len = if candidate < position
+            && position - candidate <= max_distance
+            && position + MIN_MATCH <= end
+            && u32::from_be_bytes([
+                0,
+                window[candidate],
+                window[candidate + 1],
+                window[candidate + 2],
+            ]) == bytes
+        {
+            get_match_length(window, position, candidate)
+        } else {
+            0
+        };
+
+        if match_len >= MIN_MATCH && !match_too_far(match_len, position - candidate, too_far) {
+            let b_status =
+                writer.write_length_distance(match_len as u16, (position - candidate) as u16);
+            position += match_len;
+            if let BufferStatus::Full = b_status {
+                return (0, buffer_full(position));
+            }
+        } else {
+            write_literal!(writer, data[position], position + 1);
+            position += 1;
+        }
+    }
+    (0, ProcessStatus::Ok)
+}
+
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum LZ77Status {
     /// Waiting for more input before doing any processing
@@ -557,18 +922,59 @@ pub enum LZ77Status {
     Finished,
 }
 
-#[cfg(test)]
 pub fn lz77_compress_block_finish(
     data: &[u8],
     state: &mut LZ77State,
     buffer: &mut InputBuffer,
-    mut writer: &mut DynamicWriter,
+    writer: &mut DynamicWriter,
 ) -> (usize, LZ77Status) {
-    let (consumed, status, _) =
-        lz77_compress_block(data, state, buffer, &mut writer, Flush::Finish);
+    let (consumed, status, _) = lz77_compress_block(data, state, buffer, writer, Flush::Finish);
     (consumed, status)
 }
 
+/// Add `data` to the window and the hash chains without looking for matches in it, so the data
+/// compressed afterwards can refer back to it.
+///
+/// Must be called at the start of a block. The literals produced are discarded, so the caller
+/// has to output `data` some other way, e.g as stored blocks.
+pub fn lz77_add_without_matching(
+    mut data: &[u8],
+    state: &mut LZ77State,
+    buffer: &mut InputBuffer,
+    writer: &mut DynamicWriter,
+) {
+    debug_assert!(writer.buffer_length() == 0);
+    let matching_type = state.matching_type;
+    let search_depth = state.search_depth;
+    let (adaptive_search, fast_runs, fast_path) =
+        (state.adaptive_search, state.fast_runs, state.fast_path);
+    // Greedy matching without any hash checks adds every position to the hash chains and outputs
+    // only literals. RLE mode doesn't use the hash chains, so it only needs the data in the window.
+    if state.max_hash_checks > 0 {
+        state.matching_type = MatchingType::Greedy;
+    }
+    state.search_depth = 0;
+    state.adaptive_search = false;
+    state.fast_runs = false;
+    state.fast_path = false;
+
+    loop {
+        let (consumed, status, _) = lz77_compress_block(data, state, buffer, writer, Flush::Block);
+        writer.clear();
+        state.reset_input_bytes();
+        data = &data[consumed..];
+        if status == LZ77Status::Finished && data.is_empty() {
+            break;
+        }
+    }
+
+    state.matching_type = matching_type;
+    state.search_depth = search_depth;
+    state.adaptive_search = adaptive_search;
+    state.fast_runs = fast_runs;
+    state.fast_path = fast_path;
+}
+
 /// Compress a slice with lz77 compression.
 ///
 /// This function processes one window at a time, and returns when there is no input left,
@@ -582,16 +988,23 @@ pub fn lz77_compress_block(
     data: &[u8],
     state: &mut LZ77State,
     buffer: &mut InputBuffer,
-    mut writer: &mut DynamicWriter,
+    writer: &mut DynamicWriter,
     flush: Flush,
 ) -> (usize, LZ77Status, usize) {
-    // Currently we only support the maximum window size
-    let window_size = DEFAULT_WINDOW_SIZE;
+    let window_size = state.window_size;
+    let (lazy_if_less_than, too_far) = if state.tuned {
+        (
+            usize::from(tuning::lazy_if_less_than(state.lazy_if_less_than)),
+            tuning::TOO_FAR,
+        )
+    } else {
+        (usize::from(state.lazy_if_less_than), DEFAULT_TOO_FAR)
+    };
 
     // Indicates whether we should try to process all the data including the lookahead, or if we
     // should wait until we have at least one window size of data before doing anything.
-    let finish = flush == Flush::Finish || flush == Flush::Sync;
-    let sync = flush == Flush::Sync;
+    let sync = flush == Flush::Sync || flush == Flush::Block || flush == Flush::Partial;
+    let finish = flush == Flush::Finish || sync;
 
     let mut current_position = 0;
 
@@ -621,7 +1034,7 @@ pub fn lz77_compress_block(
         // so we get the block input size right.
         let pending_previous = state.pending_byte_as_num();
 
-        assert!(writer.buffer_length() <= (window_size * 2));
+        assert!(writer.buffer_length() <= (DEFAULT_WINDOW_SIZE * 2));
         // Don't do anything until we are either flushing, or we have at least one window of
         // data.
         if buffer.current_end() >= (window_size * 2) + MAX_MATCH || finish {
@@ -629,6 +1042,7 @@ pub fn lz77_compress_block(
                 if buffer.get_buffer().len() >= 2
                     && add_initial
                     && state.current_block_input_bytes == 0
+                    && !state.has_dictionary
                 {
                     let b = buffer.get_buffer();
                     // Warm up the hash with the two first values, so we can find  matches at
@@ -655,20 +1069,36 @@ pub fn lz77_compress_block(
             let start = state.overlap + window_start;
             let end = cmp::min(window_size + window_start, buffer.current_end());
 
+            let symbols_before = writer.buffer_length();
             let (overlap, p_status) = process_chunk(
                 buffer.get_buffer(),
                 &(start..end),
                 &mut state.match_state,
+                &mut state.optimal_state,
                 &mut state.hash_table,
-                &mut writer,
-                state.max_hash_checks,
-                state.lazy_if_less_than as usize,
+                writer,
+                state.search_depth,
+                lazy_if_less_than,
                 state.matching_type,
+                state.max_distance,
+                too_far,
+                state.fast_runs,
+                state.fast_path,
+                state.rle_patterns,
             );
+            if state.adaptive_search {
+                let processed = match p_status {
+                    ProcessStatus::BufferFull(written) => written,
+                    ProcessStatus::Ok => end,
+                };
+                let symbols = writer.buffer_length() - symbols_before;
+                state.adjust_search_depth(processed.saturating_sub(start), symbols);
+            }
 
             state.bytes_to_hash = overlap;
 
             if let ProcessStatus::BufferFull(written) = p_status {
+                instrument!(lz77_buffer_full);
                 state.current_block_input_bytes +=
                     (written - start + pending_previous - state.pending_byte_as_num()) as u64;
 
@@ -843,12 +1273,39 @@ impl TestStruct {
         matching_type: MatchingType,
     ) -> TestStruct {
         TestStruct {
-            state: LZ77State::new(max_hash_checks, lazy_if_less_than, matching_type),
+            state: LZ77State::new(
+                max_hash_checks,
+                lazy_if_less_than,
+                matching_type,
+                MAX_WINDOW_BITS,
+                MAX_DISTANCE,
+                HashFunction::ShiftXor,
+            ),
             buffer: InputBuffer::empty(),
             writer: DynamicWriter::new(),
         }
     }
 
+    /// Compress all of `data`, returning the output.
+    fn compress_all(&mut self, data: &[u8]) -> Vec<LZValue> {
+        let mut out = Vec::<LZValue>::with_capacity(data.len() / 3);
+        let mut slice = data;
+
+        while !self.state.is_last_block {
+            let bytes_written = lz77_compress_block_finish(
+                slice,
+                &mut self.state,
+                &mut self.buffer,
+                &mut self.writer,
+            )
+            .0;
+            slice = &slice[bytes_written..];
+            out.extend(self.writer.get_buffer());
+            self.writer.clear();
+        }
+        out
+    }
+
     fn compress_block(&mut self, data: &[u8], flush: bool) -> (usize, LZ77Status, usize) {
         lz77_compress_block(
             data,
@@ -886,25 +1343,7 @@ pub fn lz77_compress_conf(
         lazy_if_less_than,
         matching_type,
     ));
-    let mut out = Vec::<LZValue>::with_capacity(data.len() / 3);
-    {
-        let test = test_boxed.as_mut();
-        let mut slice = data;
-
-        while !test.state.is_last_block {
-            let bytes_written = lz77_compress_block_finish(
-                slice,
-                &mut test.state,
-                &mut test.buffer,
-                &mut test.writer,
-            )
-            .0;
-            slice = &slice[bytes_written..];
-            out.extend(test.writer.get_buffer());
-            test.writer.clear();
-        }
-    }
-
+    let out = test_boxed.compress_all(data);
     Some(out)
 }
 
@@ -913,10 +1352,10 @@ mod test {
     use super::*;
 
     use crate::chained_hash_table::WINDOW_SIZE;
-    use crate::compression_options::DEFAULT_LAZY_IF_LESS_THAN;
+    use crate::compression_options::{DEFAULT_LAZY_IF_LESS_THAN, DEFAULT_MAX_HASH_CHECKS};
     use crate::lzvalue::{ld, lit, LZType, LZValue};
     use crate::output_writer::MAX_BUFFER_LENGTH;
-    use crate::test_utils::get_test_data;
+    use crate::test_utils::{get_test_data, noise};
 
     /// Helper function to print the output from the lz77 compression function
     fn print_output(input: &[LZValue]) {
@@ -1040,7 +1479,14 @@ mod test {
         let mut writer = DynamicWriter::new();
 
         let mut buffer = InputBuffer::empty();
-        let mut state = LZ77State::new(4096, DEFAULT_LAZY_IF_LESS_THAN, MatchingType::Lazy);
+        let mut state = LZ77State::new(
+            4096,
+            DEFAULT_LAZY_IF_LESS_THAN,
+            MatchingType::Lazy,
+            MAX_WINDOW_BITS,
+            MAX_DISTANCE,
+            HashFunction::ShiftXor,
+        );
         let status = lz77_compress_block_finish(data, &mut state, &mut buffer, &mut writer);
         assert_eq!(status.1, LZ77Status::Finished);
         assert!(&buffer.get_buffer()[..data.len()] == data);
@@ -1057,7 +1503,14 @@ mod test {
         let mut writer = DynamicWriter::new();
 
         let mut buffer = InputBuffer::empty();
-        let mut state = LZ77State::new(0, DEFAULT_LAZY_IF_LESS_THAN, MatchingType::Lazy);
+        let mut state = LZ77State::new(
+            0,
+            DEFAULT_LAZY_IF_LESS_THAN,
+            MatchingType::Lazy,
+            MAX_WINDOW_BITS,
+            MAX_DISTANCE,
+            HashFunction::ShiftXor,
+        );
         let (bytes_consumed, status) =
             lz77_compress_block_finish(&data, &mut state, &mut buffer, &mut writer);
         assert_eq!(
@@ -1221,6 +1674,126 @@ mod test {
         // ------------l2 l4  <-ld4,20-> l1 l1  <---ld5,10-->
         assert!(dec == [2, 4, 5, 5, 5, 5, 1, 1, 5, 5, 2, 4, 5]);
     }
+
+    /// Check that matches don't refer further back than the window size or search distance.
+    #[test]
+    fn small_window() {
+        let data = get_test_data();
+        for &(window_bits, max_search_distance, limit) in &[
+            (9, MAX_DISTANCE, 512),
+            (MAX_WINDOW_BITS, 100, 100),
+            (10, 2000, 1024),
+        ] {
+            for &matching_type in &[MatchingType::Lazy, MatchingType::Greedy] {
+                let mut test = TestStruct {
+                    state: LZ77State::new(
+                        HIGH_MAX_HASH_CHECKS,
+                        32,
+                        matching_type,
+                        window_bits,
+                        max_search_distance,
+                        HashFunction::ShiftXor,
+                    ),
+                    buffer: InputBuffer::empty(),
+                    writer: DynamicWriter::new(),
+                };
+                let compressed = test.compress_all(&data);
+                let mut found_match = false;
+                for v in &compressed {
+                    if let LZType::StoredLengthDistance(_, d) = v.value() {
+                        assert!(d <= limit, "Distance {} too large", d);
+                        found_match = true;
+                    }
+                }
+                assert!(found_match);
+                assert!(decompress_lz77(&compressed) == data);
+            }
+        }
+    }
+
+    #[test]
+    fn fast_runs() {
+        let text = get_test_data();
+        let mut data = Vec::new();
+        for (n, &run) in [1000, 70_000, 258, 300, 5, 40_000].iter().enumerate() {
+            data.extend_from_slice(&text[n * 3000..(n + 1) * 3000]);
+            data.extend(iter::repeat(n as u8).take(run));
+        }
+
+        for &matching_type in &[MatchingType::Greedy, MatchingType::Lazy] {
+            let mut test = TestStruct::with_config(
+                DEFAULT_MAX_HASH_CHECKS,
+                DEFAULT_LAZY_IF_LESS_THAN,
+                matching_type,
+            );
+            let slow = test.compress_all(&data);
+            let mut test = TestStruct::with_config(
+                DEFAULT_MAX_HASH_CHECKS,
+                DEFAULT_LAZY_IF_LESS_THAN,
+                matching_type,
+            );
+            test.state.set_fast_runs(true);
+            let fast = test.compress_all(&data);
+            assert!(decompress_lz77(&fast) == data);
+            assert!(fast.len() <= slow.len(), "{} > {}", fast.len(), slow.len());
+        }
+    }
+
+    #[test]
+    fn fast_path() {
+        let text = get_test_data();
+        let mut data = text[..50_000].to_vec();
+        data.extend(iter::repeat(0).take(70_000));
+        data.extend_from_slice(&text[20_000..]);
+
+        let mut test = TestStruct::with_config(1, 0, MatchingType::Greedy);
+        let greedy = test.compress_all(&data);
+        let mut test = TestStruct::with_config(1, 0, MatchingType::Greedy);
+        test.state.set_fast_path(true);
+        let fast = test.compress_all(&data);
+        assert!(decompress_lz77(&fast) == data);
+        assert!(fast != greedy);
+
+        // Only used with greedy matching and a single hash check.
+        let mut test = TestStruct::with_config(2, 0, MatchingType::Greedy);
+        test.state.set_fast_path(true);
+        assert!(!test.state.fast_path);
+    }
+
+    #[test]
+    fn adaptive_search_depth() {
+        let text = get_test_data();
+        let noise = noise(200_000);
+
+        let mut test = TestStruct::with_config(
+            DEFAULT_MAX_HASH_CHECKS,
+            DEFAULT_LAZY_IF_LESS_THAN,
+            MatchingType::Lazy,
+        );
+        test.state.set_adaptive_search(true);
+        let mut out = Vec::new();
+        let mut feed = |test: &mut TestStruct, mut data: &[u8]| {
+            while !data.is_empty() {
+                let (consumed, _, _) = test.compress_block(data, false);
+                data = &data[consumed..];
+                out.extend(test.writer.get_buffer());
+                test.writer.clear();
+            }
+        };
+
+        feed(&mut test, &text[..100_000]);
+        assert_eq!(test.state.search_depth, DEFAULT_MAX_HASH_CHECKS);
+        feed(&mut test, &noise);
+        assert_eq!(test.state.search_depth, MIN_ADAPTIVE_HASH_CHECKS);
+        feed(&mut test, &text[100_000..]);
+        assert_eq!(test.state.search_depth, DEFAULT_MAX_HASH_CHECKS);
+
+        out.extend(test.compress_all(&[]));
+        let mut expected = text[..100_000].to_vec();
+        expected.extend_from_slice(&noise);
+        expected.extend_from_slice(&text[100_000..]);
+        assert!(decompress_lz77(&out) == expected);
+    }
 }
 
 #[cfg(all(test, feature = "benchmarks"))]
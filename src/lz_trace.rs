@@ -0,0 +1,260 @@
+//! Recording the LZ77 tokens chosen by the encoder, and replaying them through the Huffman
+//! coding and bitstream stages.
+//!
+//! This is a debugging aid for working on the match finder: a trace recorded before and after
+//! a change shows exactly where the choice of matches differs, without differences in the
+//! block splitting or Huffman coding getting in the way. A trace can be stored compactly with
+//! [`to_bytes`](struct.LzTrace.html#method.to_bytes) to compare against later.
+//!
+//! Only available with the `lz-trace` feature.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use deflate::lz_trace::LzTrace;
+//! use deflate::Compression;
+//!
+//! let data = b"abcabcabcabc, abcabcabcabc";
+//! let default = LzTrace::record(data, Compression::Default);
+//! let fast = LzTrace::record(data, Compression::Fast);
+//! if let Some(position) = default.first_difference(&fast) {
+//!     println!("The matches differ from byte {}", position);
+//! }
+//! let compressed = default.replay().unwrap();
+//! # let _ = compressed;
+//! ```
+use std::io;
+
+use crate::compress::Flush;
+use crate::compression_options::CompressionOptions;
+use crate::deflate_state::DeflateState;
+use crate::error::Error;
+use crate::huffman_table::MIN_MATCH;
+use crate::lzvalue::{LZType, LZValue};
+use crate::tokens::{deflate_tokens, Token};
+use crate::writer::compress_until_done;
+
+/// The first bytes of a serialized trace.
+const MAGIC: &[u8; 4] = b"LZT\x01";
+
+/// A token in a trace, along with the position in the input of the data it represents.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct TraceEntry {
+    /// Position in the input of the first byte covered by the token.
+    pub position: u64,
+    /// The literal or match output at this position.
+    pub token: Token,
+}
+
+/// The sequence of LZ77 tokens output when compressing some data.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct LzTrace {
+    entries: Vec<TraceEntry>,
+    input_len: u64,
+}
+
+impl LzTrace {
+    /// Compress `data` using `options`, and record the tokens output by the LZ77 stage.
+    ///
+    /// Blocks the encoder decides to output as stored blocks are included as the tokens that
+    /// were found for them, as if they had been compressed.
+    pub fn record<O: Into<CompressionOptions>>(data: &[u8], options: O) -> LzTrace {
+        let mut deflate_state = DeflateState::new(options.into(), io::sink());
+        deflate_state.lz_trace = Some(Vec::new());
+        compress_until_done(data, &mut deflate_state, Flush::Finish)
+            .expect("Writing to io::Sink failed!");
+        let tokens = deflate_state.lz_trace.take().unwrap_or_default();
+        LzTrace::from_tokens(tokens)
+    }
+
+    fn from_tokens(tokens: Vec<Token>) -> LzTrace {
+        let mut position = 0;
+        let entries = tokens
+            .into_iter()
+            .map(|token| {
+                let entry = TraceEntry { position, token };
+                position += token.len();
+                entry
+            })
+            .collect();
+        LzTrace {
+            entries,
+            input_len: position,
+        }
+    }
+
+    /// The tokens in the trace, in the order they were output.
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    /// The total length of the data represented by the trace.
+    pub fn input_len(&self) -> u64 {
+        self.input_len
+    }
+
+    /// The position in the input of the first token that differs between the two traces, or
+    /// `None` if they are the same.
+    ///
+    /// If one trace is a prefix of the other, the position is the end of the shorter one.
+    pub fn first_difference(&self, other: &LzTrace) -> Option<u64> {
+        match self
+            .entries
+            .iter()
+            .zip(&other.entries)
+            .find(|(a, b)| a != b)
+        {
+            Some((a, _)) => Some(a.position),
+            None if self.entries.len() != other.entries.len() => {
+                Some(self.input_len.min(other.input_len))
+            }
+            None => None,
+        }
+    }
+
+    /// Compress the tokens in the trace to a raw DEFLATE stream, using the same Huffman coding
+    /// as [`deflate_tokens`](../fn.deflate_tokens.html).
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidToken` if one of the tokens is invalid, which can only happen if
+    /// the trace was read from corrupted data.
+    pub fn replay(&self) -> Result<Vec<u8>, Error> {
+        let tokens: Vec<Token> = self.entries.iter().map(|entry| entry.token).collect();
+        deflate_tokens(&tokens)
+    }
+
+    /// Serialize the trace.
+    ///
+    /// The positions are not stored, as they follow from the tokens. Each literal takes 2
+    /// bytes, and each match 2 to 4 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + self.entries.len() * 2);
+        bytes.extend_from_slice(MAGIC);
+        for entry in &self.entries {
+            match entry.token {
+                Token::Literal(l) => bytes.extend_from_slice(&[0, l]),
+                Token::Match { length, distance } => {
+                    write_varint(&mut bytes, u32::from(length - MIN_MATCH + 1));
+                    write_varint(&mut bytes, u32::from(distance));
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Read back a trace serialized with [`to_bytes`](#method.to_bytes), returning `None` if
+    /// `bytes` isn't a valid trace.
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<LzTrace> {
+        if !bytes.starts_with(MAGIC) {
+            return None;
+        }
+        bytes = &bytes[MAGIC.len()..];
+        let mut tokens = Vec::new();
+        while !bytes.is_empty() {
+            let token = match read_varint(&mut bytes)? {
+                0 => {
+                    let (&l, rest) = bytes.split_first()?;
+                    bytes = rest;
+                    Token::Literal(l)
+                }
+                length => Token::Match {
+                    length: to_u16(length + u32::from(MIN_MATCH) - 1)?,
+                    distance: to_u16(read_varint(&mut bytes)?)?,
+                },
+            };
+            tokens.push(token);
+        }
+        Some(LzTrace::from_tokens(tokens))
+    }
+}
+
+/// Convert a value from the LZ77 stage to a token.
+pub(crate) fn to_token(value: &LZValue) -> Token {
+    match value.value() {
+        LZType::Literal(l) => Token::Literal(l),
+        LZType::StoredLengthDistance(l, d) => Token::Match {
+            length: l.actual_length(),
+            distance: d,
+        },
+    }
+}
+
+/// Write `value` using 7 bits per byte, with the top bit set on all but the last byte.
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..21).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u32::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Convert a decoded value to `u16`, returning `None` if it doesn't fit.
+fn to_u16(value: u32) -> Option<u16> {
+    if value <= u32::from(u16::max_value()) {
+        Some(value as u16)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+    use crate::Compression;
+
+    #[test]
+    fn record_and_replay() {
+        let data = get_test_data();
+        let trace = LzTrace::record(&data, Compression::Default);
+        assert_eq!(trace.input_len(), data.len() as u64);
+        assert!(decompress_to_end(&trace.replay().unwrap()) == data);
+
+        let bytes = trace.to_bytes();
+        assert!(bytes.len() < data.len());
+        assert_eq!(LzTrace::from_bytes(&bytes), Some(trace.clone()));
+        assert_eq!(LzTrace::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(LzTrace::from_bytes(&bytes[1..]), None);
+
+        assert!(LzTrace::record(&[], Compression::Default)
+            .entries()
+            .is_empty());
+    }
+
+    #[test]
+    fn first_difference() {
+        let data = get_test_data();
+        let default = LzTrace::record(&data, Compression::Default);
+        assert_eq!(
+            default.first_difference(&LzTrace::record(&data, Compression::Default)),
+            None
+        );
+
+        let fast = LzTrace::record(&data, Compression::Fast);
+        let position = default.first_difference(&fast).unwrap();
+        let index = default
+            .entries()
+            .iter()
+            .position(|e| e.position == position)
+            .unwrap();
+        assert!(default.entries()[..index] == fast.entries()[..index]);
+        assert!(default.entries()[index] != fast.entries()[index]);
+
+        let prefix = LzTrace::record(&data[..1000], Compression::Default);
+        assert!(default.first_difference(&prefix).unwrap() <= 1000);
+    }
+}
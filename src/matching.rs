@@ -9,61 +9,9 @@ const MIN_MATCH: usize = crate::huffman_table::MIN_MATCH as usize;
 /// Get the length of the checked match
 /// The function returns number of bytes at and including `current_pos` that are the same as the
 /// ones at `pos_to_check`
+#[cfg(not(feature = "unsafe-speed"))]
 #[inline]
 pub fn get_match_length(data: &[u8], current_pos: usize, pos_to_check: usize) -> usize {
-    // Unsafe version using unaligned loads for comparison.
-    // Faster when benching the matching function alone,
-    // but not as significant when running the full thing.
-    /*
-        type Comp = u64;
-
-        use std::mem::size_of;
-
-        let max = cmp::min(data.len() - current_pos, MAX_MATCH);
-        let mut left = max;
-        let s = size_of::<Comp>();
-
-        unsafe {
-            let mut cur = data.as_ptr().offset(current_pos as isize);
-            let mut tc = data.as_ptr().offset(pos_to_check as isize);
-            while left >= s &&
-                  (*(cur as *const Comp) == *(tc as *const Comp)) {
-                      left -= s;
-                      cur = cur.offset(s as isize);
-                      tc = tc.offset(s as isize);
-                  }
-            while left > 0 && *cur == *tc {
-                left -= 1;
-                cur = cur.offset(1);
-                tc = tc.offset(1);
-            }
-        }
-
-        max - left
-    */
-
-    // Slightly faster than naive in single bench.
-    // Does not use unaligned loads.
-    // let l = cmp::min(MAX_MATCH, data.len() - current_pos);
-
-    // let a = unsafe{&data.get_unchecked(current_pos..current_pos + l)};
-    // let b = unsafe{&data.get_unchecked(pos_to_check..)};
-
-    // let mut len = 0;
-
-    // for (l, r) in a
-    //     .iter()
-    //     .zip(b.iter()) {
-    //         if *l == *r {
-    //             len += 1;
-    //             continue;
-    //         } else {
-    //             break;
-    //         }
-    //     }
-    // len as usize
-
-    // Naive version
     data[current_pos..]
         .iter()
         .zip(data[pos_to_check..].iter())
@@ -72,6 +20,84 @@ pub fn get_match_length(data: &[u8], current_pos: usize, pos_to_check: usize) ->
         .count()
 }
 
+/// Get the length of the checked match
+/// The function returns number of bytes at and including `current_pos` that are the same as the
+/// ones at `pos_to_check`
+///
+/// This version compares 8 bytes at a time using unaligned loads.
+#[cfg(feature = "unsafe-speed")]
+#[inline]
+pub fn get_match_length(data: &[u8], current_pos: usize, pos_to_check: usize) -> usize {
+    use std::mem::size_of;
+    use std::ptr;
+
+    // Limit the length to what is available after both positions, so none of the loads below
+    // can go past the end of the slice.
+    let max = cmp::min(
+        MAX_MATCH,
+        cmp::min(
+            data.len().saturating_sub(current_pos),
+            data.len().saturating_sub(pos_to_check),
+        ),
+    );
+    let mut length = 0;
+    while length + size_of::<u64>() <= max {
+        // SAFETY: `current_pos + length + 8` and `pos_to_check + length + 8` are both at most
+        // `data.len()` as `length + 8 <= max`, and `read_unaligned` has no alignment
+        // requirement.
+        let diff = unsafe {
+            let a = ptr::read_unaligned(data.as_ptr().add(current_pos + length) as *const u64);
+            let b = ptr::read_unaligned(data.as_ptr().add(pos_to_check + length) as *const u64);
+            u64::from_le(a) ^ u64::from_le(b)
+        };
+        if diff != 0 {
+            // The lowest set bit is in the first byte that differs.
+            return length + (diff.trailing_zeros() / 8) as usize;
+        }
+        length += size_of::<u64>();
+    }
+    while length < max {
+        // SAFETY: Both positions are in bounds as `length < max`.
+        let equal = unsafe {
+            data.get_unchecked(current_pos + length) == data.get_unchecked(pos_to_check + length)
+        };
+        if !equal {
+            break;
+        }
+        length += 1;
+    }
+    length
+}
+
+/// Check whether the two bytes starting at `a` are the same as the two starting at `b`.
+///
+/// # Safety
+///
+/// `a + 1` and `b + 1` have to be less than `data.len()`.
+#[cfg(feature = "unsafe-speed")]
+#[inline(always)]
+unsafe fn two_bytes_equal(data: &[u8], a: usize, b: usize) -> bool {
+    debug_assert!(a + 1 < data.len() && b + 1 < data.len());
+    // SAFETY: The caller guarantees both ranges are in bounds.
+    unsafe { data.get_unchecked(a..a + 2) == data.get_unchecked(b..b + 2) }
+}
+
+/// Hint to the CPU that the memory at `ptr` will be read soon, so it can start loading it into
+/// the cache. This does nothing on targets other than x86_64.
+#[cfg(feature = "unsafe-speed")]
+#[inline(always)]
+fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        // SAFETY: A prefetch is only a hint and never faults, whether or not `ptr` is valid, and
+        // SSE, which the instruction is part of, is always available on x86_64.
+        unsafe { _mm_prefetch(ptr as *const i8, _MM_HINT_T0) };
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = ptr;
+}
+
 /// Try finding the position and length of the longest match in the input data.
 /// # Returns
 /// (length, distance from position)
@@ -84,14 +110,17 @@ pub fn get_match_length(data: &[u8], current_pos: usize, pos_to_check: usize) ->
 /// `position`: The position in the data to match against.
 /// `prev_length`: The length of the previous `longest_match` check to compare against.
 /// `max_hash_checks`: The maximum number of matching hash chain positions to check.
+/// `max_distance`: The maximum distance back a match may start, at most `WINDOW_SIZE`.
 pub fn longest_match(
     data: &[u8],
     hash_table: &ChainedHashTable,
     position: usize,
     prev_length: usize,
     max_hash_checks: u16,
+    max_distance: usize,
 ) -> (usize, usize) {
     // debug_assert_eq!(position, hash_table.current_head() as usize);
+    debug_assert!(max_distance <= WINDOW_SIZE);
 
     // If we already have a match at the maximum length,
     // or we can't grow further, we stop here.
@@ -99,31 +128,31 @@ pub fn longest_match(
         return (0, 0);
     }
 
-    let limit = if position > WINDOW_SIZE {
-        position - WINDOW_SIZE
-    } else {
-        0
-    };
+    let limit = position.saturating_sub(max_distance);
 
     // Make sure the length is at least one to simplify the matching code, as
     // otherwise the matching code might underflow.
     let prev_length = cmp::max(prev_length, 1);
 
     let max_length = cmp::min(data.len() - position, MAX_MATCH);
+    if prev_length >= max_length {
+        return (0, 0);
+    }
 
     // The position in the hash chain we are currently checking.
-    let mut current_head = position;
+    let mut current_head = hash_table.get_prev(position) as usize;
 
     // The best match length we've found so far, and it's distance.
     let mut best_length = prev_length;
     let mut best_distance = 0;
 
     // The position of the previous value in the hash chain.
-    let mut prev_head;
+    let mut prev_head = position;
+
+    instrument!(match_searches);
 
     for _ in 0..max_hash_checks {
-        prev_head = current_head;
-        current_head = hash_table.get_prev(current_head) as usize;
+        instrument!(chain_links_walked);
         if current_head >= prev_head || current_head < limit {
             // If the current hash chain value refers to itself, or is referring to
             // a value that's higher (we only move backwars through the chain),
@@ -131,6 +160,19 @@ pub fn longest_match(
             break;
         }
 
+        // Look up the next link in the chain before comparing the data at this one. The two
+        // loads don't depend on each other, so the CPU can fetch the next link while the data is
+        // compared rather than only starting when the next iteration begins.
+        let next_head = hash_table.get_prev(current_head) as usize;
+        // Also start loading the link after that and the data the next iteration compares, to
+        // hide the cache misses during deep searches. The positions may be past the end of the
+        // chain, which is fine for a prefetch.
+        #[cfg(feature = "unsafe-speed")]
+        {
+            prefetch(hash_table.prev_ptr(next_head));
+            prefetch(data.as_ptr().wrapping_add(next_head + best_length - 1));
+        }
+
         // We only check further if the match length can actually increase
         // Checking if the end byte and the potential next byte matches is generally
         // more likely to give a quick answer rather than checking from the start first, given
@@ -138,9 +180,20 @@ pub fn longest_match(
         // If there is no previous match, best_length will be 1 and the two first bytes will
         // be checked instead.
         // Since we've made sure best_length is always at least 1, this shouldn't underflow.
-        if data[position + best_length - 1..=position + best_length]
-            == data[current_head + best_length - 1..=current_head + best_length]
-        {
+        #[cfg(not(feature = "unsafe-speed"))]
+        let ends_match = data[position + best_length - 1..=position + best_length]
+            == data[current_head + best_length - 1..=current_head + best_length];
+        // SAFETY: `best_length < max_length` as we stop once a match of `max_length` is found,
+        // so `position + best_length < data.len()`, and `current_head < position`.
+        #[cfg(feature = "unsafe-speed")]
+        let ends_match = unsafe {
+            two_bytes_equal(
+                data,
+                position + best_length - 1,
+                current_head + best_length - 1,
+            )
+        };
+        if ends_match {
             // Actually check how many bytes match.
             // At the moment this will check the two bytes we just checked again,
             // though adding code for skipping these bytes may not result in any speed
@@ -156,6 +209,9 @@ pub fn longest_match(
                 }
             }
         }
+
+        prev_head = current_head;
+        current_head = next_head;
     }
 
     if best_length > prev_length {
@@ -285,13 +341,14 @@ pub fn longest_match_current(data: &[u8], hash_table: &ChainedHashTable) -> (usi
         hash_table.current_head() as usize,
         MIN_MATCH as usize - 1,
         MAX_HASH_CHECKS,
+        WINDOW_SIZE,
     )
 }
 
 #[cfg(test)]
 mod test {
     use super::{get_match_length, longest_match, longest_match_fast};
-    use crate::chained_hash_table::{filled_hash_table, ChainedHashTable, HASH_BYTES};
+    use crate::chained_hash_table::{filled_hash_table, ChainedHashTable, HASH_BYTES, WINDOW_SIZE};
 
     /// Test that match lengths are calculated correctly
     #[test]
@@ -336,7 +393,8 @@ mod test {
             hash_table.add_hash_value(n, b);
         }
 
-        let (match_length, match_dist) = longest_match(test_data, &hash_table, 1, 0, 4096);
+        let (match_length, match_dist) =
+            longest_match(test_data, &hash_table, 1, 0, 4096, WINDOW_SIZE);
 
         assert_eq!(match_dist, 1);
         assert!(match_length == 6);
@@ -355,7 +413,8 @@ mod test {
             let hash_table = filled_hash_table(&data[..start_pos + 1]);
             let pos = hash_table.current_head() as usize;
 
-            let naive_match = longest_match(&data[..], &hash_table, pos, 0, NUM_CHECKS);
+            let naive_match =
+                longest_match(&data[..], &hash_table, pos, 0, NUM_CHECKS, WINDOW_SIZE);
             let fast_match = longest_match_fast(&data[..], &hash_table, pos, 0, NUM_CHECKS);
 
             if fast_match.0 > naive_match.0 {
@@ -383,7 +442,7 @@ mod test {
 #[cfg(all(test, feature = "benchmarks"))]
 mod bench {
     use super::{longest_match, longest_match_fast};
-    use chained_hash_table::filled_hash_table;
+    use chained_hash_table::{filled_hash_table, WINDOW_SIZE};
     use test_std::Bencher;
     use test_utils::get_test_data;
     #[bench]
@@ -394,9 +453,9 @@ mod bench {
         let pos = hash_table.current_head() as usize;
         println!(
             "M: {:?}",
-            longest_match(&data[..], &hash_table, pos, 0, 4096)
+            longest_match(&data[..], &hash_table, pos, 0, 4096, WINDOW_SIZE)
         );
-        b.iter(|| longest_match(&data[..], &hash_table, pos, 0, 4096));
+        b.iter(|| longest_match(&data[..], &hash_table, pos, 0, 4096, WINDOW_SIZE));
     }
 
     #[bench]
@@ -0,0 +1,418 @@
+//! Optimal parsing, which picks the literals and matches to output by finding the cheapest path
+//! through all the matches in a piece of the input, rather than deciding at each position like
+//! greedy and lazy matching do.
+//!
+//! The cost of each symbol is modelled from the symbol statistics of the previous attempt, and
+//! the search is repeated a few times with the statistics of the path it found, similar to what
+//! zopfli does. The first attempt uses the costs of the fixed Huffman codes, or the statistics of
+//! the previous piece of input if there is one.
+use std::cmp;
+use std::mem;
+use std::ops::Range;
+
+use crate::chained_hash_table::ChainedHashTable;
+use crate::huffman_table::{
+    get_distance_code, get_length_code, num_extra_bits_for_distance_code,
+    num_extra_bits_for_length_code, FIXED_CODE_LENGTHS, FIXED_CODE_LENGTHS_DISTANCE,
+    LENGTH_BITS_START, NUM_DISTANCE_CODES, NUM_LITERALS_AND_LENGTHS,
+};
+use crate::lz77::{buffer_full, ProcessStatus};
+use crate::lzvalue::{LZType, LZValue};
+use crate::matching::get_match_length;
+use crate::output_writer::{BufferStatus, DynamicWriter};
+use crate::two_pass::Frequencies;
+
+const MIN_MATCH: usize = crate::huffman_table::MIN_MATCH as usize;
+const MAX_MATCH: usize = crate::huffman_table::MAX_MATCH as usize;
+
+/// The number of times the cheapest path is searched for with updated symbol costs.
+const OPTIMAL_ITERATIONS: usize = 5;
+
+/// The number of bits each symbol would take if coded with its entropy.
+///
+/// Unused symbols are given the cost of a symbol used once, so they stay usable.
+fn entropy_costs(
+    frequencies: &Frequencies,
+) -> ([f64; NUM_LITERALS_AND_LENGTHS], [f64; NUM_DISTANCE_CODES]) {
+    let mut l_costs = [0.0; NUM_LITERALS_AND_LENGTHS];
+    symbol_costs(&frequencies.literal_length, &mut l_costs);
+    let mut d_costs = [0.0; NUM_DISTANCE_CODES];
+    symbol_costs(&frequencies.distance, &mut d_costs);
+    (l_costs, d_costs)
+}
+
+/// Set each of `costs` to the entropy of the symbol used the corresponding number of times in
+/// `counts`.
+fn symbol_costs(counts: &[u64], costs: &mut [f64]) {
+    let total = cmp::max(counts.iter().sum::<u64>(), 1) as f64;
+    for (cost, &count) in costs.iter_mut().zip(counts) {
+        *cost = (total / cmp::max(count, 1) as f64).log2();
+    }
+}
+
+/// The approximate number of bits a block with these symbols would take with Huffman codes made
+/// from the frequencies, not counting the block header.
+fn entropy_bits(frequencies: &Frequencies) -> f64 {
+    let (l_costs, d_costs) = entropy_costs(frequencies);
+    let literal_lengths: f64 = frequencies
+        .literal_length
+        .iter()
+        .zip(&l_costs[..])
+        .enumerate()
+        .map(|(n, (&count, &cost))| {
+            let extra = if n >= LENGTH_BITS_START as usize {
+                num_extra_bits_for_length_code((n - LENGTH_BITS_START as usize) as u8)
+            } else {
+                0
+            };
+            count as f64 * (cost + f64::from(extra))
+        })
+        .sum();
+    let distances: f64 = frequencies
+        .distance
+        .iter()
+        .zip(&d_costs[..])
+        .enumerate()
+        .map(|(n, (&count, &cost))| {
+            count as f64 * (cost + f64::from(num_extra_bits_for_distance_code(n as u8)))
+        })
+        .sum();
+    literal_lengths + distances
+}
+
+/// The modelled cost in bits of each literal, match length and distance code.
+struct CostModel {
+    literal: [f64; 256],
+    /// Cost of the length symbol and extra bits, indexed by match length.
+    length: [f64; MAX_MATCH + 1],
+    /// Cost of the distance symbol and extra bits, indexed by distance code.
+    distance: [f64; NUM_DISTANCE_CODES],
+}
+
+impl CostModel {
+    fn new(
+        l_costs: &[f64; NUM_LITERALS_AND_LENGTHS],
+        d_costs: &[f64; NUM_DISTANCE_CODES],
+    ) -> CostModel {
+        let mut model = CostModel {
+            literal: [0.0; 256],
+            length: [0.0; MAX_MATCH + 1],
+            distance: [0.0; NUM_DISTANCE_CODES],
+        };
+        model.literal.copy_from_slice(&l_costs[..256]);
+        for length in MIN_MATCH..=MAX_MATCH {
+            let code = get_length_code(length as u16);
+            let extra = num_extra_bits_for_length_code((code - LENGTH_BITS_START as usize) as u8);
+            model.length[length] = l_costs[code] + f64::from(extra);
+        }
+        for (code, cost) in model.distance.iter_mut().enumerate() {
+            *cost = d_costs[code] + f64::from(num_extra_bits_for_distance_code(code as u8));
+        }
+        model
+    }
+
+    /// The costs of the symbols with the fixed Huffman codes.
+    fn fixed() -> CostModel {
+        let mut l_costs = [0.0; NUM_LITERALS_AND_LENGTHS];
+        for (cost, &length) in l_costs.iter_mut().zip(&FIXED_CODE_LENGTHS[..]) {
+            *cost = f64::from(length);
+        }
+        let mut d_costs = [0.0; NUM_DISTANCE_CODES];
+        for (cost, &length) in d_costs.iter_mut().zip(&FIXED_CODE_LENGTHS_DISTANCE[..]) {
+            *cost = f64::from(length);
+        }
+        CostModel::new(&l_costs, &d_costs)
+    }
+
+    fn from_frequencies(frequencies: &Frequencies) -> CostModel {
+        let (l_costs, d_costs) = entropy_costs(frequencies);
+        CostModel::new(&l_costs, &d_costs)
+    }
+}
+
+/// Buffers used by the optimal parser, kept between calls to avoid reallocating them, along with
+/// the symbol statistics of the last path output.
+pub struct OptimalState {
+    /// The matches found at each position, as (length, distance) pairs with increasing lengths.
+    matches: Vec<(u16, u16)>,
+    /// Index of the first match of each position in `matches`.
+    match_starts: Vec<u32>,
+    /// The cost of the cheapest path found to each position.
+    costs: Vec<f64>,
+    /// The length and distance of the last step of the cheapest path to each position, with a
+    /// length of 1 for literals.
+    steps: Vec<(u16, u16)>,
+    path: Vec<LZValue>,
+    best_path: Vec<LZValue>,
+    /// Symbol statistics of the last path output, used as the starting cost model for the next
+    /// piece of input.
+    frequencies: Option<Box<Frequencies>>,
+}
+
+impl OptimalState {
+    pub fn new() -> OptimalState {
+        OptimalState {
+            matches: Vec::new(),
+            match_starts: Vec::new(),
+            costs: Vec::new(),
+            steps: Vec::new(),
+            path: Vec::new(),
+            best_path: Vec::new(),
+            frequencies: None,
+        }
+    }
+
+    /// Forget the statistics of the earlier input, keeping the allocations.
+    pub fn reset(&mut self) {
+        self.frequencies = None;
+    }
+
+    /// Add the positions in `range` to the hash table, and note all the matches at each of them
+    /// that are longer than the ones closer to it, without going past the end of the range.
+    fn find_matches(
+        &mut self,
+        data: &[u8],
+        range: &Range<usize>,
+        hash_table: &mut ChainedHashTable,
+        max_hash_checks: u16,
+        max_distance: usize,
+    ) {
+        self.matches.clear();
+        self.match_starts.clear();
+        // Matches are not allowed to extend past the end of the range.
+        let window = &data[..range.end];
+        // The longest match at the previous position.
+        let mut prev_longest = (0, 0);
+        for position in range.clone() {
+            self.match_starts.push(self.matches.len() as u32);
+            if position + 2 >= data.len() {
+                // There are no bytes to hash after this one, so no matches either.
+                continue;
+            }
+            hash_table.add_hash_value(position, data[position + 2]);
+
+            let max_length = cmp::min(MAX_MATCH, range.end - position);
+            if max_length < MIN_MATCH {
+                continue;
+            }
+
+            // If there was a match of the maximum length at the previous position, the same
+            // distance gives one at least one byte shorter here. The cheapest path search only
+            // uses the longest match inside such repetitive data anyhow, so we don't need to look
+            // for any others, which would mean comparing the whole match length at every
+            // position.
+            let (prev_length, prev_distance) = prev_longest;
+            if prev_length == MAX_MATCH
+                && max_length == MAX_MATCH
+                && window[position + MAX_MATCH - 1]
+                    == window[position + MAX_MATCH - 1 - prev_distance]
+            {
+                self.matches.push((MAX_MATCH as u16, prev_distance as u16));
+                continue;
+            }
+
+            let limit = position.saturating_sub(max_distance);
+            let mut best_length = MIN_MATCH - 1;
+            let mut prev_head = position;
+            let mut current_head = hash_table.get_prev(position) as usize;
+            for _ in 0..max_hash_checks {
+                if current_head >= prev_head || current_head < limit {
+                    break;
+                }
+                if window[position + best_length] == window[current_head + best_length] {
+                    let length = get_match_length(window, position, current_head);
+                    if length > best_length {
+                        best_length = length;
+                        self.matches
+                            .push((length as u16, (position - current_head) as u16));
+                        if length == max_length {
+                            break;
+                        }
+                    }
+                }
+                prev_head = current_head;
+                current_head = hash_table.get_prev(current_head) as usize;
+            }
+            prev_longest = self
+                .matches
+                .last()
+                .filter(|_| best_length >= MIN_MATCH)
+                .map_or((0, 0), |&(l, d)| (usize::from(l), usize::from(d)));
+        }
+        self.match_starts.push(self.matches.len() as u32);
+    }
+
+    /// Find the cheapest path through the matches found for `input` using the cost model, and
+    /// store it in `path`.
+    fn cheapest_path(&mut self, input: &[u8], model: &CostModel) {
+        let n = input.len();
+        self.costs.clear();
+        self.costs.resize(n + 1, std::f64::INFINITY);
+        self.steps.clear();
+        self.steps.resize(n + 1, (0, 0));
+        self.costs[0] = 0.0;
+
+        let mut prev_longest = 0;
+        for (position, &byte) in input.iter().enumerate() {
+            let cost = self.costs[position];
+            let literal_cost = cost + model.literal[usize::from(byte)];
+            if literal_cost < self.costs[position + 1] {
+                self.costs[position + 1] = literal_cost;
+                self.steps[position + 1] = (1, 0);
+            }
+
+            let matches = &self.matches
+                [self.match_starts[position] as usize..self.match_starts[position + 1] as usize];
+            let longest = matches.last().map_or(0, |&(length, _)| usize::from(length));
+            // Inside highly repetitive data, such as long runs of the same byte, there is a
+            // match of the maximum length at every position. Only trying the longest one there
+            // keeps the search from trying every length at every position.
+            let only_longest = longest == MAX_MATCH && prev_longest == MAX_MATCH;
+            prev_longest = longest;
+
+            let mut shorter = MIN_MATCH - 1;
+            for &(length, distance) in matches {
+                let length = usize::from(length);
+                let distance_cost = cost + model.distance[usize::from(get_distance_code(distance))];
+                let first = if only_longest { length } else { shorter + 1 };
+                for l in first..=length {
+                    let match_cost = distance_cost + model.length[l];
+                    if match_cost < self.costs[position + l] {
+                        self.costs[position + l] = match_cost;
+                        self.steps[position + l] = (l as u16, distance);
+                    }
+                }
+                shorter = length;
+            }
+        }
+
+        self.path.clear();
+        let mut position = n;
+        while position > 0 {
+            let (length, distance) = self.steps[position];
+            let length = usize::from(length);
+            position -= length;
+            self.path.push(if distance == 0 {
+                LZValue::literal(input[position])
+            } else {
+                LZValue::length_distance(length as u16, distance)
+            });
+        }
+        self.path.reverse();
+    }
+
+    /// Find the path with the smallest estimated size for `input`, storing it in `best_path`.
+    fn optimize(&mut self, input: &[u8]) {
+        let mut model = match self.frequencies {
+            Some(ref frequencies) => CostModel::from_frequencies(frequencies),
+            None => CostModel::fixed(),
+        };
+        let mut best_bits = std::f64::INFINITY;
+        for _ in 0..OPTIMAL_ITERATIONS {
+            self.cheapest_path(input, &model);
+            let frequencies = Frequencies::from_values(&self.path);
+            let bits = entropy_bits(&frequencies);
+            if bits < best_bits {
+                best_bits = bits;
+                mem::swap(&mut self.path, &mut self.best_path);
+                self.frequencies = Some(Box::new(frequencies));
+            } else {
+                // The cost model no longer improves.
+                break;
+            }
+            model = CostModel::from_frequencies(self.frequencies.as_ref().unwrap());
+        }
+    }
+}
+
+/// LZ77-compress the data in `iterated_data` using optimal parsing.
+///
+/// The input is parsed in pieces no longer than the remaining space in the writer, so that the
+/// writer can only be full at the end of a piece. Matches never extend past the end of the range,
+/// so there is never any overlap into the next window.
+pub fn process_chunk_optimal(
+    data: &[u8],
+    iterated_data: &Range<usize>,
+    state: &mut OptimalState,
+    hash_table: &mut ChainedHashTable,
+    writer: &mut DynamicWriter,
+    max_hash_checks: u16,
+    max_distance: usize,
+) -> (usize, ProcessStatus) {
+    let end = cmp::min(data.len(), iterated_data.end);
+    let mut start = iterated_data.start;
+    while start < end {
+        let piece = start..cmp::min(end, start + writer.remaining_capacity());
+        state.find_matches(data, &piece, hash_table, max_hash_checks, max_distance);
+        state.optimize(&data[piece.clone()]);
+
+        let mut b_status = BufferStatus::NotFull;
+        for value in &state.best_path {
+            debug_assert!(b_status == BufferStatus::NotFull);
+            b_status = match value.value() {
+                LZType::Literal(l) => writer.write_literal(l),
+                LZType::StoredLengthDistance(l, d) => {
+                    writer.write_length_distance(l.actual_length(), d)
+                }
+            };
+        }
+        if let BufferStatus::Full = b_status {
+            return (0, buffer_full(piece.end));
+        }
+        start = piece.end;
+    }
+    (0, ProcessStatus::Ok)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression_options::CompressionOptions;
+    use crate::lz77::MatchingType;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+    use crate::write::DeflateEncoder;
+    use crate::{try_deflate_bytes_conf, Compression};
+    use std::io::Write;
+
+    fn optimal_options() -> CompressionOptions {
+        CompressionOptions {
+            matching_type: MatchingType::OptimalParse,
+            ..CompressionOptions::high()
+        }
+    }
+
+    #[test]
+    fn optimal_smaller_than_lazy() {
+        let data = &get_test_data()[..50_000];
+        let compressed = try_deflate_bytes_conf(data, optimal_options()).unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+        let lazy = try_deflate_bytes_conf(data, Compression::Best).unwrap();
+        assert!(compressed.len() < lazy.len());
+    }
+
+    #[test]
+    fn optimal_roundtrip() {
+        let text = &get_test_data()[..20_000];
+        let mut data = vec![0; 1000];
+        data.extend_from_slice(text);
+        data.extend(vec![7; 70_000]);
+        data.extend_from_slice(text);
+
+        let mut small_blocks = optimal_options();
+        small_blocks.max_block_symbols = 100;
+        let mut small_window = optimal_options();
+        small_window.window_bits = 9;
+        for &options in &[optimal_options(), small_blocks, small_window] {
+            let compressed = try_deflate_bytes_conf(&data[..], options).unwrap();
+            assert!(decompress_to_end(&compressed) == data);
+
+            // Flushing in between writes.
+            let mut encoder = DeflateEncoder::new(Vec::new(), options);
+            for chunk in data.chunks(9000) {
+                encoder.write_all(chunk).unwrap();
+                encoder.flush().unwrap();
+            }
+            let compressed = encoder.finish().unwrap();
+            assert!(decompress_to_end(&compressed) == data);
+        }
+    }
+}
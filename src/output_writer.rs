@@ -1,5 +1,6 @@
 use std::u16;
 
+use crate::buffer_alloc::{global, take_vec, vec_with_capacity, BufVec};
 use crate::huffman_table::{
     get_distance_code, get_length_code, END_OF_BLOCK_POSITION, NUM_DISTANCE_CODES,
     NUM_LITERALS_AND_LENGTHS,
@@ -26,17 +27,19 @@ pub enum BufferStatus {
 
 /// Struct that buffers lz77 data and keeps track of the usage of different codes
 pub struct DynamicWriter {
-    buffer: Vec<LZValue>,
+    buffer: BufVec<LZValue>,
     // The two last length codes are not actually used, but only participates in code construction
     // Therefore, we ignore them to get the correct number of lengths
     frequencies: [FrequencyType; NUM_LITERALS_AND_LENGTHS],
     distance_frequencies: [FrequencyType; NUM_DISTANCE_CODES],
+    /// The number of values after which the buffer is considered full.
+    max_length: usize,
 }
 
 impl DynamicWriter {
     #[inline]
     pub fn check_buffer_length(&self) -> BufferStatus {
-        if self.buffer.len() >= MAX_BUFFER_LENGTH {
+        if self.buffer.len() >= self.max_length {
             BufferStatus::Full
         } else {
             BufferStatus::NotFull
@@ -45,7 +48,7 @@ impl DynamicWriter {
 
     #[inline]
     pub fn write_literal(&mut self, literal: u8) -> BufferStatus {
-        debug_assert!(self.buffer.len() < MAX_BUFFER_LENGTH);
+        debug_assert!(self.buffer.len() < self.max_length);
         self.buffer.push(LZValue::literal(literal));
         self.frequencies[usize::from(literal)] += 1;
         self.check_buffer_length()
@@ -64,6 +67,11 @@ impl DynamicWriter {
         self.check_buffer_length()
     }
 
+    /// The number of values that can be written before the buffer is full.
+    pub fn remaining_capacity(&self) -> usize {
+        self.max_length - self.buffer.len()
+    }
+
     pub fn buffer_length(&self) -> usize {
         self.buffer.len()
     }
@@ -73,10 +81,18 @@ impl DynamicWriter {
     }
 
     pub fn new() -> DynamicWriter {
+        DynamicWriter::with_buffer(vec_with_capacity(MAX_BUFFER_LENGTH, &global()))
+    }
+
+    /// Create a new writer reusing the allocation of `buffer`.
+    pub fn with_buffer(mut buffer: BufVec<LZValue>) -> DynamicWriter {
+        buffer.clear();
+        buffer.reserve(MAX_BUFFER_LENGTH);
         let mut w = DynamicWriter {
-            buffer: Vec::with_capacity(MAX_BUFFER_LENGTH),
+            buffer,
             frequencies: [0; NUM_LITERALS_AND_LENGTHS],
             distance_frequencies: [0; NUM_DISTANCE_CODES],
+            max_length: MAX_BUFFER_LENGTH,
         };
         // This will always be 1,
         // since there will always only be one end of block marker in each block
@@ -84,6 +100,14 @@ impl DynamicWriter {
         w
     }
 
+    /// Set the number of values after which the buffer is considered full.
+    ///
+    /// `max_length` has to be between 1 and `MAX_BUFFER_LENGTH`.
+    pub fn set_max_length(&mut self, max_length: usize) {
+        debug_assert!(max_length > 0 && max_length <= MAX_BUFFER_LENGTH);
+        self.max_length = max_length;
+    }
+
     /// Special output function used with RLE compression
     /// that avoids bothering to lookup a distance code.
     #[inline]
@@ -115,6 +139,11 @@ impl DynamicWriter {
         self.clear_frequencies();
         self.clear_data();
     }
+
+    /// Take the allocation out of the writer so it can be reused, leaving it empty.
+    pub fn take_buffer(&mut self) -> BufVec<LZValue> {
+        take_vec(&mut self.buffer)
+    }
 }
 
 #[cfg(test)]
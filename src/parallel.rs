@@ -0,0 +1,173 @@
+//! This module contains functionality for compressing the input on several threads.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{cmp, panic, thread};
+
+use crate::compress::Flush;
+use crate::compression_options::{CompressionOptions, MAX_DISTANCE};
+use crate::deflate_state::DeflateState;
+use crate::error::Error;
+use crate::writer::compress_until_done;
+
+/// The number of bytes of input in each of the chunks compressed in parallel.
+pub const PARALLEL_CHUNK_SIZE: usize = 128 * 1024;
+/// The number of threads used if the caller leaves it to us, as the number of cores isn't
+/// available from the standard library in the oldest supported Rust version.
+pub const DEFAULT_PARALLEL_THREADS: usize = 4;
+
+/// Compress chunk number `index` of `input`, using the window before it as a preset dictionary,
+/// and return the output.
+///
+/// The output ends with a sync flush, or with the end of the stream for the last chunk, so the
+/// output of all the chunks can be joined into one stream.
+fn compress_chunk(
+    deflate_state: &mut DeflateState<Vec<u8>>,
+    input: &[u8],
+    index: usize,
+) -> Result<Vec<u8>, Error> {
+    let start = index * PARALLEL_CHUNK_SIZE;
+    let end = cmp::min(start + PARALLEL_CHUNK_SIZE, input.len());
+    if start > 0 {
+        deflate_state
+            .set_dictionary(&input[start.saturating_sub(usize::from(MAX_DISTANCE))..start]);
+    }
+    deflate_state.set_input_size_hint((end - start) as u64);
+    let flush = if end == input.len() {
+        Flush::Finish
+    } else {
+        Flush::Sync
+    };
+    compress_until_done(&input[start..end], deflate_state, flush)?;
+    Ok(deflate_state.reset(Vec::new())?)
+}
+
+/// Compress each chunk that hasn't been taken by another thread yet, as counted by `next_chunk`,
+/// returning the output of each along with its index.
+fn compress_chunks(
+    options: CompressionOptions,
+    input: &[u8],
+    num_chunks: usize,
+    next_chunk: &AtomicUsize,
+) -> Result<Vec<(usize, Vec<u8>)>, Error> {
+    // We use a box here to avoid putting the buffers on the stack.
+    let mut deflate_state = Box::new(DeflateState::new(options, Vec::new()));
+    let mut compressed = Vec::new();
+    loop {
+        let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+        if index >= num_chunks {
+            return Ok(compressed);
+        }
+        compressed.push((index, compress_chunk(&mut deflate_state, input, index)?));
+    }
+}
+
+/// Compress `input` in chunks of `PARALLEL_CHUNK_SIZE` bytes on `n_threads` threads, or
+/// `DEFAULT_PARALLEL_THREADS` if `n_threads` is 0.
+pub fn compress_parallel(
+    input: &[u8],
+    options: CompressionOptions,
+    n_threads: usize,
+) -> Result<Vec<u8>, Error> {
+    options.validate()?;
+    let num_chunks = cmp::max(
+        (input.len() + PARALLEL_CHUNK_SIZE - 1) / PARALLEL_CHUNK_SIZE,
+        1,
+    );
+    let n_threads = if n_threads == 0 {
+        DEFAULT_PARALLEL_THREADS
+    } else {
+        n_threads
+    };
+    let n_threads = cmp::min(n_threads, num_chunks);
+
+    // Each thread takes the next chunk that hasn't been compressed yet until there are none left.
+    let next_chunk = AtomicUsize::new(0);
+    let results: Vec<_> = if n_threads == 1 {
+        vec![compress_chunks(options, input, num_chunks, &next_chunk)]
+    } else {
+        // The threads can't borrow the input, so they share a copy of it.
+        let input: Arc<[u8]> = input.into();
+        let next_chunk = Arc::new(next_chunk);
+        let handles: Vec<_> = (0..n_threads)
+            .map(|_| {
+                let input = Arc::clone(&input);
+                let next_chunk = Arc::clone(&next_chunk);
+                thread::spawn(move || compress_chunks(options, &input, num_chunks, &next_chunk))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    };
+
+    let mut chunks = vec![Vec::new(); num_chunks];
+    for result in results {
+        for (index, compressed) in result? {
+            chunks[index] = compressed;
+        }
+    }
+    Ok(chunks.concat())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+    use crate::{try_deflate_bytes_conf, try_deflate_bytes_parallel};
+
+    #[test]
+    fn parallel_roundtrip() {
+        let text = get_test_data();
+        let mut data = Vec::new();
+        while data.len() < PARALLEL_CHUNK_SIZE * 5 {
+            data.extend_from_slice(&text);
+        }
+        for &options in &[
+            CompressionOptions::default(),
+            CompressionOptions::fast(),
+            CompressionOptions::rle(),
+        ] {
+            let compressed = try_deflate_bytes_parallel(&data, options, 1).unwrap();
+            assert!(decompress_to_end(&compressed) == data);
+            // The output doesn't depend on the number of threads.
+            for &n_threads in &[0, 2, 3, 16] {
+                assert!(
+                    try_deflate_bytes_parallel(&data, options, n_threads).unwrap() == compressed
+                );
+            }
+            // Priming each chunk with the data before it should keep the output close in size
+            // to compressing the input in one piece.
+            let sequential = try_deflate_bytes_conf(&data, options).unwrap();
+            assert!(compressed.len() < sequential.len() + sequential.len() / 50);
+        }
+    }
+
+    #[test]
+    fn parallel_chunk_boundaries() {
+        let data = get_test_data();
+        for &len in &[
+            0,
+            1,
+            PARALLEL_CHUNK_SIZE - 1,
+            PARALLEL_CHUNK_SIZE,
+            PARALLEL_CHUNK_SIZE + 1,
+            PARALLEL_CHUNK_SIZE * 2,
+        ] {
+            let input: Vec<u8> = data.iter().cycle().take(len).cloned().collect();
+            let compressed =
+                try_deflate_bytes_parallel(&input, CompressionOptions::default(), 4).unwrap();
+            assert!(decompress_to_end(&compressed) == input);
+        }
+    }
+
+    #[test]
+    fn parallel_invalid_options() {
+        let mut options = CompressionOptions::default();
+        options.max_search_distance = 0;
+        match compress_parallel(&[1, 2, 3], options, 2) {
+            Err(Error::InvalidOptions(_)) => (),
+            _ => panic!("invalid options were accepted"),
+        }
+    }
+}
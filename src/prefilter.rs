@@ -0,0 +1,240 @@
+//! This module contains reversible filters that can be applied to numeric data before
+//! compressing it to improve the compression ratio.
+//!
+//! Arrays of integers or floating point values, like sensor readings or scientific data, tend to
+//! compress poorly with DEFLATE as neighbouring values rarely repeat exactly. Storing the
+//! difference between neighbouring values (delta filtering), or grouping the bytes of each value
+//! by significance (shuffling), makes the data much more repetitive.
+//!
+//! The filtered data starts with a small header recording the filter used, so it can be
+//! reversed with [`unfilter`](fn.unfilter.html) after decompressing without any other
+//! information. This header is specific to this crate.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use deflate::prefilter::{apply, unfilter, Prefilter};
+//!
+//! let values: Vec<u8> = (0..1000u32).flat_map(|n| (n * 3).to_le_bytes().to_vec()).collect();
+//! let filtered = apply(&values, Prefilter::ShuffleDelta(4));
+//! let compressed = deflate::try_deflate_bytes(&filtered).unwrap();
+//! # assert!(compressed.len() < deflate::try_deflate_bytes(&values).unwrap().len());
+//! // ...decompress the data, then:
+//! assert_eq!(unfilter(&filtered).unwrap(), values);
+//! ```
+use crate::compression_options::CompressionOptions;
+
+/// Length of the header added to the filtered data.
+pub const HEADER_LEN: usize = 2;
+
+/// The upper four bits of the first header byte, to help catch unfiltering data that wasn't
+/// filtered with this module.
+const HEADER_MAGIC: u8 = 0xD0;
+
+/// The filter to apply to the data.
+///
+/// The value for each filter is the width of each element in bytes, e.g 4 for an array of `u32`
+/// or `f32`. A width of 0 is not valid.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Prefilter {
+    /// Store the data unmodified.
+    None,
+    /// Replace each byte by the difference from the corresponding byte of the previous element.
+    ///
+    /// Works well for slowly changing integer values.
+    Delta(u8),
+    /// Group the bytes of the elements by their position in the element, i.e all the first
+    /// bytes, followed by all the second bytes and so on.
+    ///
+    /// Works well for values where the most significant bytes rarely change, like floating point
+    /// values.
+    Shuffle(u8),
+    /// Shuffle the bytes, then replace each byte by the difference from the previous byte.
+    ShuffleDelta(u8),
+}
+
+impl Prefilter {
+    fn id_and_width(self) -> (u8, u8) {
+        match self {
+            Prefilter::None => (0, 1),
+            Prefilter::Delta(width) => (1, width),
+            Prefilter::Shuffle(width) => (2, width),
+            Prefilter::ShuffleDelta(width) => (3, width),
+        }
+    }
+
+    fn from_id_and_width(id: u8, width: u8) -> Option<Prefilter> {
+        if width == 0 {
+            return None;
+        }
+        Some(match id {
+            0 => Prefilter::None,
+            1 => Prefilter::Delta(width),
+            2 => Prefilter::Shuffle(width),
+            3 => Prefilter::ShuffleDelta(width),
+            _ => return None,
+        })
+    }
+}
+
+/// Replace each byte with the difference from the byte `width` bytes before it.
+fn delta_encode(data: &mut [u8], width: usize) {
+    for i in (width..data.len()).rev() {
+        data[i] = data[i].wrapping_sub(data[i - width]);
+    }
+}
+
+fn delta_decode(data: &mut [u8], width: usize) {
+    for i in width..data.len() {
+        data[i] = data[i].wrapping_add(data[i - width]);
+    }
+}
+
+/// Transpose the bytes of `width`-byte elements, leaving any trailing partial element as is.
+fn shuffle(data: &[u8], width: usize, out: &mut Vec<u8>) {
+    let elements = data.len() / width;
+    for byte in 0..width {
+        out.extend(data.iter().skip(byte).step_by(width).take(elements));
+    }
+    out.extend_from_slice(&data[elements * width..]);
+}
+
+fn unshuffle(data: &[u8], width: usize) -> Vec<u8> {
+    let elements = data.len() / width;
+    let mut out = vec![0; data.len()];
+    for (byte, plane) in data[..elements * width].chunks(elements.max(1)).enumerate() {
+        for (element, &b) in plane.iter().enumerate() {
+            out[element * width + byte] = b;
+        }
+    }
+    out[elements * width..].copy_from_slice(&data[elements * width..]);
+    out
+}
+
+/// Apply `filter` to `data`, returning the filtered data prefixed by a header describing the
+/// filter.
+///
+/// # Panics
+///
+/// Panics if the element width of the filter is 0.
+pub fn apply(data: &[u8], filter: Prefilter) -> Vec<u8> {
+    let (id, width) = filter.id_and_width();
+    assert!(width > 0, "the element width of a prefilter can not be 0");
+    let mut out = Vec::with_capacity(data.len() + HEADER_LEN);
+    out.push(HEADER_MAGIC | id);
+    out.push(width);
+    let width = usize::from(width);
+    match filter {
+        Prefilter::None => out.extend_from_slice(data),
+        Prefilter::Delta(_) => {
+            out.extend_from_slice(data);
+            delta_encode(&mut out[HEADER_LEN..], width);
+        }
+        Prefilter::Shuffle(_) => shuffle(data, width, &mut out),
+        Prefilter::ShuffleDelta(_) => {
+            shuffle(data, width, &mut out);
+            delta_encode(&mut out[HEADER_LEN..], 1);
+        }
+    }
+    out
+}
+
+/// Reverse the filter applied by [`apply`](fn.apply.html), returning the original data.
+///
+/// Returns `None` if `data` doesn't start with a valid header.
+pub fn unfilter(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < HEADER_LEN || data[0] & 0xF0 != HEADER_MAGIC {
+        return None;
+    }
+    let filter = Prefilter::from_id_and_width(data[0] & 0x0F, data[1])?;
+    let width = usize::from(data[1]);
+    let data = &data[HEADER_LEN..];
+    Some(match filter {
+        Prefilter::None => data.to_vec(),
+        Prefilter::Delta(_) => {
+            let mut out = data.to_vec();
+            delta_decode(&mut out, width);
+            out
+        }
+        Prefilter::Shuffle(_) => unshuffle(data, width),
+        Prefilter::ShuffleDelta(_) => {
+            let mut planes = data.to_vec();
+            delta_decode(&mut planes, 1);
+            unshuffle(&planes, width)
+        }
+    })
+}
+
+/// Filter `data` using `filter` and compress the result using DEFLATE with the given options.
+///
+/// The decompressed data has to be passed to [`unfilter`](fn.unfilter.html) to get the original
+/// data back.
+pub fn deflate_bytes_filtered<O: Into<CompressionOptions>>(
+    data: &[u8],
+    filter: Prefilter,
+    options: O,
+) -> Vec<u8> {
+    crate::try_deflate_bytes_conf(&apply(data, filter), options.into().clamped())
+        .expect("Write error!")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data, XorShift};
+
+    fn filters(width: u8) -> [Prefilter; 4] {
+        [
+            Prefilter::None,
+            Prefilter::Delta(width),
+            Prefilter::Shuffle(width),
+            Prefilter::ShuffleDelta(width),
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = get_test_data();
+        for &len in &[0, 1, 3, 7, 1000, data.len()] {
+            for &width in &[1, 2, 3, 4, 8] {
+                for &filter in &filters(width) {
+                    let filtered = apply(&data[..len], filter);
+                    assert_eq!(filtered.len(), len + HEADER_LEN);
+                    assert!(unfilter(&filtered).unwrap() == &data[..len]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shuffle_layout() {
+        let filtered = apply(&[1, 2, 3, 4, 5, 6, 7], Prefilter::Shuffle(3));
+        assert_eq!(filtered[HEADER_LEN..], [1, 4, 2, 5, 3, 6, 7]);
+        let filtered = apply(&[1, 2, 4, 7, 8], Prefilter::Delta(1));
+        assert_eq!(filtered[HEADER_LEN..], [1, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(unfilter(&[]).is_none());
+        assert!(unfilter(&[HEADER_MAGIC | 1]).is_none());
+        assert!(unfilter(&[HEADER_MAGIC | 9, 4]).is_none());
+        assert!(unfilter(&[HEADER_MAGIC | 1, 0]).is_none());
+        assert!(unfilter(&[0x01, 4]).is_none());
+    }
+
+    #[test]
+    fn improves_ratio() {
+        // A slowly increasing series of 32-bit values with some noise.
+        let values: Vec<u8> = (0..20000u32)
+            .zip(XorShift::new(12345))
+            .flat_map(|(n, r)| (n * 50 + (r & 0xF)).to_le_bytes().to_vec())
+            .collect();
+        let plain = crate::try_deflate_bytes(&values).unwrap();
+        for &filter in &filters(4)[1..] {
+            let compressed = deflate_bytes_filtered(&values, filter, CompressionOptions::default());
+            assert!(compressed.len() < plain.len(), "{:?}", filter);
+            assert!(unfilter(&decompress_to_end(&compressed)).unwrap() == values);
+        }
+    }
+}
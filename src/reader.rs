@@ -0,0 +1,436 @@
+//! Encoders implementing a `Read` interface, which compress the data read from another reader.
+//!
+//! These wrap the encoders from the `write` module, feeding them the data read from the source
+//! and returning their output as it becomes available, so the input never has to be held in
+//! memory all at once.
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use crate::compression_options::CompressionOptions;
+use crate::writer;
+
+/// Number of bytes read from the source at a time.
+const READ_CHUNK_SIZE: usize = 32 * 1024;
+
+/// The parts of a writing encoder used to drive it from a reader.
+trait PullEncoder: Write {
+    /// The buffer the compressed output is written to.
+    fn output(&mut self) -> &mut Vec<u8>;
+    fn try_finish(&mut self) -> io::Result<()>;
+    fn set_finish_on_drop(&mut self, finish_on_drop: bool);
+}
+
+impl PullEncoder for writer::DeflateEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.writer_mut()
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+
+    fn set_finish_on_drop(&mut self, finish_on_drop: bool) {
+        self.set_finish_on_drop(finish_on_drop)
+    }
+}
+
+impl PullEncoder for writer::ZlibEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.writer_mut()
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+
+    fn set_finish_on_drop(&mut self, finish_on_drop: bool) {
+        self.set_finish_on_drop(finish_on_drop)
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl PullEncoder for writer::gzip::GzEncoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.writer_mut()
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+
+    fn set_finish_on_drop(&mut self, finish_on_drop: bool) {
+        self.set_finish_on_drop(finish_on_drop)
+    }
+}
+
+/// A source reader along with the encoder its data is compressed with.
+struct Pull<R, E> {
+    source: R,
+    encoder: E,
+    /// Buffer for the data read from the source.
+    input: Vec<u8>,
+    /// Position in the output of the encoder of the first byte that hasn't been read yet.
+    pos: usize,
+    /// Total number of compressed bytes read so far.
+    total_out: u64,
+    /// Whether the end of the source has been reached, and the stream finished.
+    done: bool,
+}
+
+impl<R: Read, E: PullEncoder> Pull<R, E> {
+    fn new(source: R, mut encoder: E) -> Pull<R, E> {
+        // The output is only kept in memory, so there's no point finishing it on drop.
+        encoder.set_finish_on_drop(false);
+        Pull {
+            source,
+            encoder,
+            input: vec![0; READ_CHUNK_SIZE],
+            pos: 0,
+            total_out: 0,
+            done: false,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let output = self.encoder.output();
+            if self.pos < output.len() {
+                let len = cmp::min(buf.len(), output.len() - self.pos);
+                buf[..len].copy_from_slice(&output[self.pos..self.pos + len]);
+                self.pos += len;
+                if self.pos == output.len() {
+                    output.clear();
+                    self.pos = 0;
+                }
+                self.total_out += len as u64;
+                return Ok(len);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            // Keep reading until the encoder has some output to return.
+            let read = self.source.read(&mut self.input)?;
+            if read == 0 {
+                self.encoder.try_finish()?;
+                self.done = true;
+            } else {
+                self.encoder.write_all(&self.input[..read])?;
+            }
+        }
+    }
+}
+
+/// A DEFLATE encoder implementing the [`Read`] trait, compressing the data read from the wrapped
+/// reader.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use deflate::read::DeflateEncoder;
+/// use deflate::Compression;
+///
+/// let data = &b"This is some test data"[..];
+/// let mut encoder = DeflateEncoder::new(data, Compression::Default);
+/// let mut compressed_data = Vec::new();
+/// encoder.read_to_end(&mut compressed_data).unwrap();
+/// # assert_eq!(deflate::try_deflate_bytes(data).unwrap(), compressed_data);
+/// ```
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+pub struct DeflateEncoder<R> {
+    inner: Pull<R, writer::DeflateEncoder<Vec<u8>>>,
+}
+
+impl<R: Read> DeflateEncoder<R> {
+    /// Create a new `DeflateEncoder` reading the data to compress from `reader`, using the
+    /// provided compression options.
+    pub fn new<O: Into<CompressionOptions>>(reader: R, options: O) -> DeflateEncoder<R> {
+        let encoder = writer::DeflateEncoder::new(Vec::new(), options);
+        DeflateEncoder {
+            inner: Pull::new(reader, encoder),
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner.source
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    ///
+    /// Reading from it directly will make the data read go missing from the compressed stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner.source
+    }
+
+    /// Consume the encoder, returning the wrapped reader.
+    ///
+    /// Any data read from it that hasn't been output in compressed form yet is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.source
+    }
+
+    /// The total number of bytes read from the wrapped reader so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.encoder.total_in()
+    }
+
+    /// The total number of bytes of compressed data read from the encoder so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out
+    }
+}
+
+impl<R: Read> Read for DeflateEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// A zlib encoder implementing the [`Read`] trait, compressing the data read from the wrapped
+/// reader.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use deflate::read::ZlibEncoder;
+/// use deflate::Compression;
+///
+/// let data = &b"This is some test data"[..];
+/// let mut encoder = ZlibEncoder::new(data, Compression::Default);
+/// let mut compressed_data = Vec::new();
+/// encoder.read_to_end(&mut compressed_data).unwrap();
+/// # assert_eq!(deflate::try_deflate_bytes_zlib(data).unwrap(), compressed_data);
+/// ```
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+pub struct ZlibEncoder<R> {
+    inner: Pull<R, writer::ZlibEncoder<Vec<u8>>>,
+}
+
+impl<R: Read> ZlibEncoder<R> {
+    /// Create a new `ZlibEncoder` reading the data to compress from `reader`, using the
+    /// provided compression options.
+    pub fn new<O: Into<CompressionOptions>>(reader: R, options: O) -> ZlibEncoder<R> {
+        ZlibEncoder::from_encoder(reader, writer::ZlibEncoder::new(Vec::new(), options))
+    }
+
+    /// Create a new `ZlibEncoder` reading the data to compress from `reader`, using the
+    /// provided compression options and the preset dictionary `dictionary`.
+    ///
+    /// See [`write::ZlibEncoder::new_with_dict`](../write/struct.ZlibEncoder.html#method.new_with_dict).
+    pub fn new_with_dict<O: Into<CompressionOptions>>(
+        reader: R,
+        options: O,
+        dictionary: &[u8],
+    ) -> ZlibEncoder<R> {
+        let encoder = writer::ZlibEncoder::new_with_dict(Vec::new(), options, dictionary);
+        ZlibEncoder::from_encoder(reader, encoder)
+    }
+
+    fn from_encoder(reader: R, encoder: writer::ZlibEncoder<Vec<u8>>) -> ZlibEncoder<R> {
+        ZlibEncoder {
+            inner: Pull::new(reader, encoder),
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner.source
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    ///
+    /// Reading from it directly will make the data read go missing from the compressed stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner.source
+    }
+
+    /// Consume the encoder, returning the wrapped reader.
+    ///
+    /// Any data read from it that hasn't been output in compressed form yet is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.source
+    }
+
+    /// The total number of bytes read from the wrapped reader so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.encoder.total_in()
+    }
+
+    /// The total number of bytes of compressed data read from the encoder so far, including
+    /// the zlib header.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out
+    }
+}
+
+impl<R: Read> Read for ZlibEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "gzip")]
+pub use self::gzip::GzEncoder;
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use std::io::{self, Read};
+
+    use gzip_header::GzBuilder;
+
+    use super::Pull;
+    use crate::compression_options::CompressionOptions;
+    use crate::writer;
+
+    /// A gzip encoder implementing the [`Read`] trait, compressing the data read from the
+    /// wrapped reader.
+    ///
+    /// Only available with the `gzip` feature.
+    ///
+    /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+    pub struct GzEncoder<R> {
+        inner: Pull<R, writer::gzip::GzEncoder<Vec<u8>>>,
+    }
+
+    impl<R: Read> GzEncoder<R> {
+        /// Create a new `GzEncoder` reading the data to compress from `reader`, using the
+        /// provided compression options. The header details will be blank.
+        pub fn new<O: Into<CompressionOptions>>(reader: R, options: O) -> GzEncoder<R> {
+            GzEncoder::from_builder(
+                GzBuilder::new().os(writer::gzip::default_os()),
+                reader,
+                options,
+            )
+        }
+
+        /// Create a new `GzEncoder` reading the data to compress from `reader`, using the
+        /// provided `GzBuilder` to create the header.
+        pub fn from_builder<O: Into<CompressionOptions>>(
+            builder: GzBuilder,
+            reader: R,
+            options: O,
+        ) -> GzEncoder<R> {
+            let encoder = writer::gzip::GzEncoder::from_builder(builder, Vec::new(), options);
+            GzEncoder {
+                inner: Pull::new(reader, encoder),
+            }
+        }
+
+        /// Get a reference to the wrapped reader.
+        pub fn get_ref(&self) -> &R {
+            &self.inner.source
+        }
+
+        /// Get a mutable reference to the wrapped reader.
+        ///
+        /// Reading from it directly will make the data read go missing from the compressed
+        /// stream.
+        pub fn get_mut(&mut self) -> &mut R {
+            &mut self.inner.source
+        }
+
+        /// Consume the encoder, returning the wrapped reader.
+        ///
+        /// Any data read from it that hasn't been output in compressed form yet is lost.
+        pub fn into_inner(self) -> R {
+            self.inner.source
+        }
+
+        /// The total number of bytes read from the wrapped reader so far.
+        pub fn total_in(&self) -> u64 {
+            self.inner.encoder.total_in()
+        }
+
+        /// The total number of bytes of compressed data read from the encoder so far, including
+        /// the gzip header.
+        pub fn total_out(&self) -> u64 {
+            self.inner.total_out
+        }
+    }
+
+    impl<R: Read> Read for GzEncoder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, decompress_zlib, get_test_data};
+    use crate::{try_deflate_bytes_conf, try_deflate_bytes_zlib_conf};
+
+    /// A reader returning at most `max` bytes per call.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        max: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = cmp::min(cmp::min(buf.len(), self.max), self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn read_encoders() {
+        let data = get_test_data();
+        let options = CompressionOptions::default();
+
+        let mut encoder = DeflateEncoder::new(&data[..], options);
+        let mut compressed = Vec::new();
+        encoder.read_to_end(&mut compressed).unwrap();
+        assert!(compressed == try_deflate_bytes_conf(&data, options).unwrap());
+        assert_eq!(encoder.total_in(), data.len() as u64);
+        assert_eq!(encoder.total_out(), compressed.len() as u64);
+
+        // Small reads from a source returning little data at a time.
+        let source = Trickle {
+            data: &data,
+            max: 1000,
+        };
+        let mut encoder = ZlibEncoder::new(source, options);
+        let mut compressed = Vec::new();
+        let mut buf = [0; 100];
+        loop {
+            match encoder.read(&mut buf).unwrap() {
+                0 => break,
+                n => compressed.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert!(compressed == try_deflate_bytes_zlib_conf(&data, options).unwrap());
+        assert!(decompress_zlib(&compressed) == data);
+        assert!(encoder.into_inner().data.is_empty());
+
+        let mut encoder = DeflateEncoder::new(&[][..], options);
+        let mut compressed = Vec::new();
+        encoder.read_to_end(&mut compressed).unwrap();
+        assert!(decompress_to_end(&compressed).is_empty());
+        assert_eq!(encoder.read(&mut [0; 10]).unwrap(), 0);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn read_gzip() {
+        use crate::test_utils::decompress_gzip;
+
+        let data = get_test_data();
+        let mut encoder = GzEncoder::new(&data[..], CompressionOptions::high());
+        let mut compressed = Vec::new();
+        encoder.read_to_end(&mut compressed).unwrap();
+        assert!(decompress_gzip(&compressed).1 == data);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::lz77::{buffer_full, ProcessStatus};
+use crate::matching::get_match_length;
 use crate::output_writer::{BufferStatus, DynamicWriter};
 
 use std::cmp;
@@ -6,24 +7,66 @@ use std::ops::Range;
 
 const MIN_MATCH: usize = crate::huffman_table::MIN_MATCH as usize;
 const MAX_MATCH: usize = crate::huffman_table::MAX_MATCH as usize;
+/// The longest repeating pattern looked for, e.g 4 for a repeated 32-bit value.
+const MAX_PERIOD: usize = 4;
 
 /// Simple match function for run-length encoding.
 ///
 /// Checks how many of the next bytes from the start of the slice `data` matches prev.
-fn get_match_length_rle(data: &[u8], prev: u8) -> usize {
-    data.iter()
-        .take(MAX_MATCH)
-        .take_while(|&&b| b == prev)
-        .count()
+pub fn get_match_length_rle(data: &[u8], prev: u8) -> usize {
+    let data = &data[..cmp::min(data.len(), MAX_MATCH)];
+    // Compare 8 bytes at a time, which is a lot faster for long runs.
+    let pattern = u64::from_le_bytes([prev; 8]);
+    let mut chunks = data.chunks_exact(8);
+    let mut length = 0;
+    for chunk in chunks.by_ref() {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(chunk);
+        let diff = u64::from_le_bytes(bytes) ^ pattern;
+        if diff != 0 {
+            // The lowest set bit is in the first byte that differs.
+            return length + (diff.trailing_zeros() / 8) as usize;
+        }
+        length += 8;
+    }
+    length
+        + chunks
+            .remainder()
+            .iter()
+            .take_while(|&&b| b == prev)
+            .count()
+}
+
+/// Find the longest match at `position` repeating one of the patterns of 2 to `max_period` bytes
+/// just before it, returning the length and distance of the match, or `(0, 0)` if there is no
+/// match of at least `MIN_MATCH` bytes.
+fn get_match_short_period(data: &[u8], position: usize, max_period: usize) -> (usize, usize) {
+    let mut best = (0, 0);
+    for period in 2..=cmp::min(max_period, position) {
+        if data[position] == data[position - period] {
+            let length = get_match_length(data, position, position - period);
+            if length > best.0 {
+                best = (length, period);
+            }
+        }
+    }
+    if best.0 >= MIN_MATCH {
+        best
+    } else {
+        (0, 0)
+    }
 }
 
 /// L77-Compress data using the RLE(Run-length encoding) strategy
 ///
-/// This function simply looks for runs of data of at least length 3.
+/// This function simply looks for runs of data of at least length 3, and repeats of patterns of
+/// up to `MAX_PERIOD` bytes, like those in an array filled with the same 16 or 32-bit value.
+/// Matches will not have a distance longer than `max_distance`.
 pub fn process_chunk_greedy_rle(
     data: &[u8],
     iterated_data: &Range<usize>,
     writer: &mut DynamicWriter,
+    max_distance: usize,
 ) -> (usize, ProcessStatus) {
     if data.is_empty() {
         return (0, ProcessStatus::Ok);
@@ -38,6 +81,7 @@ pub fn process_chunk_greedy_rle(
     let current_chunk = &data[cmp::min(start, end)..end];
     let mut insert_it = current_chunk.iter().enumerate();
     let mut overlap = 0;
+    let max_period = cmp::min(MAX_PERIOD, max_distance);
     // Make sure to output the first byte
     if iterated_data.start == 0 && !data.is_empty() {
         write_literal!(writer, data[0], 1);
@@ -45,26 +89,31 @@ pub fn process_chunk_greedy_rle(
 
     while let Some((n, &b)) = insert_it.next() {
         let position = n + start;
-        let match_len = if prev == b {
+        let (match_len, distance) = if prev == b {
             //TODO: Avoid comparing with self here.
             // Would use as_slice() but that doesn't work on an enumerated iterator.
-            get_match_length_rle(&data[position..], prev)
+            (get_match_length_rle(&data[position..], prev), 1)
         } else {
-            0
+            get_match_short_period(data, position, max_period)
         };
         if match_len >= MIN_MATCH {
             if position + match_len > end {
                 overlap = position + match_len - end;
             };
-            let b_status = writer.write_length_rle(match_len as u16);
+            let b_status = if distance == 1 {
+                writer.write_length_rle(match_len as u16)
+            } else {
+                writer.write_length_distance(match_len as u16, distance as u16)
+            };
             if b_status == BufferStatus::Full {
                 return (overlap, buffer_full(position + match_len));
             }
             insert_it.nth(match_len - 2);
+            prev = data[position + match_len - 1];
         } else {
             write_literal!(writer, b, position + 1);
+            prev = b;
         }
-        prev = b;
     }
 
     (overlap, ProcessStatus::Ok)
@@ -84,7 +133,7 @@ mod test {
         let input = b"textaaaaaaaaatext";
         let mut w = DynamicWriter::new();
         let r = 0..input.len();
-        let (overlap, _) = process_chunk_greedy_rle(input, &r, &mut w);
+        let (overlap, _) = process_chunk_greedy_rle(input, &r, &mut w, 32768);
         let expected = [
             l('t'),
             l('e'),
@@ -102,4 +151,57 @@ mod test {
         assert!(w.get_buffer() == expected);
         assert_eq!(overlap, 0);
     }
+
+    #[test]
+    fn rle_match_length() {
+        let naive = |data: &[u8], prev: u8| {
+            data.iter()
+                .take(MAX_MATCH)
+                .take_while(|&&b| b == prev)
+                .count()
+        };
+        let mut data = vec![7u8; 300];
+        for &len in &[0, 1, 7, 8, 9, 15, 16, 17, 100, 257, 258, 259, 300] {
+            for &end in &[len, len + 1, len + 9] {
+                let end = cmp::min(end, data.len());
+                if len < data.len() {
+                    data[len] = 3;
+                }
+                assert_eq!(
+                    get_match_length_rle(&data[..end], 7),
+                    naive(&data[..end], 7)
+                );
+                if len < data.len() {
+                    data[len] = 7;
+                }
+            }
+        }
+        assert_eq!(get_match_length_rle(&data, 7), MAX_MATCH);
+    }
+
+    #[test]
+    fn rle_short_period() {
+        let input = b"xabababab0123012301230";
+        let mut w = DynamicWriter::new();
+        let (overlap, _) = process_chunk_greedy_rle(input, &(0..input.len()), &mut w, 32768);
+        let expected = [
+            l('x'),
+            l('a'),
+            l('b'),
+            ld(6, 2),
+            l('0'),
+            l('1'),
+            l('2'),
+            l('3'),
+            ld(9, 4),
+        ];
+        assert!(w.get_buffer() == expected);
+        assert_eq!(overlap, 0);
+
+        // Patterns longer than the maximum distance are not used.
+        let mut w = DynamicWriter::new();
+        process_chunk_greedy_rle(input, &(0..input.len()), &mut w, 2);
+        assert_eq!(w.get_buffer()[3], ld(6, 2));
+        assert_eq!(w.get_buffer().len(), 4 + 13);
+    }
 }
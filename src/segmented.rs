@@ -0,0 +1,251 @@
+//! This module contains functionality for splitting compressed output into segments along block
+//! boundaries.
+use std::mem;
+
+use crate::compress::{BlockInfo, BlockKind, Flush};
+use crate::compression_options::CompressionOptions;
+use crate::deflate_state::DeflateState;
+use crate::error::Error;
+use crate::writer::compress_until_done;
+
+/// A piece of a compressed stream containing exactly one DEFLATE block.
+///
+/// As blocks are generally not aligned to byte boundaries, the first and last byte of a segment
+/// may be shared with the previous and next segment respectively.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct BlockSegment {
+    /// The bytes of the output stream that contain the block.
+    pub data: Vec<u8>,
+    /// Number of bits at the start of the first byte in `data` that belong to the previous block.
+    pub bit_offset: u8,
+    /// The length of the block in bits.
+    ///
+    /// For the final block, this includes the padding bits at the end of the stream.
+    pub bit_len: u64,
+    /// The type of the block.
+    pub kind: BlockKind,
+    /// Number of bytes of input data contained in the block.
+    pub input_len: u64,
+    /// Whether this block has the final block flag set.
+    pub final_block: bool,
+}
+
+/// Split the compressed stream `output` into segments using the block information in `blocks`.
+///
+/// The blocks are expected to be in order and to cover the whole stream.
+pub fn split_into_segments(output: &[u8], blocks: &[BlockInfo]) -> Vec<BlockSegment> {
+    let total_bits = output.len() as u64 * 8;
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(n, block)| {
+            // Any padding bits after a block belong to the block in front of them.
+            let end_bit = blocks.get(n + 1).map_or(total_bits, |next| next.start_bit);
+            let start_byte = (block.start_bit / 8) as usize;
+            let end_byte = ((end_bit + 7) / 8) as usize;
+            BlockSegment {
+                data: output[start_byte..end_byte].to_vec(),
+                bit_offset: (block.start_bit % 8) as u8,
+                bit_len: end_bit - block.start_bit,
+                kind: block.kind,
+                input_len: block.input_len,
+                final_block: block.final_block,
+            }
+        })
+        .collect()
+}
+
+/// A byte-aligned piece of a compressed stream that can be decompressed without the data
+/// before it.
+///
+/// All segments except the last end with a full flush, meaning the last block is followed by an
+/// empty stored block, and the data after it doesn't refer back to the data before it. The last
+/// segment ends with the final block of the stream.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct IndependentSegment {
+    /// The compressed data.
+    pub data: Vec<u8>,
+    /// The position in the input of the first byte of data contained in the segment.
+    pub input_start: u64,
+    /// Number of bytes of input data contained in the segment.
+    pub input_len: u64,
+}
+
+/// Compress `input` in pieces of `segment_len` bytes, doing a full flush after each piece.
+pub fn compress_independent_segments(
+    input: &[u8],
+    options: CompressionOptions,
+    segment_len: usize,
+) -> Result<Vec<IndependentSegment>, Error> {
+    if segment_len == 0 {
+        return Err(Error::InvalidOptions("the segment length can not be 0"));
+    }
+    let mut deflate_state = Box::new(DeflateState::try_new(options, Vec::new())?);
+    deflate_state.set_input_size_hint(input.len() as u64);
+    let mut chunks = input.chunks(segment_len).peekable();
+    let mut segments = Vec::with_capacity(input.len() / segment_len + 1);
+    let mut input_start = 0;
+    loop {
+        let chunk = chunks.next().unwrap_or(&[]);
+        let last = chunks.peek().is_none();
+        let flush = if last { Flush::Finish } else { Flush::Full };
+        compress_until_done(chunk, &mut deflate_state, flush)?;
+        let output = deflate_state.inner.as_mut().expect("Missing writer!");
+        segments.push(IndependentSegment {
+            data: mem::replace(output, Vec::new()),
+            input_start,
+            input_len: chunk.len() as u64,
+        });
+        input_start += chunk.len() as u64;
+        if last {
+            break;
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+    use crate::{
+        try_deflate_bytes_conf, try_deflate_bytes_independent_segments,
+        try_deflate_bytes_segmented, try_deflate_bytes_with_block_boundaries, CompressionOptions,
+    };
+
+    /// Join the segments back together into one stream.
+    fn join_segments(segments: &[BlockSegment]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut bit_pos = 0u64;
+        for segment in segments {
+            for i in 0..segment.bit_len {
+                let src = u64::from(segment.bit_offset) + i;
+                let bit = (segment.data[(src / 8) as usize] >> (src % 8)) & 1;
+                if bit_pos % 8 == 0 {
+                    out.push(0);
+                }
+                *out.last_mut().unwrap() |= bit << (bit_pos % 8);
+                bit_pos += 1;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn segments_roundtrip() {
+        let data = get_test_data();
+        for &options in &[
+            CompressionOptions::default(),
+            CompressionOptions::fast(),
+            CompressionOptions::rle(),
+        ] {
+            let segments = try_deflate_bytes_segmented(&data, options).unwrap();
+            assert!(segments.len() > 1);
+            assert!(segments.last().unwrap().final_block);
+            assert!(segments[..segments.len() - 1]
+                .iter()
+                .all(|s| !s.final_block));
+            assert_eq!(
+                segments.iter().map(|s| s.input_len).sum::<u64>(),
+                data.len() as u64
+            );
+
+            let joined = join_segments(&segments);
+            assert!(joined == try_deflate_bytes_conf(&data, options).unwrap());
+            assert!(decompress_to_end(&joined) == data);
+        }
+    }
+
+    #[test]
+    fn segments_empty_input() {
+        let segments = try_deflate_bytes_segmented(&[], CompressionOptions::default()).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert!(segments[0].final_block);
+        assert!(decompress_to_end(&join_segments(&segments)).is_empty());
+    }
+
+    #[test]
+    fn block_boundaries() {
+        let data = get_test_data();
+        // Use groups of lines as the records.
+        let boundaries: Vec<_> = data
+            .iter()
+            .enumerate()
+            .filter(|&(_, &b)| b == b'\n')
+            .map(|(i, _)| i + 1)
+            .step_by(100)
+            .collect();
+        assert!(boundaries.len() > 10);
+        for &options in &[CompressionOptions::default(), CompressionOptions::fast()] {
+            let segments =
+                try_deflate_bytes_with_block_boundaries(&data, options, &boundaries).unwrap();
+            let starts: Vec<_> = segments
+                .iter()
+                .scan(0, |start, s| {
+                    *start += s.input_len as usize;
+                    Some(*start - s.input_len as usize)
+                })
+                .collect();
+            assert!(boundaries.iter().all(|b| starts.contains(b)));
+            assert!(segments.iter().all(|s| s.input_len > 0));
+            assert!(segments.last().unwrap().final_block);
+            assert!(decompress_to_end(&join_segments(&segments)) == data);
+        }
+
+        // Empty records and a boundary at the end of the input don't add any blocks.
+        let segments = try_deflate_bytes_with_block_boundaries(
+            b"abcabc",
+            CompressionOptions::default(),
+            &[0, 3, 3, 6],
+        )
+        .unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].input_len, 3);
+        assert_eq!(segments[2].input_len, 0);
+        assert!(decompress_to_end(&join_segments(&segments)) == b"abcabc");
+
+        for boundaries in &[&[3, 2][..], &[7], &[2, 4, 8]] {
+            match try_deflate_bytes_with_block_boundaries(
+                b"abcabc",
+                CompressionOptions::default(),
+                boundaries,
+            ) {
+                Err(Error::InvalidOptions(_)) => (),
+                _ => panic!("invalid block boundaries were accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn independent_segments() {
+        let data = get_test_data();
+        for &options in &[CompressionOptions::default(), CompressionOptions::rle()] {
+            let segments = try_deflate_bytes_independent_segments(&data, options, 40000).unwrap();
+            assert_eq!(segments.len(), (data.len() + 39999) / 40000);
+            let mut joined = Vec::new();
+            for (n, segment) in segments.iter().enumerate() {
+                assert_eq!(segment.input_start, n as u64 * 40000);
+                let input = &data[segment.input_start as usize..][..segment.input_len as usize];
+                let mut standalone = segment.data.clone();
+                if n + 1 < segments.len() {
+                    assert_eq!(segment.data[segment.data.len() - 4..], [0, 0, 255, 255]);
+                    // Terminate the stream with an empty final block.
+                    standalone.extend_from_slice(&[3, 0]);
+                }
+                assert!(decompress_to_end(&standalone) == input);
+                joined.extend_from_slice(&segment.data);
+            }
+            assert!(decompress_to_end(&joined) == data);
+        }
+
+        let segments =
+            try_deflate_bytes_independent_segments(&[], CompressionOptions::default(), 10).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert!(decompress_to_end(&segments[0].data).is_empty());
+
+        match try_deflate_bytes_independent_segments(b"abc", CompressionOptions::default(), 0) {
+            Err(Error::InvalidOptions(_)) => (),
+            _ => panic!("a segment length of 0 was accepted"),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! The destination of the compressed output.
+//!
+//! The compression pipeline writes its output through the [`Sink`](trait.Sink.html) trait rather
+//! than `std::io::Write` directly, so the encoders can be used with output types that don't
+//! implement `Write`. Every `Write` implementation is a `Sink`, so nothing has to be done to use
+//! the encoders with the standard library writers.
+use std::io::{self, Write};
+
+/// A destination for compressed data.
+///
+/// This only contains the part of `std::io::Write` used by the encoders, with a different method
+/// name so there is no ambiguity when both traits are in scope. The same conventions apply: a
+/// write can accept only part of the data, and is repeated by the encoder if it fails with an
+/// error that converts to an `io::Error` of the kind `Interrupted` or `WouldBlock`.
+pub trait Sink {
+    /// The error returned when writing fails.
+    ///
+    /// The encoders implement `std::io::Write`, so it is converted to an `io::Error` when it is
+    /// passed on.
+    type Error: Into<io::Error>;
+
+    /// Write some of `buf`, returning the number of bytes accepted.
+    fn write_output(&mut self, buf: &[u8]) -> Result<usize, Self::Error>;
+}
+
+impl<W: Write + ?Sized> Sink for W {
+    type Error = io::Error;
+
+    fn write_output(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(self, buf)
+    }
+}
+
+/// Helpers for the encoders, which work with `io::Error`s whatever the sink is.
+pub(crate) trait SinkExt: Sink {
+    /// Write some of `buf`, converting the error to an `io::Error`.
+    fn write_some_output(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_output(buf).map_err(Into::into)
+    }
+
+    /// Write all of `buf`, retrying if the write is interrupted.
+    fn write_all_output(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write_some_output(buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => buf = &buf[n..],
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> SinkExt for S {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+    use crate::write::DeflateEncoder;
+    use crate::CompressionOptions;
+
+    /// A sink that doesn't implement `Write`, accepting at most 100 bytes per call, and at most
+    /// `limit` bytes in total.
+    struct Chunks {
+        chunks: Vec<Vec<u8>>,
+        limit: usize,
+    }
+
+    impl Chunks {
+        fn new(limit: usize) -> Chunks {
+            Chunks {
+                chunks: Vec::new(),
+                limit,
+            }
+        }
+    }
+
+    /// The error returned once the limit of a `Chunks` sink is reached.
+    #[derive(Debug)]
+    struct Full;
+
+    impl From<Full> for io::Error {
+        fn from(_: Full) -> io::Error {
+            io::Error::new(io::ErrorKind::Other, "sink is full")
+        }
+    }
+
+    impl Sink for Chunks {
+        type Error = Full;
+
+        fn write_output(&mut self, buf: &[u8]) -> Result<usize, Full> {
+            let written: usize = self.chunks.iter().map(Vec::len).sum();
+            if written >= self.limit {
+                return Err(Full);
+            }
+            let len = buf.len().min(100).min(self.limit - written);
+            self.chunks.push(buf[..len].to_vec());
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn custom_sink() {
+        let data = get_test_data();
+        let mut encoder = DeflateEncoder::new(
+            Chunks::new(usize::max_value()),
+            CompressionOptions::default(),
+        );
+        encoder.write_all(&data).unwrap();
+        let chunks = encoder.finish().unwrap().chunks;
+        assert!(chunks.iter().all(|c| c.len() <= 100));
+        assert!(decompress_to_end(&chunks.concat()) == data);
+    }
+
+    #[test]
+    fn custom_sink_error() {
+        let data = get_test_data();
+        let mut encoder = DeflateEncoder::new(Chunks::new(1000), CompressionOptions::default());
+        let err = encoder
+            .write_all(&data)
+            .and_then(|_| encoder.try_finish())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "sink is full");
+    }
+}
@@ -0,0 +1,336 @@
+//! This module contains types for keeping track of statistics about the output stream.
+#[cfg(feature = "timing")]
+use std::time::{Duration, Instant};
+
+use crate::compress::{BlockInfo, BlockKind};
+#[cfg(feature = "symbol-stats")]
+use crate::huffman_table::{get_distance_code, NUM_DISTANCE_CODES};
+#[cfg(feature = "symbol-stats")]
+use crate::lzvalue::{LZType, LZValue};
+
+/// Statistics about the blocks of one type output by an encoder.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct BlockStats {
+    /// Number of blocks output.
+    pub count: u64,
+    /// Number of input bytes contained in the blocks.
+    pub input_bytes: u64,
+    /// Combined size of the blocks in bits, including the block headers.
+    pub output_bits: u64,
+    /// Combined size of the block headers in bits, including the Huffman tables of dynamic blocks.
+    pub header_bits: u64,
+}
+
+impl BlockStats {
+    /// Combined size of the blocks in bytes, rounded up.
+    pub fn output_bytes(&self) -> u64 {
+        (self.output_bits + 7) / 8
+    }
+
+    /// Combined size of the blocks in bits, excluding the block headers.
+    pub fn data_bits(&self) -> u64 {
+        self.output_bits - self.header_bits
+    }
+
+    /// The size of the blocks relative to the input they contain, or `None` if the blocks don't
+    /// contain any input.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.input_bytes == 0 {
+            None
+        } else {
+            Some(self.output_bits as f64 / (self.input_bytes * 8) as f64)
+        }
+    }
+}
+
+/// Statistics about the blocks output by an encoder so far.
+///
+/// The sizes don't include any padding or wrapper headers and trailers, so the sum of the
+/// block sizes may be slightly smaller than the actual output.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct CompressionStats {
+    /// Statistics for stored (uncompressed) blocks.
+    pub stored: BlockStats,
+    /// Statistics for blocks using the fixed Huffman codes.
+    pub fixed: BlockStats,
+    /// Statistics for blocks using dynamic Huffman codes.
+    pub dynamic: BlockStats,
+    /// Statistics about the literals and matches output.
+    #[cfg(feature = "symbol-stats")]
+    pub symbols: SymbolStats,
+    /// Time spent in each stage of compression.
+    #[cfg(feature = "timing")]
+    pub timings: StageTimings,
+}
+
+impl CompressionStats {
+    /// Get the statistics for blocks of the given type.
+    pub fn for_kind(&self, kind: BlockKind) -> &BlockStats {
+        match kind {
+            BlockKind::Stored => &self.stored,
+            BlockKind::Fixed => &self.fixed,
+            BlockKind::Dynamic => &self.dynamic,
+        }
+    }
+
+    /// Total number of blocks output.
+    pub fn total_blocks(&self) -> u64 {
+        self.stored.count + self.fixed.count + self.dynamic.count
+    }
+
+    /// Total number of input bytes contained in the blocks output.
+    pub fn total_input_bytes(&self) -> u64 {
+        self.stored.input_bytes + self.fixed.input_bytes + self.dynamic.input_bytes
+    }
+
+    /// Total size of the blocks output in bits.
+    pub fn total_output_bits(&self) -> u64 {
+        self.stored.output_bits + self.fixed.output_bits + self.dynamic.output_bits
+    }
+
+    /// Total size of the block headers output in bits.
+    pub fn total_header_bits(&self) -> u64 {
+        self.stored.header_bits + self.fixed.header_bits + self.dynamic.header_bits
+    }
+
+    pub(crate) fn add_block(&mut self, block: &BlockInfo) {
+        let stats = match block.kind {
+            BlockKind::Stored => &mut self.stored,
+            BlockKind::Fixed => &mut self.fixed,
+            BlockKind::Dynamic => &mut self.dynamic,
+        };
+        stats.count += 1;
+        stats.input_bytes += block.input_len;
+        stats.output_bits += block.end_bit - block.start_bit;
+        stats.header_bits += block.header_bits;
+    }
+}
+
+/// Time spent in each stage of compressing a stream.
+///
+/// This is useful for finding out where the time goes when compression is slower than
+/// expected. The time measurement itself adds some overhead, so the timings of short writes
+/// are a bit inflated.
+///
+/// Only available with the `timing` feature, which can't be used on targets without a clock,
+/// such as `wasm32-unknown-unknown`.
+#[cfg(feature = "timing")]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct StageTimings {
+    /// Time spent finding matches and buffering the input.
+    pub lz77: Duration,
+    /// Time spent generating Huffman codes and choosing block types.
+    pub huffman: Duration,
+    /// Time spent writing blocks to the output buffer.
+    pub bitstream: Duration,
+    /// Time spent computing the checksum of the input.
+    pub checksum: Duration,
+}
+
+#[cfg(feature = "timing")]
+impl StageTimings {
+    /// Total time spent in all the stages.
+    pub fn total(&self) -> Duration {
+        self.lz77 + self.huffman + self.bitstream + self.checksum
+    }
+}
+
+/// A stage of compression measured with the `timing` feature.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Stage {
+    Lz77,
+    Huffman,
+    Bitstream,
+    Checksum,
+}
+
+/// Measures the time spent in a stage of compression if the `timing` feature is enabled, and
+/// does nothing otherwise.
+pub(crate) struct StageTimer {
+    #[cfg(feature = "timing")]
+    start: Instant,
+}
+
+impl StageTimer {
+    #[inline]
+    pub fn start() -> StageTimer {
+        StageTimer {
+            #[cfg(feature = "timing")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Add the time since the timer was started to the time spent in `stage`.
+    #[inline]
+    pub fn stop(self, stats: &mut CompressionStats, stage: Stage) {
+        #[cfg(feature = "timing")]
+        {
+            let elapsed = self.start.elapsed();
+            let timings = &mut stats.timings;
+            match stage {
+                Stage::Lz77 => timings.lz77 += elapsed,
+                Stage::Huffman => timings.huffman += elapsed,
+                Stage::Bitstream => timings.bitstream += elapsed,
+                Stage::Checksum => timings.checksum += elapsed,
+            }
+        }
+        #[cfg(not(feature = "timing"))]
+        let _ = (stats, stage);
+    }
+}
+
+/// A summary of a finished stream, describing where the bits of the output went.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct CompressionReport {
+    /// Statistics about the blocks in the stream.
+    pub stats: CompressionStats,
+    /// Size of the zlib or gzip header in bytes, or 0 for raw DEFLATE streams.
+    pub header_bytes: u64,
+    /// Size of the zlib or gzip trailer in bytes, or 0 for raw DEFLATE streams.
+    pub trailer_bytes: u64,
+    /// Number of bytes of input data compressed.
+    pub input_bytes: u64,
+    /// Total size of the output in bytes, including the header and trailer.
+    pub output_bytes: u64,
+}
+
+impl CompressionReport {
+    /// Number of bytes taken up by the header and trailer.
+    pub fn wrapper_bytes(&self) -> u64 {
+        self.header_bytes + self.trailer_bytes
+    }
+
+    /// Number of bits spent on block headers, including the Huffman tables of dynamic blocks.
+    pub fn table_bits(&self) -> u64 {
+        self.stats.total_header_bits()
+    }
+
+    /// Number of bits spent on the contents of the blocks.
+    pub fn data_bits(&self) -> u64 {
+        self.stats.total_output_bits() - self.stats.total_header_bits()
+    }
+
+    /// Number of bits output that don't belong to any block or to the wrapper, i.e. the padding
+    /// at the end of the DEFLATE stream.
+    pub fn padding_bits(&self) -> u64 {
+        (self.output_bytes - self.wrapper_bytes()) * 8 - self.stats.total_output_bits()
+    }
+
+    /// The size of the output relative to the input, or `None` if the input was empty.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.input_bytes == 0 {
+            None
+        } else {
+            Some(self.output_bytes as f64 / self.input_bytes as f64)
+        }
+    }
+
+    /// The size of the blocks of the given type relative to the input they contain, or `None` if
+    /// no input was output using this block type.
+    pub fn ratio_for(&self, kind: BlockKind) -> Option<f64> {
+        self.stats.for_kind(kind).ratio()
+    }
+}
+
+/// Statistics about the literals and length-distance matches output by an encoder.
+///
+/// Only available with the `symbol-stats` feature.
+#[cfg(feature = "symbol-stats")]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SymbolStats {
+    /// Number of literal bytes output.
+    pub literals: u64,
+    /// Number of length-distance matches output.
+    pub matches: u64,
+    /// Sum of the lengths of all the matches.
+    pub total_match_length: u64,
+    /// Sum of the distances of all the matches.
+    pub total_match_distance: u64,
+    /// Number of matches of each length, where index `n` counts matches of length `n + 3`.
+    pub length_histogram: [u64; 256],
+    /// Number of matches using each of the DEFLATE distance codes.
+    pub distance_code_histogram: [u64; NUM_DISTANCE_CODES],
+}
+
+#[cfg(feature = "symbol-stats")]
+impl Default for SymbolStats {
+    fn default() -> SymbolStats {
+        SymbolStats {
+            literals: 0,
+            matches: 0,
+            total_match_length: 0,
+            total_match_distance: 0,
+            length_histogram: [0; 256],
+            distance_code_histogram: [0; NUM_DISTANCE_CODES],
+        }
+    }
+}
+
+#[cfg(feature = "symbol-stats")]
+impl SymbolStats {
+    /// The average length of the matches output, or 0 if there were none.
+    pub fn average_match_length(&self) -> f64 {
+        if self.matches == 0 {
+            0.0
+        } else {
+            self.total_match_length as f64 / self.matches as f64
+        }
+    }
+
+    /// The average distance of the matches output, or 0 if there were none.
+    pub fn average_match_distance(&self) -> f64 {
+        if self.matches == 0 {
+            0.0
+        } else {
+            self.total_match_distance as f64 / self.matches as f64
+        }
+    }
+
+    pub(crate) fn add_values(&mut self, values: &[LZValue]) {
+        for value in values {
+            match value.value() {
+                LZType::Literal(_) => self.literals += 1,
+                LZType::StoredLengthDistance(length, distance) => {
+                    let stored_length = length.stored_length();
+                    self.matches += 1;
+                    self.total_match_length += u64::from(stored_length) + 3;
+                    self.total_match_distance += u64::from(distance);
+                    self.length_histogram[usize::from(stored_length)] += 1;
+                    self.distance_code_histogram[usize::from(get_distance_code(distance))] += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "symbol-stats"))]
+mod test {
+    use crate::test_utils::get_test_data;
+    use crate::write::DeflateEncoder;
+    use crate::CompressionOptions;
+    use std::io::Write;
+
+    #[test]
+    fn symbol_stats() {
+        let data = get_test_data();
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        let (_, stats) = compressor.finish_with_stats().unwrap();
+        let symbols = stats.symbols;
+        assert!(symbols.matches > 0);
+        // Every input byte is either a literal or part of a match.
+        assert_eq!(
+            symbols.literals + symbols.total_match_length,
+            data.len() as u64
+        );
+        assert_eq!(
+            symbols.length_histogram.iter().sum::<u64>(),
+            symbols.matches
+        );
+        assert_eq!(
+            symbols.distance_code_histogram.iter().sum::<u64>(),
+            symbols.matches
+        );
+        assert!(symbols.average_match_length() >= 3.0);
+    }
+}
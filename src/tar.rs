@@ -0,0 +1,200 @@
+//! Creating `.tar.gz` archives with the [`tar`](https://docs.rs/tar) crate.
+//!
+//! [`TarGzBuilder`](struct.TarGzBuilder.html) is a `tar::Builder` writing through a
+//! [`GzEncoder`](../write/struct.GzEncoder.html), so entries are compressed as they are added
+//! instead of building the whole archive in memory first.
+//!
+//! Only available with the `tar` feature.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! # fn try_main() -> std::io::Result<()> {
+//! use std::fs::File;
+//!
+//! use deflate::tar::tar_gz_dir;
+//! use deflate::Compression;
+//!
+//! let output = File::create("assets.tar.gz")?;
+//! tar_gz_dir("assets", output, Compression::Default)?;
+//! # Ok(())
+//! # }
+//! ```
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+use gzip_header::GzBuilder;
+use tar_crate::Builder;
+
+use crate::compression_options::CompressionOptions;
+use crate::writer::gzip::{default_os, GzEncoder};
+
+pub use tar_crate::{EntryType, Header};
+
+/// A `tar::Builder` compressing the archive with gzip as it is written.
+///
+/// All the methods of `tar::Builder` for adding entries are available through `Deref`. The
+/// encoder passes the output on to the wrapped writer about once per DEFLATE block, so there
+/// is no need to wrap it in a `BufWriter`.
+///
+/// The archive has to be completed with [`finish`](#method.finish), which writes the end of
+/// the archive and the gzip trailer. As with `tar::Builder`, dropping it instead does this too,
+/// but ignores any errors.
+///
+/// # Examples
+///
+/// ```rust
+/// # fn try_main() -> std::io::Result<()> {
+/// use deflate::tar::{Header, TarGzBuilder};
+/// use deflate::Compression;
+///
+/// let mut builder = TarGzBuilder::new(Vec::new(), Compression::Default);
+/// let data = b"Hello from inside the archive";
+/// let mut header = Header::new_gnu();
+/// header.set_size(data.len() as u64);
+/// header.set_mode(0o644);
+/// builder.append_data(&mut header, "hello.txt", &data[..])?;
+/// let archive = builder.finish()?;
+/// # let _ = archive;
+/// # Ok(())
+/// # }
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub struct TarGzBuilder<W: Write> {
+    builder: Builder<GzEncoder<W>>,
+}
+
+impl<W: Write> TarGzBuilder<W> {
+    /// Create a new builder writing a compressed archive to `writer`.
+    ///
+    /// The gzip header only has the OS field filled in, the modification times of the files
+    /// are stored in the archive itself. Symbolic links are stored as links rather than
+    /// followed, like `tar` does by default.
+    pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> TarGzBuilder<W> {
+        TarGzBuilder::from_builder(GzBuilder::new().os(default_os()), writer, options)
+    }
+
+    /// Create a new builder writing a compressed archive to `writer`, using the provided
+    /// `GzBuilder` for the gzip header.
+    pub fn from_builder<O: Into<CompressionOptions>>(
+        gz_builder: GzBuilder,
+        writer: W,
+        options: O,
+    ) -> TarGzBuilder<W> {
+        let mut builder = Builder::new(GzEncoder::from_builder(gz_builder, writer, options));
+        builder.follow_symlinks(false);
+        TarGzBuilder { builder }
+    }
+
+    /// Write the end of the archive and the gzip trailer, and return the wrapped writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.builder.into_inner()?.finish()
+    }
+}
+
+impl<W: Write> Deref for TarGzBuilder<W> {
+    type Target = Builder<GzEncoder<W>>;
+
+    fn deref(&self) -> &Builder<GzEncoder<W>> {
+        &self.builder
+    }
+}
+
+impl<W: Write> DerefMut for TarGzBuilder<W> {
+    fn deref_mut(&mut self) -> &mut Builder<GzEncoder<W>> {
+        &mut self.builder
+    }
+}
+
+/// Create a `.tar.gz` archive of the directory at `path` and everything in it, writing it to
+/// `writer`.
+///
+/// The entries are stored under the name of the directory, the same as running
+/// `tar -czf archive.tar.gz dir` in the parent of the directory. Returns the writer once the
+/// archive is complete.
+///
+/// # Errors
+///
+/// Returns an error if reading the directory or any of the files in it fails, or if
+/// writing to `writer` fails.
+pub fn tar_gz_dir<P: AsRef<Path>, W: Write, O: Into<CompressionOptions>>(
+    path: P,
+    writer: W,
+    options: O,
+) -> io::Result<W> {
+    let path = path.as_ref();
+    // Resolve paths like "." to get the actual name of the directory. The root directory
+    // doesn't have one, so its contents are stored under ".".
+    let name = match path.canonicalize()?.file_name() {
+        Some(name) => Path::new(name).to_path_buf(),
+        None => Path::new(".").to_path_buf(),
+    };
+    let mut builder = TarGzBuilder::new(writer, options);
+    builder.append_dir_all(name, path)?;
+    builder.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::decompress_gzip;
+    use crate::CompressionOptions;
+    use std::fs;
+    use std::io::Read;
+    use tar_crate::Archive;
+
+    #[test]
+    fn tar_gz_round_trip() {
+        let archive = tar_gz_dir("tests/afl", Vec::new(), CompressionOptions::default()).unwrap();
+        let tar = decompress_gzip(&archive).1;
+
+        let mut files = 0;
+        for entry in Archive::new(&tar[..]).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().into_owned();
+            assert!(name.starts_with("afl"), "{:?}", name);
+            if entry.header().entry_type() == EntryType::Regular {
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents).unwrap();
+                let on_disk = Path::new("tests").join(&name);
+                assert!(contents == fs::read(on_disk).unwrap());
+                files += 1;
+            }
+        }
+        assert!(files > 0);
+
+        assert!(tar_gz_dir(
+            "tests/does-not-exist",
+            Vec::new(),
+            CompressionOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn tar_gz_builder() {
+        let data = crate::test_utils::get_test_data();
+        let mut builder = TarGzBuilder::new(Vec::new(), CompressionOptions::default());
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "pg11.txt", &data[..])
+            .unwrap();
+        let archive = builder.finish().unwrap();
+        assert!(archive.len() < data.len() / 2);
+
+        let tar = decompress_gzip(&archive).1;
+        let mut archive = Archive::new(&tar[..]);
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(&*entry.path().unwrap(), Path::new("pg11.txt"));
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).unwrap();
+        assert!(contents == data);
+        assert!(entries.next().is_none());
+    }
+}
@@ -0,0 +1,247 @@
+//! Helpers for checking that compressed data round-trips, for use in the tests of crates
+//! building on this one.
+//!
+//! Only available with the `test-support` feature. Decompression is done with the
+//! [`inflate`](../inflate/index.html) module.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use deflate::test_support::{assert_roundtrip_all_presets, validate_stream};
+//! use deflate::Format;
+//!
+//! let data = b"Some data to compress, some data to compress.";
+//! assert_roundtrip_all_presets(data);
+//!
+//! // Check a stored stream, e.g one produced by an earlier version.
+//! let golden = deflate::try_deflate_bytes_zlib(data).unwrap();
+//! validate_stream(&golden, Format::Zlib, data).unwrap();
+//! ```
+use std::error;
+use std::fmt;
+use std::io::Write;
+
+use crate::compression_options::CompressionOptions;
+use crate::inflate::{self, InflateError};
+use crate::writer::{AnyEncoder, Format};
+
+/// The problem found by [`validate_stream`](fn.validate_stream.html).
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ValidationError {
+    /// The stream could not be decompressed, with a description of the problem.
+    Invalid(String),
+    /// The checksum or size in the trailer didn't match the decompressed data.
+    TrailerMismatch,
+    /// The decompressed data differed from the expected data, starting at the contained
+    /// position.
+    DataMismatch(usize),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::Invalid(ref desc) => write!(f, "invalid stream: {}", desc),
+            ValidationError::TrailerMismatch => f.write_str("trailer doesn't match the data"),
+            ValidationError::DataMismatch(n) => {
+                write!(f, "decompressed data differs at position {}", n)
+            }
+        }
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// Decompress `compressed`, which is in the given format, checking the checksum in the trailer
+/// of zlib and gzip streams.
+pub fn decompress(compressed: &[u8], format: Format) -> Result<Vec<u8>, ValidationError> {
+    let result = match format {
+        Format::Raw => inflate::inflate_bytes(compressed),
+        Format::Zlib => inflate::inflate_bytes_zlib(compressed),
+        #[cfg(feature = "gzip")]
+        Format::Gzip => inflate::inflate_bytes_gzip(compressed),
+    };
+    result.map_err(|e| match e {
+        InflateError::ChecksumMismatch => ValidationError::TrailerMismatch,
+        e => ValidationError::Invalid(e.to_string()),
+    })
+}
+
+/// Check that `compressed` is a valid stream in the given format that decompresses to
+/// `expected`.
+pub fn validate_stream(
+    compressed: &[u8],
+    format: Format,
+    expected: &[u8],
+) -> Result<(), ValidationError> {
+    let data = decompress(compressed, format)?;
+    if data != expected {
+        let position = data
+            .iter()
+            .zip(expected)
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| data.len().min(expected.len()));
+        return Err(ValidationError::DataMismatch(position));
+    }
+    Ok(())
+}
+
+/// The presets of compression options, with their names.
+pub fn presets() -> [(&'static str, CompressionOptions); 6] {
+    [
+        ("default", CompressionOptions::default()),
+        ("fast", CompressionOptions::fast()),
+        ("high", CompressionOptions::high()),
+        ("huffman_only", CompressionOptions::huffman_only()),
+        ("rle", CompressionOptions::rle()),
+        ("low_latency", CompressionOptions::low_latency(4096)),
+    ]
+}
+
+/// The formats supported with the enabled features.
+fn formats() -> &'static [Format] {
+    &[
+        Format::Raw,
+        Format::Zlib,
+        #[cfg(feature = "gzip")]
+        Format::Gzip,
+    ]
+}
+
+/// Compress `data` in the given format, and check that it decompresses to `data`.
+///
+/// # Panics
+///
+/// Panics if the round trip fails.
+pub fn assert_roundtrip(data: &[u8], format: Format, options: CompressionOptions) {
+    let mut encoder = AnyEncoder::new(Vec::new(), format, options);
+    encoder.write_all(data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    if let Err(e) = validate_stream(&compressed, format, data) {
+        panic!(
+            "Round trip failed for {:?} with options {:?}: {}",
+            format, options, e
+        );
+    }
+}
+
+/// Compress `data` with each of the [`presets`](fn.presets.html) in each format, and check that
+/// it decompresses to `data`.
+///
+/// # Panics
+///
+/// Panics if any of the round trips fails.
+pub fn assert_roundtrip_all_presets(data: &[u8]) {
+    for &(_, options) in presets().iter() {
+        for &format in formats() {
+            assert_roundtrip(data, format, options);
+        }
+    }
+}
+
+/// A small xorshift generator, so the chunk sequences are reproducible from the seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // The state can't be 0.
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    /// A value in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Compress `data` through the writer API, in a container format and with chunk sizes and
+/// sync flushes chosen from `seed`, and check that the output decompresses to `data`.
+///
+/// Bugs in the streaming encoder have mostly shown up only with specific sequences of chunk
+/// sizes and flushes, so this mixes tiny writes, writes around the size of the internal
+/// buffers and large writes. The sequence of operations is included in the panic message on
+/// failure, so it can be turned into a regression test.
+///
+/// # Panics
+///
+/// Panics if the round trip fails.
+pub fn assert_roundtrip_chunked(data: &[u8], options: CompressionOptions, seed: u64) {
+    let mut rng = XorShift::new(seed);
+    let format = formats()[rng.below(formats().len())];
+
+    let mut ops = Vec::new();
+    let mut encoder = AnyEncoder::new(Vec::new(), format, options);
+    let mut position = 0;
+    while position < data.len() {
+        let max = match rng.below(4) {
+            0 => 4,
+            1 => 300,
+            2 => 70_000,
+            _ => 5000,
+        };
+        let len = (rng.below(max) + 1).min(data.len() - position);
+        encoder.write_all(&data[position..position + len]).unwrap();
+        ops.push(format!("write {}", len));
+        position += len;
+        if rng.below(6) == 0 {
+            encoder.flush().unwrap();
+            ops.push("flush".to_string());
+        }
+    }
+    let compressed = encoder.finish().unwrap();
+
+    if let Err(e) = validate_stream(&compressed, format, data) {
+        panic!(
+            "Round trip failed for {:?} with seed {}, options {:?}, operations: {}: {}",
+            format,
+            seed,
+            options,
+            ops.join(", "),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::get_test_data;
+
+    #[test]
+    fn presets_roundtrip() {
+        let data = get_test_data();
+        assert_roundtrip_all_presets(&data[..20_000]);
+        assert_roundtrip_all_presets(&[]);
+    }
+
+    #[test]
+    fn validation_errors() {
+        let data = get_test_data();
+        let compressed = crate::try_deflate_bytes_zlib(&data).unwrap();
+        assert_eq!(validate_stream(&compressed, Format::Zlib, &data), Ok(()));
+        assert_eq!(
+            validate_stream(&compressed, Format::Zlib, &data[..1000]),
+            Err(ValidationError::DataMismatch(1000))
+        );
+        let mut changed = data.clone();
+        changed[500] ^= 1;
+        assert_eq!(
+            validate_stream(&compressed, Format::Zlib, &changed),
+            Err(ValidationError::DataMismatch(500))
+        );
+
+        let mut corrupt = compressed.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        assert_eq!(
+            validate_stream(&corrupt, Format::Zlib, &data),
+            Err(ValidationError::TrailerMismatch)
+        );
+        match validate_stream(&compressed[..100], Format::Zlib, &data) {
+            Err(ValidationError::Invalid(_)) => (),
+            other => panic!("Expected invalid stream error, got {:?}", other),
+        }
+    }
+}
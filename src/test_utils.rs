@@ -19,6 +19,36 @@ pub fn get_test_data() -> Vec<u8> {
     get_test_file_data(&path)
 }
 
+/// A xorshift generator, for pseudo-random test data that is the same on every run.
+pub struct XorShift(u32);
+
+impl XorShift {
+    /// The state can't be 0, as it would stay 0.
+    pub fn new(seed: u32) -> XorShift {
+        assert!(seed != 0);
+        XorShift(seed)
+    }
+}
+
+impl Iterator for XorShift {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        Some(self.0)
+    }
+}
+
+/// `len` pseudo-random bytes, which don't compress at all.
+pub fn noise(len: usize) -> Vec<u8> {
+    XorShift::new(0x2545_f491)
+        .take(len)
+        .map(|n| n as u8)
+        .collect()
+}
+
 /// Helper function to decompress into a `Vec<u8>`
 pub fn decompress_to_end(input: &[u8]) -> Vec<u8> {
     use miniz_oxide::inflate::decompress_to_vec;
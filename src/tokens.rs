@@ -0,0 +1,198 @@
+//! This module contains functionality for encoding LZ77 tokens produced elsewhere.
+//!
+//! This allows using a custom matching algorithm, while reusing the Huffman coding, block
+//! splitting and bitstream writing of this crate.
+use crate::compress::write_huffman_block;
+use crate::deflate_state::LengthBuffers;
+use crate::encoder_state::EncoderState;
+use crate::error::Error;
+use crate::huffman_lengths::{gen_huffman_lengths, BlockType};
+use crate::huffman_table::{MAX_CODE_LENGTH, MAX_DISTANCE, MAX_MATCH, MIN_MATCH};
+use crate::output_writer::{DynamicWriter, MAX_BUFFER_LENGTH};
+
+/// A literal byte or a back-reference to earlier data, the building blocks of a DEFLATE stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Token {
+    /// A single byte.
+    Literal(u8),
+    /// A copy of `length` bytes starting `distance` bytes back in the output.
+    ///
+    /// `length` has to be between 3 and 258, and `distance` between 1 and 32768 and not point
+    /// to before the start of the data.
+    Match {
+        /// Number of bytes to copy.
+        length: u16,
+        /// How far back the bytes are copied from.
+        distance: u16,
+    },
+}
+
+impl Token {
+    /// The number of bytes of data this token represents.
+    pub(crate) fn len(self) -> u64 {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => u64::from(length),
+        }
+    }
+}
+
+/// Check that all the tokens are valid, returning `Error::InvalidToken` with the index of the
+/// first one that isn't.
+fn validate(tokens: &[Token]) -> Result<(), Error> {
+    let mut position = 0u64;
+    for (n, &token) in tokens.iter().enumerate() {
+        if let Token::Match { length, distance } = token {
+            if length < MIN_MATCH
+                || length > MAX_MATCH
+                || distance == 0
+                || distance > MAX_DISTANCE
+                || u64::from(distance) > position
+            {
+                return Err(Error::InvalidToken(n));
+            }
+        }
+        position += token.len();
+    }
+    Ok(())
+}
+
+/// Compress a sequence of LZ77 tokens to a raw DEFLATE stream, choosing between fixed and
+/// dynamic Huffman codes for each block.
+///
+/// As only the tokens, and not the data they describe, are provided, no stored blocks are
+/// output.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidToken` if one of the tokens is out of range or refers back to before
+/// the start of the data.
+///
+/// # Examples
+///
+/// ```rust
+/// use deflate::{deflate_tokens, Token};
+///
+/// // "abcabcabc"
+/// let tokens = [
+///     Token::Literal(b'a'),
+///     Token::Literal(b'b'),
+///     Token::Literal(b'c'),
+///     Token::Match { length: 6, distance: 3 },
+/// ];
+/// let compressed = deflate_tokens(&tokens).unwrap();
+/// # let _ = compressed;
+/// ```
+pub fn deflate_tokens(tokens: &[Token]) -> Result<Vec<u8>, Error> {
+    validate(tokens)?;
+
+    let mut encoder_state = EncoderState::new(Vec::with_capacity(tokens.len()));
+    let mut lz77_writer = DynamicWriter::new();
+    let mut length_buffers = LengthBuffers::new();
+
+    let mut blocks = tokens.chunks(MAX_BUFFER_LENGTH).peekable();
+    if blocks.peek().is_none() {
+        // Output an empty final block for empty input.
+        write_huffman_block(None, &[], &mut encoder_state, &length_buffers, true)?;
+    }
+    while let Some(block) = blocks.next() {
+        let last_block = blocks.peek().is_none();
+        let mut input_bytes = 0;
+        for &token in block {
+            match token {
+                Token::Literal(l) => lz77_writer.write_literal(l),
+                Token::Match { length, distance } => {
+                    lz77_writer.write_length_distance(length, distance)
+                }
+            };
+            input_bytes += token.len();
+        }
+
+        let lengths = {
+            let (l_freqs, d_freqs) = lz77_writer.get_frequencies();
+            let (l_lengths, d_lengths) = encoder_state.huffman_table.get_lengths_mut();
+            gen_huffman_lengths(
+                l_freqs,
+                d_freqs,
+                input_bytes,
+                encoder_state.writer.pending_bits(),
+                MAX_CODE_LENGTH,
+                l_lengths,
+                d_lengths,
+                &mut length_buffers,
+            )
+        };
+        let block_type = match lengths {
+            Some(lengths) => {
+                let kind = lengths.shortest_compressed();
+                lengths.into_block_type(kind)
+            }
+            None => BlockType::Fixed,
+        };
+        let header = match block_type {
+            BlockType::Dynamic(ref header) => Some(header),
+            _ => None,
+        };
+        write_huffman_block(
+            header,
+            lz77_writer.get_buffer(),
+            &mut encoder_state,
+            &length_buffers,
+            last_block,
+        )?;
+        lz77_writer.clear();
+    }
+
+    encoder_state.flush();
+    Ok(encoder_state.writer.w)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lz77::lz77_compress;
+    use crate::lzvalue::LZType;
+    use crate::test_utils::{decompress_to_end, get_test_data};
+
+    #[test]
+    fn roundtrip_tokens() {
+        let data = get_test_data();
+        let tokens: Vec<Token> = lz77_compress(&data)
+            .unwrap()
+            .iter()
+            .map(|v| match v.value() {
+                LZType::Literal(l) => Token::Literal(l),
+                LZType::StoredLengthDistance(l, d) => Token::Match {
+                    length: l.actual_length(),
+                    distance: d,
+                },
+            })
+            .collect();
+        let compressed = deflate_tokens(&tokens).unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+        assert!(compressed.len() < data.len() / 2);
+
+        assert!(decompress_to_end(&deflate_tokens(&[]).unwrap()).is_empty());
+        let literals: Vec<Token> = data[..100].iter().map(|&b| Token::Literal(b)).collect();
+        assert!(decompress_to_end(&deflate_tokens(&literals).unwrap()) == &data[..100]);
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        let l = Token::Literal(0);
+        let m = |length, distance| Token::Match { length, distance };
+        for &(ref tokens, index) in &[
+            (vec![m(3, 1)], 0),
+            (vec![l, l, m(3, 3)], 2),
+            (vec![l, m(2, 1)], 1),
+            (vec![l, m(259, 1)], 1),
+            (vec![l, m(3, 0)], 1),
+        ] {
+            match deflate_tokens(tokens) {
+                Err(Error::InvalidToken(n)) => assert_eq!(n, index),
+                other => panic!("Expected invalid token error, got {:?}", other),
+            }
+        }
+        assert!(deflate_tokens(&[l, m(258, 1), m(3, 259)]).is_ok());
+    }
+}
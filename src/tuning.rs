@@ -0,0 +1,54 @@
+//! Internal constants that depend on the kind of data the encoder is tuned for.
+//!
+//! The default values are a compromise that works reasonably well for most data. The `tune-text`
+//! and `tune-binary` features select values better suited for input that is mostly text and
+//! mostly binary data respectively. The tuned values are only used with
+//! `OutputVersion::Latest`, so the output of the fixed versions doesn't depend on the features.
+//!
+//! The shift used by the `ShiftXor` hash function is not tuned, as the default `Multiplicative`
+//! hash already mixes in all the bits of each byte, which is what binary data would need.
+
+#[cfg(all(feature = "tune-text", feature = "tune-binary"))]
+compile_error!("the `tune-text` and `tune-binary` features can't be enabled at the same time");
+
+/// Matches of the minimum length further back than this are not used, as they are likely to take
+/// up more space than the literals they replace. This is the value used by the fixed versions.
+pub const DEFAULT_TOO_FAR: usize = 8 * 1024;
+
+#[cfg(not(any(feature = "tune-text", feature = "tune-binary")))]
+mod values {
+    /// The value of `TOO_FAR` used with `OutputVersion::Latest`.
+    pub const TOO_FAR: usize = super::DEFAULT_TOO_FAR;
+
+    /// The lazy matching threshold used with `OutputVersion::Latest` when the options ask for
+    /// `lazy_if_less_than`.
+    pub fn lazy_if_less_than(lazy_if_less_than: u16) -> u16 {
+        lazy_if_less_than
+    }
+}
+
+#[cfg(feature = "tune-text")]
+mod values {
+    // Literals in text are cheap to encode, so a short match far back rarely saves anything.
+    // This is the value zlib uses.
+    pub const TOO_FAR: usize = 4 * 1024;
+
+    // Lazy matching pays off more often on text, where a match starting one byte later is
+    // frequently longer.
+    pub fn lazy_if_less_than(lazy_if_less_than: u16) -> u16 {
+        lazy_if_less_than.saturating_mul(2)
+    }
+}
+
+#[cfg(all(feature = "tune-binary", not(feature = "tune-text")))]
+mod values {
+    // Literals in binary data tend to need close to 8 bits each, so even short matches far back
+    // are usually worth it.
+    pub const TOO_FAR: usize = 16 * 1024;
+
+    pub fn lazy_if_less_than(lazy_if_less_than: u16) -> u16 {
+        lazy_if_less_than / 2
+    }
+}
+
+pub use self::values::*;
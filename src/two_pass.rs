@@ -0,0 +1,449 @@
+//! This module contains a compression mode that looks at the whole input before deciding where
+//! to put block boundaries and what Huffman tables to use.
+//!
+//! The streaming compressor has to decide the Huffman codes for a block as soon as the block is
+//! full, without knowing anything about the data after it. When all the input is available up
+//! front, we can instead do the LZ77 step for the whole input first, and then merge neighbouring
+//! blocks with similar statistics so they share one set of Huffman codes, saving the cost of
+//! storing the tables for each block.
+use crate::compress::{write_huffman_block, write_stored_block};
+use crate::compression_options::{CompressionOptions, OutputVersion, SpecialOptions};
+use crate::deflate_state::LengthBuffers;
+use crate::encoder_state::EncoderState;
+use crate::error::Error;
+use crate::huffman_lengths::{gen_huffman_lengths, BlockLengths};
+use crate::huffman_table::{
+    get_distance_code, get_length_code, num_extra_bits_for_distance_code,
+    num_extra_bits_for_length_code, FIXED_CODE_LENGTHS, LENGTH_BITS_START, NUM_DISTANCE_CODES,
+    NUM_LITERALS_AND_LENGTHS,
+};
+use crate::input_buffer::InputBuffer;
+use crate::lz77::{lz77_compress_block_finish, LZ77State};
+use crate::lzvalue::{LZType, LZValue};
+use crate::output_writer::{DynamicWriter, FrequencyType};
+use crate::stored_block::MAX_STORED_BLOCK_LENGTH;
+
+/// Symbol frequencies of a block, which unlike the ones in `DynamicWriter` can't overflow.
+#[derive(Clone)]
+pub struct Frequencies {
+    pub literal_length: [u64; NUM_LITERALS_AND_LENGTHS],
+    pub distance: [u64; NUM_DISTANCE_CODES],
+}
+
+impl Frequencies {
+    fn new() -> Frequencies {
+        Frequencies {
+            literal_length: [0; NUM_LITERALS_AND_LENGTHS],
+            distance: [0; NUM_DISTANCE_CODES],
+        }
+    }
+
+    /// Count the symbols in `values`, along with the end of block symbol.
+    pub fn from_values(values: &[LZValue]) -> Frequencies {
+        let mut frequencies = Frequencies::new();
+        frequencies.literal_length[256] = 1;
+        for value in values {
+            match value.value() {
+                LZType::Literal(l) => frequencies.literal_length[usize::from(l)] += 1,
+                LZType::StoredLengthDistance(l, d) => {
+                    frequencies.literal_length[get_length_code(l.actual_length())] += 1;
+                    frequencies.distance[usize::from(get_distance_code(d))] += 1;
+                }
+            }
+        }
+        frequencies
+    }
+
+    fn add(&mut self, other: &Frequencies) {
+        for (a, b) in self
+            .literal_length
+            .iter_mut()
+            .zip(&other.literal_length[..])
+        {
+            *a += b;
+        }
+        for (a, b) in self.distance.iter_mut().zip(&other.distance[..]) {
+            *a += b;
+        }
+    }
+
+    /// Frequencies scaled down to fit in `FrequencyType`, keeping all used symbols at a frequency
+    /// of at least 1 so they are given a code.
+    pub fn scaled(&self) -> (Vec<FrequencyType>, Vec<FrequencyType>) {
+        let max = self
+            .literal_length
+            .iter()
+            .chain(&self.distance[..])
+            .cloned()
+            .max()
+            .unwrap_or(0);
+        let limit = u64::from(FrequencyType::max_value());
+        let scale = |&f: &u64| {
+            if max <= limit || f == 0 {
+                f as FrequencyType
+            } else {
+                (f * limit / max).max(1) as FrequencyType
+            }
+        };
+        (
+            self.literal_length.iter().map(scale).collect(),
+            self.distance.iter().map(scale).collect(),
+        )
+    }
+
+    /// Number of bits used by the symbols with the given code lengths, including extra bits.
+    fn data_bits(&self, l_lengths: &[u8], d_lengths: &[u8]) -> u64 {
+        let literal_lengths = self
+            .literal_length
+            .iter()
+            .zip(l_lengths)
+            .enumerate()
+            .map(|(n, (&f, &l))| {
+                let extra = if n >= LENGTH_BITS_START as usize {
+                    num_extra_bits_for_length_code((n - LENGTH_BITS_START as usize) as u8)
+                } else {
+                    0
+                };
+                f * u64::from(l + extra)
+            })
+            .sum::<u64>();
+        let distances = self
+            .distance
+            .iter()
+            .zip(d_lengths)
+            .enumerate()
+            .map(|(n, (&f, &l))| f * u64::from(l + num_extra_bits_for_distance_code(n as u8)))
+            .sum::<u64>();
+        literal_lengths + distances
+    }
+}
+
+/// A run of LZ77 output that will be written as one block.
+#[derive(Clone)]
+struct Group {
+    /// Range of values in the LZ77 output.
+    values: (usize, usize),
+    /// Range of input bytes covered.
+    input: (usize, usize),
+    frequencies: Frequencies,
+}
+
+impl Group {
+    fn input_len(&self) -> u64 {
+        (self.input.1 - self.input.0) as u64
+    }
+
+    fn merged(&self, next: &Group) -> Group {
+        let mut frequencies = self.frequencies.clone();
+        frequencies.add(&next.frequencies);
+        // Both had an end of block symbol, but the merged block only has one.
+        frequencies.literal_length[256] -= 1;
+        Group {
+            values: (self.values.0, next.values.1),
+            input: (self.input.0, next.input.1),
+            frequencies,
+        }
+    }
+}
+
+/// The cheapest way to output a group, with the size in bits (excluding the 3 bit block header).
+enum Choice {
+    Stored,
+    Fixed,
+    Dynamic(BlockLengths),
+}
+
+/// Generate the code lengths for `group` into `encoder_state`, and find the cheapest way to
+/// output it.
+fn choose(
+    group: &Group,
+    force_fixed: bool,
+    allow_stored: bool,
+    max_code_length: usize,
+    encoder_state: &mut EncoderState,
+    length_buffers: &mut LengthBuffers,
+) -> (Choice, u64) {
+    let freqs = &group.frequencies;
+    let fixed_bits = freqs.data_bits(&FIXED_CODE_LENGTHS, &[5; NUM_DISTANCE_CODES]);
+    let stored_bits = if group.input_len() == 0 || !allow_stored {
+        u64::max_value()
+    } else {
+        let blocks = (group.input_len() - 1) / MAX_STORED_BLOCK_LENGTH as u64 + 1;
+        // Data, length fields and the worst case padding.
+        (group.input_len() + blocks * 4) * 8 + blocks * 7 + (blocks - 1) * 3
+    };
+    let mut best = if fixed_bits <= stored_bits {
+        (Choice::Fixed, fixed_bits)
+    } else {
+        (Choice::Stored, stored_bits)
+    };
+    if force_fixed {
+        return (Choice::Fixed, fixed_bits);
+    }
+
+    let (l_scaled, d_scaled) = freqs.scaled();
+    let (l_lengths, d_lengths) = encoder_state.huffman_table.get_lengths_mut();
+    if let Some(lengths) = gen_huffman_lengths(
+        &l_scaled,
+        &d_scaled,
+        group.input_len(),
+        0,
+        max_code_length,
+        l_lengths,
+        d_lengths,
+        length_buffers,
+    ) {
+        // The returned length is calculated from the scaled frequencies, so replace the data part
+        // of it with the real size.
+        let scaled = Frequencies {
+            literal_length: {
+                let mut f = [0; NUM_LITERALS_AND_LENGTHS];
+                f.iter_mut()
+                    .zip(&l_scaled)
+                    .for_each(|(a, &b)| *a = b.into());
+                f
+            },
+            distance: {
+                let mut f = [0; NUM_DISTANCE_CODES];
+                f.iter_mut()
+                    .zip(&d_scaled)
+                    .for_each(|(a, &b)| *a = b.into());
+                f
+            },
+        };
+        let header_bits = lengths.dynamic_length - scaled.data_bits(&l_lengths[..], &d_lengths[..]);
+        let dynamic_bits = header_bits + freqs.data_bits(&l_lengths[..], &d_lengths[..]);
+        if dynamic_bits < best.1 {
+            best = (Choice::Dynamic(lengths), dynamic_bits);
+        }
+    }
+    best
+}
+
+/// Do the LZ77 step for all of `input`, returning the output values and the blocks the
+/// streaming compressor would have split them into.
+fn lz77_pass(input: &[u8], options: &CompressionOptions) -> (Vec<LZValue>, Vec<Group>) {
+    let mut state = Box::new(LZ77State::new(
+        options.max_hash_checks,
+        options.lazy_if_less_than,
+        options.matching_type,
+        options.window_bits,
+        options.max_search_distance,
+        options.hash_function,
+    ));
+    state.set_tuned(options.output_version != OutputVersion::V1);
+    let mut buffer = InputBuffer::empty();
+    let mut writer = DynamicWriter::new();
+    writer.set_max_length(usize::from(options.max_block_symbols));
+
+    let mut values = Vec::with_capacity(input.len() / 3);
+    let mut groups = Vec::new();
+    let mut slice = input;
+    let mut input_pos = 0;
+    while !state.is_last_block() {
+        let consumed = lz77_compress_block_finish(slice, &mut state, &mut buffer, &mut writer).0;
+        slice = &slice[consumed..];
+
+        let block_values = writer.get_buffer();
+        let frequencies = Frequencies::from_values(block_values);
+        let block_input = state.current_block_input_bytes() as usize;
+        groups.push(Group {
+            values: (values.len(), values.len() + block_values.len()),
+            input: (input_pos, input_pos + block_input),
+            frequencies,
+        });
+        values.extend_from_slice(block_values);
+        input_pos += block_input;
+        state.reset_input_bytes();
+        writer.clear();
+    }
+    debug_assert_eq!(input_pos, input.len());
+    (values, groups)
+}
+
+/// Compress `input` to a raw DEFLATE stream, doing the LZ77 step for all of the input before
+/// deciding on the block boundaries and Huffman codes.
+///
+/// If `single_block` is true, all of the input is written as one block.
+pub fn compress_two_pass(
+    input: &[u8],
+    options: CompressionOptions,
+    single_block: bool,
+) -> Result<Vec<u8>, Error> {
+    options.validate()?;
+    let options = options.clamped();
+    // A stored block can't be longer than `MAX_STORED_BLOCK_LENGTH`, so longer input would need
+    // more than one.
+    let allow_stored = !single_block || input.len() <= MAX_STORED_BLOCK_LENGTH;
+    let force_fixed = options.special == SpecialOptions::ForceFixed;
+    let max_code_length = usize::from(options.max_code_length);
+    let (values, segments) = lz77_pass(input, &options);
+
+    let mut encoder_state = EncoderState::new(Vec::with_capacity(input.len() / 2));
+    let mut length_buffers = LengthBuffers::new();
+
+    // Greedily merge each block into the previous one if sharing one set of codes is cheaper
+    // than having two blocks. Each block costs 3 bits for the block header in addition to the
+    // size returned by `choose`.
+    let mut groups: Vec<(Group, u64)> = Vec::with_capacity(segments.len());
+    for segment in segments {
+        if single_block && !groups.is_empty() {
+            let merged = groups[0].0.merged(&segment);
+            groups[0].0 = merged;
+            continue;
+        }
+        let cost = choose(
+            &segment,
+            force_fixed,
+            allow_stored,
+            max_code_length,
+            &mut encoder_state,
+            &mut length_buffers,
+        )
+        .1;
+        if let Some(&mut (ref mut last, ref mut last_cost)) = groups.last_mut() {
+            let merged = last.merged(&segment);
+            let merged_cost = choose(
+                &merged,
+                force_fixed,
+                allow_stored,
+                max_code_length,
+                &mut encoder_state,
+                &mut length_buffers,
+            )
+            .1;
+            if merged_cost <= *last_cost + cost + 3 {
+                *last = merged;
+                *last_cost = merged_cost;
+                continue;
+            }
+        }
+        groups.push((segment, cost));
+    }
+
+    let num_groups = groups.len();
+    for (n, (group, _)) in groups.into_iter().enumerate() {
+        let last_block = n + 1 == num_groups;
+        // This also leaves the code lengths for the group in the Huffman table.
+        let (choice, _) = choose(
+            &group,
+            force_fixed,
+            allow_stored,
+            max_code_length,
+            &mut encoder_state,
+            &mut length_buffers,
+        );
+        let block_values = &values[group.values.0..group.values.1];
+        match choice {
+            Choice::Stored => write_stored_block(
+                &input[group.input.0..group.input.1],
+                &mut encoder_state.writer,
+                last_block,
+            ),
+            Choice::Fixed => {
+                write_huffman_block(
+                    None,
+                    block_values,
+                    &mut encoder_state,
+                    &length_buffers,
+                    last_block,
+                )?;
+            }
+            Choice::Dynamic(lengths) => {
+                write_huffman_block(
+                    Some(&lengths.header),
+                    block_values,
+                    &mut encoder_state,
+                    &length_buffers,
+                    last_block,
+                )?;
+            }
+        }
+    }
+
+    encoder_state.flush();
+    Ok(encoder_state.writer.w)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_to_end, get_test_data, noise};
+    use crate::{try_deflate_bytes_conf, Compression};
+
+    #[test]
+    fn two_pass_roundtrip() {
+        let data = get_test_data();
+        for &options in &[
+            CompressionOptions::default(),
+            CompressionOptions::high(),
+            CompressionOptions::fast(),
+            CompressionOptions::huffman_only(),
+            CompressionOptions::rle(),
+            CompressionOptions::low_latency(0),
+        ] {
+            for &len in &[0, 1, 4, 5, 100, data.len()] {
+                let compressed = compress_two_pass(&data[..len], options, false).unwrap();
+                assert!(
+                    decompress_to_end(&compressed) == &data[..len],
+                    "{:?}",
+                    options
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn two_pass_smaller() {
+        // Repeat the data so it covers many blocks with similar statistics.
+        let data: Vec<u8> = get_test_data()
+            .chunks(7)
+            .flat_map(|c| c.iter().rev().cloned().collect::<Vec<u8>>())
+            .chain(get_test_data())
+            .collect();
+        let two_pass = compress_two_pass(&data, Compression::Default.into(), false).unwrap();
+        let streaming = try_deflate_bytes_conf(&data, Compression::Default).unwrap();
+        assert!(decompress_to_end(&two_pass) == data);
+        assert!(
+            two_pass.len() <= streaming.len(),
+            "two-pass: {}, streaming: {}",
+            two_pass.len(),
+            streaming.len()
+        );
+
+        // Incompressible data should end up stored.
+        let noise = noise(100_000);
+        let compressed = compress_two_pass(&noise, Compression::Default.into(), false).unwrap();
+        assert!(decompress_to_end(&compressed) == noise);
+        assert!(compressed.len() < noise.len() + 50);
+    }
+
+    #[test]
+    fn single_block() {
+        let data = get_test_data();
+        let mut options = CompressionOptions::default();
+        // Make the LZ77 step split the input into many blocks.
+        options.max_block_symbols = 1000;
+        for &len in &[0, 3, 100, 40000, data.len()] {
+            let input = &data[..len];
+            for &single_block in &[false, true] {
+                let compressed = compress_two_pass(input, options, single_block).unwrap();
+                assert!(decompress_to_end(&compressed) == input);
+                // The first block has the final block flag set if there is only one.
+                if single_block {
+                    assert_eq!(compressed[0] & 1, 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn two_pass_invalid_options() {
+        let mut options = CompressionOptions::default();
+        options.window_bits = 16;
+        match compress_two_pass(b"data", options, false) {
+            Err(Error::InvalidOptions(_)) => (),
+            _ => panic!("invalid options were accepted"),
+        }
+    }
+}
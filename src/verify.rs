@@ -0,0 +1,184 @@
+//! This module contains a verifier that decompresses the output as it is produced and checks that
+//! it matches the input, used when the `verify` feature is enabled.
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::inflate::{InflateState, InflateStatus};
+
+pub struct Verifier {
+    inflater: InflateState,
+    /// Buffer for the decompressed data, kept to avoid reallocating it.
+    decompressed: Vec<u8>,
+    /// Input data that hasn't been matched against the decompressed output yet.
+    pending_input: VecDeque<u8>,
+    /// Position in the output stream of the first byte of DEFLATE data, after any header.
+    stream_start: Option<u64>,
+    /// Output bytes received before the start of the DEFLATE data was known.
+    pending_output: Vec<u8>,
+    /// Position in the output stream up to which output has been received.
+    output_pos: u64,
+    /// Whether the end of the DEFLATE stream has been reached.
+    done: bool,
+    /// Whether checking is turned off, for streams that don't start with this encoder.
+    disabled: bool,
+}
+
+impl Verifier {
+    pub fn new() -> Verifier {
+        Verifier {
+            inflater: InflateState::new(),
+            decompressed: Vec::new(),
+            pending_input: VecDeque::new(),
+            stream_start: None,
+            pending_output: Vec::new(),
+            output_pos: 0,
+            done: false,
+            disabled: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.inflater.reset();
+        self.pending_input.clear();
+        self.stream_start = None;
+        self.pending_output.clear();
+        self.output_pos = 0;
+        self.done = false;
+        self.disabled = false;
+    }
+
+    /// Stop checking the output until the verifier is reset, as the output of a resumed stream
+    /// can't be decompressed without the earlier part of it.
+    pub fn disable(&mut self) {
+        self.disabled = true;
+        self.pending_input.clear();
+        self.pending_output.clear();
+    }
+
+    /// Add input data that has been consumed by the encoder.
+    pub fn add_input(&mut self, input: &[u8]) {
+        if self.disabled {
+            return;
+        }
+        self.pending_input.extend(input);
+    }
+
+    /// Note that a block starts at `start_bit` in the output stream.
+    pub fn block_start(&mut self, start_bit: u64) {
+        if self.stream_start.is_none() {
+            self.stream_start = Some(start_bit / 8);
+        }
+    }
+
+    /// Check the next part of the output stream.
+    pub fn add_output(&mut self, output: &[u8]) -> Result<(), &'static str> {
+        if self.disabled {
+            return Ok(());
+        }
+        let start = self.output_pos;
+        self.output_pos += output.len() as u64;
+        let stream_start = match self.stream_start {
+            Some(stream_start) => stream_start,
+            None => {
+                self.pending_output.extend_from_slice(output);
+                return Ok(());
+            }
+        };
+
+        if self.pending_output.is_empty() {
+            let skip = stream_start.saturating_sub(start) as usize;
+            self.inflate(&output[skip.min(output.len())..])
+        } else {
+            // Output is only kept while the start of the stream is unknown, which means it
+            // covers the output from the beginning.
+            let mut pending = mem::replace(&mut self.pending_output, Vec::new());
+            pending.extend_from_slice(output);
+            let skip = (stream_start as usize).min(pending.len());
+            self.inflate(&pending[skip..])
+        }
+    }
+
+    /// Check that the whole stream has been decompressed, and matched all the input.
+    pub fn check_finished(&self) -> Result<(), &'static str> {
+        if self.disabled {
+            Ok(())
+        } else if !self.done {
+            Err("Output ends before the end of the compressed stream.")
+        } else if !self.pending_input.is_empty() {
+            Err("Output decompresses to less data than was input.")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn inflate(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        if self.done {
+            return if data.is_empty() {
+                Ok(())
+            } else {
+                Err("Output continues past the end of the compressed stream.")
+            };
+        }
+        let mut decompressed = mem::replace(&mut self.decompressed, Vec::new());
+        decompressed.clear();
+        let res = self.inflater.update(data, &mut decompressed);
+        let checked = self.check_output(&decompressed);
+        self.decompressed = decompressed;
+        let (consumed, status) = res.map_err(|_| "Output is not valid DEFLATE data.")?;
+        checked?;
+        if status == InflateStatus::Done {
+            self.done = true;
+            if consumed < data.len() {
+                return Err("Output continues past the end of the compressed stream.");
+            }
+        }
+        Ok(())
+    }
+
+    fn check_output(&mut self, decompressed: &[u8]) -> Result<(), &'static str> {
+        if decompressed.len() > self.pending_input.len() {
+            return Err("Output decompresses to more data than was input.");
+        }
+        if !self
+            .pending_input
+            .drain(..decompressed.len())
+            .eq(decompressed.iter().cloned())
+        {
+            return Err("Output doesn't decompress to the input data.");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::get_test_data;
+    use crate::{try_deflate_bytes, try_deflate_bytes_zlib};
+
+    fn verify(input: &[u8], output: &[u8], stream_start: u64) -> Result<(), &'static str> {
+        let mut verifier = Verifier::new();
+        verifier.add_input(input);
+        // Split the output in two, the first part possibly before the start is known.
+        let (first, second) = output.split_at(output.len() / 3);
+        verifier.add_output(first)?;
+        verifier.block_start(stream_start * 8);
+        verifier.add_output(second)?;
+        verifier.check_finished()
+    }
+
+    #[test]
+    fn verify_output() {
+        let data = get_test_data();
+        let compressed = try_deflate_bytes(&data).unwrap();
+        assert!(verify(&data, &compressed, 0).is_ok());
+        let zlib = try_deflate_bytes_zlib(&data).unwrap();
+        assert!(verify(&data, &zlib[..zlib.len() - 4], 2).is_ok());
+
+        let mut corrupted = compressed.clone();
+        corrupted[compressed.len() / 2] ^= 0x10;
+        assert!(verify(&data, &corrupted, 0).is_err());
+        assert!(verify(&data, &compressed[..compressed.len() - 10], 0).is_err());
+        assert!(verify(&data[..data.len() - 1], &compressed, 0).is_err());
+    }
+}
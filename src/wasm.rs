@@ -0,0 +1,190 @@
+//! Bindings for using the encoder from JavaScript when compiled to WebAssembly.
+//!
+//! Only available with the `wasm` feature, which also enables `gzip`.
+//!
+//! The functions take and return byte arrays (`Uint8Array` on the JavaScript side) rather than
+//! going through `std::io`. When no compression level is given, `Level::Best` is used, as data
+//! compressed in the browser is usually sent over the network, where the size matters more than
+//! the time spent compressing it.
+//!
+//! ```js
+//! import { deflateRaw, gzip, StreamEncoder, StreamFormat } from "deflate";
+//!
+//! const compressed = gzip(new TextEncoder().encode("Some data"));
+//!
+//! const encoder = new StreamEncoder(StreamFormat.Zlib);
+//! const parts = [encoder.push(chunk1), encoder.push(chunk2), encoder.finish()];
+//! ```
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use gzip_header::GzBuilder;
+use wasm_bindgen::prelude::*;
+
+use crate::compression_options::CompressionOptions;
+use crate::sink::Sink;
+use crate::writer::gzip::default_os;
+use crate::writer::{AnyEncoder, Format};
+
+/// How hard the encoder tries to compress the data.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Level {
+    /// Fast compression, see `CompressionOptions::fast`.
+    Fast,
+    /// The default trade-off between speed and size.
+    Default,
+    /// The smallest output, see `CompressionOptions::high`.
+    Best,
+}
+
+impl From<Level> for CompressionOptions {
+    fn from(level: Level) -> CompressionOptions {
+        match level {
+            Level::Fast => CompressionOptions::fast(),
+            Level::Default => CompressionOptions::default(),
+            Level::Best => CompressionOptions::high(),
+        }
+    }
+}
+
+/// The container format of the output of a `StreamEncoder`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum StreamFormat {
+    /// Raw DEFLATE data.
+    Raw,
+    /// DEFLATE data with a zlib header and trailer.
+    Zlib,
+    /// DEFLATE data with a gzip header and trailer.
+    Gzip,
+}
+
+impl From<StreamFormat> for Format {
+    fn from(format: StreamFormat) -> Format {
+        match format {
+            StreamFormat::Raw => Format::Raw,
+            StreamFormat::Zlib => Format::Zlib,
+            StreamFormat::Gzip => Format::Gzip,
+        }
+    }
+}
+
+fn options(level: Option<Level>) -> CompressionOptions {
+    level.unwrap_or(Level::Best).into()
+}
+
+/// Compress `data` to a raw DEFLATE stream.
+#[wasm_bindgen(js_name = deflateRaw)]
+pub fn deflate_raw(data: &[u8], level: Option<Level>) -> Vec<u8> {
+    crate::try_deflate_bytes_conf(data, options(level)).expect("Write error!")
+}
+
+/// Compress `data` to a zlib stream.
+#[wasm_bindgen]
+pub fn zlib(data: &[u8], level: Option<Level>) -> Vec<u8> {
+    crate::try_deflate_bytes_zlib_conf(data, options(level)).expect("Write error!")
+}
+
+/// Compress `data` to a gzip stream with a blank header.
+#[wasm_bindgen]
+pub fn gzip(data: &[u8], level: Option<Level>) -> Vec<u8> {
+    crate::try_deflate_bytes_gzip_conf(data, options(level), GzBuilder::new().os(default_os()))
+        .expect("Write error!")
+}
+
+/// A sink appending to a buffer shared with the `StreamEncoder`, so the output can be handed out
+/// as soon as it is produced.
+#[derive(Clone, Debug, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        mem::replace(&mut *self.0.borrow_mut(), Vec::new())
+    }
+}
+
+impl Sink for SharedBuffer {
+    type Error = io::Error;
+
+    fn write_output(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+/// An encoder compressing data passed to it in pieces, returning the compressed data produced
+/// after each call.
+#[wasm_bindgen]
+pub struct StreamEncoder {
+    encoder: AnyEncoder<SharedBuffer>,
+    output: SharedBuffer,
+}
+
+#[wasm_bindgen]
+impl StreamEncoder {
+    /// Create a new encoder producing data in the given format.
+    #[wasm_bindgen(constructor)]
+    pub fn new(format: StreamFormat, level: Option<Level>) -> StreamEncoder {
+        let output = SharedBuffer::default();
+        StreamEncoder {
+            encoder: AnyEncoder::new(output.clone(), format.into(), options(level)),
+            output,
+        }
+    }
+
+    /// Compress `data`, returning the compressed data output so far.
+    ///
+    /// The encoder buffers some of the data, so the output can be empty.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, JsError> {
+        self.encoder.write_all(data)?;
+        Ok(self.output.take())
+    }
+
+    /// Output all the data pushed so far, so that it can be decompressed without waiting for
+    /// the rest of the stream.
+    pub fn flush(&mut self) -> Result<Vec<u8>, JsError> {
+        self.encoder.flush()?;
+        Ok(self.output.take())
+    }
+
+    /// Finish the stream, returning the rest of the compressed data.
+    pub fn finish(self) -> Result<Vec<u8>, JsError> {
+        self.encoder.finish()?;
+        Ok(self.output.take())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{decompress_gzip, decompress_to_end, decompress_zlib, get_test_data};
+
+    #[test]
+    fn one_shot() {
+        let data = get_test_data();
+        assert!(decompress_to_end(&deflate_raw(&data, None)) == data);
+        assert!(decompress_zlib(&zlib(&data, Some(Level::Fast))) == data);
+        assert!(decompress_gzip(&gzip(&data, Some(Level::Default))).1 == data);
+        assert!(
+            deflate_raw(&data, None) == crate::try_deflate_bytes_conf(&data, Level::Best).unwrap()
+        );
+    }
+
+    #[test]
+    fn stream() {
+        let data = get_test_data();
+        let mut encoder = StreamEncoder::new(StreamFormat::Zlib, None);
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(10_000) {
+            compressed.extend(encoder.push(chunk).unwrap());
+        }
+        let flushed = encoder.flush().unwrap();
+        assert!(!flushed.is_empty());
+        compressed.extend(flushed);
+        compressed.extend(encoder.finish().unwrap());
+        assert!(decompress_zlib(&compressed) == data);
+    }
+}
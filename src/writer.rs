@@ -1,27 +1,102 @@
 use std::io::Write;
-use std::{io, thread};
+#[cfg(feature = "block-offsets")]
+use std::mem;
+use std::{cmp, io, thread};
 
-use crate::checksum::{Adler32Checksum, RollingChecksum};
-use crate::compress::compress_data_dynamic_n;
-use crate::compress::Flush;
-use crate::compression_options::CompressionOptions;
+use crate::buffer_pool::BufferPool;
+use crate::chained_hash_table::WINDOW_SIZE;
+use crate::checksum::{Adler32Checksum, NoChecksum, RollingChecksum};
+use crate::compiled_block::CompiledBlock;
+use crate::compress::{compress_data_dynamic_n, write_padded_end, write_stored_block};
+use crate::compress::{BlockKind, BlockListener, BlockTypeSelector, Flush};
+use crate::compression_options::{CompressionOptions, OutputVersion};
+use crate::deferred::DeferredEnd;
 use crate::deflate_state::DeflateState;
-use crate::zlib::{write_zlib_header, CompressionLevel};
+use crate::error::Error;
+use crate::sink::{Sink, SinkExt};
+use crate::stats::{CompressionReport, CompressionStats, Stage, StageTimer};
+use crate::stored_block::MAX_STORED_BLOCK_LENGTH;
+use crate::zlib::{
+    dictionary_id, write_zlib_header, write_zlib_header_with_dict, CompressionLevel,
+};
 
 const ERR_STR: &str = "Error! The wrapped writer is missing.\
                        This is a bug, please file an issue.";
 
+/// Size of the zlib header.
+const ZLIB_HEADER_BYTES: u64 = 2;
+/// Size of the preset dictionary id following the zlib header.
+const ZLIB_DICTIONARY_ID_BYTES: u64 = 4;
+/// Size of the zlib trailer, which holds the Adler32 checksum.
+const ZLIB_TRAILER_BYTES: u64 = 4;
+
 /// Keep compressing until all the input has been compressed and output or the writer returns `Err`.
-pub fn compress_until_done<W: Write>(
+pub fn compress_until_done<W: Sink>(
+    input: &[u8],
+    deflate_state: &mut DeflateState<W>,
+    flush_mode: Flush,
+) -> io::Result<()> {
+    compress_until_done_with_checksum(input, deflate_state, flush_mode, &mut NoChecksum::new())
+}
+
+/// Like `compress_until_done`, but also update `checksum` with the input as it's consumed.
+pub fn compress_until_done_with_checksum<W: Sink, C: RollingChecksum>(
     mut input: &[u8],
     deflate_state: &mut DeflateState<W>,
     flush_mode: Flush,
+    checksum: &mut C,
 ) -> io::Result<()> {
     // This should only be used for flushing.
     assert!(flush_mode != Flush::None);
+    if flush_mode == Flush::Full {
+        // A full flush is a sync flush that also forgets the data compressed so far.
+        compress_until_done_with_checksum(input, deflate_state, Flush::Sync, checksum)?;
+        if !deflate_state.finished {
+            deflate_state.reset_window();
+        }
+        return Ok(());
+    }
+    if deflate_state.finished {
+        // Flushing again after the final block has been output is a no-op.
+        return if input.is_empty() {
+            Ok(())
+        } else {
+            Err(finished_error())
+        };
+    }
+    if flush_mode != Flush::Finish && !input.is_empty() {
+        // Compressing the input in the same calls as the flush would end the block holding it
+        // and then output another, empty, block (with a second sync marker for sync flushes), so
+        // compress it first.
+        compress_input(input, deflate_state, checksum)?;
+        input = &[];
+    }
+    if (flush_mode == Flush::Block || flush_mode == Flush::Partial)
+        && deflate_state.bytes_written == deflate_state.block_input_start
+        && !deflate_state.needs_flush
+        && deflate_state.output_buf().is_empty()
+    {
+        // All the input is already covered by the blocks that have been output, and they have
+        // been written to the writer.
+        return Ok(());
+    }
+    if flush_mode == Flush::Sync
+        && input.is_empty()
+        && deflate_state.is_synced()
+        && deflate_state.compression_options.output_version == OutputVersion::Latest
+    {
+        // Nothing has been written since the last sync marker, so another one would only add
+        // an empty block. Release 1.0.0 did output it, so it's kept for `OutputVersion::V1`.
+        return Ok(());
+    }
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("flush", mode = ?flush_mode).entered();
+    let mut retries = 0;
     loop {
-        match compress_data_dynamic_n(input, deflate_state, flush_mode) {
+        let flushed = deflate_state.bytes_flushed();
+        match compress_data_dynamic_n(input, deflate_state, flush_mode, checksum) {
             Ok(0) => {
+                retries = 0;
                 if deflate_state.output_buf().is_empty() {
                     break;
                 } else {
@@ -31,21 +106,14 @@ pub fn compress_until_done<W: Write>(
                 }
             }
             Ok(n) => {
+                retries = 0;
                 if n < input.len() {
                     input = &input[n..]
                 } else {
                     input = &[];
                 }
             }
-            Err(e) => {
-                match e.kind() {
-                    // This error means that there may still be data to flush.
-                    // This could possibly get stuck if the underlying writer keeps returning this
-                    // error.
-                    io::ErrorKind::Interrupted => (),
-                    _ => return Err(e),
-                }
-            }
+            Err(e) => check_retry(e, deflate_state, flushed, &mut retries)?,
         }
     }
 
@@ -54,9 +122,220 @@ pub fn compress_until_done<W: Write>(
         deflate_state.bytes_written_control.get()
     );
 
+    if flush_mode == Flush::Sync {
+        deflate_state.bytes_written_at_flush = deflate_state.bytes_written;
+        deflate_state.synced = true;
+    }
+    if flush_mode == Flush::Finish {
+        deflate_state.finished = true;
+    }
+
+    Ok(())
+}
+
+/// Compress all of `input` without flushing.
+fn compress_input<W: Sink, C: RollingChecksum>(
+    mut input: &[u8],
+    deflate_state: &mut DeflateState<W>,
+    checksum: &mut C,
+) -> io::Result<()> {
+    let mut retries = 0;
+    while !input.is_empty() {
+        let flushed = deflate_state.bytes_flushed();
+        match compress_data_dynamic_n(input, deflate_state, Flush::None, checksum) {
+            Ok(n) => {
+                retries = 0;
+                input = &input[n..];
+            }
+            Err(e) => check_retry(e, deflate_state, flushed, &mut retries)?,
+        }
+    }
+    Ok(())
+}
+
+/// End the current block and output `data` as stored blocks, without looking for matches in it.
+///
+/// `data` is still added to the window, so the data compressed after this can refer back to it.
+fn write_stored_chunk<W: Sink>(data: &[u8], deflate_state: &mut DeflateState<W>) -> io::Result<()> {
+    if deflate_state.finished {
+        return Err(finished_error());
+    }
+    if data.is_empty() {
+        return Ok(());
+    }
+    compress_until_done(&[], deflate_state, Flush::Block)?;
+    for chunk in data.chunks(MAX_STORED_BLOCK_LENGTH) {
+        let start_bit = deflate_state.output_bit_position();
+        write_stored_block(chunk, &mut deflate_state.encoder_state.writer, false);
+        let len = chunk.len() as u64;
+        let header_bits = deflate_state.output_bit_position() - start_bit - len * 8;
+        deflate_state.count_input(chunk);
+        deflate_state.record_block(BlockKind::Stored, len, start_bit, header_bits, false);
+        deflate_state.write_pending_output()?;
+    }
+    deflate_state.add_to_window(data);
+    Ok(())
+}
+
+/// End the current block and output the blocks of `block`, setting the final block flag on the
+/// last one if `final_block` is set.
+fn write_compiled_block<W: Sink>(
+    block: &CompiledBlock,
+    deflate_state: &mut DeflateState<W>,
+    final_block: bool,
+) -> io::Result<()> {
+    if deflate_state.finished {
+        return Err(finished_error());
+    }
+    compress_until_done(&[], deflate_state, Flush::Block)?;
+    if final_block && block.blocks().is_empty() {
+        // There is no block to set the flag on, so end the stream with an empty block.
+        return compress_until_done(&[], deflate_state, Flush::Finish);
+    }
+    block.splice(deflate_state, final_block);
+    if final_block {
+        deflate_state.encoder_state.flush();
+        deflate_state.finished = true;
+    }
+    deflate_state.write_pending_output()?;
+    // The encoder doesn't have the data in its window, so start over with an empty one.
+    deflate_state.reset_window();
+    Ok(())
+}
+
+/// Compress all pending data and end the stream, padding it to a multiple of the alignment set
+/// with `pad_to`, counting the `trailer_bytes` that will be written after it.
+fn finish_stream<W: Sink>(
+    deflate_state: &mut DeflateState<W>,
+    trailer_bytes: u64,
+) -> io::Result<()> {
+    let alignment = deflate_state.pad_to;
+    if alignment <= 1 || deflate_state.finished {
+        return compress_until_done(&[], deflate_state, Flush::Finish);
+    }
+    compress_until_done(&[], deflate_state, Flush::Block)?;
+    write_padded_end(deflate_state, trailer_bytes, alignment);
+    deflate_state.finished = true;
+    deflate_state.write_pending_output()?;
+    #[cfg(feature = "verify")]
+    {
+        let res = deflate_state.verifier.check_finished();
+        deflate_state.check_verified(res)?;
+    }
     Ok(())
 }
 
+/// Compress all pending data and output it without ending the stream, returning the state
+/// needed to resume it, where `checksum` is the checksum of the input so far.
+fn finish_deferred_stream<W: Sink>(
+    deflate_state: &mut DeflateState<W>,
+    checksum: u32,
+) -> io::Result<DeferredEnd> {
+    if deflate_state.finished {
+        return Err(finished_error());
+    }
+    compress_until_done(&[], deflate_state, Flush::Block)?;
+    deflate_state.encoder_state.writer.flush_whole_bytes();
+    deflate_state.write_pending_output()?;
+    let writer = &deflate_state.encoder_state.writer;
+    Ok(DeferredEnd::new(
+        writer.pending_byte(),
+        writer.pending_bits(),
+        deflate_state.bytes_written,
+        deflate_state.output_buf_start,
+        checksum,
+    ))
+}
+
+/// Add `data` to `checksum`, counting the time it takes in `stats` with the `timing` feature.
+fn update_checksum<C: RollingChecksum>(
+    checksum: &mut C,
+    data: &[u8],
+    stats: &mut CompressionStats,
+) {
+    let timer = StageTimer::start();
+    checksum.update_from_slice(data);
+    timer.stop(stats, Stage::Checksum);
+}
+
+/// Decide whether to try again after compressing returned `err`, given the number of bytes that
+/// had been passed on to the wrapped writer before the attempt, returning the error if not.
+fn check_retry<W: Sink>(
+    err: io::Error,
+    deflate_state: &DeflateState<W>,
+    flushed: u64,
+    retries: &mut u32,
+) -> io::Result<()> {
+    match err.kind() {
+        // This error means that there may still be data to flush.
+        // To avoid getting stuck if the underlying writer keeps returning this
+        // error, we give up after a number of attempts that made no progress.
+        io::ErrorKind::Interrupted => {
+            if deflate_state.bytes_flushed() > flushed {
+                *retries = 0;
+            } else {
+                *retries += 1;
+                match deflate_state.max_interrupted_retries {
+                    Some(max) if *retries > max => return Err(err),
+                    _ => (),
+                }
+            }
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
+
+/// The error returned when writing to an encoder that has already been finished.
+pub fn finished_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "the encoder has already been finished",
+    )
+}
+
+/// Compress data passed to `write`, returning the number of bytes consumed, and updating
+/// `checksum` with them.
+///
+/// If the `auto_flush_after` option is set, the input is split so that a sync flush can be done
+/// once the given number of bytes have been written since the last flush.
+fn write_auto_flush<W: Sink, C: RollingChecksum>(
+    buf: &[u8],
+    deflate_state: &mut DeflateState<W>,
+    checksum: &mut C,
+) -> io::Result<usize> {
+    if deflate_state.finished {
+        return Err(finished_error());
+    }
+    let flush_mode = deflate_state.flush_mode;
+    let flush_after = u64::from(deflate_state.compression_options.auto_flush_after);
+    if flush_after == 0 {
+        return compress_data_dynamic_n(buf, deflate_state, flush_mode, checksum);
+    }
+
+    let since_flush = deflate_state.bytes_written - deflate_state.bytes_written_at_flush;
+    if since_flush >= flush_after {
+        // A previous automatic flush couldn't be completed, so try again before taking more input.
+        compress_until_done(&[], deflate_state, Flush::Sync)?;
+    }
+
+    let remaining =
+        flush_after - (deflate_state.bytes_written - deflate_state.bytes_written_at_flush);
+    let input = &buf[..cmp::min(buf.len() as u64, remaining) as usize];
+    let consumed = match compress_data_dynamic_n(input, deflate_state, flush_mode, checksum)? {
+        // If this is returned, the whole input was consumed.
+        0 => input.len(),
+        n => n,
+    };
+
+    if deflate_state.bytes_written - deflate_state.bytes_written_at_flush >= flush_after {
+        // The input has already been consumed at this point, so if flushing fails, the error is
+        // returned from the next call instead (or the stream is poisoned).
+        let _ = compress_until_done(&[], deflate_state, Flush::Sync);
+    }
+    Ok(consumed)
+}
+
 /// A DEFLATE encoder/compressor.
 ///
 /// A struct implementing a [`Write`] interface that takes arbitrary data and compresses it to
@@ -85,19 +364,109 @@ pub fn compress_until_done<W: Write>(
 /// #     try_main().unwrap();
 /// # }
 /// ```
+///
+/// The encoder can optionally compute a checksum of the input, such as Adler-32 or CRC-32, for
+/// use in custom framing. See [`with_checksum`](#method.with_checksum).
+///
 /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
-pub struct DeflateEncoder<W: Write> {
+pub struct DeflateEncoder<W: Sink, C: RollingChecksum = NoChecksum> {
     deflate_state: DeflateState<W>,
+    checksum: C,
 }
 
-impl<W: Write> DeflateEncoder<W> {
+impl<W: Sink> DeflateEncoder<W> {
     /// Creates a new encoder using the provided compression options.
     pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> DeflateEncoder<W> {
         DeflateEncoder {
             deflate_state: DeflateState::new(options.into(), writer),
+            checksum: NoChecksum::new(),
+        }
+    }
+
+    /// Creates a new encoder using the provided compression options, returning
+    /// `Error::InvalidOptions` if they are out of range instead of adjusting them.
+    pub fn try_new<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+    ) -> Result<DeflateEncoder<W>, Error> {
+        Ok(DeflateEncoder {
+            deflate_state: DeflateState::try_new(options.into(), writer)?,
+            checksum: NoChecksum::new(),
+        })
+    }
+
+    /// Creates a new encoder using the provided compression options, taking the internal
+    /// buffers from `pool` if there are any available, and returning them to it when dropped.
+    pub fn with_pool<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        pool: &BufferPool,
+    ) -> DeflateEncoder<W> {
+        DeflateEncoder {
+            deflate_state: DeflateState::with_pool(options.into(), writer, pool),
+            checksum: NoChecksum::new(),
+        }
+    }
+
+    /// Creates a new encoder continuing a stream that was ended with
+    /// [`finish_deferred`](#method.finish_deferred), so the output written to `writer` has to
+    /// be appended directly to the output from before.
+    ///
+    /// The data compressed before can't be referred back to, so the compression is no better
+    /// than if the stream was started over. The statistics only cover the data written after
+    /// resuming.
+    pub fn resume<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        end: &DeferredEnd,
+    ) -> DeflateEncoder<W> {
+        let mut deflate_state = DeflateState::new(options.into(), writer);
+        deflate_state.resume(end);
+        DeflateEncoder {
+            deflate_state,
+            checksum: NoChecksum::new(),
+        }
+    }
+}
+
+impl<W: Sink, C: RollingChecksum> DeflateEncoder<W, C> {
+    /// Creates a new encoder using the provided compression options, which also computes the
+    /// checksum `checksum` of the input data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// use deflate::checksum::{update_adler32, Adler32Checksum};
+    /// use deflate::write::DeflateEncoder;
+    /// use deflate::Compression;
+    ///
+    /// let data = b"This is some test data";
+    /// let mut encoder =
+    ///     DeflateEncoder::with_checksum(Vec::new(), Compression::Default, Adler32Checksum::new());
+    /// encoder.write_all(data).unwrap();
+    /// assert_eq!(encoder.checksum(), update_adler32(1, data));
+    /// ```
+    pub fn with_checksum<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        checksum: C,
+    ) -> DeflateEncoder<W, C> {
+        DeflateEncoder {
+            deflate_state: DeflateState::new(options.into(), writer),
+            checksum,
         }
     }
 
+    /// Return the checksum of the input data consumed so far.
+    ///
+    /// This is always 1 unless a checksum was provided using
+    /// [`with_checksum`](#method.with_checksum).
+    pub fn checksum(&self) -> u32 {
+        self.checksum.current_hash()
+    }
+
     /// Encode all pending data to the contained writer, consume this `DeflateEncoder`,
     /// and return the contained writer if writing succeeds.
     pub fn finish(mut self) -> io::Result<W> {
@@ -107,37 +476,337 @@ impl<W: Write> DeflateEncoder<W> {
         Ok(self.deflate_state.inner.take().expect(ERR_STR))
     }
 
+    /// Encode all pending data to the contained writer, consume this `DeflateEncoder`,
+    /// and return the contained writer along with the final statistics if writing succeeds.
+    pub fn finish_with_stats(mut self) -> io::Result<(W, CompressionStats)> {
+        self.output_all()?;
+        let stats = self.deflate_state.stats;
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), stats))
+    }
+
+    /// Encode all pending data to the contained writer, consume this `DeflateEncoder`,
+    /// and return the contained writer along with a report describing the output if writing
+    /// succeeds.
+    pub fn finish_with_report(mut self) -> io::Result<(W, CompressionReport)> {
+        self.output_all()?;
+        let report = self.deflate_state.report(0, 0);
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), report))
+    }
+
+    /// Encode all pending data to the contained writer without ending the stream, consume this
+    /// `DeflateEncoder`, and return the contained writer along with the state needed to resume
+    /// the stream later.
+    ///
+    /// The stream is not valid until it has been resumed with [`resume`](#method.resume) and
+    /// finished. This is useful for compressing data that arrives over a long time, such as
+    /// logs, in separate sessions that don't have to keep the encoder around in between.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// use deflate::write::DeflateEncoder;
+    /// use deflate::{Compression, DeferredEnd};
+    ///
+    /// let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+    /// encoder.write_all(b"First session. ").unwrap();
+    /// let (mut compressed, end) = encoder.finish_deferred().unwrap();
+    /// // The state can be stored along with the output and read back later.
+    /// let end = DeferredEnd::from_bytes(&end.to_bytes()).unwrap();
+    ///
+    /// let mut encoder = DeflateEncoder::resume(Vec::new(), Compression::Default, &end);
+    /// encoder.write_all(b"Second session.").unwrap();
+    /// compressed.extend(encoder.finish().unwrap());
+    /// ```
+    pub fn finish_deferred(mut self) -> io::Result<(W, DeferredEnd)> {
+        let checksum = self.checksum.current_hash();
+        let end = finish_deferred_stream(&mut self.deflate_state, checksum)?;
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), end))
+    }
+
     /// Resets the encoder (except the compression options), replacing the current writer
     /// with a new one, returning the old one.
+    ///
+    /// The internal buffers are kept and reused, so reusing an encoder for many small streams,
+    /// e.g. one per message, avoids allocating new buffers for each of them.
+    ///
+    /// If the encoder has been poisoned by a write error, the remaining data is discarded instead
+    /// of being output to the old writer.
     pub fn reset(&mut self, w: W) -> io::Result<W> {
-        self.output_all()?;
+        if !self.deflate_state.is_poisoned() {
+            self.output_all()?;
+        }
+        self.checksum.reset();
         self.deflate_state.reset(w)
     }
 
     /// Output all pending data as if encoding is done, but without resetting anything
     fn output_all(&mut self) -> io::Result<()> {
-        compress_until_done(&[], &mut self.deflate_state, Flush::Finish)
+        finish_stream(&mut self.deflate_state, 0)
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub(crate) fn writer_mut(&mut self) -> &mut W {
+        self.deflate_state.inner.as_mut().expect(ERR_STR)
+    }
+
+    /// Set a selector that can override the type of each block output from now on.
+    ///
+    /// The selector is kept when the encoder is reset.
+    pub fn set_block_type_selector<S: BlockTypeSelector + Send + 'static>(&mut self, selector: S) {
+        self.deflate_state.block_type_selector = Some(Box::new(selector));
+    }
+
+    /// Set a listener that is called for each block output from now on.
+    ///
+    /// The positions in the output reported to the listener include the header written by the
+    /// encoder, and count from the start of the stream. The listener is kept when the encoder is
+    /// reset.
+    pub fn set_block_listener<L: BlockListener + Send + 'static>(&mut self, listener: L) {
+        self.deflate_state.block_listener = Some(Box::new(listener));
+    }
+
+    /// Set how many consecutive times flushing or finishing is retried when the wrapped writer
+    /// returns `ErrorKind::Interrupted` without accepting any data, before the error is returned.
+    ///
+    /// `None` means retrying indefinitely. The default is 1000 retries.
+    pub fn set_max_interrupted_retries(&mut self, max_retries: Option<u32>) {
+        self.deflate_state.max_interrupted_retries = max_retries;
+    }
+
+    /// Pad the finished stream with empty blocks, so that its length is a multiple of
+    /// `alignment` bytes, e.g for placing it in a container with sector-aligned entries.
+    ///
+    /// This makes the stream up to `alignment + 6` bytes longer. An alignment of 0 or 1 turns
+    /// padding off, which is the default. The alignment is kept when the encoder is reset.
+    pub fn pad_to(&mut self, alignment: u64) {
+        self.deflate_state.pad_to = alignment;
+    }
+
+    /// Set whether the rest of the data should be output when the encoder is dropped without
+    /// calling [`finish()`](#method.finish). The default is `true`.
+    ///
+    /// Any error that occurs when finishing on drop is discarded, so it is better to call
+    /// `finish()` explicitly when writing may fail.
+    pub fn set_finish_on_drop(&mut self, finish_on_drop: bool) {
+        self.deflate_state.finish_on_drop = finish_on_drop;
+    }
+
+    /// Tell the encoder the total number of bytes that will be written to it, if known in
+    /// advance.
+    ///
+    /// This is only used to size the internal output buffer, avoiding allocating more memory
+    /// than needed for small inputs, and doesn't change the compressed output. Writing more or
+    /// less data than indicated is not an error. The hint is not kept when the encoder is reset.
+    pub fn set_input_size_hint(&mut self, size: u64) {
+        self.deflate_state.set_input_size_hint(size);
+    }
+
+    /// Automatically do a sync flush once `bytes` bytes of input have been written since the
+    /// last flush, or never if `bytes` is `0`.
+    ///
+    /// This overrides the [`auto_flush_after`](../struct.CompressionOptions.html#structfield.auto_flush_after)
+    /// compression option, and can be changed at any point in the stream. The setting is kept
+    /// when the encoder is reset.
+    pub fn set_auto_flush_after(&mut self, bytes: u32) {
+        self.deflate_state.compression_options.auto_flush_after = bytes;
+    }
+
+    /// Returns a copy of the last error that occured when writing to the wrapped writer, if any.
+    ///
+    /// Errors indicating that writing should be retried (`Interrupted` and `WouldBlock`) are not
+    /// recorded.
+    ///
+    /// Any other error poisons the encoder, as the state of the output stream is no longer known.
+    /// Writing, flushing and finishing will then fail with an error of the same kind until the
+    /// encoder is reset.
+    pub fn last_error(&self) -> Option<&io::Error> {
+        self.deflate_state.last_error.as_ref()
+    }
+
+    /// Statistics about the blocks output so far.
+    ///
+    /// The statistics are cleared when the encoder is reset.
+    pub fn stats(&self) -> CompressionStats {
+        self.deflate_state.stats
+    }
+
+    /// The position in bits of the end of the data output so far, counted from the start of the
+    /// stream (including any header written by the encoder).
+    ///
+    /// This includes the bits of the last partial byte that are still held by the encoder, and
+    /// will be output along with later data, but not input that has been written to the encoder
+    /// but not compressed yet. After a flush, it is the exact length of the output in bits.
+    pub fn bit_position(&self) -> u64 {
+        self.deflate_state.output_bit_position()
+    }
+
+    /// The total number of bytes of input written to the encoder so far.
+    ///
+    /// This is a 64-bit count, so streams larger than 4 GiB are counted correctly.
+    pub fn total_in(&self) -> u64 {
+        self.deflate_state.bytes_written
+    }
+
+    /// The total number of bytes of output passed on to the wrapped writer so far.
+    ///
+    /// Output that is still held by the encoder, such as the last partial byte, is not counted
+    /// until it has been flushed. Once the stream is finished, this is the length of the whole
+    /// stream.
+    pub fn total_out(&self) -> u64 {
+        self.deflate_state.bytes_flushed()
+    }
+
+    /// Flush the encoder like [`flush()`](#method.flush), and also forget the data compressed
+    /// so far, so the data written afterwards doesn't refer back to it. This corresponds to
+    /// `Z_FULL_FLUSH` in zlib.
+    ///
+    /// The compressed data following a full flush can be decompressed without the data before
+    /// it, which makes it a restart point for seekable or recoverable streams, such as the ones
+    /// used by `dictzip` or indexed gzip files. This costs some compression ratio, as matches
+    /// can't refer back across the flush.
+    pub fn full_flush(&mut self) -> io::Result<()> {
+        compress_until_done(&[], &mut self.deflate_state, Flush::Full)
+    }
+
+    /// End the current block and output all complete bytes of compressed data, followed by an
+    /// empty fixed block. This corresponds to `Z_PARTIAL_FLUSH` in zlib.
+    ///
+    /// Unlike [`flush()`](#method.flush), the output isn't padded to a byte boundary, and the
+    /// last few bits are held back until more data is written. The empty block makes sure the
+    /// bytes that have been output are enough to decompress all of the data written so far.
+    /// This is cheaper than a sync flush, as it only adds 10 bits to the stream.
+    pub fn partial_flush(&mut self) -> io::Result<()> {
+        compress_until_done(&[], &mut self.deflate_state, Flush::Partial)
+    }
+
+    /// End the current block and output all complete bytes of compressed data, without padding
+    /// the output to a byte boundary. This corresponds to `Z_BLOCK` in zlib.
+    ///
+    /// The last few bits of the block are held back until more data is written, so unlike
+    /// [`partial_flush()`](#method.partial_flush), the output doesn't necessarily hold all of
+    /// the data written so far. [`bit_position()`](#method.bit_position) gives the position of
+    /// the end of the block in the stream.
+    pub fn block_flush(&mut self) -> io::Result<()> {
+        compress_until_done(&[], &mut self.deflate_state, Flush::Block)
+    }
+
+    /// The compression options used by the encoder.
+    ///
+    /// Options that were out of range when the encoder was created are returned as adjusted.
+    pub fn options(&self) -> &CompressionOptions {
+        &self.deflate_state.compression_options
+    }
+
+    /// Whether the stream has been finished with [`try_finish()`](#method.try_finish).
+    ///
+    /// Once finished, writing to the encoder returns an error until it is reset.
+    pub fn is_finished(&self) -> bool {
+        self.deflate_state.finished
+    }
+
+    /// Encode all pending data and output the end of the stream to the contained writer,
+    /// without consuming the encoder.
+    ///
+    /// This can be called again if writing fails. Calling it after the stream has been finished
+    /// does nothing.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.output_all()
+    }
+
+    /// Replace the wrapped writer with `writer` without ending the stream, returning the old
+    /// one.
+    ///
+    /// The output that is ready is passed on to the old writer first. Unlike
+    /// [`reset()`](#method.reset), the compression state is kept, so the output written to the
+    /// new writer continues the same stream. Input that hasn't been compressed yet, and the
+    /// last partial byte of output, are written to the new writer later, so call
+    /// [`flush()`](#method.flush) first to have everything written so far end up in the old
+    /// writer.
+    pub fn replace_writer(&mut self, writer: W) -> io::Result<W> {
+        self.deflate_state.replace_writer(writer)
+    }
+
+    /// End the current block and output `data` as-is in stored (uncompressed) blocks.
+    ///
+    /// This skips looking for matches in data that is known not to compress, such as an
+    /// already compressed image, or encrypted data. The data is part of the stream like any
+    /// other input, and included in the checksum, and the data written after this can still
+    /// refer back to it.
+    ///
+    /// The stored blocks are passed on to the wrapped writer before returning. If that fails,
+    /// the stream can't be continued.
+    pub fn write_stored_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        write_stored_chunk(data, &mut self.deflate_state)?;
+        update_checksum(&mut self.checksum, data, &mut self.deflate_state.stats);
+        Ok(())
+    }
+
+    /// End the current block and output the pre-compressed blocks of `block`.
+    ///
+    /// If `final_block` is set, the last block is marked as the end of the stream, which is then
+    /// finished as with [`try_finish()`](#method.try_finish). Otherwise the encoder continues after the blocks as after
+    /// [`write_stored_chunk`](#method.write_stored_chunk), without referring back to the data
+    /// written so far.
+    ///
+    /// The blocks are passed on to the wrapped writer before returning. If that fails, the
+    /// stream can't be continued.
+    pub fn write_compiled_block(
+        &mut self,
+        block: &CompiledBlock,
+        final_block: bool,
+    ) -> io::Result<()> {
+        write_compiled_block(block, &mut self.deflate_state, final_block)?;
+        update_checksum(
+            &mut self.checksum,
+            block.input(),
+            &mut self.deflate_state.stats,
+        );
+        Ok(())
+    }
+
+    /// The position in the output of the first bit of each block output so far, counted from the
+    /// start of the stream (including any header written by the encoder).
+    ///
+    /// The offsets are cleared when the encoder is reset. Only available with the
+    /// `block-offsets` feature.
+    #[cfg(feature = "block-offsets")]
+    pub fn block_offsets(&self) -> &[u64] {
+        &self.deflate_state.block_offsets
+    }
+
+    /// Encode all pending data to the contained writer, consume this `DeflateEncoder`,
+    /// and return the contained writer along with the starting bit offset of each block if
+    /// writing succeeds.
+    ///
+    /// Only available with the `block-offsets` feature.
+    #[cfg(feature = "block-offsets")]
+    pub fn finish_with_block_offsets(mut self) -> io::Result<(W, Vec<u64>)> {
+        self.output_all()?;
+        let offsets = mem::replace(&mut self.deflate_state.block_offsets, Vec::new());
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), offsets))
     }
 }
 
-impl<W: Write> io::Write for DeflateEncoder<W> {
+impl<W: Sink, C: RollingChecksum> io::Write for DeflateEncoder<W, C> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let flush_mode = self.deflate_state.flush_mode;
-        compress_data_dynamic_n(buf, &mut self.deflate_state, flush_mode)
+        write_auto_flush(buf, &mut self.deflate_state, &mut self.checksum)
     }
 
     /// Flush the encoder.
     ///
     /// This will flush the encoder, emulating the Sync flush method from Zlib.
     /// This essentially finishes the current block, and sends an additional empty stored block to
-    /// the writer.
+    /// the writer. Nothing is output if no data has been written since the last flush.
     fn flush(&mut self) -> io::Result<()> {
         compress_until_done(&[], &mut self.deflate_state, Flush::Sync)
     }
 }
 
-impl<W: Write> Drop for DeflateEncoder<W> {
-    /// When the encoder is dropped, output the rest of the data.
+impl<W: Sink, C: RollingChecksum> Drop for DeflateEncoder<W, C> {
+    /// When the encoder is dropped, output the rest of the data, unless this has been disabled
+    /// with [`set_finish_on_drop()`](#method.set_finish_on_drop).
     ///
     /// WARNING: This may silently fail if writing fails, so using this to finish encoding
     /// for writers where writing might fail is not recommended, for that call
@@ -145,7 +814,10 @@ impl<W: Write> Drop for DeflateEncoder<W> {
     fn drop(&mut self) {
         // Not sure if implementing drop is a good idea or not, but we follow flate2 for now.
         // We only do this if we are not panicking, to avoid a double panic.
-        if self.deflate_state.inner.is_some() && !thread::panicking() {
+        if self.deflate_state.finish_on_drop
+            && self.deflate_state.inner.is_some()
+            && !thread::panicking()
+        {
             let _ = self.output_all();
         }
     }
@@ -179,29 +851,188 @@ impl<W: Write> Drop for DeflateEncoder<W> {
 /// #     try_main().unwrap();
 /// # }
 /// ```
+///
+/// The checksum written in the trailer can be replaced by any type implementing
+/// [`RollingChecksum`] using [`with_checksum`](#method.with_checksum), which is useful for custom
+/// containers. The output will then no longer be a valid zlib stream.
+///
 /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
-pub struct ZlibEncoder<W: Write> {
+/// [`RollingChecksum`]: ../checksum/trait.RollingChecksum.html
+pub struct ZlibEncoder<W: Sink, C: RollingChecksum = Adler32Checksum> {
     deflate_state: DeflateState<W>,
-    checksum: Adler32Checksum,
+    checksum: C,
+    /// Checksum provided by the user to write in the trailer instead of computing it.
+    precomputed_checksum: Option<u32>,
+    /// The FLEVEL value to write in the header.
+    level_hint: CompressionLevel,
+    /// Whether to output the header and trailer, which can be turned off to produce streams
+    /// missing one of them.
+    write_header: bool,
+    write_trailer: bool,
     header_written: bool,
+    /// Whether the end of the stream, including the trailer, has been output.
+    finished: bool,
+    /// The part of the preset dictionary that can be referred back to, and its id, if any.
+    dictionary: Option<(Vec<u8>, u32)>,
 }
 
-impl<W: Write> ZlibEncoder<W> {
+impl<W: Sink> ZlibEncoder<W> {
     /// Create a new `ZlibEncoder` using the provided compression options.
     pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> ZlibEncoder<W> {
         ZlibEncoder {
             deflate_state: DeflateState::new(options.into(), writer),
             checksum: Adler32Checksum::new(),
+            precomputed_checksum: None,
+            level_hint: CompressionLevel::Default,
+            write_header: true,
+            write_trailer: true,
+            header_written: false,
+            finished: false,
+            dictionary: None,
+        }
+    }
+
+    /// Create a new `ZlibEncoder` using the provided compression options, returning
+    /// `Error::InvalidOptions` if they are out of range instead of adjusting them.
+    pub fn try_new<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+    ) -> Result<ZlibEncoder<W>, Error> {
+        Ok(ZlibEncoder {
+            deflate_state: DeflateState::try_new(options.into(), writer)?,
+            checksum: Adler32Checksum::new(),
+            precomputed_checksum: None,
+            level_hint: CompressionLevel::Default,
+            write_header: true,
+            write_trailer: true,
+            header_written: false,
+            finished: false,
+            dictionary: None,
+        })
+    }
+
+    /// Create a new `ZlibEncoder` using the provided compression options, taking the internal
+    /// buffers from `pool` if there are any available, and returning them to it when dropped.
+    pub fn with_pool<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        pool: &BufferPool,
+    ) -> ZlibEncoder<W> {
+        ZlibEncoder {
+            deflate_state: DeflateState::with_pool(options.into(), writer, pool),
+            checksum: Adler32Checksum::new(),
+            precomputed_checksum: None,
+            level_hint: CompressionLevel::Default,
+            write_header: true,
+            write_trailer: true,
+            header_written: false,
+            finished: false,
+            dictionary: None,
+        }
+    }
+
+    /// Create a new `ZlibEncoder` using the provided compression options and the preset
+    /// dictionary `dictionary`.
+    ///
+    /// The dictionary is data that the compressed data can refer back to as if it came just
+    /// before it, which helps compressing short inputs that are similar to it. The header
+    /// records the Adler-32 checksum of the dictionary, and the same dictionary has to be
+    /// given to the decompressor to decompress the data. Only the last 32 KiB of the
+    /// dictionary can be referred back to, so longer dictionaries should end with the most
+    /// useful data. The dictionary is kept when the encoder is reset.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    ///
+    /// use deflate::inflate::inflate_bytes_zlib_dict;
+    /// use deflate::write::ZlibEncoder;
+    /// use deflate::Compression;
+    ///
+    /// let dictionary = b"content-type: application/json";
+    /// let data = b"content-type: application/json; charset=utf-8";
+    /// let mut encoder = ZlibEncoder::new_with_dict(Vec::new(), Compression::Default, dictionary);
+    /// encoder.write_all(data).unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    /// assert_eq!(inflate_bytes_zlib_dict(&compressed, dictionary).unwrap(), &data[..]);
+    /// ```
+    pub fn new_with_dict<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        dictionary: &[u8],
+    ) -> ZlibEncoder<W> {
+        let mut encoder = ZlibEncoder::new(writer, options);
+        let start = dictionary.len().saturating_sub(WINDOW_SIZE);
+        encoder.dictionary = Some((dictionary[start..].to_vec(), dictionary_id(dictionary)));
+        encoder.deflate_state.set_dictionary(dictionary);
+        encoder
+    }
+
+    /// Create a new `ZlibEncoder` continuing a stream that was ended with
+    /// [`finish_deferred`](#method.finish_deferred), so the output written to `writer` has to
+    /// be appended directly to the output from before.
+    ///
+    /// No header is written, and the checksum in the trailer covers the data from before as
+    /// well. The data compressed before can't be referred back to.
+    pub fn resume<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        end: &DeferredEnd,
+    ) -> ZlibEncoder<W> {
+        let mut deflate_state = DeflateState::new(options.into(), writer);
+        deflate_state.resume(end);
+        ZlibEncoder {
+            deflate_state,
+            checksum: Adler32Checksum::with_initial(end.checksum()),
+            precomputed_checksum: None,
+            level_hint: CompressionLevel::Default,
+            write_header: true,
+            write_trailer: true,
+            header_written: true,
+            finished: false,
+            dictionary: None,
+        }
+    }
+}
+
+impl<W: Sink, C: RollingChecksum> ZlibEncoder<W, C> {
+    /// Create a new `ZlibEncoder` using the provided compression options, computing the checksum
+    /// written in the trailer using `checksum` instead of Adler-32.
+    pub fn with_checksum<O: Into<CompressionOptions>>(
+        writer: W,
+        options: O,
+        checksum: C,
+    ) -> ZlibEncoder<W, C> {
+        ZlibEncoder {
+            deflate_state: DeflateState::new(options.into(), writer),
+            checksum,
+            precomputed_checksum: None,
+            level_hint: CompressionLevel::Default,
+            write_header: true,
+            write_trailer: true,
             header_written: false,
+            finished: false,
+            dictionary: None,
         }
     }
 
     /// Output all pending data ,including the trailer(checksum) as if encoding is done,
     /// but without resetting anything.
     fn output_all(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
         self.check_write_header()?;
-        compress_until_done(&[], &mut self.deflate_state, Flush::Finish)?;
-        self.write_trailer()
+        let trailer_bytes = if self.write_trailer {
+            ZLIB_TRAILER_BYTES
+        } else {
+            0
+        };
+        finish_stream(&mut self.deflate_state, trailer_bytes)?;
+        self.write_trailer()?;
+        self.finished = true;
+        Ok(())
     }
 
     /// Encode all pending data to the contained writer, consume this `ZlibEncoder`,
@@ -213,19 +1044,87 @@ impl<W: Write> ZlibEncoder<W> {
         Ok(self.deflate_state.inner.take().expect(ERR_STR))
     }
 
-    /// Resets the encoder (except the compression options), replacing the current writer
-    /// with a new one, returning the old one.
-    pub fn reset(&mut self, writer: W) -> io::Result<W> {
+    /// Encode all pending data to the contained writer, consume this `ZlibEncoder`,
+    /// and return the contained writer along with the final statistics if writing succeeds.
+    pub fn finish_with_stats(mut self) -> io::Result<(W, CompressionStats)> {
+        self.output_all()?;
+        let stats = self.deflate_state.stats;
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), stats))
+    }
+
+    /// Encode all pending data to the contained writer, consume this `ZlibEncoder`,
+    /// and return the contained writer along with a report describing the output if writing
+    /// succeeds.
+    pub fn finish_with_report(mut self) -> io::Result<(W, CompressionReport)> {
         self.output_all()?;
+        let header_bytes = match (self.write_header, &self.dictionary) {
+            (false, _) => 0,
+            (true, Some(_)) => ZLIB_HEADER_BYTES + ZLIB_DICTIONARY_ID_BYTES,
+            (true, None) => ZLIB_HEADER_BYTES,
+        };
+        let trailer_bytes = if self.write_trailer {
+            ZLIB_TRAILER_BYTES
+        } else {
+            0
+        };
+        let report = self.deflate_state.report(header_bytes, trailer_bytes);
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), report))
+    }
+
+    /// Write the header and encode all pending data to the contained writer without ending the
+    /// stream, consume this `ZlibEncoder`, and return the contained writer along with the state
+    /// needed to resume the stream later with [`resume`](#method.resume).
+    ///
+    /// No trailer is written, it is written by the resumed encoder when it is finished.
+    pub fn finish_deferred(mut self) -> io::Result<(W, DeferredEnd)> {
+        self.check_write_header()?;
+        let checksum = self.checksum.current_hash();
+        let end = finish_deferred_stream(&mut self.deflate_state, checksum)?;
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), end))
+    }
+
+    /// Resets the encoder (except the compression options), replacing the current writer
+    /// with a new one, returning the old one.
+    ///
+    /// If the encoder has been poisoned by a write error, the remaining data is discarded instead
+    /// of being output to the old writer.
+    pub fn reset(&mut self, writer: W) -> io::Result<W> {
+        if !self.deflate_state.is_poisoned() {
+            self.output_all()?;
+        }
         self.header_written = false;
-        self.checksum = Adler32Checksum::new();
-        self.deflate_state.reset(writer)
+        self.finished = false;
+        self.checksum.reset();
+        self.precomputed_checksum = None;
+        let writer = self.deflate_state.reset(writer)?;
+        if let Some((ref dictionary, _)) = self.dictionary {
+            self.deflate_state.set_dictionary(dictionary);
+        }
+        Ok(writer)
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub(crate) fn writer_mut(&mut self) -> &mut W {
+        self.deflate_state.inner.as_mut().expect(ERR_STR)
     }
 
     /// Check if a zlib header should be written.
     fn check_write_header(&mut self) -> io::Result<()> {
-        if !self.header_written {
-            write_zlib_header(self.deflate_state.output_buf(), CompressionLevel::Default)?;
+        if !self.header_written && self.write_header {
+            let window_bits = self.deflate_state.compression_options.window_bits;
+            match self.dictionary {
+                Some((_, id)) => write_zlib_header_with_dict(
+                    self.deflate_state.output_buf(),
+                    self.level_hint,
+                    window_bits,
+                    id,
+                )?,
+                None => write_zlib_header(
+                    self.deflate_state.output_buf(),
+                    self.level_hint,
+                    window_bits,
+                )?,
+            }
             self.header_written = true;
         }
         Ok(())
@@ -233,57 +1132,337 @@ impl<W: Write> ZlibEncoder<W> {
 
     /// Write the trailer, which for zlib is the Adler32 checksum.
     fn write_trailer(&mut self) -> io::Result<()> {
-        let hash = self.checksum.current_hash();
+        if !self.write_trailer {
+            return Ok(());
+        }
+        let hash = self.checksum();
 
-        self.deflate_state
+        let res = self
+            .deflate_state
             .inner
             .as_mut()
             .expect(ERR_STR)
-            .write_all(&hash.to_be_bytes())?;
+            .write_all_output(&hash.to_be_bytes());
+        if let Err(ref e) = res {
+            self.deflate_state.record_error(e);
+        }
+        res
+    }
+
+    /// Return the checksum of the currently consumed data, which is Adler-32 unless another
+    /// checksum was provided.
+    ///
+    /// If a precomputed checksum has been set, that is returned instead.
+    pub fn checksum(&self) -> u32 {
+        self.precomputed_checksum
+            .unwrap_or_else(|| self.checksum.current_hash())
+    }
+
+    /// Use `checksum` as the checksum of the whole input in the trailer, skipping computing it.
+    ///
+    /// This is useful when the checksum of the input is already known, e.g if it is stored
+    /// alongside the data. The value is not verified, so if it's wrong the output will fail to
+    /// decompress. It is cleared when the encoder is reset.
+    pub fn set_precomputed_checksum(&mut self, checksum: u32) {
+        self.precomputed_checksum = Some(checksum);
+    }
+
+    /// Set the compression level hint written to the FLEVEL field of the zlib header,
+    /// regardless of the compression options actually used.
+    ///
+    /// The default is `CompressionLevel::Default`. This has no effect if the header has already
+    /// been written. The value is kept when the encoder is reset.
+    pub fn set_level_hint(&mut self, level: CompressionLevel) {
+        self.level_hint = level;
+    }
+
+    /// Set whether the two byte zlib header is written at the start of the stream.
+    ///
+    /// Turning this off produces a stream that is not valid zlib, consisting of only the
+    /// compressed data followed by the Adler-32 trailer. Some legacy formats and malformed, but
+    /// common, PDF streams look like this. This has no effect if the header has already been
+    /// written. The setting is kept when the encoder is reset.
+    pub fn set_write_header(&mut self, write_header: bool) {
+        self.write_header = write_header;
+    }
+
+    /// Set whether the four byte Adler-32 trailer is written at the end of the stream.
+    ///
+    /// Turning this off produces a stream that is not valid zlib, consisting of the zlib header
+    /// followed by only the compressed data. Many decoders accept such streams if told to
+    /// ignore the missing checksum. The setting is kept when the encoder is reset.
+    pub fn set_write_trailer(&mut self, write_trailer: bool) {
+        self.write_trailer = write_trailer;
+    }
+
+    /// Set a selector that can override the type of each block output from now on.
+    ///
+    /// The selector is kept when the encoder is reset.
+    pub fn set_block_type_selector<S: BlockTypeSelector + Send + 'static>(&mut self, selector: S) {
+        self.deflate_state.block_type_selector = Some(Box::new(selector));
+    }
+
+    /// Set a listener that is called for each block output from now on.
+    ///
+    /// The positions in the output reported to the listener include the header written by the
+    /// encoder, and count from the start of the stream. The listener is kept when the encoder is
+    /// reset.
+    pub fn set_block_listener<L: BlockListener + Send + 'static>(&mut self, listener: L) {
+        self.deflate_state.block_listener = Some(Box::new(listener));
+    }
+
+    /// Set how many consecutive times flushing or finishing is retried when the wrapped writer
+    /// returns `ErrorKind::Interrupted` without accepting any data, before the error is returned.
+    ///
+    /// `None` means retrying indefinitely. The default is 1000 retries.
+    pub fn set_max_interrupted_retries(&mut self, max_retries: Option<u32>) {
+        self.deflate_state.max_interrupted_retries = max_retries;
+    }
+
+    /// Pad the finished stream, including the zlib header and trailer, with empty blocks so
+    /// that its length is a multiple of `alignment` bytes.
+    ///
+    /// See [`DeflateEncoder::pad_to`](struct.DeflateEncoder.html#method.pad_to).
+    pub fn pad_to(&mut self, alignment: u64) {
+        self.deflate_state.pad_to = alignment;
+    }
+
+    /// Set whether the rest of the data should be output when the encoder is dropped without
+    /// calling [`finish()`](#method.finish). The default is `true`.
+    ///
+    /// Any error that occurs when finishing on drop is discarded, so it is better to call
+    /// `finish()` explicitly when writing may fail.
+    pub fn set_finish_on_drop(&mut self, finish_on_drop: bool) {
+        self.deflate_state.finish_on_drop = finish_on_drop;
+    }
+
+    /// Tell the encoder the total number of bytes that will be written to it, if known in
+    /// advance.
+    ///
+    /// This is only used to size the internal output buffer, avoiding allocating more memory
+    /// than needed for small inputs, and doesn't change the compressed output. Writing more or
+    /// less data than indicated is not an error. The hint is not kept when the encoder is reset.
+    pub fn set_input_size_hint(&mut self, size: u64) {
+        self.deflate_state.set_input_size_hint(size);
+    }
+
+    /// Automatically do a sync flush once `bytes` bytes of input have been written since the
+    /// last flush, or never if `bytes` is `0`.
+    ///
+    /// This overrides the [`auto_flush_after`](../struct.CompressionOptions.html#structfield.auto_flush_after)
+    /// compression option, and can be changed at any point in the stream. The setting is kept
+    /// when the encoder is reset.
+    pub fn set_auto_flush_after(&mut self, bytes: u32) {
+        self.deflate_state.compression_options.auto_flush_after = bytes;
+    }
+
+    /// Returns a copy of the last error that occured when writing to the wrapped writer, if any.
+    ///
+    /// Errors indicating that writing should be retried (`Interrupted` and `WouldBlock`) are not
+    /// recorded.
+    ///
+    /// Any other error poisons the encoder, as the state of the output stream is no longer known.
+    /// Writing, flushing and finishing will then fail with an error of the same kind until the
+    /// encoder is reset.
+    pub fn last_error(&self) -> Option<&io::Error> {
+        self.deflate_state.last_error.as_ref()
+    }
+
+    /// Statistics about the blocks output so far.
+    ///
+    /// The statistics are cleared when the encoder is reset.
+    pub fn stats(&self) -> CompressionStats {
+        self.deflate_state.stats
+    }
+
+    /// The position in bits of the end of the data output so far, counted from the start of the
+    /// stream (including any header written by the encoder).
+    ///
+    /// This includes the bits of the last partial byte that are still held by the encoder, and
+    /// will be output along with later data, but not input that has been written to the encoder
+    /// but not compressed yet. After a flush, it is the exact length of the output in bits.
+    pub fn bit_position(&self) -> u64 {
+        self.deflate_state.output_bit_position()
+    }
+
+    /// The total number of bytes of input written to the encoder so far.
+    ///
+    /// This is a 64-bit count, so streams larger than 4 GiB are counted correctly.
+    pub fn total_in(&self) -> u64 {
+        self.deflate_state.bytes_written
+    }
+
+    /// The total number of bytes of output passed on to the wrapped writer so far, including the
+    /// header and trailer.
+    ///
+    /// Output that is still held by the encoder, such as the last partial byte, is not counted
+    /// until it has been flushed. Once the stream is finished, this is the length of the whole
+    /// stream.
+    pub fn total_out(&self) -> u64 {
+        let trailer_bytes = if self.finished && self.write_trailer {
+            ZLIB_TRAILER_BYTES
+        } else {
+            0
+        };
+        self.deflate_state.bytes_flushed() + trailer_bytes
+    }
+
+    /// Flush the encoder like [`flush()`](#method.flush), and also forget the data compressed
+    /// so far, so the data written afterwards doesn't refer back to it.
+    ///
+    /// See [`DeflateEncoder::full_flush`](struct.DeflateEncoder.html#method.full_flush). This
+    /// also forgets the preset dictionary, if any.
+    pub fn full_flush(&mut self) -> io::Result<()> {
+        self.check_write_header()?;
+        compress_until_done(&[], &mut self.deflate_state, Flush::Full)
+    }
+
+    /// End the current block and output all complete bytes of compressed data, followed by an
+    /// empty fixed block, so the output so far can be decompressed.
+    ///
+    /// See [`DeflateEncoder::partial_flush`](struct.DeflateEncoder.html#method.partial_flush).
+    pub fn partial_flush(&mut self) -> io::Result<()> {
+        self.check_write_header()?;
+        compress_until_done(&[], &mut self.deflate_state, Flush::Partial)
+    }
+
+    /// End the current block and output all complete bytes of compressed data, without padding
+    /// the output to a byte boundary.
+    ///
+    /// See [`DeflateEncoder::block_flush`](struct.DeflateEncoder.html#method.block_flush).
+    pub fn block_flush(&mut self) -> io::Result<()> {
+        self.check_write_header()?;
+        compress_until_done(&[], &mut self.deflate_state, Flush::Block)
+    }
+
+    /// The compression options used by the encoder.
+    ///
+    /// Options that were out of range when the encoder was created are returned as adjusted.
+    pub fn options(&self) -> &CompressionOptions {
+        &self.deflate_state.compression_options
+    }
+
+    /// Whether the stream has been finished with [`try_finish()`](#method.try_finish).
+    ///
+    /// Once finished, writing to the encoder returns an error until it is reset.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Encode all pending data and output the end of the stream to the contained writer,
+    /// without consuming the encoder.
+    ///
+    /// This can be called again if writing fails. Calling it after the stream has been finished
+    /// does nothing.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.output_all()
+    }
+
+    /// Replace the wrapped writer with `writer` without ending the stream, returning the old
+    /// one.
+    ///
+    /// The output that is ready is passed on to the old writer first. Unlike
+    /// [`reset()`](#method.reset), the compression state is kept, so the output written to the
+    /// new writer continues the same stream. Input that hasn't been compressed yet, and the
+    /// last partial byte of output, are written to the new writer later, so call
+    /// [`flush()`](#method.flush) first to have everything written so far end up in the old
+    /// writer.
+    pub fn replace_writer(&mut self, writer: W) -> io::Result<W> {
+        self.deflate_state.replace_writer(writer)
+    }
 
+    /// End the current block and output `data` as-is in stored (uncompressed) blocks.
+    ///
+    /// See [`DeflateEncoder::write_stored_chunk`](struct.DeflateEncoder.html#method.write_stored_chunk).
+    pub fn write_stored_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        self.check_write_header()?;
+        write_stored_chunk(data, &mut self.deflate_state)?;
+        if self.precomputed_checksum.is_none() {
+            update_checksum(&mut self.checksum, data, &mut self.deflate_state.stats);
+        }
         Ok(())
     }
 
-    /// Return the adler32 checksum of the currently consumed data.
-    pub fn checksum(&self) -> u32 {
-        self.checksum.current_hash()
+    /// End the current block and output the pre-compressed blocks of `block`.
+    ///
+    /// See
+    /// [`DeflateEncoder::write_compiled_block`](struct.DeflateEncoder.html#method.write_compiled_block).
+    pub fn write_compiled_block(
+        &mut self,
+        block: &CompiledBlock,
+        final_block: bool,
+    ) -> io::Result<()> {
+        self.check_write_header()?;
+        write_compiled_block(block, &mut self.deflate_state, final_block)?;
+        if self.precomputed_checksum.is_none() {
+            update_checksum(
+                &mut self.checksum,
+                block.input(),
+                &mut self.deflate_state.stats,
+            );
+        }
+        if final_block {
+            self.output_all()?;
+        }
+        Ok(())
+    }
+
+    /// The position in the output of the first bit of each block output so far, counted from the
+    /// start of the stream (including any header written by the encoder).
+    ///
+    /// The offsets are cleared when the encoder is reset. Only available with the
+    /// `block-offsets` feature.
+    #[cfg(feature = "block-offsets")]
+    pub fn block_offsets(&self) -> &[u64] {
+        &self.deflate_state.block_offsets
+    }
+
+    /// Encode all pending data to the contained writer, consume this `ZlibEncoder`,
+    /// and return the contained writer along with the starting bit offset of each block if
+    /// writing succeeds.
+    ///
+    /// Only available with the `block-offsets` feature.
+    #[cfg(feature = "block-offsets")]
+    pub fn finish_with_block_offsets(mut self) -> io::Result<(W, Vec<u64>)> {
+        self.output_all()?;
+        let offsets = mem::replace(&mut self.deflate_state.block_offsets, Vec::new());
+        Ok((self.deflate_state.inner.take().expect(ERR_STR), offsets))
     }
 }
 
-impl<W: Write> io::Write for ZlibEncoder<W> {
+impl<W: Sink, C: RollingChecksum> io::Write for ZlibEncoder<W, C> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.check_write_header()?;
-        let flush_mode = self.deflate_state.flush_mode;
-        let res = compress_data_dynamic_n(buf, &mut self.deflate_state, flush_mode);
-        match res {
-            // If this is returned, the whole buffer was consumed
-            Ok(0) => self.checksum.update_from_slice(buf),
-            // Otherwise, only part of it was consumed, so only that part
-            // added to the checksum.
-            Ok(n) => self.checksum.update_from_slice(&buf[0..n]),
-            _ => (),
-        };
-        res
+        if self.precomputed_checksum.is_none() {
+            write_auto_flush(buf, &mut self.deflate_state, &mut self.checksum)
+        } else {
+            write_auto_flush(buf, &mut self.deflate_state, &mut NoChecksum::new())
+        }
     }
 
     /// Flush the encoder.
     ///
     /// This will flush the encoder, emulating the Sync flush method from Zlib.
     /// This essentially finishes the current block, and sends an additional empty stored block to
-    /// the writer.
+    /// the writer. Nothing is output if no data has been written since the last flush.
     fn flush(&mut self) -> io::Result<()> {
+        self.check_write_header()?;
         compress_until_done(&[], &mut self.deflate_state, Flush::Sync)
     }
 }
 
-impl<W: Write> Drop for ZlibEncoder<W> {
-    /// When the encoder is dropped, output the rest of the data.
+impl<W: Sink, C: RollingChecksum> Drop for ZlibEncoder<W, C> {
+    /// When the encoder is dropped, output the rest of the data, unless this has been disabled
+    /// with [`set_finish_on_drop()`](#method.set_finish_on_drop).
     ///
     /// WARNING: This may silently fail if writing fails, so using this to finish encoding
     /// for writers where writing might fail is not recommended, for that call
     /// [`finish()`](#method.finish) instead.
     fn drop(&mut self) {
-        if self.deflate_state.inner.is_some() && !thread::panicking() {
+        if self.deflate_state.finish_on_drop
+            && self.deflate_state.inner.is_some()
+            && !thread::panicking()
+        {
             let _ = self.output_all();
         }
     }
@@ -292,12 +1471,38 @@ impl<W: Write> Drop for ZlibEncoder<W> {
 #[cfg(feature = "gzip")]
 pub mod gzip {
 
+    use std::fs;
     use std::io::{Cursor, Write};
+    use std::path::Path;
+    use std::time::UNIX_EPOCH;
     use std::{io, thread};
 
     use super::*;
 
-    use gzip_header::{Crc, GzBuilder};
+    use crate::checksum::Crc32Checksum;
+    use gzip_header::{ExtraFlags, FileSystemType, GzBuilder, GzHeader};
+
+    /// Create a `GzBuilder` that produces a header with the same metadata as `header`.
+    ///
+    /// This copies the filename, comment, extra field, modification time, OS and extra flags,
+    /// so that tools recompressing a gzip file can preserve its metadata. OS values not known
+    /// by the `gzip_header` crate are written as 255 (unknown).
+    pub fn builder_from_header(header: &GzHeader) -> GzBuilder {
+        let mut builder = GzBuilder::new()
+            .mtime(header.mtime())
+            .os(FileSystemType::from_u8(header.os()))
+            .xfl(ExtraFlags::from_u8(header.xfl()));
+        if let Some(filename) = header.filename() {
+            builder = builder.filename(filename);
+        }
+        if let Some(comment) = header.comment() {
+            builder = builder.comment(comment);
+        }
+        if let Some(extra) = header.extra() {
+            builder = builder.extra(extra);
+        }
+        builder
+    }
 
     /// A Gzip encoder/compressor.
     ///
@@ -327,18 +1532,68 @@ pub mod gzip {
     /// #     try_main().unwrap();
     /// # }
     /// ```
+    ///
+    /// The checksum written in the trailer can be replaced by any type implementing
+    /// [`RollingChecksum`] using [`with_checksum`](#method.with_checksum), which is useful for
+    /// custom containers. The output will then no longer be a valid gzip stream.
+    ///
     /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
-    pub struct GzEncoder<W: Write> {
+    /// [`RollingChecksum`]: ../../checksum/trait.RollingChecksum.html
+    pub struct GzEncoder<W: Sink, C: RollingChecksum = Crc32Checksum> {
         inner: DeflateEncoder<W>,
-        checksum: Crc,
+        checksum: C,
+        /// Checksum provided by the user to write in the trailer instead of computing it.
+        precomputed_checksum: Option<u32>,
         header: Vec<u8>,
+        /// Length of the header that was written, for reporting.
+        header_len: u64,
+        /// OS to write in the header instead of the one from the builder.
+        os: Option<FileSystemType>,
+        /// Whether to set the FTEXT flag in the header.
+        text: bool,
+        /// Whether the end of the stream, including the trailer, has been output.
+        finished: bool,
+    }
+
+    /// The FTEXT flag in the FLG byte of the gzip header.
+    const FTEXT: u8 = 1;
+
+    /// The OS value written in the header when no other value is set: `Unix` on unix-like
+    /// systems, `Apple` on macOS and iOS and `Tops20OrNTFS` (10) on Windows, matching current
+    /// versions of zlib, and `Unknown` on other platforms.
+    pub fn default_os() -> FileSystemType {
+        if cfg!(windows) {
+            FileSystemType::Tops20OrNTFS
+        } else if cfg!(any(target_os = "macos", target_os = "ios")) {
+            FileSystemType::Apple
+        } else if cfg!(unix) {
+            FileSystemType::Unix
+        } else {
+            FileSystemType::Unknown
+        }
+    }
+
+    /// The header used when none is provided.
+    fn blank_header() -> Vec<u8> {
+        GzBuilder::new().os(default_os()).into_header()
     }
 
-    impl<W: Write> GzEncoder<W> {
+    /// Size of the gzip trailer.
+    const GZIP_TRAILER_BYTES: u64 = 8;
+
+    /// The value of the ISIZE field of the gzip trailer for `total_in` bytes of input.
+    ///
+    /// RFC 1952 defines ISIZE as the size of the input modulo 2^32, so it wraps around for streams
+    /// of 4 GiB or more.
+    fn gzip_isize(total_in: u64) -> u32 {
+        (total_in % (1 << 32)) as u32
+    }
+
+    impl<W: Sink> GzEncoder<W> {
         /// Create a new `GzEncoder` writing deflate-compressed data to the underlying writer when
         /// written to, wrapped in a gzip header and trailer. The header details will be blank.
         pub fn new<O: Into<CompressionOptions>>(writer: W, options: O) -> GzEncoder<W> {
-            GzEncoder::from_builder(GzBuilder::new(), writer, options)
+            GzEncoder::from_builder(GzBuilder::new().os(default_os()), writer, options)
         }
 
         /// Create a new GzEncoder from the provided `GzBuilder`. This allows customising
@@ -350,18 +1605,157 @@ pub mod gzip {
         ) -> GzEncoder<W> {
             GzEncoder {
                 inner: DeflateEncoder::new(writer, options),
-                checksum: Crc::new(),
+                checksum: Crc32Checksum::new(),
+                precomputed_checksum: None,
                 header: builder.into_header(),
+                header_len: 0,
+                os: None,
+                text: false,
+                finished: false,
+            }
+        }
+
+        /// Create a new `GzEncoder` writing a header with the same metadata as `header`, e.g
+        /// one read from an existing gzip file using [`read_gz_header`].
+        ///
+        /// [`read_gz_header`]: ../gzip/fn.read_gz_header.html
+        pub fn from_header<O: Into<CompressionOptions>>(
+            header: &GzHeader,
+            writer: W,
+            options: O,
+        ) -> GzEncoder<W> {
+            GzEncoder::from_builder(builder_from_header(header), writer, options)
+        }
+
+        /// Create a new `GzEncoder` for compressing the file at `path`, filling in the file
+        /// name and modification time fields of the header from the file's metadata.
+        ///
+        /// Only the final component of the path is stored as the file name, converted lossily
+        /// to UTF-8 if needed. The modification time is left as 0 (unknown) if it isn't
+        /// available on the platform or doesn't fit in the header field. The contents of the
+        /// file are not read, and have to be written to the encoder as usual.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the metadata of the file can not be read.
+        pub fn for_file<P: AsRef<Path>, O: Into<CompressionOptions>>(
+            path: P,
+            writer: W,
+            options: O,
+        ) -> io::Result<GzEncoder<W>> {
+            let path = path.as_ref();
+            let metadata = fs::metadata(path)?;
+            let mut builder = GzBuilder::new();
+            if let Some(name) = path.file_name() {
+                builder = builder.filename(name.to_string_lossy().into_owned());
+            }
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .filter(|&secs| secs <= u64::from(u32::max_value()))
+                .map(|secs| secs as u32)
+                .unwrap_or(0);
+            builder = builder.mtime(mtime);
+            Ok(GzEncoder::from_builder(builder, writer, options))
+        }
+
+        /// Create a new `GzEncoder` with a blank header, returning `Error::InvalidOptions` if
+        /// the compression options are out of range instead of adjusting them.
+        pub fn try_new<O: Into<CompressionOptions>>(
+            writer: W,
+            options: O,
+        ) -> Result<GzEncoder<W>, Error> {
+            Ok(GzEncoder {
+                inner: DeflateEncoder::try_new(writer, options)?,
+                checksum: Crc32Checksum::new(),
+                precomputed_checksum: None,
+                header: blank_header(),
+                header_len: 0,
+                os: None,
+                text: false,
+                finished: false,
+            })
+        }
+
+        /// Create a new `GzEncoder` with a blank header, taking the internal buffers from
+        /// `pool` if there are any available, and returning them to it when dropped.
+        pub fn with_pool<O: Into<CompressionOptions>>(
+            writer: W,
+            options: O,
+            pool: &BufferPool,
+        ) -> GzEncoder<W> {
+            GzEncoder {
+                inner: DeflateEncoder::with_pool(writer, options, pool),
+                checksum: Crc32Checksum::new(),
+                precomputed_checksum: None,
+                header: blank_header(),
+                header_len: 0,
+                os: None,
+                text: false,
+                finished: false,
+            }
+        }
+
+        /// Create a new `GzEncoder` continuing a stream that was ended with
+        /// [`finish_deferred`](#method.finish_deferred), so the output written to `writer` has
+        /// to be appended directly to the output from before.
+        ///
+        /// No header is written, and the trailer covers the data from before as well. The data
+        /// compressed before can't be referred back to.
+        pub fn resume<O: Into<CompressionOptions>>(
+            writer: W,
+            options: O,
+            end: &DeferredEnd,
+        ) -> GzEncoder<W> {
+            GzEncoder {
+                inner: DeflateEncoder::resume(writer, options, end),
+                checksum: Crc32Checksum::with_initial(end.checksum(), gzip_isize(end.total_in())),
+                precomputed_checksum: None,
+                header: Vec::new(),
+                header_len: 0,
+                os: None,
+                text: false,
+                finished: false,
+            }
+        }
+    }
+
+    impl<W: Sink, C: RollingChecksum> GzEncoder<W, C> {
+        /// Create a new `GzEncoder` with a blank header, computing the checksum written in the
+        /// trailer using `checksum` instead of CRC-32.
+        pub fn with_checksum<O: Into<CompressionOptions>>(
+            writer: W,
+            options: O,
+            checksum: C,
+        ) -> GzEncoder<W, C> {
+            GzEncoder {
+                inner: DeflateEncoder::new(writer, options),
+                checksum,
+                precomputed_checksum: None,
+                header: blank_header(),
+                header_len: 0,
+                os: None,
+                text: false,
+                finished: false,
             }
         }
 
         /// Write header to the output buffer if it hasn't been done yet.
         fn check_write_header(&mut self) {
             if !self.header.is_empty() {
+                if let Some(os) = self.os {
+                    self.header[9] = os.as_u8();
+                }
+                if self.text {
+                    self.header[3] |= FTEXT;
+                }
                 self.inner
                     .deflate_state
                     .output_buf()
                     .extend_from_slice(&self.header);
+                self.header_len = self.header.len() as u64;
                 self.header.clear();
             }
         }
@@ -369,9 +1763,14 @@ pub mod gzip {
         /// Output all pending data ,including the trailer(checksum + count) as if encoding is done.
         /// but without resetting anything.
         fn output_all(&mut self) -> io::Result<()> {
+            if self.finished {
+                return Ok(());
+            }
             self.check_write_header();
-            self.inner.output_all()?;
-            self.write_trailer()
+            finish_stream(&mut self.inner.deflate_state, GZIP_TRAILER_BYTES)?;
+            self.write_trailer()?;
+            self.finished = true;
+            Ok(())
         }
 
         /// Encode all pending data to the contained writer, consume this `GzEncoder`,
@@ -383,9 +1782,48 @@ pub mod gzip {
             Ok(self.inner.deflate_state.inner.take().expect(ERR_STR))
         }
 
-        fn reset_no_header(&mut self, writer: W) -> io::Result<W> {
+        /// Encode all pending data to the contained writer, consume this `GzEncoder`,
+        /// and return the contained writer along with the final statistics if writing succeeds.
+        pub fn finish_with_stats(mut self) -> io::Result<(W, CompressionStats)> {
+            self.output_all()?;
+            let stats = self.inner.deflate_state.stats;
+            Ok((self.inner.deflate_state.inner.take().expect(ERR_STR), stats))
+        }
+
+        /// Encode all pending data to the contained writer, consume this `GzEncoder`,
+        /// and return the contained writer along with a report describing the output if writing
+        /// succeeds.
+        pub fn finish_with_report(mut self) -> io::Result<(W, CompressionReport)> {
             self.output_all()?;
-            self.checksum = Crc::new();
+            let report = self
+                .inner
+                .deflate_state
+                .report(self.header_len, GZIP_TRAILER_BYTES);
+            Ok((
+                self.inner.deflate_state.inner.take().expect(ERR_STR),
+                report,
+            ))
+        }
+
+        /// Write the header and encode all pending data to the contained writer without ending
+        /// the stream, consume this `GzEncoder`, and return the contained writer along with the
+        /// state needed to resume the stream later with [`resume`](#method.resume).
+        ///
+        /// No trailer is written, it is written by the resumed encoder when it is finished.
+        pub fn finish_deferred(mut self) -> io::Result<(W, DeferredEnd)> {
+            self.check_write_header();
+            let checksum = self.checksum.current_hash();
+            let end = finish_deferred_stream(&mut self.inner.deflate_state, checksum)?;
+            Ok((self.inner.deflate_state.inner.take().expect(ERR_STR), end))
+        }
+
+        fn reset_no_header(&mut self, writer: W) -> io::Result<W> {
+            if !self.inner.deflate_state.is_poisoned() {
+                self.output_all()?;
+            }
+            self.finished = false;
+            self.checksum.reset();
+            self.precomputed_checksum = None;
             self.inner.deflate_state.reset(writer)
         }
 
@@ -393,7 +1831,7 @@ pub mod gzip {
         /// with a new one, returning the old one. (Using a blank header).
         pub fn reset(&mut self, writer: W) -> io::Result<W> {
             let w = self.reset_no_header(writer);
-            self.header = GzBuilder::new().into_header();
+            self.header = blank_header();
             w
         }
 
@@ -406,10 +1844,62 @@ pub mod gzip {
             w
         }
 
+        /// End the current gzip member and start a new one using the provided `GzBuilder` to
+        /// create its header, writing both to the current writer.
+        ///
+        /// A gzip file can consist of several members, which decoders such as `gzip -d`
+        /// decompress as the concatenation of their contents. This is what tools like `bgzip` and
+        /// `pigz` output, so the members can be located and decompressed independently. The new
+        /// member can't refer back to the data in earlier ones.
+        ///
+        /// The statistics, block offsets and positions in the output start over from the new
+        /// member, as if the encoder had been reset. The compression options and other settings
+        /// are kept.
+        ///
+        /// # Examples
+        ///
+        /// ```rust
+        /// # use std::io;
+        /// #
+        /// # fn try_main() -> io::Result<Vec<u8>> {
+        /// #
+        /// use std::io::Write;
+        ///
+        /// use deflate::gzip::GzBuilder;
+        /// use deflate::write::GzEncoder;
+        /// use deflate::Compression;
+        ///
+        /// let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        /// encoder.write_all(b"The first member. ")?;
+        /// encoder.start_new_member(GzBuilder::new().comment("second"))?;
+        /// encoder.write_all(b"The second member.")?;
+        /// let compressed_data = encoder.finish()?;
+        /// # Ok(compressed_data)
+        /// #
+        /// # }
+        /// # fn main() {
+        /// #     try_main().unwrap();
+        /// # }
+        /// ```
+        pub fn start_new_member(&mut self, builder: GzBuilder) -> io::Result<()> {
+            self.output_all()?;
+            self.inner.deflate_state.reset_keep_writer()?;
+            self.finished = false;
+            self.checksum.reset();
+            self.precomputed_checksum = None;
+            self.header = builder.into_header();
+            Ok(())
+        }
+
+        /// Get a mutable reference to the wrapped writer.
+        pub(crate) fn writer_mut(&mut self) -> &mut W {
+            self.inner.writer_mut()
+        }
+
         /// Write the checksum and number of bytes mod 2^32 to the output writer.
         fn write_trailer(&mut self) -> io::Result<()> {
-            let crc = self.checksum.sum();
-            let amount = self.checksum.amt_as_u32();
+            let crc = self.checksum();
+            let amount = gzip_isize(self.inner.deflate_state.bytes_written);
 
             // We use a buffer here to make sure we don't end up writing only half the header if
             // writing fails.
@@ -417,181 +1907,2148 @@ pub mod gzip {
             let mut temp = Cursor::new(&mut buf[..]);
             temp.write_all(&crc.to_le_bytes()).unwrap();
             temp.write_all(&amount.to_le_bytes()).unwrap();
-            self.inner
+            let res = self
+                .inner
                 .deflate_state
                 .inner
                 .as_mut()
                 .expect(ERR_STR)
-                .write_all(temp.into_inner())
+                .write_all_output(temp.into_inner());
+            if let Err(ref e) = res {
+                self.inner.deflate_state.record_error(e);
+            }
+            res
         }
 
-        /// Get the crc32 checksum of the data consumed so far.
+        /// Get the checksum of the data consumed so far, which is CRC-32 unless another checksum
+        /// was provided.
+        ///
+        /// If a precomputed checksum has been set, that is returned instead.
         pub fn checksum(&self) -> u32 {
-            self.checksum.sum()
+            self.precomputed_checksum
+                .unwrap_or_else(|| self.checksum.current_hash())
         }
-    }
 
-    impl<W: Write> io::Write for GzEncoder<W> {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            self.check_write_header();
-            let res = self.inner.write(buf);
-            match res {
-                Ok(0) => self.checksum.update(buf),
-                Ok(n) => self.checksum.update(&buf[0..n]),
-                _ => (),
-            };
-            res
+        /// Use `checksum` as the checksum of the whole input in the trailer, skipping computing
+        /// it.
+        ///
+        /// This is useful when the checksum of the input is already known, e.g if it is stored
+        /// alongside the data. The value is not verified, so if it's wrong the output will fail
+        /// to decompress. It is cleared when the encoder is reset.
+        pub fn set_precomputed_checksum(&mut self, checksum: u32) {
+            self.precomputed_checksum = Some(checksum);
         }
 
-        /// Flush the encoder.
+        /// Set the OS field of the header, overriding the value from the builder or the
+        /// [platform default](fn.default_os.html).
         ///
-        /// This will flush the encoder, emulating the Sync flush method from Zlib.
-        /// This essentially finishes the current block, and sends an additional empty stored
-        /// block to the writer.
-        fn flush(&mut self) -> io::Result<()> {
-            self.inner.flush()
+        /// This has no effect if the header has already been written. The value is kept when
+        /// the encoder is reset.
+        pub fn set_os(&mut self, os: FileSystemType) {
+            self.os = Some(os);
         }
-    }
 
-    impl<W: Write> Drop for GzEncoder<W> {
-        /// When the encoder is dropped, output the rest of the data.
+        /// Set whether the FTEXT flag is set in the header, indicating that the data is
+        /// probably text. The default is `false`.
         ///
-        /// WARNING: This may silently fail if writing fails, so using this to finish encoding
-        /// for writers where writing might fail is not recommended, for that call
-        /// [`finish()`](#method.finish) instead.
-        fn drop(&mut self) {
-            if self.inner.deflate_state.inner.is_some() && !thread::panicking() {
-                let _ = self.output_all();
-            }
+        /// This has no effect if the header has already been written. The value is kept when
+        /// the encoder is reset.
+        pub fn set_text(&mut self, text: bool) {
+            self.text = text;
         }
-    }
 
-    #[cfg(test)]
-    mod test {
-        use super::*;
-        use crate::test_utils::{decompress_gzip, get_test_data};
-        #[test]
-        fn gzip_writer() {
-            let data = get_test_data();
-            let comment = b"Comment";
-            let compressed = {
-                let mut compressor = GzEncoder::from_builder(
-                    GzBuilder::new().comment(&comment[..]),
-                    Vec::with_capacity(data.len() / 3),
-                    CompressionOptions::default(),
+        /// Set a selector that can override the type of each block output from now on.
+        ///
+        /// The selector is kept when the encoder is reset.
+        pub fn set_block_type_selector<S: BlockTypeSelector + Send + 'static>(
+            &mut self,
+            selector: S,
+        ) {
+            self.inner.set_block_type_selector(selector);
+        }
+
+        /// Set a listener that is called for each block output from now on.
+        ///
+        /// The positions in the output reported to the listener include the gzip header, and
+        /// count from the start of the stream. The listener is kept when the encoder is reset.
+        pub fn set_block_listener<L: BlockListener + Send + 'static>(&mut self, listener: L) {
+            self.inner.set_block_listener(listener);
+        }
+
+        /// Set how many consecutive times flushing or finishing is retried when the wrapped
+        /// writer returns `ErrorKind::Interrupted` without accepting any data, before the error
+        /// is returned.
+        ///
+        /// `None` means retrying indefinitely. The default is 1000 retries.
+        pub fn set_max_interrupted_retries(&mut self, max_retries: Option<u32>) {
+            self.inner.set_max_interrupted_retries(max_retries);
+        }
+
+        /// Pad the finished stream, including the gzip header and trailer, with empty blocks so
+        /// that its length is a multiple of `alignment` bytes.
+        ///
+        /// See [`DeflateEncoder::pad_to`](struct.DeflateEncoder.html#method.pad_to).
+        pub fn pad_to(&mut self, alignment: u64) {
+            self.inner.deflate_state.pad_to = alignment;
+        }
+
+        /// Set whether the rest of the data should be output when the encoder is dropped without
+        /// calling [`finish()`](#method.finish). The default is `true`.
+        ///
+        /// Any error that occurs when finishing on drop is discarded, so it is better to call
+        /// `finish()` explicitly when writing may fail.
+        pub fn set_finish_on_drop(&mut self, finish_on_drop: bool) {
+            self.inner.set_finish_on_drop(finish_on_drop);
+        }
+
+        /// Tell the encoder the total number of bytes that will be written to it, if known in
+        /// advance.
+        ///
+        /// This is only used to size the internal output buffer, avoiding allocating more memory
+        /// than needed for small inputs, and doesn't change the compressed output. Writing more
+        /// or less data than indicated is not an error. The hint is not kept when the encoder is
+        /// reset.
+        pub fn set_input_size_hint(&mut self, size: u64) {
+            self.inner.set_input_size_hint(size);
+        }
+
+        /// Automatically do a sync flush once `bytes` bytes of input have been written since the
+        /// last flush, or never if `bytes` is `0`.
+        ///
+        /// This overrides the [`auto_flush_after`](../struct.CompressionOptions.html#structfield.auto_flush_after)
+        /// compression option, and can be changed at any point in the stream. The setting is
+        /// kept when the encoder is reset.
+        pub fn set_auto_flush_after(&mut self, bytes: u32) {
+            self.inner.set_auto_flush_after(bytes);
+        }
+
+        /// Returns a copy of the last error that occured when writing to the wrapped writer, if
+        /// any.
+        ///
+        /// Errors indicating that writing should be retried (`Interrupted` and `WouldBlock`) are
+        /// not recorded.
+        ///
+        /// Any other error poisons the encoder, as the state of the output stream is no longer
+        /// known. Writing, flushing and finishing will then fail with an error of the same kind
+        /// until the encoder is reset.
+        pub fn last_error(&self) -> Option<&io::Error> {
+            self.inner.last_error()
+        }
+
+        /// Statistics about the blocks output so far.
+        ///
+        /// The statistics are cleared when the encoder is reset.
+        pub fn stats(&self) -> CompressionStats {
+            self.inner.stats()
+        }
+
+        /// The position in bits of the end of the data output so far, counted from the start of
+        /// the stream (including the gzip header).
+        ///
+        /// This includes the bits of the last partial byte that are still held by the encoder,
+        /// and will be output along with later data, but not input that has been written to the
+        /// encoder but not compressed yet. After a flush, it is the exact length of the output in
+        /// bits.
+        pub fn bit_position(&self) -> u64 {
+            self.inner.bit_position()
+        }
+
+        /// The total number of bytes of input written to the encoder so far.
+        ///
+        /// This is a 64-bit count, so streams larger than 4 GiB are counted correctly. Note that
+        /// the size stored in the gzip trailer is this value modulo 2^32, as specified by
+        /// RFC 1952.
+        pub fn total_in(&self) -> u64 {
+            self.inner.total_in()
+        }
+
+        /// The total number of bytes of output passed on to the wrapped writer so far, including
+        /// the header and trailer.
+        ///
+        /// Output that is still held by the encoder, such as the last partial byte, is not
+        /// counted until it has been flushed. Once the stream is finished, this is the length of
+        /// the whole stream.
+        pub fn total_out(&self) -> u64 {
+            let trailer_bytes = if self.finished { GZIP_TRAILER_BYTES } else { 0 };
+            self.inner.total_out() + trailer_bytes
+        }
+
+        /// Flush the encoder like [`flush()`](#method.flush), and also forget the data
+        /// compressed so far, so the data written afterwards doesn't refer back to it.
+        ///
+        /// See
+        /// [`DeflateEncoder::full_flush`](struct.DeflateEncoder.html#method.full_flush).
+        pub fn full_flush(&mut self) -> io::Result<()> {
+            self.check_write_header();
+            self.inner.full_flush()
+        }
+
+        /// End the current block and output all complete bytes of compressed data, followed by
+        /// an empty fixed block, so the output so far can be decompressed.
+        ///
+        /// See
+        /// [`DeflateEncoder::partial_flush`](struct.DeflateEncoder.html#method.partial_flush).
+        pub fn partial_flush(&mut self) -> io::Result<()> {
+            self.check_write_header();
+            self.inner.partial_flush()
+        }
+
+        /// End the current block and output all complete bytes of compressed data, without
+        /// padding the output to a byte boundary.
+        ///
+        /// See
+        /// [`DeflateEncoder::block_flush`](struct.DeflateEncoder.html#method.block_flush).
+        pub fn block_flush(&mut self) -> io::Result<()> {
+            self.check_write_header();
+            self.inner.block_flush()
+        }
+
+        /// The compression options used by the encoder.
+        ///
+        /// Options that were out of range when the encoder was created are returned as adjusted.
+        pub fn options(&self) -> &CompressionOptions {
+            self.inner.options()
+        }
+
+        /// Whether the stream has been finished with [`try_finish()`](#method.try_finish).
+        ///
+        /// Once finished, writing to the encoder returns an error until it is reset.
+        pub fn is_finished(&self) -> bool {
+            self.finished
+        }
+
+        /// Encode all pending data and output the end of the stream to the contained writer,
+        /// without consuming the encoder.
+        ///
+        /// This can be called again if writing fails. Calling it after the stream has been finished
+        /// does nothing.
+        pub fn try_finish(&mut self) -> io::Result<()> {
+            self.output_all()
+        }
+
+        /// Replace the wrapped writer with `writer` without ending the stream, returning the old
+        /// one.
+        ///
+        /// The output that is ready is passed on to the old writer first. Unlike
+        /// [`reset()`](#method.reset), the compression state is kept, so the output written to the
+        /// new writer continues the same stream. Input that hasn't been compressed yet, and the
+        /// last partial byte of output, are written to the new writer later, so call
+        /// [`flush()`](#method.flush) first to have everything written so far end up in the old
+        /// writer.
+        pub fn replace_writer(&mut self, writer: W) -> io::Result<W> {
+            self.inner.deflate_state.replace_writer(writer)
+        }
+
+        /// End the current block and output `data` as-is in stored (uncompressed) blocks.
+        ///
+        /// See
+        /// [`DeflateEncoder::write_stored_chunk`](struct.DeflateEncoder.html#method.write_stored_chunk).
+        pub fn write_stored_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+            self.check_write_header();
+            self.inner.write_stored_chunk(data)?;
+            if self.precomputed_checksum.is_none() {
+                update_checksum(
+                    &mut self.checksum,
+                    data,
+                    &mut self.inner.deflate_state.stats,
+                );
+            }
+            Ok(())
+        }
+
+        /// End the current block and output the pre-compressed blocks of `block`.
+        ///
+        /// See
+        /// [`DeflateEncoder::write_compiled_block`](struct.DeflateEncoder.html#method.write_compiled_block).
+        pub fn write_compiled_block(
+            &mut self,
+            block: &CompiledBlock,
+            final_block: bool,
+        ) -> io::Result<()> {
+            self.check_write_header();
+            self.inner.write_compiled_block(block, final_block)?;
+            if self.precomputed_checksum.is_none() {
+                update_checksum(
+                    &mut self.checksum,
+                    block.input(),
+                    &mut self.inner.deflate_state.stats,
+                );
+            }
+            if final_block {
+                self.output_all()?;
+            }
+            Ok(())
+        }
+
+        /// The position in the output of the first bit of each block output so far, counted from
+        /// the start of the stream (including the gzip header).
+        ///
+        /// The offsets are cleared when the encoder is reset. Only available with the
+        /// `block-offsets` feature.
+        #[cfg(feature = "block-offsets")]
+        pub fn block_offsets(&self) -> &[u64] {
+            self.inner.block_offsets()
+        }
+
+        /// Encode all pending data to the contained writer, consume this `GzEncoder`,
+        /// and return the contained writer along with the starting bit offset of each block if
+        /// writing succeeds.
+        ///
+        /// Only available with the `block-offsets` feature.
+        #[cfg(feature = "block-offsets")]
+        pub fn finish_with_block_offsets(mut self) -> io::Result<(W, Vec<u64>)> {
+            self.output_all()?;
+            let offsets = mem::replace(&mut self.inner.deflate_state.block_offsets, Vec::new());
+            Ok((
+                self.inner.deflate_state.inner.take().expect(ERR_STR),
+                offsets,
+            ))
+        }
+    }
+
+    impl<W: Sink, C: RollingChecksum> io::Write for GzEncoder<W, C> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.check_write_header();
+            if self.precomputed_checksum.is_none() {
+                write_auto_flush(buf, &mut self.inner.deflate_state, &mut self.checksum)
+            } else {
+                self.inner.write(buf)
+            }
+        }
+
+        /// Flush the encoder.
+        ///
+        /// This will flush the encoder, emulating the Sync flush method from Zlib.
+        /// This essentially finishes the current block, and sends an additional empty stored
+        /// block to the writer. Nothing is output if no data has been written since the last
+        /// flush.
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl<W: Sink, C: RollingChecksum> Drop for GzEncoder<W, C> {
+        /// When the encoder is dropped, output the rest of the data, unless this has been disabled
+        /// with [`set_finish_on_drop()`](#method.set_finish_on_drop).
+        ///
+        /// WARNING: This may silently fail if writing fails, so using this to finish encoding
+        /// for writers where writing might fail is not recommended, for that call
+        /// [`finish()`](#method.finish) instead.
+        fn drop(&mut self) {
+            if self.inner.deflate_state.finish_on_drop
+                && self.inner.deflate_state.inner.is_some()
+                && !thread::panicking()
+            {
+                let _ = self.output_all();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::test_utils::{decompress_gzip, get_test_data};
+        #[test]
+        fn gzip_writer() {
+            let data = get_test_data();
+            let comment = b"Comment";
+            let compressed = {
+                let mut compressor = GzEncoder::from_builder(
+                    GzBuilder::new().comment(&comment[..]),
+                    Vec::with_capacity(data.len() / 3),
+                    CompressionOptions::default(),
                 );
                 compressor.write_all(&data[0..data.len() / 2]).unwrap();
                 compressor.write_all(&data[data.len() / 2..]).unwrap();
                 compressor.finish().unwrap()
             };
 
-            let (dec, res) = decompress_gzip(&compressed);
-            assert_eq!(dec.comment().unwrap(), comment);
-            assert!(res == data);
+            let (dec, res) = decompress_gzip(&compressed);
+            assert_eq!(dec.comment().unwrap(), comment);
+            assert!(res == data);
+        }
+
+        #[test]
+        fn gzip_header_roundtrip() {
+            use gzip_header::read_gz_header;
+
+            let data = get_test_data();
+            let builder = GzBuilder::new()
+                .filename(&b"file.txt"[..])
+                .comment(&b"Comment"[..])
+                .extra(&b"AB\x02\x00xy"[..])
+                .mtime(1_234_567)
+                .os(FileSystemType::Unix)
+                .xfl(ExtraFlags::MaximumCompression);
+            let mut compressor =
+                GzEncoder::from_builder(builder, Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data).unwrap();
+            let original = compressor.finish().unwrap();
+
+            let header = read_gz_header(&mut &original[..]).unwrap();
+            let mut compressor =
+                GzEncoder::from_header(&header, Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data).unwrap();
+            let recompressed = compressor.finish().unwrap();
+            assert!(recompressed == original);
+
+            let (dec, res) = decompress_gzip(&recompressed);
+            assert!(dec == header);
+            assert!(res == data);
+        }
+
+        #[test]
+        fn gzip_no_checksum() {
+            use crate::checksum::NoChecksum;
+
+            let data = get_test_data();
+            let mut compressor = GzEncoder::with_checksum(
+                Vec::new(),
+                CompressionOptions::default(),
+                NoChecksum::new(),
+            );
+            compressor.write_all(&data).unwrap();
+            let compressed = compressor.finish().unwrap();
+            let trailer = &compressed[compressed.len() - 8..];
+            assert_eq!(trailer[..4], 1u32.to_le_bytes());
+            assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        }
+
+        #[test]
+        fn gzip_for_file() {
+            use gzip_header::read_gz_header;
+
+            let path = Path::new("tests/pg11.txt");
+            let data = get_test_data();
+            let mut compressor =
+                GzEncoder::for_file(path, Vec::new(), CompressionOptions::default()).unwrap();
+            compressor.write_all(&data).unwrap();
+            let compressed = compressor.finish().unwrap();
+
+            let header = read_gz_header(&mut &compressed[..]).unwrap();
+            assert_eq!(header.filename(), Some(&b"pg11.txt"[..]));
+            let modified = fs::metadata(path).unwrap().modified().unwrap();
+            let mtime = modified.duration_since(UNIX_EPOCH).unwrap().as_secs();
+            assert_eq!(u64::from(header.mtime()), mtime);
+            assert!(decompress_gzip(&compressed).1 == data);
+
+            assert!(GzEncoder::for_file(
+                "tests/does-not-exist",
+                Vec::new(),
+                CompressionOptions::default()
+            )
+            .is_err());
+        }
+
+        #[test]
+        fn gzip_pad_to() {
+            let data = get_test_data();
+            let mut compressor = GzEncoder::from_builder(
+                GzBuilder::new().filename("pg11.txt"),
+                Vec::new(),
+                CompressionOptions::default(),
+            );
+            compressor.pad_to(512);
+            compressor.write_all(&data).unwrap();
+            let compressed = compressor.finish().unwrap();
+            assert_eq!(compressed.len() % 512, 0);
+            assert!(decompress_gzip(&compressed).1 == data);
+        }
+
+        #[test]
+        fn gzip_finish_deferred() {
+            let data = get_test_data();
+            let (first, second) = data.split_at(20_000);
+            let mut compressor = GzEncoder::from_builder(
+                GzBuilder::new().filename("pg11.txt"),
+                Vec::new(),
+                CompressionOptions::default(),
+            );
+            compressor.write_all(first).unwrap();
+            let (mut compressed, end) = compressor.finish_deferred().unwrap();
+            let mut compressor = GzEncoder::resume(Vec::new(), CompressionOptions::default(), &end);
+            compressor.write_all(second).unwrap();
+            compressed.extend(compressor.finish().unwrap());
+            let (header, decompressed) = decompress_gzip(&compressed);
+            assert_eq!(header.filename(), Some(&b"pg11.txt"[..]));
+            assert!(decompressed == data);
+        }
+
+        #[test]
+        fn gzip_os_and_text() {
+            use gzip_header::read_gz_header;
+
+            let data = get_test_data();
+            let compressed = crate::try_deflate_bytes_gzip(&data).unwrap();
+            assert_eq!(compressed[9], default_os().as_u8());
+            assert_eq!(compressed[3] & FTEXT, 0);
+
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.set_os(FileSystemType::Amiga);
+            compressor.set_text(true);
+            compressor.write_all(&data).unwrap();
+            let compressed = compressor.finish().unwrap();
+            assert_eq!(compressed[3] & FTEXT, FTEXT);
+            let header = read_gz_header(&mut &compressed[..]).unwrap();
+            assert_eq!(header.os(), FileSystemType::Amiga.as_u8());
+            assert!(decompress_gzip(&compressed).1 == data);
+        }
+
+        #[test]
+        fn gzip_isize_wraps() {
+            assert_eq!(gzip_isize(0), 0);
+            assert_eq!(gzip_isize(u64::from(u32::MAX)), u32::MAX);
+            assert_eq!(gzip_isize(1 << 32), 0);
+            assert_eq!(gzip_isize((5 << 32) + 1234), 1234);
+
+            // Pretend almost 4 GiB has already been written to check the counters don't
+            // overflow.
+            let data = get_test_data();
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            let start = u64::from(u32::MAX) - 1000;
+            compressor.inner.deflate_state.bytes_written = start;
+            compressor.inner.deflate_state.bytes_written_at_flush = start;
+            compressor
+                .inner
+                .deflate_state
+                .bytes_written_control
+                .add(start);
+            compressor.write_all(&data).unwrap();
+            assert_eq!(compressor.total_in(), start + data.len() as u64);
+            let compressed = compressor.finish().unwrap();
+            let trailer = &compressed[compressed.len() - 8..];
+            assert_eq!(
+                trailer[4..],
+                ((data.len() as u64 - 1001) as u32).to_le_bytes()
+            );
+        }
+
+        #[test]
+        fn gzip_precomputed_checksum() {
+            let data = get_test_data();
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data).unwrap();
+            let checksum = compressor.checksum();
+            let expected = compressor.finish().unwrap();
+
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.set_precomputed_checksum(checksum);
+            compressor.write_all(&data).unwrap();
+            assert!(compressor.finish().unwrap() == expected);
+        }
+
+        #[test]
+        fn gzip_report() {
+            let data = get_test_data();
+            let mut compressor = GzEncoder::from_builder(
+                GzBuilder::new().comment(&b"Comment"[..]),
+                Vec::new(),
+                CompressionOptions::default(),
+            );
+            compressor.write_all(&data).unwrap();
+            let (compressed, report) = compressor.finish_with_report().unwrap();
+            assert_eq!(report.header_bytes, 10 + 8);
+            assert_eq!(report.trailer_bytes, 8);
+            assert_eq!(report.output_bytes, compressed.len() as u64);
+            assert!(report.padding_bits() < 8);
+        }
+
+        #[test]
+        fn gzip_total_in_out() {
+            let data = get_test_data();
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data).unwrap();
+            assert_eq!(compressor.total_in(), data.len() as u64);
+            compressor.try_finish().unwrap();
+            let total_out = compressor.total_out();
+            assert_eq!(total_out, compressor.finish().unwrap().len() as u64);
+        }
+
+        #[test]
+        fn gzip_multiple_members() {
+            let data = get_test_data();
+            let (first, second) = data.split_at(data.len() / 3);
+            let builder = || GzBuilder::new().comment(&b"Second"[..]);
+
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(first).unwrap();
+            compressor.start_new_member(builder()).unwrap();
+            compressor.write_all(second).unwrap();
+            assert_eq!(compressor.total_in(), second.len() as u64);
+            let compressed = compressor.finish().unwrap();
+
+            // The output should be the same as compressing the members separately.
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(first).unwrap();
+            let first_member = compressor.finish().unwrap();
+            let mut compressor =
+                GzEncoder::from_builder(builder(), Vec::new(), CompressionOptions::default());
+            compressor.write_all(second).unwrap();
+            let second_member = compressor.finish().unwrap();
+            assert!(compressed[..first_member.len()] == first_member[..]);
+            assert!(compressed[first_member.len()..] == second_member[..]);
+
+            assert!(decompress_gzip(&first_member).1 == first);
+            let (header, res) = decompress_gzip(&second_member);
+            assert_eq!(header.comment().unwrap(), b"Second");
+            assert!(res == second);
+
+            // Empty members are still output.
+            let mut compressor = GzEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.start_new_member(GzBuilder::new()).unwrap();
+            let compressed = compressor.finish().unwrap();
+            let empty = GzEncoder::new(Vec::new(), CompressionOptions::default())
+                .finish()
+                .unwrap();
+            assert!(compressed == [&empty[..], &empty[..]].concat());
+        }
+    }
+}
+
+/// The container format to wrap DEFLATE-compressed data in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Format {
+    /// Raw DEFLATE data without any header or trailer.
+    Raw,
+    /// DEFLATE data wrapped in a zlib header and trailer.
+    Zlib,
+    /// DEFLATE data wrapped in a gzip header and trailer (with blank header details).
+    #[cfg(feature = "gzip")]
+    Gzip,
+}
+
+/// An encoder/compressor using a container format selected at runtime.
+///
+/// This is a convenience wrapper around the other encoders for applications where the format
+/// is determined by e.g a configuration value.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::io;
+/// #
+/// # fn try_main() -> io::Result<Vec<u8>> {
+/// #
+/// use std::io::Write;
+///
+/// use deflate::{Compression, Format};
+/// use deflate::write::AnyEncoder;
+///
+/// let data = b"This is some test data";
+/// let mut encoder = AnyEncoder::new(Vec::new(), Format::Zlib, Compression::Default);
+/// encoder.write_all(data)?;
+/// let compressed_data = encoder.finish()?;
+/// # Ok(compressed_data)
+/// #
+/// # }
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+pub enum AnyEncoder<W: Sink> {
+    /// Raw DEFLATE encoder.
+    Raw(DeflateEncoder<W>),
+    /// Zlib encoder.
+    Zlib(ZlibEncoder<W>),
+    /// Gzip encoder.
+    #[cfg(feature = "gzip")]
+    Gzip(gzip::GzEncoder<W>),
+}
+
+impl<W: Sink> AnyEncoder<W> {
+    /// Create a new encoder writing data in the given format using the provided compression
+    /// options.
+    pub fn new<O: Into<CompressionOptions>>(
+        writer: W,
+        format: Format,
+        options: O,
+    ) -> AnyEncoder<W> {
+        match format {
+            Format::Raw => AnyEncoder::Raw(DeflateEncoder::new(writer, options)),
+            Format::Zlib => AnyEncoder::Zlib(ZlibEncoder::new(writer, options)),
+            #[cfg(feature = "gzip")]
+            Format::Gzip => AnyEncoder::Gzip(gzip::GzEncoder::new(writer, options)),
+        }
+    }
+
+    /// Create a new encoder writing data in the given format using the provided compression
+    /// options, taking the internal buffers from `pool` if there are any available, and
+    /// returning them to it when dropped.
+    pub fn with_pool<O: Into<CompressionOptions>>(
+        writer: W,
+        format: Format,
+        options: O,
+        pool: &BufferPool,
+    ) -> AnyEncoder<W> {
+        match format {
+            Format::Raw => AnyEncoder::Raw(DeflateEncoder::with_pool(writer, options, pool)),
+            Format::Zlib => AnyEncoder::Zlib(ZlibEncoder::with_pool(writer, options, pool)),
+            #[cfg(feature = "gzip")]
+            Format::Gzip => AnyEncoder::Gzip(gzip::GzEncoder::with_pool(writer, options, pool)),
+        }
+    }
+
+    /// The format of the data output by this encoder.
+    pub fn format(&self) -> Format {
+        match *self {
+            AnyEncoder::Raw(_) => Format::Raw,
+            AnyEncoder::Zlib(_) => Format::Zlib,
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(_) => Format::Gzip,
+        }
+    }
+
+    /// Statistics about the blocks output so far.
+    pub fn stats(&self) -> CompressionStats {
+        match *self {
+            AnyEncoder::Raw(ref e) => e.stats(),
+            AnyEncoder::Zlib(ref e) => e.stats(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref e) => e.stats(),
+        }
+    }
+
+    /// The compression options used by the encoder.
+    pub fn options(&self) -> &CompressionOptions {
+        match *self {
+            AnyEncoder::Raw(ref e) => e.options(),
+            AnyEncoder::Zlib(ref e) => e.options(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref e) => e.options(),
+        }
+    }
+
+    /// Whether the stream has been finished with [`try_finish()`](#method.try_finish).
+    pub fn is_finished(&self) -> bool {
+        match *self {
+            AnyEncoder::Raw(ref e) => e.is_finished(),
+            AnyEncoder::Zlib(ref e) => e.is_finished(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref e) => e.is_finished(),
+        }
+    }
+
+    /// Encode all pending data and output the end of the stream to the contained writer,
+    /// without consuming the encoder.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.try_finish(),
+            AnyEncoder::Zlib(ref mut e) => e.try_finish(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.try_finish(),
+        }
+    }
+
+    /// Flush the encoder, and forget the data compressed so far, so the data written afterwards
+    /// doesn't refer back to it.
+    pub fn full_flush(&mut self) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.full_flush(),
+            AnyEncoder::Zlib(ref mut e) => e.full_flush(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.full_flush(),
+        }
+    }
+
+    /// End the current block and output all complete bytes of compressed data, followed by an
+    /// empty fixed block, so the output so far can be decompressed.
+    pub fn partial_flush(&mut self) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.partial_flush(),
+            AnyEncoder::Zlib(ref mut e) => e.partial_flush(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.partial_flush(),
+        }
+    }
+
+    /// End the current block and output all complete bytes of compressed data, without padding
+    /// the output to a byte boundary.
+    pub fn block_flush(&mut self) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.block_flush(),
+            AnyEncoder::Zlib(ref mut e) => e.block_flush(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.block_flush(),
+        }
+    }
+
+    /// Pad the finished stream with empty blocks, so that its length is a multiple of
+    /// `alignment` bytes.
+    pub fn pad_to(&mut self, alignment: u64) {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.pad_to(alignment),
+            AnyEncoder::Zlib(ref mut e) => e.pad_to(alignment),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.pad_to(alignment),
+        }
+    }
+
+    /// Replace the wrapped writer with `writer` without ending the stream, returning the old
+    /// one.
+    pub fn replace_writer(&mut self, writer: W) -> io::Result<W> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.replace_writer(writer),
+            AnyEncoder::Zlib(ref mut e) => e.replace_writer(writer),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.replace_writer(writer),
+        }
+    }
+
+    /// End the current block and output `data` as-is in stored (uncompressed) blocks.
+    pub fn write_stored_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.write_stored_chunk(data),
+            AnyEncoder::Zlib(ref mut e) => e.write_stored_chunk(data),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.write_stored_chunk(data),
+        }
+    }
+
+    /// End the current block and output the pre-compressed blocks of `block`.
+    pub fn write_compiled_block(
+        &mut self,
+        block: &CompiledBlock,
+        final_block: bool,
+    ) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.write_compiled_block(block, final_block),
+            AnyEncoder::Zlib(ref mut e) => e.write_compiled_block(block, final_block),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.write_compiled_block(block, final_block),
+        }
+    }
+
+    /// Encode all pending data to the contained writer, consume this `AnyEncoder`,
+    /// and return the contained writer if writing succeeds.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            AnyEncoder::Raw(e) => e.finish(),
+            AnyEncoder::Zlib(e) => e.finish(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(e) => e.finish(),
+        }
+    }
+
+    /// Resets the encoder (except the compression options and format), replacing the current
+    /// writer with a new one, returning the old one.
+    pub fn reset(&mut self, writer: W) -> io::Result<W> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.reset(writer),
+            AnyEncoder::Zlib(ref mut e) => e.reset(writer),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.reset(writer),
+        }
+    }
+}
+
+impl<W: Sink> io::Write for AnyEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.write(buf),
+            AnyEncoder::Zlib(ref mut e) => e.write(buf),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.write(buf),
+        }
+    }
+
+    /// Flush the encoder.
+    ///
+    /// This will flush the encoder, emulating the Sync flush method from Zlib.
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            AnyEncoder::Raw(ref mut e) => e.flush(),
+            AnyEncoder::Zlib(ref mut e) => e.flush(),
+            #[cfg(feature = "gzip")]
+            AnyEncoder::Gzip(ref mut e) => e.flush(),
+        }
+    }
+}
+
+/// Write `rows` rows of `row_len` bytes each from `data` to `writer`, where the start of each row
+/// is `stride` bytes after the start of the previous one.
+///
+/// This allows compressing image-style data that has padding between rows, or is a
+/// sub-rectangle of a larger image, without first copying the rows into a contiguous buffer.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` without writing anything if `stride` is smaller than
+/// `row_len` or `data` is too short to hold all the rows, otherwise any error returned by the
+/// writer.
+///
+/// # Examples
+///
+/// ```rust
+/// use deflate::write::{write_strided, ZlibEncoder};
+/// use deflate::Compression;
+///
+/// // A 2x2 image with 2 bytes of padding after each row.
+/// let data = [1, 2, 0, 0, 3, 4, 0, 0];
+/// let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+/// write_strided(&mut encoder, &data, 2, 4, 2).unwrap();
+/// let compressed = encoder.finish().unwrap();
+/// # assert_eq!(compressed, deflate::try_deflate_bytes_zlib(&[1, 2, 3, 4]).unwrap());
+/// ```
+pub fn write_strided<W: Write + ?Sized>(
+    writer: &mut W,
+    data: &[u8],
+    row_len: usize,
+    stride: usize,
+    rows: usize,
+) -> io::Result<()> {
+    // Empty rows don't need any input, whatever the stride is.
+    if rows == 0 || row_len == 0 {
+        return Ok(());
+    }
+    if stride < row_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "stride is smaller than the row length",
+        ));
+    }
+    let needed = (rows - 1)
+        .checked_mul(stride)
+        .and_then(|n| n.checked_add(row_len));
+    match needed {
+        Some(n) if n <= data.len() => (),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "input is too short for the given dimensions",
+            ))
+        }
+    }
+    for row in data.chunks(stride).take(rows) {
+        writer.write_all(&row[..row_len])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compression_options::CompressionOptions;
+    use crate::test_utils::{decompress_to_end, decompress_zlib, get_test_data, noise};
+    use std::cell::Cell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    #[test]
+    fn deflate_writer() {
+        let data = get_test_data();
+        let compressed = {
+            let mut compressor = DeflateEncoder::new(
+                Vec::with_capacity(data.len() / 3),
+                CompressionOptions::high(),
+            );
+            // Write in multiple steps to see if this works as it's supposed to.
+            compressor.write_all(&data[0..data.len() / 2]).unwrap();
+            compressor.write_all(&data[data.len() / 2..]).unwrap();
+            compressor.finish().unwrap()
+        };
+
+        let res = decompress_to_end(&compressed);
+        assert!(res == data);
+    }
+
+    #[test]
+    fn zlib_writer() {
+        let data = get_test_data();
+        let compressed = {
+            let mut compressor = ZlibEncoder::new(
+                Vec::with_capacity(data.len() / 3),
+                CompressionOptions::high(),
+            );
+            compressor.write_all(&data[0..data.len() / 2]).unwrap();
+            compressor.write_all(&data[data.len() / 2..]).unwrap();
+            compressor.finish().unwrap()
+        };
+
+        let res = decompress_zlib(&compressed);
+        assert!(res == data);
+    }
+
+    #[test]
+    fn zlib_flush_before_write() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.flush().unwrap();
+        compressor.write_all(&data).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_zlib(&compressed) == data);
+    }
+
+    #[test]
+    /// Check if the result of compressing after resetting is the same as before.
+    fn writer_reset() {
+        let data = get_test_data();
+        let mut compressor = DeflateEncoder::new(
+            Vec::with_capacity(data.len() / 3),
+            CompressionOptions::default(),
+        );
+        compressor.write_all(&data).unwrap();
+        let res1 = compressor
+            .reset(Vec::with_capacity(data.len() / 3))
+            .unwrap();
+        compressor.write_all(&data).unwrap();
+        let res2 = compressor.finish().unwrap();
+        assert!(res1 == res2);
+    }
+
+    #[test]
+    fn writer_reset_reuses_buffers() {
+        let data = get_test_data();
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        let input_buffer = compressor.deflate_state.input_buffer.get_buffer().as_ptr();
+        for message in data.chunks(5000).take(20) {
+            compressor.write_all(message).unwrap();
+            let compressed = compressor.reset(Vec::new()).unwrap();
+            assert!(
+                compressed
+                    == crate::try_deflate_bytes_conf(message, CompressionOptions::default())
+                        .unwrap()
+            );
+            assert_eq!(
+                compressor.deflate_state.input_buffer.get_buffer().as_ptr(),
+                input_buffer
+            );
+        }
+    }
+
+    #[test]
+    fn writer_reset_zlib() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(
+            Vec::with_capacity(data.len() / 3),
+            CompressionOptions::default(),
+        );
+        compressor.write_all(&data).unwrap();
+        let res1 = compressor
+            .reset(Vec::with_capacity(data.len() / 3))
+            .unwrap();
+        compressor.write_all(&data).unwrap();
+        let res2 = compressor.finish().unwrap();
+        assert!(res1 == res2);
+    }
+
+    #[test]
+    fn writer_sync() {
+        let data = get_test_data();
+        let compressed = {
+            let mut compressor = DeflateEncoder::new(
+                Vec::with_capacity(data.len() / 3),
+                CompressionOptions::default(),
+            );
+            let split = data.len() / 2;
+            compressor.write_all(&data[..split]).unwrap();
+            compressor.flush().unwrap();
+            {
+                let buf = &mut compressor.deflate_state.inner.as_mut().unwrap();
+                let buf_len = buf.len();
+                // Check for the sync marker. (excluding the header as it might not line
+                // up with the byte boundary.)
+                assert_eq!(buf[buf_len - 4..], [0, 0, 255, 255]);
+            }
+            compressor.write_all(&data[split..]).unwrap();
+            compressor.finish().unwrap()
+        };
+
+        let decompressed = decompress_to_end(&compressed);
+
+        assert!(decompressed == data);
+    }
+
+    #[test]
+    fn finish_after_sync() {
+        let data = get_test_data();
+        for &len in &[0, 10, data.len()] {
+            let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data[..len]).unwrap();
+            compressor.flush().unwrap();
+            let compressed = compressor.finish().unwrap();
+            // The stream should end with the sync marker followed by an empty fixed block with
+            // the final block flag set.
+            assert_eq!(compressed[compressed.len() - 6..], [0, 0, 255, 255, 3, 0]);
+            assert!(decompress_to_end(&compressed) == &data[..len]);
+        }
+    }
+
+    #[test]
+    fn options_and_try_finish() {
+        let data = get_test_data();
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::fast());
+        compressor.set_auto_flush_after(1000);
+        assert_eq!(compressor.options().auto_flush_after, 1000);
+        assert_eq!(
+            compressor.options().max_hash_checks,
+            CompressionOptions::fast().max_hash_checks
+        );
+        compressor.write_all(&data).unwrap();
+        assert!(!compressor.is_finished());
+        compressor.try_finish().unwrap();
+        assert!(compressor.is_finished());
+        compressor.try_finish().unwrap();
+        compressor.flush().unwrap();
+        assert!(compressor.write(&data[..10]).is_err());
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        compressor.try_finish().unwrap();
+        assert!(compressor.is_finished());
+        assert!(compressor.write(&data[..10]).is_err());
+        // The trailer should only be written once.
+        let compressed = compressor.reset(Vec::new()).unwrap();
+        assert!(!compressor.is_finished());
+        assert!(decompress_zlib(&compressed) == data);
+        compressor.write_all(&data).unwrap();
+        assert!(compressor.finish().unwrap() == compressed);
+    }
+
+    #[test]
+    fn replace_writer() {
+        let data = get_test_data();
+        let split = data.len() / 3;
+        for &flush in &[false, true] {
+            let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data[..split]).unwrap();
+            if flush {
+                compressor.flush().unwrap();
+            }
+            let mut compressed = compressor.replace_writer(Vec::new()).unwrap();
+            if flush {
+                assert_eq!(compressed[compressed.len() - 4..], [0, 0, 255, 255]);
+            }
+            compressor.write_all(&data[split..]).unwrap();
+            compressed.extend(compressor.finish().unwrap());
+            assert!(decompress_zlib(&compressed) == data);
+        }
+    }
+
+    #[test]
+    fn write_stored_chunk() {
+        let data = get_test_data();
+        // Pseudo-random bytes standing in for already compressed data, long enough to need
+        // several stored blocks.
+        let payload = noise(100_000);
+        let mut expected = data[..1000].to_vec();
+        expected.extend_from_slice(&payload);
+        expected.extend_from_slice(&data);
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data[..1000]).unwrap();
+        compressor.write_stored_chunk(&payload).unwrap();
+        compressor.write_stored_chunk(&[]).unwrap();
+        compressor.write_all(&data).unwrap();
+        assert_eq!(compressor.total_in(), expected.len() as u64);
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_zlib(&compressed) == expected);
+        // The payload is copied to the output as-is.
+        let first = &payload[..MAX_STORED_BLOCK_LENGTH];
+        assert!(compressed.windows(first.len()).any(|w| w == first));
+
+        // Writing stored data first, or last, works as well.
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_stored_chunk(&data[..10]).unwrap();
+        compressor.write_all(&data[10..20]).unwrap();
+        compressor.write_stored_chunk(&data[20..30]).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data[..30]);
+
+        // The data written afterwards can refer back to the stored data.
+        let text = &data[..20_000];
+        for &level in &[0, 1, 3, 6, 9, 10] {
+            let options = CompressionOptions::from_level(level);
+            let mut compressor = DeflateEncoder::new(Vec::new(), options);
+            compressor.write_all(&payload[..50]).unwrap();
+            compressor.write_stored_chunk(&payload).unwrap();
+            compressor.write_stored_chunk(text).unwrap();
+            compressor.write_all(text).unwrap();
+            let compressed = compressor.finish().unwrap();
+            let stored_len = payload.len() + text.len();
+            if level > 0 {
+                assert!(compressed.len() < stored_len + text.len() / 10);
+            }
+            let mut expected = payload[..50].to_vec();
+            expected.extend_from_slice(&payload);
+            expected.extend_from_slice(text);
+            expected.extend_from_slice(text);
+            assert!(decompress_to_end(&compressed) == expected);
+        }
+    }
+
+    #[test]
+    fn pad_to() {
+        let data = get_test_data();
+        for &len in &[0, 1, 1000, 20_000] {
+            let input = &data[..len];
+            for &alignment in &[0, 1, 2, 3, 4, 5, 6, 7, 8, 13, 512, 4096] {
+                let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+                compressor.pad_to(alignment);
+                compressor.write_all(input).unwrap();
+                let (compressed, report) = compressor.finish_with_report().unwrap();
+                assert_eq!(compressed.len() as u64 % cmp::max(alignment, 1), 0);
+                assert!(decompress_to_end(&compressed) == input);
+                // The padding is made up of blocks, which are counted like any other.
+                assert!(report.padding_bits() < 8);
+
+                let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+                compressor.pad_to(alignment);
+                compressor.write_all(input).unwrap();
+                compressor.flush().unwrap();
+                let compressed = compressor.finish().unwrap();
+                assert_eq!(compressed.len() as u64 % cmp::max(alignment, 1), 0);
+                assert!(decompress_zlib(&compressed) == input);
+            }
+        }
+    }
+
+    #[test]
+    fn finish_deferred() {
+        let data = get_test_data();
+        for &split in &[0, 1, 1000, data.len() / 2] {
+            let (first, second) = data.split_at(split);
+            let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(first).unwrap();
+            let (mut compressed, end) = compressor.finish_deferred().unwrap();
+            assert_eq!(end.total_in(), split as u64);
+            assert_eq!(end.total_out(), compressed.len() as u64);
+            let end = DeferredEnd::from_bytes(&end.to_bytes()).unwrap();
+            let mut compressor =
+                DeflateEncoder::resume(Vec::new(), CompressionOptions::default(), &end);
+            compressor.write_all(second).unwrap();
+            assert_eq!(compressor.total_in(), data.len() as u64);
+            compressed.extend(compressor.finish().unwrap());
+            assert!(decompress_to_end(&compressed) == data);
+
+            let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(first).unwrap();
+            let (mut compressed, end) = compressor.finish_deferred().unwrap();
+            let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(first).unwrap();
+            assert_eq!(end.checksum(), compressor.checksum());
+            let mut compressor =
+                ZlibEncoder::resume(Vec::new(), CompressionOptions::default(), &end);
+            compressor.write_all(second).unwrap();
+            compressed.extend(compressor.finish().unwrap());
+            assert!(decompress_zlib(&compressed) == data);
+        }
+
+        // The stream can't be ended again once finished.
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.try_finish().unwrap();
+        assert!(compressor.finish_deferred().is_err());
+    }
+
+    #[cfg(feature = "timing")]
+    #[test]
+    fn stage_timings() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        let (_, stats) = compressor.finish_with_stats().unwrap();
+        let timings = stats.timings;
+        assert!(timings.lz77 > Default::default());
+        assert!(timings.huffman > Default::default());
+        assert!(timings.bitstream > Default::default());
+        assert!(timings.checksum > Default::default());
+        assert_eq!(
+            timings.total(),
+            timings.lz77 + timings.huffman + timings.bitstream + timings.checksum
+        );
+
+        compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        compressor.reset(Vec::new()).unwrap();
+        assert_eq!(compressor.stats().timings.total(), Default::default());
+    }
+
+    #[test]
+    fn input_size_hint() {
+        let data = get_test_data();
+        for &len in &[0, 10, 1000, data.len()] {
+            let input = &data[..len];
+            let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+            // Nothing should be allocated for the output before the hint is known.
+            assert_eq!(compressor.deflate_state.output_buf().capacity(), 0);
+            compressor.set_input_size_hint(len as u64);
+            assert!(compressor.deflate_state.output_buf().capacity() <= len + len / 1024 + 64);
+            compressor.write_all(input).unwrap();
+            let compressed = compressor.finish().unwrap();
+            assert!(
+                compressed
+                    == crate::try_deflate_bytes_conf(input, CompressionOptions::default()).unwrap()
+            );
+        }
+
+        // A wrong hint is fine.
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_input_size_hint(5);
+        compressor.write_all(&data).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_zlib(&compressed) == data);
+    }
+
+    #[test]
+    fn repeated_sync() {
+        let data = get_test_data();
+        let compress = |flushes| {
+            let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.write_all(&data[..1000]).unwrap();
+            for _ in 0..flushes {
+                compressor.flush().unwrap();
+            }
+            compressor.write_all(&data[1000..]).unwrap();
+            compressor.flush().unwrap();
+            compressor.flush().unwrap();
+            compressor.finish().unwrap()
+        };
+        let once = compress(1);
+        assert!(once == compress(5));
+        assert!(decompress_to_end(&once) == data);
+
+        // Flushing at the start of a stream still outputs a sync marker.
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.flush().unwrap();
+        compressor.flush().unwrap();
+        let out = compressor.deflate_state.inner.as_ref().unwrap();
+        assert_eq!(out[..], [2, 0, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn bit_position() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        let mut last = compressor.bit_position();
+        for chunk in data.chunks(5000) {
+            compressor.write_all(chunk).unwrap();
+            let position = compressor.bit_position();
+            assert!(position >= last);
+            compressor.flush().unwrap();
+            // After a sync flush everything has been output, ending on a byte boundary.
+            let position = compressor.bit_position();
+            assert_eq!(
+                position,
+                compressor.deflate_state.inner.as_ref().unwrap().len() as u64 * 8
+            );
+            last = position;
+        }
+
+        compress_until_done(&[], &mut compressor.deflate_state, Flush::Finish).unwrap();
+        let bits = compressor.bit_position();
+        let compressed = compressor.finish().unwrap();
+        // The trailer is written after the compressed data.
+        assert_eq!(bits, (compressed.len() as u64 - ZLIB_TRAILER_BYTES) * 8);
+    }
+
+    #[test]
+    fn total_in_out() {
+        let data = get_test_data();
+        let mut deflate = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        let mut zlib = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        for chunk in data.chunks(5000) {
+            deflate.write_all(chunk).unwrap();
+            zlib.write_all(chunk).unwrap();
+            deflate.flush().unwrap();
+            zlib.flush().unwrap();
+            assert_eq!(deflate.total_out(), deflate.writer_mut().len() as u64);
+            assert_eq!(zlib.total_out(), zlib.writer_mut().len() as u64);
+        }
+        assert_eq!(deflate.total_in(), data.len() as u64);
+        assert_eq!(zlib.total_in(), data.len() as u64);
+
+        deflate.try_finish().unwrap();
+        zlib.try_finish().unwrap();
+        assert_eq!(deflate.total_out(), deflate.writer_mut().len() as u64);
+        assert_eq!(zlib.total_out(), zlib.writer_mut().len() as u64);
+
+        zlib.reset(Vec::new()).unwrap();
+        assert_eq!(zlib.total_out(), 0);
+    }
+
+    #[test]
+    fn full_flush() {
+        let data = get_test_data();
+        let (first, second) = data.split_at(20_000);
+        // The second part repeats the first, so it would refer back to it without the flush.
+        let second = [first, second].concat();
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(first).unwrap();
+        compressor.full_flush().unwrap();
+        let restart = compressor.writer_mut().len();
+        compressor.write_all(&second).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_zlib(&compressed) == [first, &second[..]].concat());
+
+        // The data after the flush decompresses on its own, as a raw stream followed by the
+        // zlib trailer.
+        let rest = &compressed[restart..compressed.len() - ZLIB_TRAILER_BYTES as usize];
+        assert!(decompress_to_end(rest) == second);
+
+        // The output only differs from a sync flush in not referring back.
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(first).unwrap();
+        compressor.flush().unwrap();
+        let sync_flushed = compressor.writer_mut().clone();
+        compressor.full_flush().unwrap();
+        assert!(*compressor.writer_mut() == sync_flushed);
+    }
+
+    #[test]
+    fn partial_flush() {
+        use miniz_oxide::inflate::stream::{inflate, InflateState};
+        use miniz_oxide::{DataFormat, MZFlush};
+
+        let data = get_test_data();
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        let mut inflater = InflateState::new_boxed(DataFormat::Raw);
+        let mut decompressed = Vec::new();
+        let mut consumed = 0;
+        let mut written = 0;
+        let mut held_back = false;
+        for chunk in data.chunks(7_000) {
+            compressor.write_all(chunk).unwrap();
+            compressor.partial_flush().unwrap();
+            written += chunk.len();
+            // The output isn't padded, so the last bits of the empty block may be held back.
+            held_back |= compressor.bit_position() > compressor.total_out() * 8;
+            // The bytes output so far are enough to decompress everything written so far.
+            let output = compressor.writer_mut().clone();
+            let mut out = [0; 4096];
+            loop {
+                let res = inflate(&mut inflater, &output[consumed..], &mut out, MZFlush::None);
+                consumed += res.bytes_consumed;
+                decompressed.extend_from_slice(&out[..res.bytes_written]);
+                if res.bytes_written == 0 {
+                    break;
+                }
+            }
+            assert!(decompressed == data[..written]);
+            // Flushing again without writing anything doesn't add another empty block.
+            let position = compressor.bit_position();
+            compressor.partial_flush().unwrap();
+            assert_eq!(compressor.bit_position(), position);
+        }
+        assert!(held_back);
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+    }
+
+    #[test]
+    fn block_flush() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        let mut block_ends = Vec::new();
+        for chunk in data.chunks(10_000) {
+            compressor.write_all(chunk).unwrap();
+            compressor.block_flush().unwrap();
+            block_ends.push(compressor.bit_position());
+            // Only whole bytes are output.
+            assert_eq!(compressor.total_out(), compressor.bit_position() / 8);
+        }
+        // The blocks aren't padded, so at least one of them ends in the middle of a byte.
+        assert!(block_ends.iter().any(|&end| end % 8 != 0));
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_zlib(&compressed) == data);
+    }
+
+    /// Test data xored with pseudo-random noise, so it doesn't compress well.
+    fn scrambled_test_data() -> Vec<u8> {
+        let data = get_test_data();
+        let noise = noise(data.len());
+        data.iter().zip(noise).map(|(&a, b)| a ^ b).collect()
+    }
+
+    /// A writer that fails with `WouldBlock` on every other call while blocking is enabled, and
+    /// otherwise accepts only a limited amount of data per call.
+    struct NonBlockingWriter {
+        data: Vec<u8>,
+        calls: usize,
+        blocking: Rc<Cell<bool>>,
+    }
+
+    impl Write for NonBlockingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.blocking.get() && self.calls % 2 == 1 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(1000);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn retry(e: io::Error) {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => (),
+            _ => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    /// Write all of `data` to `writer`, retrying calls that fail with `WouldBlock`.
+    fn write_retrying<W: Write>(writer: &mut W, data: &[u8]) {
+        for (n, mut chunk) in data.chunks(10000).enumerate() {
+            while !chunk.is_empty() {
+                match writer.write(chunk) {
+                    Ok(n) => chunk = &chunk[n..],
+                    Err(e) => retry(e),
+                }
+            }
+            if n % 8 == 7 {
+                while let Err(e) = writer.flush() {
+                    retry(e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn would_block_writer() {
+        let data = scrambled_test_data();
+        let blocking = Rc::new(Cell::new(true));
+        let writer = || NonBlockingWriter {
+            data: Vec::new(),
+            calls: 0,
+            blocking: blocking.clone(),
+        };
+
+        let mut compressor = DeflateEncoder::new(writer(), CompressionOptions::default());
+        write_retrying(&mut compressor, &data);
+        blocking.set(false);
+        let res = compressor.finish().unwrap();
+        assert!(decompress_to_end(&res.data) == data);
+
+        blocking.set(true);
+        let mut compressor = ZlibEncoder::new(writer(), CompressionOptions::default());
+        write_retrying(&mut compressor, &data);
+        blocking.set(false);
+        let res = compressor.finish().unwrap();
+        // This checks the adler32 checksum too.
+        assert!(decompress_zlib(&res.data) == data);
+    }
+
+    #[test]
+    fn would_block_on_flush() {
+        let data = &get_test_data()[..10_000];
+        for &(partial, retry_flush) in &[(true, true), (false, true), (true, false), (false, false)]
+        {
+            let blocking = Rc::new(Cell::new(false));
+            let writer = NonBlockingWriter {
+                data: Vec::new(),
+                calls: 0,
+                blocking: blocking.clone(),
+            };
+            let mut compressor = DeflateEncoder::new(writer, CompressionOptions::default());
+            compressor.write_all(data).unwrap();
+            // Make the first write of the flush fail, so the output of the flush is left in the
+            // buffer for the retry, or for `finish` if the flush isn't retried.
+            blocking.set(true);
+            compressor.writer_mut().calls = 0;
+            loop {
+                let res = if partial {
+                    compressor.partial_flush()
+                } else {
+                    compressor.block_flush()
+                };
+                match res {
+                    Ok(()) => break,
+                    Err(e) => retry(e),
+                }
+                if !retry_flush {
+                    break;
+                }
+            }
+            blocking.set(false);
+            let res = compressor.finish().unwrap();
+            assert!(decompress_to_end(&res.data) == data);
+        }
+    }
+
+    /// A writer that returns `Interrupted` a given number of times before accepting data.
+    struct InterruptingWriter {
+        data: Vec<u8>,
+        interrupts: usize,
+    }
+
+    impl Write for InterruptingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.interrupts > 0 {
+                self.interrupts -= 1;
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::compression_options::CompressionOptions;
-    use crate::test_utils::{decompress_to_end, decompress_zlib, get_test_data};
-    use std::io::Write;
+    #[test]
+    fn interrupted_retries() {
+        let data = b"Some data that fits in the buffer";
+        let compress = |interrupts, max_retries| {
+            let writer = InterruptingWriter {
+                data: Vec::new(),
+                interrupts,
+            };
+            let mut compressor = DeflateEncoder::new(writer, CompressionOptions::default());
+            compressor.set_max_interrupted_retries(max_retries);
+            compressor.write_all(data).unwrap();
+            compressor.finish().map(|w| w.data)
+        };
+
+        assert!(decompress_to_end(&compress(10, Some(10)).unwrap()) == &data[..]);
+        assert!(decompress_to_end(&compress(5000, None).unwrap()) == &data[..]);
+        let err = compress(11, Some(10)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        let err = compress(usize::max_value(), Some(10)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    /// A writer that counts the bytes written to it, failing once `limit` is reached.
+    struct LimitedWriter {
+        written: Rc<Cell<usize>>,
+        limit: usize,
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.limit.saturating_sub(self.written.get()));
+            if n == 0 && !buf.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Limit reached"));
+            }
+            self.written.set(self.written.get() + n);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
-    fn deflate_writer() {
+    fn finish_on_drop() {
         let data = get_test_data();
-        let compressed = {
-            let mut compressor = DeflateEncoder::new(
-                Vec::with_capacity(data.len() / 3),
-                CompressionOptions::high(),
-            );
-            // Write in multiple steps to see if this works as it's supposed to.
-            compressor.write_all(&data[0..data.len() / 2]).unwrap();
-            compressor.write_all(&data[data.len() / 2..]).unwrap();
-            compressor.finish().unwrap()
+        let written = Rc::new(Cell::new(0));
+        let writer = |limit| LimitedWriter {
+            written: written.clone(),
+            limit,
         };
 
-        let res = decompress_to_end(&compressed);
-        assert!(res == data);
+        {
+            let mut compressor =
+                DeflateEncoder::new(writer(usize::max_value()), CompressionOptions::fast());
+            compressor.write_all(&data[..1000]).unwrap();
+        }
+        assert!(written.get() > 0);
+
+        written.set(0);
+        {
+            let mut compressor =
+                ZlibEncoder::new(writer(usize::max_value()), CompressionOptions::fast());
+            compressor.set_finish_on_drop(false);
+            compressor.write_all(&data[..1000]).unwrap();
+        }
+        assert_eq!(written.get(), 0);
+
+        let mut compressor = DeflateEncoder::new(writer(10), CompressionOptions::fast());
+        compressor.write_all(&data[..1000]).unwrap();
+        assert!(compressor.last_error().is_none());
+        assert!(compressor.flush().is_err());
+        assert_eq!(
+            compressor.last_error().map(|e| e.kind()),
+            Some(io::ErrorKind::BrokenPipe)
+        );
     }
 
     #[test]
-    fn zlib_writer() {
+    fn poisoned_after_error() {
         let data = get_test_data();
-        let compressed = {
-            let mut compressor = ZlibEncoder::new(
-                Vec::with_capacity(data.len() / 3),
-                CompressionOptions::high(),
-            );
-            compressor.write_all(&data[0..data.len() / 2]).unwrap();
-            compressor.write_all(&data[data.len() / 2..]).unwrap();
-            compressor.finish().unwrap()
+        let written = Rc::new(Cell::new(0));
+        let writer = |limit| LimitedWriter {
+            written: written.clone(),
+            limit,
         };
 
-        let res = decompress_zlib(&compressed);
-        assert!(res == data);
+        let mut compressor = ZlibEncoder::new(writer(10), CompressionOptions::default());
+        compressor.write_all(&data[..1000]).unwrap();
+        assert!(compressor.flush().is_err());
+        let n = written.get();
+
+        // Nothing more is output once the stream is poisoned.
+        let err = compressor.write(&data[1000..2000]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            compressor.flush().unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(written.get(), n);
+
+        // Resetting gives a working encoder again.
+        compressor.reset(writer(usize::max_value())).unwrap();
+        assert!(compressor.last_error().is_none());
+        written.set(0);
+        compressor.write_all(&data).unwrap();
+        compressor.finish().unwrap();
+        assert!(written.get() > 0);
+
+        let compressor = DeflateEncoder::new(writer(0), CompressionOptions::default());
+        assert!(compressor.finish().is_err());
     }
 
     #[test]
-    /// Check if the result of compressing after resetting is the same as before.
-    fn writer_reset() {
+    fn block_stats() {
         let data = get_test_data();
-        let mut compressor = DeflateEncoder::new(
-            Vec::with_capacity(data.len() / 3),
-            CompressionOptions::default(),
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        compressor.output_all().unwrap();
+        let stats = compressor.stats();
+        assert!(stats.dynamic.count > 0);
+        assert_eq!(stats.total_input_bytes(), data.len() as u64);
+        let output_len = compressor.deflate_state.bytes_flushed();
+        assert_eq!((stats.total_output_bits() + 7) / 8, output_len);
+
+        compressor.reset(Vec::new()).unwrap();
+        assert_eq!(compressor.stats(), CompressionStats::default());
+    }
+
+    /// Checksum that just sums the bytes, for testing custom checksums.
+    struct ByteSum(u32);
+
+    impl RollingChecksum for ByteSum {
+        fn update_from_slice(&mut self, data: &[u8]) {
+            for &byte in data {
+                self.0 = self.0.wrapping_add(u32::from(byte));
+            }
+        }
+
+        fn current_hash(&self) -> u32 {
+            self.0
+        }
+
+        fn reset(&mut self) {
+            self.0 = 0;
+        }
+    }
+
+    #[test]
+    fn custom_checksum() {
+        let data = get_test_data();
+        let expected = data
+            .iter()
+            .fold(0u32, |sum, &byte| sum.wrapping_add(u32::from(byte)));
+        let mut compressor =
+            ZlibEncoder::with_checksum(Vec::new(), CompressionOptions::default(), ByteSum(0));
+        compressor.write_all(&data[..1000]).unwrap();
+        compressor.write_all(&data[1000..]).unwrap();
+        assert_eq!(compressor.checksum(), expected);
+        let compressed = compressor.finish().unwrap();
+        assert_eq!(compressed[compressed.len() - 4..], expected.to_be_bytes());
+        let decompressed =
+            miniz_oxide::inflate::decompress_to_vec(&compressed[2..compressed.len() - 4]).unwrap();
+        assert!(decompressed == data);
+    }
+
+    #[test]
+    fn checksum_while_writing() {
+        let data = get_test_data();
+        for &flush_after in &[0, 3000] {
+            let mut options = CompressionOptions::default();
+            options.auto_flush_after = flush_after;
+            let mut compressor = ZlibEncoder::new(Vec::new(), options);
+            let mut written = 0;
+            for chunk in data.chunks(7000) {
+                compressor.write_all(chunk).unwrap();
+                written += chunk.len();
+                assert_eq!(
+                    compressor.checksum(),
+                    crate::checksum::update_adler32(1, &data[..written])
+                );
+            }
+            let compressed = compressor.finish().unwrap();
+            assert!(decompress_zlib(&compressed) == data);
+        }
+    }
+
+    #[test]
+    fn deflate_encoder_checksum() {
+        let data = get_test_data();
+        let mut compressor =
+            DeflateEncoder::with_checksum(Vec::new(), CompressionOptions::default(), ByteSum(0));
+        compressor.write_all(&data[..5000]).unwrap();
+        compressor.reset(Vec::new()).unwrap();
+        assert_eq!(compressor.checksum(), 0);
+        compressor.write_all(&data).unwrap();
+        assert_eq!(
+            compressor.checksum(),
+            data.iter()
+                .fold(0u32, |sum, &byte| sum.wrapping_add(u32::from(byte)))
         );
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+    }
+
+    #[test]
+    fn precomputed_checksum() {
+        let data = get_test_data();
+        let expected = crate::try_deflate_bytes_zlib(&data).unwrap();
+        let checksum = crate::checksum::update_adler32(1, &data);
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_precomputed_checksum(checksum);
         compressor.write_all(&data).unwrap();
-        let res1 = compressor
-            .reset(Vec::with_capacity(data.len() / 3))
-            .unwrap();
+        assert_eq!(compressor.checksum(), checksum);
+        assert!(compressor.finish().unwrap() == expected);
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_precomputed_checksum(12345);
         compressor.write_all(&data).unwrap();
-        let res2 = compressor.finish().unwrap();
-        assert!(res1 == res2);
+        compressor.reset(Vec::new()).unwrap();
+        compressor.write_all(&data).unwrap();
+        assert!(compressor.finish().unwrap() == expected);
     }
 
     #[test]
-    fn writer_reset_zlib() {
+    fn strided_input() {
         let data = get_test_data();
-        let mut compressor = ZlibEncoder::new(
-            Vec::with_capacity(data.len() / 3),
-            CompressionOptions::default(),
+        let (row_len, stride, rows) = (300, 512, data.len() / 512);
+        let contiguous: Vec<u8> = data
+            .chunks(stride)
+            .take(rows)
+            .flat_map(|row| row[..row_len].iter().cloned())
+            .collect();
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        write_strided(&mut compressor, &data, row_len, stride, rows).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(compressed == crate::try_deflate_bytes_zlib(&contiguous).unwrap());
+
+        // The last row doesn't need padding after it.
+        let end = (rows - 1) * stride + row_len;
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        write_strided(&mut compressor, &data[..end], row_len, stride, rows).unwrap();
+        assert!(decompress_to_end(&compressor.finish().unwrap()) == contiguous);
+
+        let mut out = Vec::new();
+        let err = write_strided(&mut out, &data[..end - 1], row_len, stride, rows).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = write_strided(&mut out, &data, stride + 1, stride, rows).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = write_strided(&mut out, &data, row_len, 0, rows).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        write_strided(&mut out, &[], 0, 0, rows).unwrap();
+        write_strided(&mut out, &data, 0, stride, rows).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn level_hint() {
+        let data = get_test_data();
+        let expected = crate::try_deflate_bytes_zlib(&data).unwrap();
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_level_hint(CompressionLevel::Maximum);
+        compressor.write_all(&data).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert_eq!(compressed[1] >> 6, 3);
+        assert_eq!(
+            (u16::from(compressed[0]) << 8 | u16::from(compressed[1])) % 31,
+            0
+        );
+        assert!(compressed[2..] == expected[2..]);
+        assert!(decompress_zlib(&compressed) == data);
+    }
+
+    #[test]
+    fn zlib_dictionary() {
+        use crate::inflate::inflate_bytes_zlib_dict;
+
+        let data = get_test_data();
+        let (dictionary, data) = data.split_at(50_000);
+        let data = &data[..10_000];
+        let mut encoder =
+            ZlibEncoder::new_with_dict(Vec::new(), CompressionOptions::default(), dictionary);
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.reset(Vec::new()).unwrap();
+        assert!(
+            compressed
+                == crate::try_deflate_bytes_zlib_dict(
+                    data,
+                    dictionary,
+                    CompressionOptions::default()
+                )
+                .unwrap()
         );
+        assert!(inflate_bytes_zlib_dict(&compressed, dictionary).unwrap() == data);
+
+        // The dictionary is kept after resetting.
+        encoder.write_all(&data[..5000]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&data[5000..]).unwrap();
+        let (compressed, report) = encoder.finish_with_report().unwrap();
+        assert_eq!(
+            report.header_bytes,
+            ZLIB_HEADER_BYTES + ZLIB_DICTIONARY_ID_BYTES
+        );
+        assert!(inflate_bytes_zlib_dict(&compressed, dictionary).unwrap() == data);
+    }
+
+    #[test]
+    fn zlib_without_header_or_trailer() {
+        let data = get_test_data();
+        let expected = crate::try_deflate_bytes_zlib(&data).unwrap();
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_write_header(false);
         compressor.write_all(&data).unwrap();
-        let res1 = compressor
-            .reset(Vec::with_capacity(data.len() / 3))
-            .unwrap();
+        let (compressed, report) = compressor.finish_with_report().unwrap();
+        assert!(compressed[..] == expected[2..]);
+        assert_eq!(report.header_bytes, 0);
+        assert_eq!(report.trailer_bytes, ZLIB_TRAILER_BYTES);
+
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.set_write_trailer(false);
         compressor.write_all(&data).unwrap();
-        let res2 = compressor.finish().unwrap();
-        assert!(res1 == res2);
+        compressor.reset(Vec::new()).unwrap();
+        compressor.write_all(&data).unwrap();
+        let (compressed, report) = compressor.finish_with_report().unwrap();
+        assert!(compressed[..] == expected[..expected.len() - 4]);
+        assert_eq!(report.header_bytes, ZLIB_HEADER_BYTES);
+        assert_eq!(report.trailer_bytes, 0);
+        assert_eq!(report.output_bytes, compressed.len() as u64);
     }
 
     #[test]
-    fn writer_sync() {
+    fn finish_with_report() {
+        use crate::compress::BlockKind;
+
         let data = get_test_data();
-        let compressed = {
-            let mut compressor = DeflateEncoder::new(
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        let (compressed, report) = compressor.finish_with_report().unwrap();
+        assert_eq!(report.header_bytes, 2);
+        assert_eq!(report.trailer_bytes, 4);
+        assert_eq!(report.input_bytes, data.len() as u64);
+        assert_eq!(report.output_bytes, compressed.len() as u64);
+        assert!(report.padding_bits() < 8);
+        assert!(report.table_bits() > 0 && report.table_bits() < report.data_bits());
+        assert!(report.ratio_for(BlockKind::Dynamic).unwrap() < 1.0);
+        assert_eq!(report.ratio_for(BlockKind::Stored), None);
+
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        compressor.flush().unwrap();
+        let (compressed, report) = compressor.finish_with_report().unwrap();
+        assert_eq!(report.wrapper_bytes(), 0);
+        assert_eq!(report.output_bytes, compressed.len() as u64);
+        // The header of the empty stored block from the sync flush takes up at least 32 bits.
+        assert!(report.stats.stored.header_bits >= 32);
+        assert_eq!(report.stats.stored.data_bits(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "block-offsets")]
+    fn block_offsets() {
+        let data = get_test_data();
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data[..data.len() / 2]).unwrap();
+        compressor.flush().unwrap();
+        assert!(!compressor.block_offsets().is_empty());
+        compressor.write_all(&data[data.len() / 2..]).unwrap();
+        let (compressed, offsets) = compressor.finish_with_block_offsets().unwrap();
+
+        assert!(offsets.len() > 1);
+        assert_eq!(offsets[0], 16);
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(*offsets.last().unwrap() < compressed.len() as u64 * 8);
+
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data).unwrap();
+        compressor.reset(Vec::new()).unwrap();
+        assert!(compressor.block_offsets().is_empty());
+    }
+
+    #[test]
+    fn block_listener() {
+        use crate::compress::BlockInfo;
+        use std::sync::{Arc, Mutex};
+
+        let data = get_test_data();
+        let blocks = Arc::new(Mutex::new(Vec::new()));
+        let mut compressor = ZlibEncoder::new(Vec::new(), CompressionOptions::default());
+        {
+            let blocks = blocks.clone();
+            compressor.set_block_listener(move |info: &BlockInfo| {
+                blocks.lock().unwrap().push(*info);
+            });
+        }
+        compressor.write_all(&data).unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let blocks = blocks.lock().unwrap();
+        assert!(blocks.len() > 1);
+        // The first block starts right after the 2-byte zlib header.
+        assert_eq!(blocks[0].start_bit, 16);
+        let mut input_pos = 0;
+        for pair in blocks.windows(2) {
+            assert_eq!(pair[0].end_bit, pair[1].start_bit);
+        }
+        for block in blocks.iter() {
+            assert_eq!(block.input_start, input_pos);
+            input_pos += block.input_len;
+        }
+        assert_eq!(input_pos, data.len() as u64);
+        let last = blocks.last().unwrap();
+        assert!(last.final_block);
+        // The stream ends with padding and the 4-byte checksum.
+        assert_eq!((last.end_bit + 7) / 8 + 4, compressed.len() as u64);
+    }
+
+    #[test]
+    fn any_encoder() {
+        let data = get_test_data();
+        let compress = |format| {
+            let mut compressor = AnyEncoder::new(
                 Vec::with_capacity(data.len() / 3),
+                format,
                 CompressionOptions::default(),
             );
-            let split = data.len() / 2;
-            compressor.write_all(&data[..split]).unwrap();
-            compressor.flush().unwrap();
-            {
-                let buf = &mut compressor.deflate_state.inner.as_mut().unwrap();
-                let buf_len = buf.len();
-                // Check for the sync marker. (excluding the header as it might not line
-                // up with the byte boundary.)
-                assert_eq!(buf[buf_len - 4..], [0, 0, 255, 255]);
-            }
-            compressor.write_all(&data[split..]).unwrap();
+            assert_eq!(compressor.format(), format);
+            compressor.write_all(&data).unwrap();
             compressor.finish().unwrap()
         };
 
-        let decompressed = decompress_to_end(&compressed);
+        assert!(decompress_to_end(&compress(Format::Raw)) == data);
+        assert!(decompress_zlib(&compress(Format::Zlib)) == data);
+        #[cfg(feature = "gzip")]
+        {
+            let (_, res) = crate::test_utils::decompress_gzip(&compress(Format::Gzip));
+            assert!(res == data);
+        }
+    }
 
-        assert!(decompressed == data);
+    #[test]
+    fn block_type_selector() {
+        use crate::compress::{BlockCosts, BlockKind};
+        // Use data that doesn't compress well so the blocks are short enough to be stored.
+        let data = scrambled_test_data();
+        for &forced in &[BlockKind::Stored, BlockKind::Fixed, BlockKind::Dynamic] {
+            let mut compressor = DeflateEncoder::new(
+                Vec::with_capacity(data.len()),
+                CompressionOptions::default(),
+            );
+            compressor.deflate_state.block_log = Some(Vec::new());
+            compressor.set_block_type_selector(move |costs: &BlockCosts, _| {
+                assert!(costs.input_len > 0);
+                forced
+            });
+            compressor.write_all(&data).unwrap();
+            compressor.output_all().unwrap();
+            let blocks = compressor.deflate_state.block_log.take().unwrap();
+            // The final block may be a short fixed one.
+            assert!(blocks.len() > 2);
+            assert!(blocks[..blocks.len() - 1].iter().all(|b| b.kind == forced));
+            let compressed = compressor.finish().unwrap();
+            if forced == BlockKind::Stored {
+                assert!(compressed.len() > data.len());
+            }
+            assert!(decompress_to_end(&compressed) == data);
+        }
+    }
+
+    #[test]
+    fn low_latency() {
+        use crate::compress::BlockKind;
+
+        let data = get_test_data();
+        let flush_after = 1000;
+        let mut compressor = ZlibEncoder::new(
+            Vec::new(),
+            CompressionOptions::low_latency(flush_after as u32),
+        );
+        compressor.deflate_state.block_log = Some(Vec::new());
+        for chunk in data.chunks(777) {
+            compressor.write_all(chunk).unwrap();
+            // Everything up to the last multiple of the threshold should have been flushed.
+            let written = compressor.deflate_state.bytes_written;
+            assert_eq!(
+                compressor.deflate_state.bytes_written_at_flush,
+                written - written % flush_after
+            );
+        }
+        compressor.output_all().unwrap();
+        let blocks = compressor.deflate_state.block_log.take().unwrap();
+        let syncs = blocks
+            .iter()
+            .filter(|b| b.kind == BlockKind::Stored && b.input_len == 0)
+            .count();
+        assert_eq!(syncs as u64, data.len() as u64 / flush_after);
+        assert!(blocks
+            .iter()
+            .all(|b| b.kind == BlockKind::Fixed || b.input_len == 0));
+        // Blocks are limited to 4096 symbols, each covering at most 258 bytes.
+        assert!(blocks.iter().all(|b| b.input_len <= 4096 * 258));
+
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_zlib(&compressed) == data);
+    }
+
+    #[test]
+    fn auto_flush_setter() {
+        let data = get_test_data();
+        let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+        compressor.write_all(&data[..5000]).unwrap();
+        assert_eq!(compressor.deflate_state.bytes_written_at_flush, 0);
+
+        compressor.set_auto_flush_after(2000);
+        compressor.write_all(&data[5000..6500]).unwrap();
+        // The first flush happens as soon as the threshold is passed.
+        assert_eq!(compressor.deflate_state.bytes_written_at_flush, 5000);
+        let out = compressor.deflate_state.inner.as_ref().unwrap();
+        assert_eq!(out[out.len() - 4..], [0, 0, 255, 255]);
+        compressor.write_all(&data[6500..7500]).unwrap();
+        assert_eq!(compressor.deflate_state.bytes_written_at_flush, 7000);
+
+        compressor.set_auto_flush_after(0);
+        compressor.write_all(&data[7500..20000]).unwrap();
+        assert_eq!(compressor.deflate_state.bytes_written_at_flush, 7000);
+        compressor.write_all(&data[20000..]).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert!(decompress_to_end(&compressed) == data);
+    }
+
+    #[test]
+    fn max_block_symbols() {
+        use crate::compress::BlockKind;
+
+        let data = get_test_data();
+        let mut options = CompressionOptions::default();
+        options.max_block_symbols = 1000;
+        let mut compressor = DeflateEncoder::new(Vec::new(), options);
+        compressor.deflate_state.block_log = Some(Vec::new());
+        compressor.write_all(&data).unwrap();
+        compressor.output_all().unwrap();
+        let blocks = compressor.deflate_state.block_log.take().unwrap();
+        let default_blocks = {
+            let mut compressor = DeflateEncoder::new(Vec::new(), CompressionOptions::default());
+            compressor.deflate_state.block_log = Some(Vec::new());
+            compressor.write_all(&data).unwrap();
+            compressor.output_all().unwrap();
+            compressor.deflate_state.block_log.take().unwrap().len()
+        };
+        assert!(blocks.len() > default_blocks * 10);
+        assert!(blocks.iter().any(|b| b.kind == BlockKind::Dynamic));
+        assert!(decompress_to_end(&compressor.finish().unwrap()) == data);
     }
 
     #[test]
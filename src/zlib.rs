@@ -2,35 +2,49 @@
 //! header.
 //!
 //! The Zlib header contains some metadata (a window size and a compression level), and optionally
-//! a block of data serving as an extra dictionary for the compressor/decompressor.
-//! The dictionary is not implemented in this library.
-//! The data in the header aside from the dictionary doesn't actually have any effect on the
+//! the id of a preset dictionary the data was compressed with, which the decompressor has to be
+//! given to decompress it.
+//! The data in the header aside from the dictionary id doesn't actually have any effect on the
 //! decompressed data, it only offers some hints for the decompressor on how the data was
 //! compressed.
 
 use std::io::{Result, Write};
 
+use crate::checksum::{Adler32Checksum, RollingChecksum};
+
 // CM = 8 means to use the DEFLATE compression method.
 const DEFAULT_CM: u8 = 8;
 // CINFO = 7 Indicates a 32k window size.
+#[cfg(test)]
 const DEFAULT_CINFO: u8 = 7 << 4;
+#[cfg(test)]
 const DEFAULT_CMF: u8 = DEFAULT_CM | DEFAULT_CINFO;
 
 // No dict by default.
 #[cfg(test)]
 const DEFAULT_FDICT: u8 = 0;
+// Set if the header is followed by the id of a preset dictionary.
+const FDICT: u8 = 1 << 5;
 // FLEVEL = 0 means fastest compression algorithm.
 const _DEFAULT_FLEVEL: u8 = 0 << 7;
 
 // The 16-bit value consisting of CMF and FLG must be divisible by this to be valid.
 const FCHECK_DIVISOR: u8 = 31;
 
-#[allow(dead_code)]
+/// The compression level hint stored in the FLEVEL field of the zlib header.
+///
+/// This is purely informational and has no effect on the compression itself. Decompressors
+/// ignore it, but some tools inspect it to decide whether recompressing may be worthwhile.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 #[repr(u8)]
 pub enum CompressionLevel {
+    /// The fastest algorithm was used.
     Fastest = 0 << 6,
+    /// A fast algorithm was used.
     Fast = 1 << 6,
+    /// The default algorithm was used.
     Default = 2 << 6,
+    /// The slowest algorithm with the maximum compression was used.
     Maximum = 3 << 6,
 }
 
@@ -49,16 +63,46 @@ fn add_fcheck(cmf: u8, flg: u8) -> u8 {
 }
 
 /// Write a zlib header with an empty dictionary to the writer using the specified
-/// compression level preset.
-pub fn write_zlib_header<W: Write>(writer: &mut W, level: CompressionLevel) -> Result<()> {
-    writer.write_all(&get_zlib_header(level))
+/// compression level preset and window size.
+pub fn write_zlib_header<W: Write>(
+    writer: &mut W,
+    level: CompressionLevel,
+    window_bits: u8,
+) -> Result<()> {
+    writer.write_all(&get_zlib_header(level, window_bits))
+}
+
+/// Write a zlib header for a stream compressed using the preset dictionary with the id
+/// `dictionary_id`, using the specified compression level preset and window size.
+pub fn write_zlib_header_with_dict<W: Write>(
+    writer: &mut W,
+    level: CompressionLevel,
+    window_bits: u8,
+    dictionary_id: u32,
+) -> Result<()> {
+    writer.write_all(&zlib_header(level, window_bits, FDICT))?;
+    writer.write_all(&dictionary_id.to_be_bytes())
+}
+
+/// Get the zlib header for the `CompressionLevel` level and a window size of
+/// `2^window_bits` bytes with no dictionary.
+pub fn get_zlib_header(level: CompressionLevel, window_bits: u8) -> [u8; 2] {
+    zlib_header(level, window_bits, 0)
+}
+
+fn zlib_header(level: CompressionLevel, window_bits: u8, fdict: u8) -> [u8; 2] {
+    debug_assert!(window_bits >= 8 && window_bits <= 15);
+    // CINFO is the base two logarithm of the window size minus eight.
+    let cmf = DEFAULT_CM | ((window_bits - 8) << 4);
+    [cmf, add_fcheck(cmf, level as u8 | fdict)]
 }
 
-/// Get the zlib header for the `CompressionLevel` level using the default window size and no
+/// The id of a preset dictionary written in the header, which is the Adler-32 checksum of the
 /// dictionary.
-pub fn get_zlib_header(level: CompressionLevel) -> [u8; 2] {
-    let cmf = DEFAULT_CMF;
-    [cmf, add_fcheck(cmf, level as u8)]
+pub fn dictionary_id(dictionary: &[u8]) -> u32 {
+    let mut checksum = Adler32Checksum::new();
+    checksum.update_from_slice(dictionary);
+    checksum.current_hash()
 }
 
 #[cfg(test)]
@@ -78,10 +122,34 @@ mod test {
 
     #[test]
     fn test_header() {
-        let header = get_zlib_header(CompressionLevel::Fastest);
+        let header = get_zlib_header(CompressionLevel::Fastest, 15);
+        assert_eq!(header[0], DEFAULT_CMF);
+        assert_eq!(
+            ((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
+            0
+        );
+
+        for window_bits in 9..=15 {
+            let header = get_zlib_header(CompressionLevel::Default, window_bits);
+            assert_eq!(header[0] >> 4, window_bits - 8);
+            assert_eq!(
+                ((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn test_header_with_dict() {
+        let mut header = Vec::new();
+        write_zlib_header_with_dict(&mut header, CompressionLevel::Default, 15, 0x0102_0304)
+            .unwrap();
+        assert_eq!(header.len(), 6);
+        assert_eq!(header[1] & FDICT, FDICT);
         assert_eq!(
             ((usize::from(header[0]) * 256) + usize::from(header[1])) % 31,
             0
         );
+        assert_eq!(header[2..], [1, 2, 3, 4]);
     }
 }
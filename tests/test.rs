@@ -1,3 +1,4 @@
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
 extern crate deflate;
 extern crate miniz_oxide;
 
@@ -24,7 +25,7 @@ fn roundtrip(data: &[u8]) {
 }
 
 fn roundtrip_conf(data: &[u8], level: CompressionOptions) {
-    let compressed = deflate::deflate_bytes_zlib_conf(data, level);
+    let compressed = deflate::try_deflate_bytes_zlib_conf(data, level).unwrap();
     println!("Compressed len: {}, level: {:?}", compressed.len(), level);
     let decompressed =
         miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).expect("Decompression failed!");
@@ -59,7 +60,7 @@ fn file_zlib_compare_output() {
 fn block_type() {
     let test_file = "tests/short.bin";
     let test_data = get_test_file_data(test_file);
-    let compressed = deflate::deflate_bytes_zlib(&test_data);
+    let compressed = deflate::try_deflate_bytes_zlib(&test_data).unwrap();
     assert_eq!(compressed.len(), 30);
 
     roundtrip(b"test");
@@ -98,9 +99,9 @@ fn fast() {
 
 #[test]
 fn rle() {
-    use deflate::{deflate_bytes_conf, CompressionOptions};
+    use deflate::{try_deflate_bytes_conf, CompressionOptions};
     let test_data = get_test_data();
-    let compressed = deflate_bytes_conf(&test_data, CompressionOptions::rle());
+    let compressed = try_deflate_bytes_conf(&test_data, CompressionOptions::rle()).unwrap();
     let decompressed =
         miniz_oxide::inflate::decompress_to_vec(&compressed).expect("Decompression failed!");
 
@@ -198,3 +199,204 @@ mod issue_47 {
         }
     }
 }
+
+/// FNV-1a hash of the output, to avoid storing the full expected output in the test.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// The output with `OutputVersion::V1` must never change, if this test fails a change to the
+// compression algorithm has to be restricted to `OutputVersion::Latest`.
+#[test]
+fn stable_output_v1() {
+    use deflate::OutputVersion;
+    let data = get_test_file_data("tests/pg11.txt");
+    // The length and hash of the output of release 1.0.0 with the same options.
+    let expected = [
+        (60528, 10906475685725074610),
+        (76006, 5602167815473748162),
+        (60338, 733850150149226393),
+        (98009, 3143052144934649939),
+        (98034, 6676982825409377549),
+        (62532, 14265061709613392574),
+    ];
+    let options = [
+        CompressionOptions::default(),
+        CompressionOptions::fast(),
+        CompressionOptions::high(),
+        CompressionOptions::huffman_only(),
+        CompressionOptions::rle(),
+    ];
+    let mut results = Vec::new();
+    for &options in &options {
+        let options = CompressionOptions {
+            output_version: OutputVersion::V1,
+            ..options
+        };
+        let compressed = deflate::try_deflate_bytes_conf(&data, options).unwrap();
+        results.push((compressed.len(), fnv1a(&compressed)));
+    }
+
+    // Written in chunks with a sync flush in the middle.
+    let options = CompressionOptions {
+        output_version: OutputVersion::V1,
+        ..CompressionOptions::default()
+    };
+    let mut encoder = deflate::write::DeflateEncoder::new(Vec::new(), options);
+    for chunk in data.chunks(10_000) {
+        encoder.write_all(chunk).unwrap();
+    }
+    encoder.flush().unwrap();
+    encoder.write_all(&data[..5000]).unwrap();
+    let compressed = encoder.finish().unwrap();
+    results.push((compressed.len(), fnv1a(&compressed)));
+
+    assert_eq!(results, expected);
+}
+
+// Flushing twice in a row outputs two sync markers with `OutputVersion::V1`, like release 1.0.0.
+#[test]
+fn stable_output_v1_double_flush() {
+    use deflate::OutputVersion;
+    // The output of release 1.0.0 with the same writes and flushes.
+    let expected = [
+        10, 206, 207, 77, 85, 72, 73, 44, 73, 212, 81, 40, 70, 99, 230, 230, 23, 65, 248, 122, 0,
+        0, 0, 0, 255, 255, 2, 0, 0, 0, 255, 255, 82, 112, 204, 75, 65, 136, 233, 1, 0, 0, 0, 255,
+        255, 2, 0, 0, 0, 255, 255, 3, 0,
+    ];
+    let compress = |output_version| {
+        let options = CompressionOptions {
+            output_version,
+            ..CompressionOptions::default()
+        };
+        let mut encoder = deflate::write::DeflateEncoder::new(Vec::new(), options);
+        encoder
+            .write_all(b"Some data, some data, some more data.")
+            .unwrap();
+        encoder.flush().unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(b" And some more.").unwrap();
+        encoder.flush().unwrap();
+        encoder.flush().unwrap();
+        encoder.finish().unwrap()
+    };
+    assert_eq!(compress(OutputVersion::V1), &expected[..]);
+    // The latest version leaves out the redundant empty blocks.
+    assert!(compress(OutputVersion::Latest).len() < expected.len());
+}
+
+// Compressing more than 4 GiB takes a while, so this is only run on request.
+#[test]
+#[ignore]
+#[cfg(feature = "gzip")]
+fn gzip_larger_than_4gib() {
+    use std::io;
+
+    /// Counts the bytes written, keeping only the last 8 to check the trailer.
+    struct Tail {
+        len: u64,
+        last: Vec<u8>,
+    }
+
+    impl Write for Tail {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.len += buf.len() as u64;
+            self.last.extend_from_slice(buf);
+            let excess = self.last.len().saturating_sub(8);
+            self.last.drain(..excess);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let chunk = vec![0u8; 1 << 20];
+    let chunks = 4 * 1024 + 100;
+    let total = chunks as u64 * chunk.len() as u64;
+    let tail = Tail {
+        len: 0,
+        last: Vec::new(),
+    };
+    let mut encoder = deflate::write::GzEncoder::new(tail, CompressionOptions::rle());
+    for _ in 0..chunks {
+        encoder.write_all(&chunk).unwrap();
+    }
+    assert_eq!(encoder.total_in(), total);
+    let tail = encoder.finish().unwrap();
+    assert!(tail.len > 0);
+    assert_eq!(tail.last[4..], ((total % (1 << 32)) as u32).to_le_bytes());
+}
+
+// Compress and decompress the test file with the command line tool in each format.
+#[test]
+#[cfg(feature = "cli")]
+fn cli_roundtrip() {
+    use std::path::Path;
+    use std::process::Command;
+
+    let run = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_deflate-cli"))
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "deflate-cli {:?} failed", args);
+    };
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let data = get_test_file_data("tests/pg11.txt");
+    for &(format, level) in &[("raw", "fast"), ("zlib", "default"), ("gzip", "best")] {
+        let compressed = dir.join(format!("cli_roundtrip.{}", format));
+        let decompressed = dir.join(format!("cli_roundtrip_{}.txt", format));
+        let compressed = compressed.to_str().unwrap();
+        let decompressed = decompressed.to_str().unwrap();
+        run(&["-f", format, "-l", level, "tests/pg11.txt", compressed]);
+        run(&["-d", "-f", format, compressed, decompressed]);
+        assert!(get_test_file_data(decompressed) == data);
+        assert!(get_test_file_data(compressed).len() < data.len() / 2);
+    }
+}
+
+#[cfg(feature = "allocator-api")]
+#[test]
+fn pool_with_allocator() {
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use deflate::write::ZlibEncoder;
+    use deflate::BufferPool;
+
+    /// Counts the bytes currently allocated through it.
+    struct Counting(Arc<AtomicUsize>);
+
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(layout.size(), Ordering::SeqCst);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(layout.size(), Ordering::SeqCst);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    let data = get_test_data();
+    let allocated = Arc::new(AtomicUsize::new(0));
+    let pool = BufferPool::with_allocator(Counting(allocated.clone()));
+    for _ in 0..2 {
+        let mut encoder = ZlibEncoder::with_pool(Vec::new(), CompressionOptions::default(), &pool);
+        encoder.write_all(&data).unwrap();
+        assert!(allocated.load(Ordering::SeqCst) > 128 * 1024);
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed == deflate::try_deflate_bytes_zlib(&data).unwrap());
+    }
+    // The buffers are kept in the pool until it is cleared.
+    assert!(allocated.load(Ordering::SeqCst) > 128 * 1024);
+    pool.clear();
+    assert_eq!(allocated.load(Ordering::SeqCst), 0);
+}